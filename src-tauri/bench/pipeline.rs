@@ -0,0 +1,189 @@
+//! Throughput of the matching pipeline on a synthetic survey tree: survey
+//! discovery, graded indexing and matching, each timed on its own.
+//!
+//! `cargo bench --features bench --bench pipeline -- --surveys 50 --images
+//! 2000` prints a table; `--save` writes the results as JSON and
+//! `--baseline` compares a later run against them, failing when a stage got
+//! slower than `--tolerance` allows.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use survey_labeler::{bench, generate_synthetic_tree, AppError, Rules, SyntheticTreeOptions};
+
+#[derive(Parser)]
+#[command(name = "pipeline")]
+struct Args {
+    #[arg(long, default_value_t = 20)]
+    surveys: u32,
+    /// Raw images per survey.
+    #[arg(long, default_value_t = 500)]
+    images: u32,
+    /// Share of raw images with a graded copy.
+    #[arg(long, default_value_t = 0.3)]
+    graded_ratio: f64,
+    /// Timed runs per stage after one warm-up run; the median is reported.
+    #[arg(long, default_value_t = 5)]
+    iterations: u32,
+    /// Existing tree with `Raw` and `Graded` folders, e.g. on a network
+    /// share, instead of a generated one.
+    #[arg(long)]
+    tree: Option<PathBuf>,
+    /// Write the results to this JSON file.
+    #[arg(long)]
+    save: Option<PathBuf>,
+    /// Results of an earlier run to compare with.
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+    /// Throughput drop against the baseline, in percent, that fails the run.
+    #[arg(long, default_value_t = 20.0)]
+    tolerance: f64,
+    /// Passed by `cargo bench`.
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BenchReport {
+    raw_images: u64,
+    graded_images: u64,
+    stages: Vec<StageResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StageResult {
+    stage: String,
+    /// Surveys for discovery, graded images for indexing, rows for matching.
+    items: u64,
+    median_ms: f64,
+    items_per_second: f64,
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the benchmark; `false` when a stage regressed against the baseline.
+fn run(args: Args) -> Result<bool, AppError> {
+    let (root, generated) = match &args.tree {
+        Some(tree) => (tree.clone(), false),
+        None => {
+            let root = std::env::temp_dir().join("survey-labeler-bench");
+            let _ = fs::remove_dir_all(&root);
+            generate_synthetic_tree(SyntheticTreeOptions {
+                output_dir: Some(root.to_string_lossy().to_string()),
+                surveys: args.surveys,
+                images_per_survey: args.images,
+                graded_ratio: args.graded_ratio,
+                ..SyntheticTreeOptions::default()
+            })?;
+            (root, true)
+        }
+    };
+    let report = measure(&root, args.iterations.max(1));
+    if generated {
+        let _ = fs::remove_dir_all(&root);
+    }
+    let report = report?;
+    print_report(&report);
+
+    if let Some(path) = &args.save {
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    let Some(path) = &args.baseline else {
+        return Ok(true);
+    };
+    let baseline: BenchReport = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if (baseline.raw_images, baseline.graded_images) != (report.raw_images, report.graded_images) {
+        println!("note: the baseline was measured on a tree of another size");
+    }
+    let mut passed = true;
+    for stage in &report.stages {
+        let Some(before) = baseline.stages.iter().find(|old| old.stage == stage.stage) else {
+            continue;
+        };
+        let change = (stage.items_per_second / before.items_per_second - 1.0) * 100.0;
+        let regressed = change < -args.tolerance;
+        println!(
+            "{:<16} {:>+8.1}% against baseline{}",
+            stage.stage,
+            change,
+            if regressed { "  REGRESSION" } else { "" }
+        );
+        passed &= !regressed;
+    }
+    Ok(passed)
+}
+
+fn measure(root: &Path, iterations: u32) -> Result<BenchReport, AppError> {
+    let rules = Rules::default();
+    let (raw_root, graded_root) = (root.join("Raw"), root.join("Graded"));
+    let discover = || bench::discover(raw_root.clone(), graded_root.clone(), &rules);
+
+    let pairs = discover()?;
+    let graded_images = bench::index_graded(&pairs, &rules)?;
+    let raw_images = bench::match_pairs(&pairs, &rules)?;
+    let discovery = time(iterations, || discover().map(|pairs| pairs.len() as u64))?;
+    let indexing = time(iterations, || bench::index_graded(&pairs, &rules))?;
+    let matching = time(iterations, || bench::match_pairs(&pairs, &rules))?;
+
+    let stage = |name: &str, items: u64, median: Duration| StageResult {
+        stage: name.to_string(),
+        items,
+        median_ms: median.as_secs_f64() * 1000.0,
+        items_per_second: items as f64 / median.as_secs_f64().max(1e-9),
+    };
+    Ok(BenchReport {
+        raw_images,
+        graded_images,
+        stages: vec![
+            stage("discovery", pairs.len() as u64, discovery),
+            stage("graded_indexing", graded_images, indexing),
+            stage("matching", raw_images, matching),
+        ],
+    })
+}
+
+/// Median time of `iterations` runs of `stage`, after the warm-up run the
+/// caller already made.
+fn time(
+    iterations: u32,
+    mut stage: impl FnMut() -> Result<u64, AppError>,
+) -> Result<Duration, AppError> {
+    let mut times = Vec::new();
+    for _ in 0..iterations {
+        let started = Instant::now();
+        stage()?;
+        times.push(started.elapsed());
+    }
+    times.sort();
+    Ok(times[times.len() / 2])
+}
+
+fn print_report(report: &BenchReport) {
+    println!(
+        "{} raw and {} graded images",
+        report.raw_images, report.graded_images
+    );
+    println!(
+        "{:<16} {:>10} {:>12} {:>14}",
+        "stage", "items", "median ms", "items/s"
+    );
+    for stage in &report.stages {
+        println!(
+            "{:<16} {:>10} {:>12.1} {:>14.0}",
+            stage.stage, stage.items, stage.median_ms, stage.items_per_second
+        );
+    }
+}
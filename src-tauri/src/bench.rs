@@ -0,0 +1,96 @@
+//! The stages of the matching pipeline one at a time, for the benchmark in
+//! `bench/pipeline.rs`, built with the `bench` feature only. Not part of the
+//! app's API.
+
+use std::path::PathBuf;
+
+use crate::control::CancelToken;
+use crate::matching::{build_graded_map, process_pair, PairOptions};
+use crate::rules::{compile_rules, Rules};
+use crate::scan::{scan_roots, ScanEntry, ScanOptions};
+use crate::{AppError, AppSettings, CsvSettings, Host, ProgressGranularity};
+
+/// Survey pairs found by `discover`.
+pub struct Pairs {
+    entries: Vec<ScanEntry>,
+}
+
+impl Pairs {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Finds and pairs the survey folders below both roots, keeping the pairs
+/// that a run would process.
+pub fn discover(raw_root: PathBuf, graded_root: PathBuf, rules: &Rules) -> Result<Pairs, AppError> {
+    let compiled = compile_rules(rules)?;
+    let scan = scan_roots(
+        &[raw_root],
+        &[graded_root],
+        &compiled,
+        &ScanOptions::default(),
+    )?;
+    let entries = scan
+        .entries
+        .into_iter()
+        .filter(|entry| entry.status == "OK" && entry.raw.is_some() && entry.graded.is_some())
+        .collect();
+    Ok(Pairs { entries })
+}
+
+/// Builds the graded map of every pair; returns the graded images indexed.
+pub fn index_graded(pairs: &Pairs, rules: &Rules) -> Result<u64, AppError> {
+    let compiled = compile_rules(rules)?;
+    let mut images = 0;
+    for graded in pairs
+        .entries
+        .iter()
+        .filter_map(|entry| entry.graded.as_ref())
+    {
+        let result = build_graded_map(
+            &graded.path,
+            graded.part.as_ref(),
+            &compiled,
+            None,
+            None,
+            &CancelToken::default(),
+        )?;
+        images += result.map.len()? as u64;
+    }
+    Ok(images)
+}
+
+/// Labels the raw images of every pair, dropping the rows; returns the rows
+/// produced.
+pub fn match_pairs(pairs: &Pairs, rules: &Rules) -> Result<u64, AppError> {
+    let compiled = compile_rules(rules)?;
+    let host = Host::headless(std::env::temp_dir(), false);
+    let cancel = CancelToken::default();
+    let mut rows = 0;
+    for entry in &pairs.entries {
+        let (Some(raw), Some(graded)) = (&entry.raw, &entry.graded) else {
+            continue;
+        };
+        let result = process_pair(
+            &host,
+            &compiled,
+            &entry.base_key,
+            raw,
+            graded,
+            PairOptions::new(
+                ProgressGranularity::default(),
+                &CsvSettings::default(),
+                &AppSettings::default(),
+                &cancel,
+            ),
+            &mut |_| Ok(()),
+        )?;
+        rows += result.counts.rows;
+    }
+    Ok(rows)
+}
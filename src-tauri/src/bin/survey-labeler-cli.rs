@@ -0,0 +1,305 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use survey_labeler::{
+    diff_runs, estimate_run, export_dataset, get_or_init_rules, load_profile, preview_root_scan,
+    run_root_scan, run_single_pair, AppError, DatasetExportOptions, DatasetFormat, DatasetLinkMode,
+    Host, PreviewOptions, RootRunOptions, Rules, SingleRunOptions, EXIT_OK,
+};
+
+/// Same identifier as in tauri.conf.json, so the CLI shares `rules.json` and
+/// `settings.json` with the desktop app by default.
+const APP_IDENTIFIER: &str = "com.pelatlas.survey-label-exporter";
+
+/// Headless survey labeling for file servers and batch scripts. Results are
+/// printed to stdout as JSON, progress goes to stderr.
+#[derive(Parser)]
+#[command(name = "survey-labeler-cli", version)]
+struct Cli {
+    /// Folder holding rules.json, settings.json and run workspaces
+    /// (default: the desktop app's data folder).
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+    /// Rules file to use instead of the stored rules.json.
+    #[arg(long, global = true)]
+    rules: Option<PathBuf>,
+    /// Rule profile to use instead of the active one.
+    #[arg(long, global = true, conflicts_with = "rules")]
+    profile: Option<String>,
+    /// Do not print progress to stderr.
+    #[arg(long, global = true)]
+    quiet: bool,
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// dolphin/ and no_dolphin/ folders.
+    Folders,
+    /// images/ plus a COCO annotations.json.
+    Coco,
+    /// YOLO classification layout with train/ and val/ splits.
+    Yolo,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pair the survey folders of both roots and list them with image counts.
+    Preview {
+        /// Graded root; repeat to scan several.
+        #[arg(long, required = true)]
+        graded_root: Vec<PathBuf>,
+        /// Raw root; repeat to scan several.
+        #[arg(long, required = true)]
+        raw_root: Vec<PathBuf>,
+        /// JSON file with preview options (`PreviewOptions`).
+        #[arg(long)]
+        options: Option<PathBuf>,
+    },
+    /// Count the surveys and images below both roots and estimate the
+    /// duration and output size of a run.
+    Estimate {
+        /// Graded root; repeat to scan several.
+        #[arg(long, required = true)]
+        graded_root: Vec<PathBuf>,
+        /// Raw root; repeat to scan several.
+        #[arg(long, required = true)]
+        raw_root: Vec<PathBuf>,
+        /// JSON file with the root run options (`RootRunOptions`) of the
+        /// run to estimate.
+        #[arg(long)]
+        options: Option<PathBuf>,
+    },
+    /// Label every paired survey below both roots.
+    Run {
+        /// Graded root; repeat to scan several.
+        #[arg(long, required = true)]
+        graded_root: Vec<PathBuf>,
+        /// Raw root; repeat to scan several.
+        #[arg(long, required = true)]
+        raw_root: Vec<PathBuf>,
+        #[arg(long)]
+        output_dir: PathBuf,
+        /// JSON file with root run options (`RootRunOptions`).
+        #[arg(long)]
+        options: Option<PathBuf>,
+    },
+    /// Label a single graded/raw folder pair.
+    Single {
+        #[arg(long)]
+        graded_dir: PathBuf,
+        #[arg(long)]
+        raw_dir: PathBuf,
+        #[arg(long)]
+        output_dir: PathBuf,
+        /// Survey id to use instead of the one detected in the folder names.
+        #[arg(long)]
+        survey_id: Option<String>,
+        /// JSON file with single run options (`SingleRunOptions`).
+        #[arg(long)]
+        options: Option<PathBuf>,
+    },
+    /// Compare the merged CSVs of two runs: added, removed and relabeled rows.
+    Diff {
+        #[arg(long)]
+        old_csv: PathBuf,
+        #[arg(long)]
+        new_csv: PathBuf,
+    },
+    /// Copy the raw images of a merged CSV into a training set layout.
+    ExportDataset {
+        #[arg(long)]
+        merged_csv: PathBuf,
+        #[arg(long)]
+        output_dir: PathBuf,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Folders)]
+        format: ExportFormat,
+        /// Share of images in the val/ split of a YOLO export.
+        #[arg(long, default_value_t = 0.2)]
+        val_fraction: f64,
+        /// Hard-link the images instead of copying them where possible.
+        #[arg(long)]
+        hardlink: bool,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let error_format = cli.error_format;
+    match run(cli) {
+        Ok(code) => exit_code(code),
+        Err(err) => {
+            match error_format {
+                ErrorFormat::Text => eprintln!("error: {}", err),
+                ErrorFormat::Json => match serde_json::to_string(&err.report()) {
+                    Ok(json) => eprintln!("{}", json),
+                    Err(_) => eprintln!("error: {}", err),
+                },
+            }
+            exit_code(err.exit_code())
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<i32, AppError> {
+    let data_dir = match cli.data_dir {
+        Some(dir) => dir,
+        None => default_data_dir()?,
+    };
+    let host = Host::headless(data_dir, !cli.quiet);
+    let rules = match (&cli.rules, &cli.profile) {
+        (Some(path), _) => read_json::<Rules>(path)?,
+        (None, Some(name)) => load_profile(&host, name)?,
+        (None, None) => get_or_init_rules(&host)?,
+    };
+
+    match cli.command {
+        Command::Preview {
+            graded_root,
+            raw_root,
+            options,
+        } => {
+            let options = match options {
+                Some(path) => read_json(&path)?,
+                None => PreviewOptions::default(),
+            };
+            let preview = preview_root_scan(&host, graded_root, raw_root, options, rules)?;
+            print_json(&preview)?;
+            Ok(EXIT_OK)
+        }
+        Command::Estimate {
+            graded_root,
+            raw_root,
+            options,
+        } => {
+            let options = match options {
+                Some(path) => read_json(&path)?,
+                None => default_root_options()?,
+            };
+            print_json(&estimate_run(&host, graded_root, raw_root, options, rules)?)?;
+            Ok(EXIT_OK)
+        }
+        Command::Run {
+            graded_root,
+            raw_root,
+            output_dir,
+            options,
+        } => {
+            let options = match options {
+                Some(path) => read_json(&path)?,
+                None => default_root_options()?,
+            };
+            let summary = run_root_scan(&host, graded_root, raw_root, output_dir, options, rules)?;
+            print_json(&summary)?;
+            Ok(summary.exit_code())
+        }
+        Command::Single {
+            graded_dir,
+            raw_dir,
+            output_dir,
+            survey_id,
+            options,
+        } => {
+            let options: SingleRunOptions = match options {
+                Some(path) => read_json(&path)?,
+                None => serde_json::from_value(serde_json::json!({
+                    "output_filename": "single.csv"
+                }))?,
+            };
+            let summary = run_single_pair(
+                &host, graded_dir, raw_dir, output_dir, survey_id, options, rules,
+            )?;
+            print_json(&summary)?;
+            Ok(summary.exit_code())
+        }
+        Command::Diff { old_csv, new_csv } => {
+            print_json(&diff_runs(old_csv, new_csv, rules)?)?;
+            Ok(EXIT_OK)
+        }
+        Command::ExportDataset {
+            merged_csv,
+            output_dir,
+            format,
+            val_fraction,
+            hardlink,
+        } => {
+            let options = DatasetExportOptions {
+                link_mode: if hardlink {
+                    DatasetLinkMode::Hardlink
+                } else {
+                    DatasetLinkMode::Copy
+                },
+                format: match format {
+                    ExportFormat::Folders => DatasetFormat::Folders,
+                    ExportFormat::Coco => DatasetFormat::Coco,
+                    ExportFormat::Yolo => DatasetFormat::Yolo,
+                },
+                val_fraction,
+            };
+            print_json(&export_dataset(merged_csv, output_dir, options)?)?;
+            Ok(EXIT_OK)
+        }
+    }
+}
+
+/// Options of `run` and `estimate` without `--options`: merged and
+/// per-survey CSVs.
+fn default_root_options() -> Result<RootRunOptions, AppError> {
+    Ok(serde_json::from_value(serde_json::json!({
+        "write_per_survey": true,
+        "write_merged": true,
+        "merged_filename": "merged.csv",
+        "problems_filename": "problems.csv",
+        "per_survey_dirname": "per_survey"
+    }))?)
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, AppError> {
+    let data = fs::read_to_string(path)?;
+    serde_json::from_str(&data).map_err(|err| {
+        AppError::Validation(format!(
+            "Invalid JSON in {}: {}",
+            path.to_string_lossy(),
+            err
+        ))
+    })
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), AppError> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn exit_code(code: i32) -> ExitCode {
+    ExitCode::from(u8::try_from(code).unwrap_or(1))
+}
+
+/// The desktop app's data folder (Tauri's `app_data_dir`) for this platform.
+fn default_data_dir() -> Result<PathBuf, AppError> {
+    let env_dir = |name: &str| std::env::var_os(name).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env_dir("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env_dir("XDG_DATA_HOME")
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env_dir("HOME").map(|home| home.join(".local").join("share")))
+    };
+    base.map(|dir| dir.join(APP_IDENTIFIER)).ok_or_else(|| {
+        AppError::Validation("Cannot locate the app data folder; pass --data-dir.".to_string())
+    })
+}
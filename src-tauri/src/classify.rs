@@ -0,0 +1,731 @@
+//! Choice of the winning graded candidate and the labels decided from it.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::rules::{fold_candidate, normalize_candidate, CompiledRules};
+
+#[derive(Clone, Debug)]
+pub(crate) struct CandidateWinner {
+    pub(crate) relpath: String,
+    pub(crate) winner_type: String,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct WinnerDetails {
+    pub(crate) top_folder: String,
+    pub(crate) depth: usize,
+    pub(crate) matched_tokens: Vec<String>,
+    pub(crate) full_path: String,
+}
+
+/// The labels and winner one raw image or video frame gets from its graded
+/// candidates.
+pub(crate) struct Labeling {
+    pub(crate) dolphin: u8,
+    pub(crate) labels: Vec<u8>,
+    pub(crate) graded_relpath: String,
+    pub(crate) winner_type: String,
+    pub(crate) winner: Option<WinnerDetails>,
+    pub(crate) quality: Option<String>,
+    pub(crate) confidence: f64,
+    pub(crate) decision_reason: String,
+}
+
+/// Labels from `candidates`; `uncertain` lowers the confidence for matches
+/// through an ambiguous id or a perceptual hash.
+pub(crate) fn label_candidates(
+    candidates: &[String],
+    uncertain: bool,
+    graded_root: &Path,
+    rules: &CompiledRules,
+) -> Labeling {
+    let winner = select_winner(candidates, rules);
+    let dolphin_candidates = dolphin_candidates(candidates, rules);
+    let (dolphin, decision_reason) = label_decision(
+        &dolphin_candidates,
+        &rules.positive_tokens,
+        &rules.negative_tokens,
+        rules,
+    );
+    let labels = rules
+        .labels
+        .iter()
+        .map(|label| {
+            label_value(
+                candidates,
+                &label.positive_tokens,
+                &label.negative_tokens,
+                rules,
+            )
+        })
+        .collect();
+    let (graded_relpath, winner_type) = match &winner {
+        Some(winner) if !candidates.is_empty() => {
+            (winner.relpath.clone(), winner.winner_type.clone())
+        }
+        _ => ("RAW".to_string(), "RAW".to_string()),
+    };
+    let confidence = label_confidence(candidates, &winner_type, dolphin, uncertain, rules);
+    Labeling {
+        dolphin,
+        labels,
+        graded_relpath,
+        winner_type,
+        winner: winner
+            .as_ref()
+            .map(|value| winner_details(&value.relpath, graded_root, rules)),
+        quality: select_quality(
+            winner.as_ref().map(|value| value.relpath.as_str()),
+            candidates,
+            rules,
+        ),
+        confidence,
+        decision_reason,
+    }
+}
+
+/// The `dolphin` value and extra label values `candidate` would give on its
+/// own. Copies that classify differently are kept apart when deduplicating,
+/// so a negative copy still vetoes a positive one of the same content.
+pub(crate) fn candidate_labels(candidate: &str, rules: &CompiledRules) -> Vec<u8> {
+    let candidates = [candidate.to_string()];
+    let dolphin_candidates = dolphin_candidates(&candidates, rules);
+    std::iter::once(label_value(
+        &dolphin_candidates,
+        &rules.positive_tokens,
+        &rules.negative_tokens,
+        rules,
+    ))
+    .chain(rules.labels.iter().map(|label| {
+        label_value(
+            &candidates,
+            &label.positive_tokens,
+            &label.negative_tokens,
+            rules,
+        )
+    }))
+    .collect()
+}
+
+/// Candidates that count for `dolphin`. A candidate carrying a positive token
+/// of an extra label but none of the explicit `dolphin` positive tokens was
+/// graded as that species only, so a wildcard `dolphin` rule must not label
+/// it a dolphin as well.
+fn dolphin_candidates<'a>(candidates: &'a [String], rules: &CompiledRules) -> Cow<'a, [String]> {
+    if rules.labels.is_empty() {
+        return Cow::Borrowed(candidates);
+    }
+    Cow::Owned(
+        candidates
+            .iter()
+            .filter(|candidate| {
+                let candidate = std::slice::from_ref(*candidate);
+                explicit_token_match(candidate, &rules.positive_tokens, rules)
+                    || !rules
+                        .labels
+                        .iter()
+                        .any(|label| explicit_token_match(candidate, &label.positive_tokens, rules))
+            })
+            .cloned()
+            .collect(),
+    )
+}
+
+/// `any_token_match` without the `*` wildcard.
+fn explicit_token_match(candidates: &[String], tokens: &[String], rules: &CompiledRules) -> bool {
+    let explicit: Vec<String> = tokens
+        .iter()
+        .filter(|token| *token != "*")
+        .cloned()
+        .collect();
+    any_token_match(candidates, &explicit, rules)
+}
+
+pub(crate) fn select_winner(
+    candidates: &[String],
+    rules: &CompiledRules,
+) -> Option<CandidateWinner> {
+    if candidates.is_empty() {
+        return None;
+    }
+
+    let mut scored: Vec<(u8, usize, String, String)> = candidates
+        .iter()
+        .map(|candidate| {
+            let winner_type = classify_candidate(candidate, rules);
+            let priority = match winner_type.as_str() {
+                "IND" => 1u8,
+                "SECONDARY" => 2u8,
+                _ => 99u8,
+            };
+            (priority, candidate.len(), candidate.clone(), winner_type)
+        })
+        .collect();
+
+    scored.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then_with(|| a.1.cmp(&b.1))
+            .then_with(|| a.2.cmp(&b.2))
+    });
+
+    scored.first().map(|item| CandidateWinner {
+        relpath: item.2.clone(),
+        winner_type: item.3.clone(),
+    })
+}
+
+pub(crate) fn winner_details(
+    relpath: &str,
+    graded_root: &Path,
+    rules: &CompiledRules,
+) -> WinnerDetails {
+    let folders: Vec<&str> = relpath.split('/').collect();
+    let folders = &folders[..folders.len().saturating_sub(1)];
+    WinnerDetails {
+        top_folder: folders.first().copied().unwrap_or_default().to_string(),
+        depth: folders.len(),
+        matched_tokens: matched_tokens(relpath, rules),
+        full_path: graded_root.join(relpath).to_string_lossy().to_string(),
+    }
+}
+
+/// Rule tokens (and the IND match) found in a candidate path, in rule order.
+fn matched_tokens(candidate: &str, rules: &CompiledRules) -> Vec<String> {
+    let folded = fold_candidate(candidate, rules);
+    let lower = normalize_candidate(candidate, rules);
+    let mut matched: Vec<String> = Vec::new();
+    if let Some(found) = rules.ind_re.find(&folded) {
+        matched.push(found.as_str().to_string());
+    }
+    let tokens = rules
+        .secondary_tokens
+        .iter()
+        .map(|token| (token, &folded))
+        .chain(rules.negative_tokens.iter().map(|token| (token, &lower)))
+        .chain(rules.positive_tokens.iter().map(|token| (token, &lower)));
+    for (token, path) in tokens {
+        if token != "*" && path.contains(token.as_str()) && !matched.contains(token) {
+            matched.push(token.clone());
+        }
+    }
+    matched
+}
+
+/// Quality token of the winning candidate; when the winner carries none, the
+/// highest-priority token found among the other candidates is used.
+pub(crate) fn select_quality(
+    winner: Option<&str>,
+    candidates: &[String],
+    rules: &CompiledRules,
+) -> Option<String> {
+    if rules.quality_tokens.is_empty() {
+        return None;
+    }
+    winner
+        .and_then(|winner| candidate_quality(winner, rules))
+        .or_else(|| {
+            candidates
+                .iter()
+                .filter_map(|candidate| candidate_quality(candidate, rules))
+                .min()
+        })
+        .map(|index| rules.quality_tokens[index].clone())
+}
+
+/// Index of the highest-priority quality token appearing as a whole word.
+pub(crate) fn candidate_quality(candidate: &str, rules: &CompiledRules) -> Option<usize> {
+    let lower = fold_candidate(candidate, rules);
+    let words = lower
+        .split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<HashSet<_>>();
+    rules
+        .quality_tokens
+        .iter()
+        .position(|token| words.contains(token.to_lowercase().as_str()))
+}
+
+pub(crate) fn classify_candidate(candidate: &str, rules: &CompiledRules) -> String {
+    let lower = fold_candidate(candidate, rules);
+    if rules.ind_re.is_match(&lower) {
+        return "IND".to_string();
+    }
+    if rules
+        .secondary_tokens
+        .iter()
+        .any(|token| lower.contains(token))
+    {
+        return "SECONDARY".to_string();
+    }
+    "OTHER".to_string()
+}
+
+/// Scores the `dolphin` decision from 0 to 1: the winner type sets the base
+/// (IND above SECONDARY above other folders above raw-only), further
+/// candidates agreeing with the label raise it, candidates hitting both
+/// positive and negative tokens or a weak file match (ambiguous id or
+/// perceptual fallback) lower it.
+pub(crate) fn label_confidence(
+    candidates: &[String],
+    winner_type: &str,
+    dolphin: u8,
+    weak_match: bool,
+    rules: &CompiledRules,
+) -> f64 {
+    let base = match winner_type {
+        "IND" => 0.9,
+        "SECONDARY" => 0.8,
+        "RAW" => 0.6,
+        _ => 0.7,
+    };
+    let wildcard =
+        rules.positive_tokens.is_empty() || rules.positive_tokens.iter().any(|token| token == "*");
+    let mut positive_hits = 0usize;
+    let mut negative_hits = 0usize;
+    for candidate in candidates {
+        let candidate = std::slice::from_ref(candidate);
+        if wildcard || any_token_match(candidate, &rules.positive_tokens, rules) {
+            positive_hits += 1;
+        }
+        if any_token_match(candidate, &rules.negative_tokens, rules) {
+            negative_hits += 1;
+        }
+    }
+    let agreeing = if dolphin == 1 {
+        positive_hits
+    } else {
+        negative_hits
+    };
+    let mut score = base + 0.05 * agreeing.saturating_sub(1).min(2) as f64;
+    if positive_hits > 0 && negative_hits > 0 {
+        score -= 0.2;
+    }
+    if weak_match {
+        score -= 0.1;
+    }
+    score.clamp(0.0, 1.0)
+}
+
+/// 1 when some candidate matches a positive token (any candidate if the list
+/// is empty or `*`) and none matches a negative one; 0 without candidates.
+/// Comes with the reason for the `decision_reason` column: the token and
+/// graded candidate that decided it, or why none did.
+pub(crate) fn label_decision(
+    candidates: &[String],
+    positive: &[String],
+    negative: &[String],
+    rules: &CompiledRules,
+) -> (u8, String) {
+    if candidates.is_empty() {
+        return (0, "no graded candidates".to_string());
+    }
+    if let Some((token, candidate)) = first_token_match(candidates, negative, rules) {
+        let reason = match token {
+            "*" => "negative wildcard".to_string(),
+            _ => format!("negative token '{}' in candidate {}", token, candidate),
+        };
+        return (0, reason);
+    }
+    if positive.is_empty() || positive.iter().any(|token| token == "*") {
+        return (
+            1,
+            format!("positive wildcard, {} candidate(s)", candidates.len()),
+        );
+    }
+    match first_token_match(candidates, positive, rules) {
+        Some((token, candidate)) => (
+            1,
+            format!("positive token '{}' in candidate {}", token, candidate),
+        ),
+        None => (
+            0,
+            format!("no positive token in {} candidate(s)", candidates.len()),
+        ),
+    }
+}
+
+/// The label `label_decision` gives, without its reason.
+pub(crate) fn label_value(
+    candidates: &[String],
+    positive: &[String],
+    negative: &[String],
+    rules: &CompiledRules,
+) -> u8 {
+    label_decision(candidates, positive, negative, rules).0
+}
+
+/// First (token, candidate) pair `any_token_match` would accept.
+fn first_token_match<'a>(
+    candidates: &'a [String],
+    tokens: &'a [String],
+    rules: &CompiledRules,
+) -> Option<(&'a str, &'a str)> {
+    if tokens.iter().any(|token| token == "*") {
+        return Some(("*", candidates.first()?.as_str()));
+    }
+    candidates.iter().find_map(|candidate| {
+        let lower = normalize_candidate(candidate, rules);
+        tokens
+            .iter()
+            .find(|token| lower.contains(token.as_str()))
+            .map(|token| (token.as_str(), candidate.as_str()))
+    })
+}
+
+pub(crate) fn any_token_match(
+    candidates: &[String],
+    tokens: &[String],
+    rules: &CompiledRules,
+) -> bool {
+    if tokens.is_empty() {
+        return false;
+    }
+    if tokens.iter().any(|token| token == "*") {
+        return true;
+    }
+    candidates.iter().any(|candidate| {
+        let lower = normalize_candidate(candidate, rules);
+        tokens.iter().any(|token| lower.contains(token))
+    })
+}
+
+/// Splits a folder name into lowercase word tokens, dropping numbers, very
+/// short fragments and anything the rules already match.
+pub(crate) fn folder_tokens(component: &str, rules: &CompiledRules) -> Vec<String> {
+    normalize_candidate(component, rules)
+        .split(|ch: char| !ch.is_alphanumeric())
+        .filter(|token| token.chars().count() >= 3)
+        .filter(|token| !token.chars().all(|ch| ch.is_ascii_digit()))
+        .filter(|token| !token_is_covered(token, rules))
+        .map(|token| token.to_string())
+        .collect()
+}
+
+fn token_is_covered(token: &str, rules: &CompiledRules) -> bool {
+    rules.ind_re.is_match(token)
+        || rules
+            .secondary_tokens
+            .iter()
+            .chain(rules.negative_tokens.iter())
+            .chain(rules.positive_tokens.iter())
+            .filter(|rule_token| rule_token.as_str() != "*")
+            .any(|rule_token| token.contains(rule_token.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    use crate::output::{
+        label_columns, row_columns, row_headers, CsvSettings, CONFIDENCE_COLUMN,
+        DECISION_REASON_COLUMN,
+    };
+    use crate::rules::{compile_rules, default_image_id_regex, LabelRule, Rules};
+    use crate::test_support::default_compiled;
+
+    #[test]
+    fn winner_selection_prefers_ind_then_secondary() {
+        let rules = Rules {
+            extensions: vec![".jpg".to_string()],
+            survey_id_regex_detected: "x".to_string(),
+            survey_id_regex_base: "x".to_string(),
+            image_id_regex: default_image_id_regex(),
+            graded_priority_ind_regex: "(?i)\\bind".to_string(),
+            graded_priority_secondary_tokens: vec!["best".to_string()],
+            graded_negative_contains_any: vec![],
+            graded_positive_contains_any: vec!["*".to_string()],
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let candidates = vec![
+            "alpha/best/image.jpg".to_string(),
+            "beta/ind/image.jpg".to_string(),
+            "gamma/other/image.jpg".to_string(),
+        ];
+        let winner = select_winner(&candidates, &compiled).expect("winner");
+        assert_eq!(winner.relpath, "beta/ind/image.jpg");
+        assert_eq!(winner.winner_type, "IND");
+    }
+
+    #[test]
+    fn extra_labels_get_their_own_columns() {
+        let label = |name: &str, positive: &[&str], negative: &[&str]| LabelRule {
+            name: name.to_string(),
+            positive_contains_any: positive.iter().map(|token| token.to_string()).collect(),
+            negative_contains_any: negative.iter().map(|token| token.to_string()).collect(),
+        };
+        let rules = Rules {
+            labels: vec![
+                label("turtle", &["Turtle"], &["unsure"]),
+                label("seabird", &["bird"], &[]),
+            ],
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let candidates = vec![
+            "Turtles/img_001.jpg".to_string(),
+            "IND/img_001.jpg".to_string(),
+        ];
+        let values: Vec<u8> = compiled
+            .labels
+            .iter()
+            .map(|label| {
+                label_value(
+                    &candidates,
+                    &label.positive_tokens,
+                    &label.negative_tokens,
+                    &compiled,
+                )
+            })
+            .collect();
+        assert_eq!(values, vec![1, 0]);
+        let unsure = vec!["Turtles/unsure/img_001.jpg".to_string()];
+        let turtle = &compiled.labels[0];
+        assert_eq!(
+            label_value(
+                &unsure,
+                &turtle.positive_tokens,
+                &turtle.negative_tokens,
+                &compiled
+            ),
+            0
+        );
+
+        let settings = CsvSettings {
+            label_columns: label_columns(&rules),
+            ..CsvSettings::default()
+        };
+        assert_eq!(
+            row_columns(&settings)[..6],
+            [
+                "survey_id_base",
+                "raw_relpath",
+                "filename",
+                "dolphin",
+                "turtle",
+                "seabird"
+            ]
+        );
+        let clash = CsvSettings {
+            label_columns: vec!["filename".to_string()],
+            ..CsvSettings::default()
+        };
+        assert!(row_headers(&clash).is_err());
+
+        let duplicate = Rules {
+            labels: vec![label("turtle", &["a"], &[]), label("turtle", &["b"], &[])],
+            ..Rules::default()
+        };
+        assert!(compile_rules(&duplicate).is_err());
+        let no_tokens = Rules {
+            labels: vec![label("turtle", &[], &[])],
+            ..Rules::default()
+        };
+        assert!(compile_rules(&no_tokens).is_err());
+    }
+
+    #[test]
+    fn other_species_do_not_count_as_dolphins() {
+        let label = |name: &str, positive: &str| LabelRule {
+            name: name.to_string(),
+            positive_contains_any: vec![positive.to_string()],
+            negative_contains_any: Vec::new(),
+        };
+        let compiled = compile_rules(&Rules {
+            labels: vec![label("turtle", "turtle"), label("seabird", "bird")],
+            ..Rules::default()
+        })
+        .expect("compile");
+        let labeling = |paths: &[&str]| {
+            let candidates: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+            let labeling = label_candidates(&candidates, false, Path::new("/graded"), &compiled);
+            (labeling.dolphin, labeling.labels)
+        };
+        assert_eq!(labeling(&["IND/img_001.jpg"]), (1, vec![0, 0]));
+        assert_eq!(labeling(&["Turtles/img_001.jpg"]), (0, vec![1, 0]));
+        assert_eq!(labeling(&["Birds/img_001.jpg"]), (0, vec![0, 1]));
+        assert_eq!(
+            labeling(&["Turtles/img_001.jpg", "IND/img_001.jpg"]),
+            (1, vec![1, 0])
+        );
+
+        let explicit = compile_rules(&Rules {
+            graded_positive_contains_any: vec!["dolphin".to_string()],
+            labels: vec![label("turtle", "turtle")],
+            ..Rules::default()
+        })
+        .expect("compile");
+        let candidates = vec!["Dolphins with turtle/img_001.jpg".to_string()];
+        let labeling = label_candidates(&candidates, false, Path::new("/graded"), &explicit);
+        assert_eq!((labeling.dolphin, labeling.labels), (1, vec![1]));
+    }
+
+    #[test]
+    fn label_confidence_reflects_the_decision() {
+        let rules = compile_rules(&Rules {
+            graded_positive_contains_any: vec!["*".to_string()],
+            graded_negative_contains_any: vec!["unsure".to_string()],
+            ..Rules::default()
+        })
+        .expect("compile");
+        let paths = |list: &[&str]| list.iter().map(|path| path.to_string()).collect::<Vec<_>>();
+        let ind = label_confidence(&paths(&["IND/a.jpg"]), "IND", 1, false, &rules);
+        let secondary = label_confidence(&paths(&["best/a.jpg"]), "SECONDARY", 1, false, &rules);
+        let raw_only = label_confidence(&[], "RAW", 0, false, &rules);
+        assert!(ind > secondary && secondary > raw_only);
+        let agreeing = label_confidence(
+            &paths(&["IND/a.jpg", "best/a.jpg", "other/a.jpg"]),
+            "IND",
+            1,
+            false,
+            &rules,
+        );
+        assert!((agreeing - 1.0).abs() < 1e-9);
+        let mixed = label_confidence(
+            &paths(&["IND/a.jpg", "unsure/a.jpg"]),
+            "IND",
+            0,
+            false,
+            &rules,
+        );
+        assert!(mixed < ind);
+        let weak = label_confidence(&paths(&["IND/a.jpg"]), "IND", 1, true, &rules);
+        assert!(weak < ind);
+
+        let settings = CsvSettings {
+            include_confidence: true,
+            ..CsvSettings::default()
+        };
+        assert_eq!(row_columns(&settings).last(), Some(&CONFIDENCE_COLUMN));
+    }
+
+    #[test]
+    fn label_decisions_name_the_deciding_token() {
+        let rules = compile_rules(&Rules {
+            graded_positive_contains_any: vec!["dolphin".to_string()],
+            graded_negative_contains_any: vec!["noanimal".to_string()],
+            ..Rules::default()
+        })
+        .expect("compile");
+        let paths = |list: &[&str]| list.iter().map(|path| path.to_string()).collect::<Vec<_>>();
+        let decision = |candidates: &[String]| {
+            label_decision(
+                candidates,
+                &rules.positive_tokens,
+                &rules.negative_tokens,
+                &rules,
+            )
+        };
+        let decided = |value: u8, reason: &str| (value, reason.to_string());
+        assert_eq!(decision(&[]), decided(0, "no graded candidates"));
+        assert_eq!(
+            decision(&paths(&["Dolphin/a.jpg", "NoAnimal/a.jpg"])),
+            decided(0, "negative token 'noanimal' in candidate NoAnimal/a.jpg")
+        );
+        assert_eq!(
+            decision(&paths(&["best/a.jpg", "Dolphin/a.jpg"])),
+            decided(1, "positive token 'dolphin' in candidate Dolphin/a.jpg")
+        );
+        assert_eq!(
+            decision(&paths(&["best/a.jpg"])),
+            decided(0, "no positive token in 1 candidate(s)")
+        );
+
+        let settings = CsvSettings {
+            include_decision_reason: true,
+            ..CsvSettings::default()
+        };
+        assert_eq!(row_columns(&settings).last(), Some(&DECISION_REASON_COLUMN));
+    }
+
+    #[test]
+    fn winner_details_describe_candidate_path() {
+        let rules = Rules::default();
+        let compiled = compile_rules(&rules).expect("compile");
+        let details = winner_details("Best/IND/img_001.jpg", Path::new("/graded"), &compiled);
+        assert_eq!(details.top_folder, "Best");
+        assert_eq!(details.depth, 2);
+        assert_eq!(details.matched_tokens, vec!["ind", "best"]);
+        assert_eq!(
+            details.full_path,
+            Path::new("/graded")
+                .join("Best/IND/img_001.jpg")
+                .to_string_lossy()
+        );
+
+        let flat = winner_details("img_001.jpg", Path::new("/graded"), &compiled);
+        assert_eq!(flat.top_folder, "");
+        assert_eq!(flat.depth, 0);
+    }
+
+    #[test]
+    fn quality_prefers_winner_then_priority() {
+        let rules = compile_rules(&Rules {
+            quality_tokens: vec!["Q1".to_string(), "Q2".to_string(), "Q3".to_string()],
+            ..Rules::default()
+        })
+        .expect("compile");
+        let candidates = vec![
+            "ind/Q3/img_001.jpg".to_string(),
+            "best q2/img_001.jpg".to_string(),
+            "Q1 dorsal/img_001.jpg".to_string(),
+            "aq12/img_001.jpg".to_string(),
+        ];
+        assert_eq!(
+            select_quality(Some("ind/Q3/img_001.jpg"), &candidates, &rules),
+            Some("Q3".to_string())
+        );
+        assert_eq!(
+            select_quality(Some("aq12/img_001.jpg"), &candidates, &rules),
+            Some("Q1".to_string())
+        );
+        assert_eq!(candidate_quality("aq12/img_001.jpg", &rules), None);
+        let without = compile_rules(&Rules::default()).expect("compile");
+        assert_eq!(
+            select_quality(Some("Q1/img_001.jpg"), &candidates, &without),
+            None
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn winner_ignores_candidate_order(
+            candidates in prop::collection::vec(
+                prop::collection::vec(
+                    "(ind|IND|best|other|\\PC{0,6}|[ \\t]{1,2})",
+                    1..4,
+                )
+                .prop_map(|parts| format!("{}/img_0001.jpg", parts.join("/"))),
+                0..8,
+            )
+            .prop_flat_map(|candidates| {
+                (Just(candidates.clone()), Just(candidates).prop_shuffle())
+            }),
+        ) {
+            let compiled = default_compiled(false);
+            let (candidates, shuffled) = candidates;
+            let winner = select_winner(&candidates, compiled);
+            prop_assert_eq!(winner.is_some(), !candidates.is_empty());
+            let Some(winner) = winner else {
+                return Ok(());
+            };
+            let shuffled = select_winner(&shuffled, compiled).expect("winner");
+            prop_assert_eq!(&shuffled.relpath, &winner.relpath);
+            prop_assert!(candidates.contains(&winner.relpath));
+            let rank = |winner_type: &str| match winner_type {
+                "IND" => 1,
+                "SECONDARY" => 2,
+                _ => 99,
+            };
+            let best = candidates
+                .iter()
+                .map(|candidate| rank(&classify_candidate(candidate, compiled)))
+                .min();
+            prop_assert_eq!(Some(rank(&winner.winner_type)), best);
+        }
+    }
+}
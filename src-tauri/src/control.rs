@@ -0,0 +1,591 @@
+//! Cancelling and pausing running jobs, and the queue of single-pair runs
+//! waiting for their output folder.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::OutputLock;
+use crate::rules::Rules;
+use crate::runs::{single_pair_run, RunSummary, SingleRunOptions};
+use crate::{new_run_id, unix_millis, AppError, Host, RunPauseEvent};
+
+/// Finished queued runs kept for `list_run_queue`.
+const RUN_QUEUE_HISTORY: usize = 20;
+
+/// A single pair run added with `enqueue_single_run`, reported as
+/// `run-queue` events whenever its status changes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedRun {
+    pub job_id: String,
+    /// Id of the run once it started, for `cancel_runs`, `pause_runs` and
+    /// its progress events.
+    #[serde(default)]
+    pub run_id: Option<String>,
+    pub status: QueueStatus,
+    pub graded_dir: String,
+    pub raw_dir: String,
+    pub output_dir: String,
+    pub survey_id: Option<String>,
+    pub enqueued_at_ms: u64,
+    pub summary: Option<RunSummary>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueStatus {
+    Queued,
+    /// Running, or waiting for another run to release the output folder.
+    Running,
+    Done,
+    Failed,
+}
+
+impl QueueStatus {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            QueueStatus::Queued => "queued",
+            QueueStatus::Running => "running",
+            QueueStatus::Done => "done",
+            QueueStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Shared flags a running scan polls between files; set by `cancel_runs`,
+/// `pause_runs` and `resume_runs`. A paused scan blocks at its next poll and
+/// keeps everything it has built so far until it is resumed or cancelled.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CancelToken(Arc<RunControl>);
+
+#[derive(Debug, Default)]
+struct RunControl {
+    cancelled: AtomicBool,
+    pause: Mutex<PauseState>,
+    resumed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PauseState {
+    paused_since: Option<Instant>,
+    /// Time spent paused before the current pause.
+    paused_total: Duration,
+}
+
+impl CancelToken {
+    pub(crate) fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Relaxed);
+        // Taking the lock orders the flag before any waiter's next check.
+        let _pause = self.0.pause.lock();
+        self.0.resumed.notify_all();
+    }
+
+    /// Returns whether the run was running before.
+    pub(crate) fn pause(&self) -> bool {
+        let Ok(mut pause) = self.0.pause.lock() else {
+            return false;
+        };
+        if pause.paused_since.is_some() {
+            return false;
+        }
+        pause.paused_since = Some(Instant::now());
+        true
+    }
+
+    /// Returns whether the run was paused before.
+    pub(crate) fn resume(&self) -> bool {
+        let Ok(mut pause) = self.0.pause.lock() else {
+            return false;
+        };
+        let Some(since) = pause.paused_since.take() else {
+            return false;
+        };
+        pause.paused_total += since.elapsed();
+        self.0.resumed.notify_all();
+        true
+    }
+
+    /// Total time the run has been paused, including a pause still going on.
+    pub(crate) fn paused_for(&self) -> Duration {
+        self.0
+            .pause
+            .lock()
+            .map(|pause| {
+                pause.paused_total
+                    + pause
+                        .paused_since
+                        .map(|since| since.elapsed())
+                        .unwrap_or_default()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Waits out a pause, then reports whether the run was cancelled.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        if let Ok(mut pause) = self.0.pause.lock() {
+            while pause.paused_since.is_some() && !self.0.cancelled.load(Ordering::Relaxed) {
+                pause = match self.0.resumed.wait(pause) {
+                    Ok(pause) => pause,
+                    Err(_) => break,
+                };
+            }
+        }
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), AppError> {
+        if self.is_cancelled() {
+            Err(AppError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Tells background work whether its result is still wanted: the work takes
+/// a ticket when it starts, and `advance` turns every earlier ticket stale,
+/// e.g. when a newer preview replaces the one still being counted.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Generation(Arc<AtomicU64>);
+
+impl Generation {
+    pub(crate) fn ticket(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Makes earlier tickets stale and returns the new one.
+    pub(crate) fn advance(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub(crate) fn is_current(&self, ticket: u64) -> bool {
+        self.ticket() == ticket
+    }
+}
+
+pub(crate) fn active_runs() -> &'static Mutex<HashMap<String, CancelToken>> {
+    static RUNS: OnceLock<Mutex<HashMap<String, CancelToken>>> = OnceLock::new();
+    RUNS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// The token of `run_id`, registered when the run is the first to ask for it,
+/// e.g. by a queued run before its single pair run starts.
+pub(crate) fn register_run(run_id: &str) -> CancelToken {
+    let Ok(mut runs) = active_runs().lock() else {
+        return CancelToken::default();
+    };
+    runs.entry(run_id.to_string()).or_default().clone()
+}
+
+pub(crate) fn unregister_run(run_id: &str) {
+    if let Ok(mut runs) = active_runs().lock() {
+        runs.remove(run_id);
+    }
+}
+
+/// Asks the run with `run_id`, or every active run when `None`, to stop after
+/// the file it is working on. Returns how many runs were signalled.
+pub fn cancel_runs(run_id: Option<&str>) -> usize {
+    let Ok(runs) = active_runs().lock() else {
+        return 0;
+    };
+    let mut cancelled = 0;
+    for (id, token) in runs.iter() {
+        if run_id.is_none_or(|wanted| wanted == id) {
+            token.cancel();
+            cancelled += 1;
+        }
+    }
+    cancelled
+}
+
+/// Pauses the run with `run_id`, or every active run when `None`, after the
+/// file it is working on. Its listings and graded index stay in memory, so
+/// `resume_runs` continues where it stopped. Each paused run is reported as
+/// a `run_paused` event. Returns how many runs were paused.
+pub fn pause_runs(host: &Host, run_id: Option<&str>) -> usize {
+    set_paused(host, run_id, true)
+}
+
+/// Continues runs paused by `pause_runs` and reports each as a `run_paused`
+/// event. Returns how many were resumed.
+pub fn resume_runs(host: &Host, run_id: Option<&str>) -> usize {
+    set_paused(host, run_id, false)
+}
+
+fn set_paused(host: &Host, run_id: Option<&str>, paused: bool) -> usize {
+    let changed: Vec<String> = {
+        let Ok(runs) = active_runs().lock() else {
+            return 0;
+        };
+        runs.iter()
+            .filter(|(id, token)| {
+                run_id.is_none_or(|wanted| wanted == id.as_str())
+                    && if paused {
+                        token.pause()
+                    } else {
+                        token.resume()
+                    }
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    };
+    for id in &changed {
+        host.run_paused(RunPauseEvent {
+            run_id: id.clone(),
+            paused,
+        });
+    }
+    changed.len()
+}
+
+struct QueuedJob {
+    run: QueuedRun,
+    options: SingleRunOptions,
+    rules: Rules,
+}
+
+#[derive(Default)]
+struct RunQueue {
+    pending: VecDeque<QueuedJob>,
+    running: Option<QueuedRun>,
+    finished: VecDeque<QueuedRun>,
+    /// Whether a worker thread is working through `pending`.
+    worker: bool,
+}
+
+fn run_queue() -> &'static Mutex<RunQueue> {
+    static QUEUE: OnceLock<Mutex<RunQueue>> = OnceLock::new();
+    QUEUE.get_or_init(|| Mutex::new(RunQueue::default()))
+}
+
+/// Adds a single pair run to the queue, which a background worker works
+/// through one run at a time. A queued run whose output folder is in use,
+/// e.g. by a long root run, waits for it instead of failing.
+pub fn enqueue_single_run(
+    host: &Host,
+    graded_dir: PathBuf,
+    raw_dir: PathBuf,
+    output_dir: PathBuf,
+    survey_id: Option<String>,
+    options: SingleRunOptions,
+    rules: Rules,
+) -> Result<QueuedRun, AppError> {
+    let run = QueuedRun {
+        job_id: new_run_id(),
+        run_id: None,
+        status: QueueStatus::Queued,
+        graded_dir: graded_dir.to_string_lossy().to_string(),
+        raw_dir: raw_dir.to_string_lossy().to_string(),
+        output_dir: output_dir.to_string_lossy().to_string(),
+        survey_id,
+        enqueued_at_ms: unix_millis(),
+        summary: None,
+        error: None,
+    };
+    let mut queue = run_queue()
+        .lock()
+        .map_err(|_| AppError::Message("The run queue is unavailable.".to_string()))?;
+    queue.pending.push_back(QueuedJob {
+        run: run.clone(),
+        options,
+        rules,
+    });
+    if !queue.worker {
+        queue.worker = true;
+        let host = host.clone();
+        std::thread::spawn(move || work_run_queue(&host));
+    }
+    host.queue_updated(&run);
+    Ok(run)
+}
+
+/// Finished, running and queued runs, oldest first.
+pub fn list_run_queue() -> Vec<QueuedRun> {
+    let Ok(queue) = run_queue().lock() else {
+        return Vec::new();
+    };
+    queue
+        .finished
+        .iter()
+        .chain(queue.running.iter())
+        .chain(queue.pending.iter().map(|job| &job.run))
+        .cloned()
+        .collect()
+}
+
+/// Drops a run that has not started yet. Returns whether it was queued.
+pub fn remove_queued_run(job_id: &str) -> bool {
+    let Ok(mut queue) = run_queue().lock() else {
+        return false;
+    };
+    let before = queue.pending.len();
+    queue.pending.retain(|job| job.run.job_id != job_id);
+    queue.pending.len() < before
+}
+
+fn work_run_queue(host: &Host) {
+    loop {
+        let run_id = new_run_id();
+        let Some(job) = run_queue().lock().ok().and_then(|mut queue| {
+            let job = queue.pending.pop_front();
+            match &job {
+                Some(job) => {
+                    queue.running = Some(QueuedRun {
+                        run_id: Some(run_id.clone()),
+                        status: QueueStatus::Running,
+                        ..job.run.clone()
+                    })
+                }
+                None => queue.worker = false,
+            }
+            job
+        }) else {
+            return;
+        };
+        let mut run = QueuedRun {
+            run_id: Some(run_id.clone()),
+            status: QueueStatus::Running,
+            ..job.run.clone()
+        };
+        host.queue_updated(&run);
+        let cancel = register_run(&run_id);
+        let result = run_queued_job(host, &job, &run_id, &cancel);
+        unregister_run(&run_id);
+        match result {
+            Ok(summary) => {
+                run.status = QueueStatus::Done;
+                run.summary = Some(summary);
+            }
+            Err(err) => {
+                run.status = QueueStatus::Failed;
+                run.error = Some(err.to_string());
+            }
+        }
+        host.queue_updated(&run);
+        if let Ok(mut queue) = run_queue().lock() {
+            queue.running = None;
+            queue.finished.push_back(run);
+            while queue.finished.len() > RUN_QUEUE_HISTORY {
+                queue.finished.pop_front();
+            }
+        }
+    }
+}
+
+fn run_queued_job(
+    host: &Host,
+    job: &QueuedJob,
+    run_id: &str,
+    cancel: &CancelToken,
+) -> Result<RunSummary, AppError> {
+    let output_dir = PathBuf::from(&job.run.output_dir);
+    loop {
+        if output_dir.is_dir() {
+            OutputLock::wait(&output_dir, cancel)?;
+        }
+        match single_pair_run(
+            host,
+            run_id.to_string(),
+            PathBuf::from(&job.run.graded_dir),
+            PathBuf::from(&job.run.raw_dir),
+            output_dir.clone(),
+            job.run.survey_id.clone(),
+            job.options.clone(),
+            job.rules.clone(),
+        ) {
+            // Another run took the folder between the wait and the start.
+            Err(AppError::Busy(_)) => continue,
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::history::list_workspaces;
+    use crate::matching::build_graded_map;
+    use crate::rules::compile_rules;
+    use crate::runs::run_single_pair;
+    use crate::test_support::TestTree;
+    use crate::{ProgressEvent, ProgressSink};
+
+    #[test]
+    fn output_folders_are_locked_and_queued_runs_wait_for_them() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB");
+        let graded_dir = root.join("graded").join("20240101_AB");
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(graded_dir.join("IND")).expect("create");
+        fs::write(raw_dir.join("IMG_0001.JPG"), b"raw").expect("write");
+        fs::write(graded_dir.join("IND").join("IMG_0001.JPG"), b"raw").expect("write");
+        let out = root.join("out");
+        fs::create_dir_all(&out).expect("create");
+        let host = tree.host();
+
+        let lock = OutputLock::acquire(&out, "first").expect("lock");
+        assert!(matches!(
+            OutputLock::acquire(&out, "second"),
+            Err(AppError::Busy(_))
+        ));
+        let options: SingleRunOptions =
+            serde_json::from_value(serde_json::json!({ "output_filename": "single.csv" }))
+                .expect("options");
+        let single = |host: &Host| {
+            run_single_pair(
+                host,
+                graded_dir.clone(),
+                raw_dir.clone(),
+                out.clone(),
+                None,
+                options.clone(),
+                Rules::default(),
+            )
+        };
+        assert_eq!(single(&host).err().map(|err| err.kind()), Some("busy"));
+        // The lock is taken before the run sets up its workspace.
+        assert!(list_workspaces(&host).expect("workspaces").is_empty());
+
+        let enqueue = || {
+            enqueue_single_run(
+                &host,
+                graded_dir.clone(),
+                raw_dir.clone(),
+                out.clone(),
+                None,
+                options.clone(),
+                Rules::default(),
+            )
+            .expect("enqueue")
+        };
+        let status = |job_id: &str| {
+            list_run_queue()
+                .into_iter()
+                .find(|run| run.job_id == job_id)
+                .expect("listed")
+        };
+        let wait_while_running = |job_id: &str| {
+            let deadline = Instant::now() + Duration::from_secs(20);
+            while status(job_id).status == QueueStatus::Running && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            status(job_id)
+        };
+
+        // A queued run waiting for the folder can be cancelled by its run id.
+        let cancelled = enqueue();
+        std::thread::sleep(Duration::from_millis(100));
+        let waiting = status(&cancelled.job_id);
+        assert_eq!(waiting.status, QueueStatus::Running);
+        let run_id = waiting.run_id.expect("run id");
+        assert_eq!(cancel_runs(Some(&run_id)), 1);
+        let cancelled = wait_while_running(&cancelled.job_id);
+        assert_eq!(cancelled.status, QueueStatus::Failed);
+        assert_eq!(cancelled.error, Some(AppError::Cancelled.to_string()));
+
+        let queued = enqueue();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(status(&queued.job_id).status, QueueStatus::Running);
+        assert!(!out.join("single.csv").exists());
+        drop(lock);
+        assert!(!out.join(".survey-labeler.lock").exists());
+
+        let finished = wait_while_running(&queued.job_id);
+        assert_eq!(finished.status, QueueStatus::Done, "{:?}", finished.error);
+        assert_eq!(finished.summary.map(|summary| summary.total_rows), Some(1));
+        assert!(out.join("single.csv").exists());
+        assert!(!remove_queued_run(&queued.job_id));
+        assert!(single(&host).is_ok());
+        assert!(!out.join(".survey-labeler.lock").exists());
+    }
+
+    #[test]
+    fn paused_runs_wait_until_resumed_or_cancelled() {
+        #[derive(Default)]
+        struct Collected(Mutex<Vec<RunPauseEvent>>);
+
+        impl ProgressSink for Collected {
+            fn progress(&self, _event: ProgressEvent) {}
+
+            fn run_paused(&self, event: RunPauseEvent) {
+                self.0.lock().expect("events").push(event);
+            }
+        }
+
+        let tree = TestTree::new();
+        let sink = Arc::new(Collected::default());
+        let host = Host::headless_with_sink(tree.join("data"), sink.clone());
+        let token = register_run("pause-test-run");
+        assert_eq!(pause_runs(&host, Some("some-other-run")), 0);
+        assert_eq!(pause_runs(&host, Some("pause-test-run")), 1);
+        assert_eq!(pause_runs(&host, Some("pause-test-run")), 0);
+
+        let worker = {
+            let token = token.clone();
+            std::thread::spawn(move || token.check().is_ok())
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!worker.is_finished());
+        assert!(token.paused_for() >= Duration::from_millis(50));
+        assert_eq!(resume_runs(&host, Some("pause-test-run")), 1);
+        assert!(worker.join().expect("join"));
+        assert_eq!(resume_runs(&host, Some("pause-test-run")), 0);
+        let events: Vec<_> = sink
+            .0
+            .lock()
+            .expect("events")
+            .iter()
+            .map(|event| (event.run_id.clone(), event.paused))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ("pause-test-run".to_string(), true),
+                ("pause-test-run".to_string(), false)
+            ]
+        );
+
+        token.pause();
+        let worker = {
+            let token = token.clone();
+            std::thread::spawn(move || token.check().is_ok())
+        };
+        assert_eq!(cancel_runs(Some("pause-test-run")), 1);
+        assert!(!worker.join().expect("join"));
+        if let Ok(mut runs) = active_runs().lock() {
+            runs.remove("pause-test-run");
+        }
+    }
+
+    #[test]
+    fn cancelled_runs_stop_graded_walk() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        fs::create_dir_all(root.join("IND")).expect("create");
+        fs::write(root.join("IND").join("img_001.jpg"), "a").expect("write");
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+
+        let token = register_run("cancel-test-run");
+        assert_eq!(cancel_runs(Some("some-other-run")), 0);
+        assert!(!token.is_cancelled());
+        build_graded_map(root, None, &compiled, None, None, &token).expect("map");
+        assert_eq!(cancel_runs(Some("cancel-test-run")), 1);
+        assert!(token.is_cancelled());
+        let result = build_graded_map(root, None, &compiled, None, None, &token);
+        assert!(matches!(result, Err(AppError::Cancelled)));
+        assert_eq!(AppError::Cancelled.report().kind, "cancelled");
+        if let Ok(mut runs) = active_runs().lock() {
+            runs.remove("cancel-test-run");
+        }
+    }
+}
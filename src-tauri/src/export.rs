@@ -0,0 +1,1095 @@
+//! Work on the results of finished runs: diffs between two runs, season
+//! reports over several runs and training set exports.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::matching::{open_image, survey_fields};
+use crate::output::{
+    csv_writer, label_columns, mark_output_dir, run_csv_reader, CsvSettings, LABEL_SOURCE_COLUMN,
+};
+use crate::paths::long_path;
+use crate::review::load_manual_labels;
+use crate::rules::{compile_rules, Rules};
+use crate::runs::{RunSummary, RUN_SUMMARY_FILENAME};
+use crate::AppError;
+
+const AGGREGATE_CSV_FILENAME: &str = "season_report.csv";
+const AGGREGATE_JSON_FILENAME: &str = "season_report.json";
+
+/// Effort and dolphin rate for one survey, month or area across runs.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AggregateRow {
+    pub key: String,
+    pub surveys: u64,
+    pub images: u64,
+    pub dolphin_yes: u64,
+    pub dolphin_no: u64,
+    pub dolphin_rate: f64,
+}
+
+impl AggregateRow {
+    fn new(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            ..Self::default()
+        }
+    }
+
+    fn add(&mut self, images: u64, dolphin_yes: u64) {
+        self.surveys += 1;
+        self.images += images;
+        self.dolphin_yes += dolphin_yes;
+        self.dolphin_no += images.saturating_sub(dolphin_yes);
+        self.dolphin_rate = if self.images > 0 {
+            self.dolphin_yes as f64 / self.images as f64
+        } else {
+            0.0
+        };
+    }
+}
+
+/// Differences between two merged CSVs, rows matched by survey and
+/// `raw_relpath`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunDiff {
+    pub old_csv: String,
+    pub new_csv: String,
+    /// Label columns compared: `dolphin` plus the rules' extra labels found
+    /// in both files.
+    pub label_columns: Vec<String>,
+    pub added_rows: u64,
+    pub removed_rows: u64,
+    /// Rows with at least one changed label.
+    pub changed_rows: u64,
+    pub unchanged_rows: u64,
+    /// Surveys with any difference, by base key.
+    pub surveys: Vec<SurveyDiff>,
+    pub changes: Vec<LabelChange>,
+    /// `survey_id_base/raw_relpath` of rows found more than once in the old
+    /// or the new CSV; the last of them is compared.
+    pub old_duplicates: Vec<String>,
+    pub new_duplicates: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SurveyDiff {
+    pub survey_id_base: String,
+    pub added_rows: u64,
+    pub removed_rows: u64,
+    pub changed_rows: u64,
+}
+
+/// One label of one image that differs between the two runs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelChange {
+    pub survey_id_base: String,
+    pub raw_relpath: String,
+    pub column: String,
+    pub old_value: String,
+    pub new_value: String,
+    pub old_graded_relpath: String,
+    pub new_graded_relpath: String,
+}
+
+/// How `export_dataset` puts raw images into the class folders.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetLinkMode {
+    #[default]
+    Copy,
+    /// Hard links where the file system allows them, copies elsewhere (e.g.
+    /// across drives).
+    Hardlink,
+}
+
+/// Layout `export_dataset` writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetFormat {
+    /// `dolphin/` and `no_dolphin/` folders.
+    #[default]
+    Folders,
+    /// `images/` plus a COCO `annotations.json` with one image-level
+    /// annotation (no box) per image.
+    Coco,
+    /// YOLO classification layout: `train/` and `val/`, each with one
+    /// folder per class.
+    Yolo,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DatasetExportOptions {
+    #[serde(default)]
+    pub link_mode: DatasetLinkMode,
+    #[serde(default)]
+    pub format: DatasetFormat,
+    /// Share of images put into `val/` for `yolo`, 0 to 1. The split
+    /// follows the file names, so re-exports keep images on the same side.
+    #[serde(default = "default_val_fraction")]
+    pub val_fraction: f64,
+}
+
+impl Default for DatasetExportOptions {
+    fn default() -> Self {
+        Self {
+            link_mode: DatasetLinkMode::default(),
+            format: DatasetFormat::default(),
+            val_fraction: default_val_fraction(),
+        }
+    }
+}
+
+fn default_val_fraction() -> f64 {
+    0.2
+}
+
+/// Outcome of `export_dataset`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DatasetExport {
+    pub output_dir: String,
+    /// Images placed in `dolphin/`.
+    pub dolphin: u64,
+    /// Images placed in `no_dolphin/`.
+    pub no_dolphin: u64,
+    /// Images that were hard-linked rather than copied.
+    pub linked: u64,
+    /// `annotations.json` of a `coco` export.
+    #[serde(default)]
+    pub annotations_path: Option<String>,
+    /// `labels.csv`: file name, survey, `raw_relpath`, `dolphin` and
+    /// `label_source` of every exported image.
+    #[serde(default)]
+    pub labels_path: Option<String>,
+    /// Images whose class comes from a manual label.
+    #[serde(default)]
+    pub manual_labels: u64,
+    /// Images named with a hash suffix because another image of the CSV
+    /// flattened to the same name.
+    #[serde(default)]
+    pub renamed: u64,
+    /// `survey_id_base/raw_relpath` of rows whose raw image was not found.
+    pub missing: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AggregateReport {
+    pub runs: u64,
+    /// Surveys found in more than one run; the run listed last wins.
+    pub superseded_surveys: Vec<String>,
+    pub total: AggregateRow,
+    pub surveys: Vec<AggregateRow>,
+    pub months: Vec<AggregateRow>,
+    pub areas: Vec<AggregateRow>,
+    pub csv_path: String,
+    pub json_path: String,
+}
+
+/// Compares the merged CSVs of two runs: rows only in `new_csv` are added,
+/// rows only in `old_csv` removed, and every label that differs for a row in
+/// both is listed in `changes`. Label columns are found by name: `dolphin`
+/// and the extra labels of `rules`.
+pub fn diff_runs(old_csv: PathBuf, new_csv: PathBuf, rules: Rules) -> Result<RunDiff, AppError> {
+    let label_names = label_columns(&rules);
+    let old = read_diff_rows(&old_csv, &label_names)?;
+    let new = read_diff_rows(&new_csv, &label_names)?;
+    let label_columns: Vec<String> = new
+        .label_columns
+        .iter()
+        .filter(|column| old.label_columns.contains(column))
+        .cloned()
+        .collect();
+    let mut diff = RunDiff {
+        old_csv: old_csv.to_string_lossy().to_string(),
+        new_csv: new_csv.to_string_lossy().to_string(),
+        label_columns: label_columns.clone(),
+        added_rows: 0,
+        removed_rows: 0,
+        changed_rows: 0,
+        unchanged_rows: 0,
+        surveys: Vec::new(),
+        changes: Vec::new(),
+        old_duplicates: old.duplicates,
+        new_duplicates: new.duplicates,
+    };
+    let mut surveys: BTreeMap<String, SurveyDiff> = BTreeMap::new();
+    for (key, old_row) in &old.rows {
+        let Some(new_row) = new.rows.get(key) else {
+            diff.removed_rows += 1;
+            survey_diff(&mut surveys, &key.0).removed_rows += 1;
+            continue;
+        };
+        let mut changed = false;
+        for column in &label_columns {
+            let old_value = old_row.labels.get(column).cloned().unwrap_or_default();
+            let new_value = new_row.labels.get(column).cloned().unwrap_or_default();
+            if old_value != new_value {
+                changed = true;
+                diff.changes.push(LabelChange {
+                    survey_id_base: key.0.clone(),
+                    raw_relpath: key.1.clone(),
+                    column: column.clone(),
+                    old_value,
+                    new_value,
+                    old_graded_relpath: old_row.graded_relpath.clone(),
+                    new_graded_relpath: new_row.graded_relpath.clone(),
+                });
+            }
+        }
+        if changed {
+            diff.changed_rows += 1;
+            survey_diff(&mut surveys, &key.0).changed_rows += 1;
+        } else {
+            diff.unchanged_rows += 1;
+        }
+    }
+    for key in new.rows.keys().filter(|key| !old.rows.contains_key(*key)) {
+        diff.added_rows += 1;
+        survey_diff(&mut surveys, &key.0).added_rows += 1;
+    }
+    diff.surveys = surveys.into_values().collect();
+    Ok(diff)
+}
+
+fn survey_diff<'a>(
+    surveys: &'a mut BTreeMap<String, SurveyDiff>,
+    base_key: &str,
+) -> &'a mut SurveyDiff {
+    surveys
+        .entry(base_key.to_string())
+        .or_insert_with(|| SurveyDiff {
+            survey_id_base: base_key.to_string(),
+            added_rows: 0,
+            removed_rows: 0,
+            changed_rows: 0,
+        })
+}
+
+/// Rows of a merged CSV keyed by `(survey_id_base, raw_relpath)`.
+struct DiffRows {
+    label_columns: Vec<String>,
+    rows: BTreeMap<(String, String), DiffRow>,
+    /// Keys of rows that replaced an earlier row with the same key.
+    duplicates: Vec<String>,
+}
+
+struct DiffRow {
+    graded_relpath: String,
+    labels: HashMap<String, String>,
+}
+
+fn read_diff_rows(path: &Path, label_names: &[String]) -> Result<DiffRows, AppError> {
+    if path.extension().is_some_and(|ext| {
+        ["xlsx", "parquet", "jsonl"]
+            .iter()
+            .any(|other| ext.eq_ignore_ascii_case(other))
+    }) {
+        return Err(AppError::Validation(format!(
+            "{} is not a CSV; comparing runs needs their merged CSVs.",
+            path.to_string_lossy()
+        )));
+    }
+    let mut reader = run_csv_reader(path)?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| {
+                AppError::Validation(format!(
+                    "Column {} missing in {}.",
+                    name,
+                    path.to_string_lossy()
+                ))
+            })
+    };
+    let base_column = column("survey_id_base")?;
+    let relpath_column = column("raw_relpath")?;
+    let mut label_columns = vec![(column("dolphin")?, "dolphin".to_string())];
+    label_columns.extend(
+        label_names
+            .iter()
+            .filter_map(|name| column(name).ok().map(|index| (index, name.clone()))),
+    );
+    // A column selection may leave out `graded_relpath`.
+    let graded_column = column("graded_relpath").ok();
+    let mut rows = BTreeMap::new();
+    let mut duplicates = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let field = |index: usize| record.get(index).unwrap_or_default().to_string();
+        let key = (field(base_column), field(relpath_column));
+        let row = DiffRow {
+            graded_relpath: graded_column.map(field).unwrap_or_default(),
+            labels: label_columns
+                .iter()
+                .map(|(index, name)| (name.clone(), field(*index)))
+                .collect(),
+        };
+        if rows.insert(key.clone(), row).is_some() {
+            duplicates.push(format!("{}/{}", key.0, key.1));
+        }
+    }
+    Ok(DiffRows {
+        label_columns: label_columns.into_iter().map(|(_, name)| name).collect(),
+        rows,
+        duplicates,
+    })
+}
+
+/// Class names of `export_dataset`, for `dolphin` 1 and 0; folder names in
+/// the `folders` and `yolo` layouts, category names in COCO.
+const DATASET_CLASS_DIRS: [&str; 2] = ["dolphin", "no_dolphin"];
+
+const COCO_ANNOTATIONS_FILENAME: &str = "annotations.json";
+/// One line per exported image with its label and where the label came from.
+const DATASET_LABELS_FILENAME: &str = "labels.csv";
+
+/// Copies or hard-links the raw images of a merged CSV below `output_dir` in
+/// the layout `options.format` asks for, for training. The survey folders
+/// come from the `run_summary.json` next to the CSV. Files are named
+/// `<survey_id_base>_<raw_relpath>` with `/` turned into `_`. Since that can
+/// give two images the same name (`a/b_c.jpg` and `a_b/c.jpg`), every later
+/// image whose name is taken gets a short hash of its survey and path added;
+/// `labels.csv` maps the names back. An image relabeled since an earlier
+/// export moves to its new class folder. Manual labels stored in the run's
+/// output folder win over the `dolphin` column.
+pub fn export_dataset(
+    merged_csv: PathBuf,
+    output_dir: PathBuf,
+    options: DatasetExportOptions,
+) -> Result<DatasetExport, AppError> {
+    let run_dir = merged_csv.parent().unwrap_or(Path::new(""));
+    let summary_path = run_dir.join(RUN_SUMMARY_FILENAME);
+    if !summary_path.exists() {
+        return Err(AppError::Validation(format!(
+            "No {} next to {}; export needs a merged CSV in its run's output folder.",
+            RUN_SUMMARY_FILENAME,
+            merged_csv.to_string_lossy()
+        )));
+    }
+    let summary: RunSummary = serde_json::from_str(&fs::read_to_string(&summary_path)?)?;
+    let raw_dirs: HashMap<String, PathBuf> = summary
+        .surveys
+        .into_iter()
+        .map(|survey| (survey.survey_id_base, PathBuf::from(survey.raw_path)))
+        .collect();
+    let manual_labels = load_manual_labels(run_dir)?.unwrap_or_default();
+
+    let mut reader = run_csv_reader(&merged_csv)?;
+    let headers = reader.headers()?.clone();
+    let position = |name: &str| headers.iter().position(|header| header == name);
+    let column = |name: &str| {
+        position(name).ok_or_else(|| {
+            AppError::Validation(format!(
+                "Column {} missing in {}.",
+                name,
+                merged_csv.to_string_lossy()
+            ))
+        })
+    };
+    let base_column = column("survey_id_base")?;
+    let relpath_column = column("raw_relpath")?;
+    let dolphin_column = column("dolphin")?;
+    let source_column = position(LABEL_SOURCE_COLUMN);
+    if !(0.0..=1.0).contains(&options.val_fraction) {
+        return Err(AppError::Validation(
+            "val_fraction must be between 0 and 1.".to_string(),
+        ));
+    }
+
+    // Every folder an image can land in; earlier copies are cleared from all.
+    let folders: Vec<PathBuf> = match options.format {
+        DatasetFormat::Folders => DATASET_CLASS_DIRS
+            .iter()
+            .map(|class| output_dir.join(class))
+            .collect(),
+        DatasetFormat::Coco => vec![output_dir.join("images")],
+        DatasetFormat::Yolo => ["train", "val"]
+            .iter()
+            .flat_map(|split| DATASET_CLASS_DIRS.map(|class| output_dir.join(split).join(class)))
+            .collect(),
+    };
+    for folder in &folders {
+        fs::create_dir_all(long_path(folder))?;
+    }
+    mark_output_dir(&output_dir)?;
+    let labels_path = output_dir.join(DATASET_LABELS_FILENAME);
+    let mut labels = csv_writer(&labels_path, &CsvSettings::default())?;
+    labels.write_record([
+        "file_name",
+        "survey_id_base",
+        "raw_relpath",
+        "dolphin",
+        LABEL_SOURCE_COLUMN,
+    ])?;
+    let mut export = DatasetExport {
+        output_dir: output_dir.to_string_lossy().to_string(),
+        dolphin: 0,
+        no_dolphin: 0,
+        linked: 0,
+        annotations_path: None,
+        labels_path: Some(labels_path.to_string_lossy().to_string()),
+        manual_labels: 0,
+        renamed: 0,
+        missing: Vec::new(),
+    };
+    // Lowercase names in use, for file systems that ignore case, and the
+    // row each belongs to.
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut coco_images = Vec::new();
+    let mut coco_annotations = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let field = |index: usize| record.get(index).unwrap_or_default();
+        let (base_key, relpath) = (field(base_column), field(relpath_column));
+        let manual = manual_labels.get(base_key, relpath);
+        let dolphin = match manual {
+            Some(manual) => manual.label.to_string(),
+            None => field(dolphin_column).to_string(),
+        };
+        let class = match dolphin.as_str() {
+            "1" => 0,
+            "0" => 1,
+            _ => continue,
+        };
+        let from_manual =
+            manual.is_some() || source_column.is_some_and(|index| field(index) == "manual");
+        let source = raw_dirs
+            .get(base_key)
+            .map(|dir| dir.join(relpath))
+            .filter(|path| path.is_file());
+        let Some(source) = source else {
+            export.missing.push(format!("{}/{}", base_key, relpath));
+            continue;
+        };
+        let row_key = format!("{}/{}", base_key, relpath);
+        let mut name = format!("{}_{}", base_key, relpath.replace(['/', '\\'], "_"));
+        if names
+            .get(&name.to_lowercase())
+            .is_some_and(|owner| *owner != row_key)
+        {
+            name = hashed_name(&name, &row_key);
+            export.renamed += 1;
+        }
+        names.insert(name.to_lowercase(), row_key);
+        for folder in &folders {
+            let _ = fs::remove_file(long_path(&folder.join(&name)));
+        }
+        let folder = match options.format {
+            DatasetFormat::Folders => output_dir.join(DATASET_CLASS_DIRS[class]),
+            DatasetFormat::Coco => output_dir.join("images"),
+            DatasetFormat::Yolo => {
+                let split = if in_val_split(&name, options.val_fraction) {
+                    "val"
+                } else {
+                    "train"
+                };
+                output_dir.join(split).join(DATASET_CLASS_DIRS[class])
+            }
+        };
+        if options.format == DatasetFormat::Coco {
+            let id = coco_images.len() + 1;
+            let (width, height) = image::image_dimensions(&source)
+                .ok()
+                .or_else(|| open_image(&source).map(|image| (image.width(), image.height())))
+                .unwrap_or_default();
+            coco_images.push(serde_json::json!({
+                "id": id,
+                "file_name": name,
+                "width": width,
+                "height": height,
+            }));
+            coco_annotations.push(serde_json::json!({
+                "id": id,
+                "image_id": id,
+                "category_id": class + 1,
+                "label_source": if from_manual { "manual" } else { "auto" },
+            }));
+        }
+        let target = long_path(&folder.join(&name)).into_owned();
+        let source = long_path(&source);
+        if options.link_mode == DatasetLinkMode::Hardlink && fs::hard_link(&source, &target).is_ok()
+        {
+            export.linked += 1;
+        } else {
+            fs::copy(&source, &target)?;
+        }
+        if class == 0 {
+            export.dolphin += 1;
+        } else {
+            export.no_dolphin += 1;
+        }
+        if from_manual {
+            export.manual_labels += 1;
+        }
+        labels.write_record([
+            name.as_str(),
+            base_key,
+            relpath,
+            dolphin.as_str(),
+            if from_manual { "manual" } else { "auto" },
+        ])?;
+    }
+    labels.flush()?;
+    if options.format == DatasetFormat::Coco {
+        let categories: Vec<_> = DATASET_CLASS_DIRS
+            .iter()
+            .enumerate()
+            .map(|(index, name)| serde_json::json!({ "id": index + 1, "name": name }))
+            .collect();
+        let coco = serde_json::json!({
+            "info": { "description": format!("Labels of {}", merged_csv.to_string_lossy()) },
+            "images": coco_images,
+            "annotations": coco_annotations,
+            "categories": categories,
+        });
+        let path = output_dir.join(COCO_ANNOTATIONS_FILENAME);
+        fs::write(long_path(&path), serde_json::to_string_pretty(&coco)?)?;
+        export.annotations_path = Some(path.to_string_lossy().to_string());
+    }
+    Ok(export)
+}
+
+/// `name` with the first hex digits of the SHA-256 of `row_key` before its
+/// extension.
+fn hashed_name(name: &str, row_key: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(row_key.as_bytes()));
+    let path = Path::new(name);
+    match (
+        path.file_stem().and_then(|stem| stem.to_str()),
+        path.extension().and_then(|ext| ext.to_str()),
+    ) {
+        (Some(stem), Some(ext)) => format!("{}_{}.{}", stem, &hash[..8], ext),
+        _ => format!("{}_{}", name, &hash[..8]),
+    }
+}
+
+/// Whether an exported image goes to the YOLO `val/` split: the first bytes
+/// of its name's SHA-256 against `val_fraction`, so the split is stable
+/// across runs and machines.
+fn in_val_split(name: &str, val_fraction: f64) -> bool {
+    let digest = Sha256::digest(name.as_bytes());
+    let bucket = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    f64::from(bucket) / f64::from(u32::MAX) < val_fraction
+}
+
+pub fn aggregate_runs(
+    output_dirs: &[PathBuf],
+    report_dir: PathBuf,
+    rules: Rules,
+) -> Result<AggregateReport, AppError> {
+    let compiled = compile_rules(&rules)?;
+    let mut per_survey: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    let mut superseded = BTreeSet::new();
+    for dir in output_dirs {
+        for (base_key, rows, yes) in run_survey_counts(dir)? {
+            if per_survey.insert(base_key.clone(), (rows, yes)).is_some() {
+                superseded.insert(base_key);
+            }
+        }
+    }
+
+    let mut total = AggregateRow::new("total");
+    let mut surveys = Vec::new();
+    let mut months: BTreeMap<String, AggregateRow> = BTreeMap::new();
+    let mut areas: BTreeMap<String, AggregateRow> = BTreeMap::new();
+    for (base_key, (images, dolphin_yes)) in per_survey {
+        let (date, area) = survey_fields(&base_key, &compiled);
+        let month = date
+            .and_then(|date| date.get(..7).map(str::to_string))
+            .unwrap_or_else(|| "unknown".to_string());
+        let area = area.unwrap_or_else(|| "unknown".to_string());
+        let mut survey = AggregateRow::new(&base_key);
+        survey.add(images, dolphin_yes);
+        total.add(images, dolphin_yes);
+        months
+            .entry(month.clone())
+            .or_insert_with(|| AggregateRow::new(&month))
+            .add(images, dolphin_yes);
+        areas
+            .entry(area.clone())
+            .or_insert_with(|| AggregateRow::new(&area))
+            .add(images, dolphin_yes);
+        surveys.push(survey);
+    }
+
+    if !report_dir.exists() {
+        fs::create_dir_all(&report_dir)?;
+    }
+    mark_output_dir(&report_dir)?;
+    let csv_path = report_dir.join(AGGREGATE_CSV_FILENAME);
+    let json_path = report_dir.join(AGGREGATE_JSON_FILENAME);
+    let report = AggregateReport {
+        runs: output_dirs.len() as u64,
+        superseded_surveys: superseded.into_iter().collect(),
+        total,
+        surveys,
+        months: months.into_values().collect(),
+        areas: areas.into_values().collect(),
+        csv_path: csv_path.to_string_lossy().to_string(),
+        json_path: json_path.to_string_lossy().to_string(),
+    };
+    write_aggregate_csv(&csv_path, &report)?;
+    fs::write(&json_path, serde_json::to_string_pretty(&report)?)?;
+    Ok(report)
+}
+
+/// Images and dolphin=1 images per survey of one run, taken from its
+/// `run_summary.json` or, when that lists no surveys, its merged CSV.
+fn run_survey_counts(output_dir: &Path) -> Result<Vec<(String, u64, u64)>, AppError> {
+    let summary_path = output_dir.join(RUN_SUMMARY_FILENAME);
+    if !summary_path.exists() {
+        return Err(AppError::Message(format!(
+            "No {} in {}; only runs of this version can be aggregated.",
+            RUN_SUMMARY_FILENAME,
+            output_dir.to_string_lossy()
+        )));
+    }
+    let summary: RunSummary = serde_json::from_str(&fs::read_to_string(&summary_path)?)?;
+    if !summary.surveys.is_empty() {
+        return Ok(summary
+            .surveys
+            .into_iter()
+            .map(|survey| (survey.survey_id_base, survey.rows, survey.dolphin_yes))
+            .collect());
+    }
+    let Some(merged) = summary.merged_csv_path else {
+        return Ok(Vec::new());
+    };
+    let mut reader = run_csv_reader(Path::new(&merged))?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| AppError::Message(format!("Column {} missing in {}.", name, merged)))
+    };
+    let key_column = column("survey_id_base")?;
+    let dolphin_column = column("dolphin")?;
+    let mut counts: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let entry = counts
+            .entry(record.get(key_column).unwrap_or_default().to_string())
+            .or_default();
+        entry.0 += 1;
+        if record.get(dolphin_column) == Some("1") {
+            entry.1 += 1;
+        }
+    }
+    Ok(counts
+        .into_iter()
+        .map(|(key, (rows, yes))| (key, rows, yes))
+        .collect())
+}
+
+fn write_aggregate_csv(path: &Path, report: &AggregateReport) -> Result<(), AppError> {
+    let mut writer = csv_writer(path, &CsvSettings::default())?;
+    writer.write_record([
+        "level",
+        "key",
+        "surveys",
+        "images",
+        "dolphin_yes",
+        "dolphin_no",
+        "dolphin_rate",
+    ])?;
+    let levels = std::iter::once(("total", &report.total))
+        .chain(report.months.iter().map(|row| ("month", row)))
+        .chain(report.areas.iter().map(|row| ("area", row)))
+        .chain(report.surveys.iter().map(|row| ("survey", row)));
+    for (level, row) in levels {
+        writer.write_record([
+            level,
+            row.key.as_str(),
+            &row.surveys.to_string(),
+            &row.images.to_string(),
+            &row.dolphin_yes.to_string(),
+            &row.dolphin_no.to_string(),
+            &format!("{:.4}", row.dolphin_rate),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::output::write_run_summary;
+    use crate::review::set_manual_label;
+    use crate::rules::LabelRule;
+    use crate::runs::SurveySummary;
+    use crate::test_support::{root_options, TestTree};
+
+    #[test]
+    fn run_diff_reports_flipped_labels() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let header = "survey_id_base,raw_relpath,filename,dolphin,calf,graded_relpath\n";
+        fs::write(
+            root.join("old.csv"),
+            format!(
+                "{}{}{}{}",
+                header,
+                "20240101_AB,a.jpg,a.jpg,1,0,Dolphins/a.jpg\n",
+                "20240101_AB,b.jpg,b.jpg,0,0,RAW\n",
+                "20240102_CD,c.jpg,c.jpg,0,0,RAW\n"
+            ),
+        )
+        .expect("write");
+        fs::write(
+            root.join("new.csv"),
+            format!(
+                "{}{}{}{}",
+                "survey_id_base,raw_relpath,filename,dolphin,graded_relpath\n",
+                "20240101_AB,a.jpg,a.jpg,1,Dolphins/a.jpg\n",
+                "20240101_AB,b.jpg,b.jpg,1,Dolphins/b.jpg\n",
+                "20240103_EF,d.jpg,d.jpg,0,RAW\n"
+            ),
+        )
+        .expect("write");
+
+        let diff =
+            diff_runs(root.join("old.csv"), root.join("new.csv"), Rules::default()).expect("diff");
+        assert_eq!(diff.label_columns, vec!["dolphin".to_string()]);
+        assert_eq!(
+            (
+                diff.added_rows,
+                diff.removed_rows,
+                diff.changed_rows,
+                diff.unchanged_rows
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(
+            diff.changes,
+            vec![LabelChange {
+                survey_id_base: "20240101_AB".to_string(),
+                raw_relpath: "b.jpg".to_string(),
+                column: "dolphin".to_string(),
+                old_value: "0".to_string(),
+                new_value: "1".to_string(),
+                old_graded_relpath: "RAW".to_string(),
+                new_graded_relpath: "Dolphins/b.jpg".to_string(),
+            }]
+        );
+        let keys: Vec<&str> = diff
+            .surveys
+            .iter()
+            .map(|survey| survey.survey_id_base.as_str())
+            .collect();
+        assert_eq!(keys, vec!["20240101_AB", "20240102_CD", "20240103_EF"]);
+        assert!(diff.old_duplicates.is_empty() && diff.new_duplicates.is_empty());
+        assert!(diff_runs(
+            root.join("old.csv"),
+            root.join("new.xlsx"),
+            Rules::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn run_diff_finds_label_columns_by_name_and_reports_duplicates() {
+        let tree = TestTree::new();
+        let rules = Rules {
+            labels: vec![LabelRule {
+                name: "calf".to_string(),
+                positive_contains_any: vec!["calf".to_string()],
+                negative_contains_any: Vec::new(),
+            }],
+            ..Rules::default()
+        };
+        // A column selection put `calf` last and `label_confidence` between
+        // `dolphin` and `graded_relpath`.
+        tree.write(
+            "old.csv",
+            "survey_id_base,raw_relpath,dolphin,label_confidence,graded_relpath,calf\n\
+             20240101_AB,a.jpg,1,0.90,IND/a.jpg,0\n\
+             20240101_AB,b.jpg,0,0.60,RAW,0\n",
+        );
+        tree.write(
+            "new.csv",
+            "survey_id_base,raw_relpath,dolphin,label_confidence,graded_relpath,calf\n\
+             20240101_AB,a.jpg,1,0.95,IND/a.jpg,1\n\
+             20240101_AB,b.jpg,0,0.60,RAW,0\n\
+             20240101_AB,b.jpg,0,0.60,RAW,0\n",
+        );
+        let diff = diff_runs(tree.join("old.csv"), tree.join("new.csv"), rules).expect("diff");
+        assert_eq!(diff.label_columns, ["dolphin", "calf"]);
+        assert_eq!((diff.changed_rows, diff.unchanged_rows), (1, 1));
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(diff.changes[0].column, "calf");
+        assert!(diff.old_duplicates.is_empty());
+        assert_eq!(diff.new_duplicates, ["20240101_AB/b.jpg"]);
+    }
+
+    #[test]
+    fn dataset_export_sorts_raw_images_into_class_folders() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB survey");
+        fs::create_dir_all(raw_dir.join("card 1")).expect("create");
+        fs::create_dir_all(root.join("graded").join("20240101_AB").join("Dolphins"))
+            .expect("create");
+        fs::write(raw_dir.join("card 1").join("IMG 0001.JPG"), b"one").expect("write");
+        fs::write(raw_dir.join("card 1").join("IMG 0002.JPG"), b"two").expect("write");
+        fs::write(
+            root.join("graded")
+                .join("20240101_AB")
+                .join("Dolphins")
+                .join("IMG 0001.JPG"),
+            b"one",
+        )
+        .expect("write");
+        let host = tree.host();
+        let options = root_options(serde_json::json!({}));
+        tree.run_root(&host, options, Rules::default())
+            .expect("run");
+
+        let dataset = root.join("dataset");
+        let export = export_dataset(
+            root.join("out").join("merged.csv"),
+            dataset.clone(),
+            DatasetExportOptions {
+                link_mode: DatasetLinkMode::Hardlink,
+                ..DatasetExportOptions::default()
+            },
+        )
+        .expect("export");
+        assert_eq!((export.dolphin, export.no_dolphin), (1, 1));
+        assert_eq!(export.linked, 2);
+        assert!(export.missing.is_empty());
+        let yes = dataset
+            .join("dolphin")
+            .join("20240101_AB_card 1_IMG 0001.JPG");
+        assert_eq!(fs::read(&yes).expect("read"), b"one");
+        assert!(dataset
+            .join("no_dolphin")
+            .join("20240101_AB_card 1_IMG 0002.JPG")
+            .is_file());
+
+        // Exporting again replaces the earlier files instead of failing.
+        let again = export_dataset(
+            root.join("out").join("merged.csv"),
+            dataset,
+            DatasetExportOptions::default(),
+        )
+        .expect("export again");
+        assert_eq!((again.dolphin, again.linked), (1, 0));
+
+        // A manual label kept in the run's folder wins over the CSV column.
+        set_manual_label(
+            root.join("out").join("merged.csv"),
+            "20240101_AB/card 1/IMG 0002.JPG",
+            Some(1),
+            None,
+        )
+        .expect("label");
+        let relabeled = export_dataset(
+            root.join("out").join("merged.csv"),
+            root.join("relabeled"),
+            DatasetExportOptions::default(),
+        )
+        .expect("relabeled");
+        assert_eq!((relabeled.dolphin, relabeled.manual_labels), (2, 1));
+        let labels =
+            fs::read_to_string(relabeled.labels_path.expect("labels")).expect("read labels");
+        assert!(labels
+            .contains("20240101_AB_card 1_IMG 0002.JPG,20240101_AB,card 1/IMG 0002.JPG,1,manual"));
+        assert!(labels
+            .contains("20240101_AB_card 1_IMG 0001.JPG,20240101_AB,card 1/IMG 0001.JPG,1,auto"));
+        set_manual_label(
+            root.join("out").join("merged.csv"),
+            "20240101_AB/card 1/IMG 0002.JPG",
+            None,
+            None,
+        )
+        .expect("clear label");
+
+        let coco = export_dataset(
+            root.join("out").join("merged.csv"),
+            root.join("coco"),
+            DatasetExportOptions {
+                format: DatasetFormat::Coco,
+                ..DatasetExportOptions::default()
+            },
+        )
+        .expect("coco");
+        let annotations: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(coco.annotations_path.expect("annotations")).expect("read"),
+        )
+        .expect("json");
+        assert_eq!(annotations["images"].as_array().map(Vec::len), Some(2));
+        assert_eq!(annotations["categories"][0]["name"], "dolphin");
+        let first = &annotations["images"][0];
+        assert_eq!(first["file_name"], "20240101_AB_card 1_IMG 0001.JPG");
+        assert_eq!(annotations["annotations"][0]["category_id"], 1);
+        assert!(root
+            .join("coco")
+            .join("images")
+            .join("20240101_AB_card 1_IMG 0002.JPG")
+            .is_file());
+
+        let yolo = root.join("yolo");
+        export_dataset(
+            root.join("out").join("merged.csv"),
+            yolo.clone(),
+            DatasetExportOptions {
+                format: DatasetFormat::Yolo,
+                val_fraction: 1.0,
+                ..DatasetExportOptions::default()
+            },
+        )
+        .expect("yolo");
+        assert!(yolo
+            .join("val")
+            .join("dolphin")
+            .join("20240101_AB_card 1_IMG 0001.JPG")
+            .is_file());
+        assert!(yolo.join("train").join("no_dolphin").is_dir());
+        assert!(!in_val_split("a.jpg", 0.0));
+        assert!(export_dataset(
+            root.join("merged.csv"),
+            root.join("x"),
+            DatasetExportOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn dataset_export_renames_colliding_file_names() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/a/b_c.jpg", "first");
+        tree.write("raw/20240101_AB/a_b/c.jpg", "second");
+        tree.write("graded/20240101_AB/IND/c.jpg", "second");
+        let host = tree.host();
+        tree.run_root(&host, root_options(serde_json::json!({})), Rules::default())
+            .expect("run");
+
+        let export = export_dataset(
+            tree.join("out").join("merged.csv"),
+            tree.join("dataset"),
+            DatasetExportOptions {
+                format: DatasetFormat::Coco,
+                ..DatasetExportOptions::default()
+            },
+        )
+        .expect("export");
+        assert_eq!((export.dolphin + export.no_dolphin, export.renamed), (2, 1));
+        let mut names: Vec<String> = fs::read_dir(tree.join("dataset").join("images"))
+            .expect("images")
+            .map(|entry| {
+                entry
+                    .expect("entry")
+                    .file_name()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0], "20240101_AB_a_b_c.jpg");
+        let hashed = hashed_name("20240101_AB_a_b_c.jpg", "20240101_AB/a_b/c.jpg");
+        assert_eq!(names[1], hashed);
+        assert_eq!(
+            fs::read(tree.join("dataset").join("images").join(&hashed)).expect("read"),
+            b"second"
+        );
+        let labels = fs::read_to_string(export.labels_path.expect("labels")).expect("labels");
+        assert!(labels.contains(&format!("{},20240101_AB,a_b/c.jpg,1,auto", hashed)));
+    }
+
+    #[test]
+    fn aggregate_runs_builds_season_report() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let run = |name: &str, surveys: &[(&str, u64, u64)]| {
+            let dir = root.join(name);
+            fs::create_dir_all(&dir).expect("create");
+            let summary = RunSummary {
+                run_id: name.to_string(),
+                processed_surveys: surveys.len() as u64,
+                total_rows: 0,
+                dolphin_yes: 0,
+                dolphin_no: 0,
+                ambiguity_warnings: 0,
+                collapsed_candidates: 0,
+                id_collisions: 0,
+                perceptual_matches: 0,
+                scan_reused: false,
+                problems_count: 0,
+                error_count: 0,
+                warning_count: 0,
+                info_count: 0,
+                output_dir: dir.to_string_lossy().to_string(),
+                merged_csv_path: None,
+                problems_csv_path: None,
+                problems_detail_csv_path: None,
+                pairings_csv_path: None,
+                summary_markdown_path: None,
+                html_report_path: None,
+                qa_workbook_path: None,
+                access_export_path: None,
+                sqlite_path: None,
+                orphaned_graded: 0,
+                orphaned_graded_path: None,
+                surveys: surveys
+                    .iter()
+                    .map(|(key, rows, yes)| SurveySummary {
+                        survey_id_base: key.to_string(),
+                        raw_path: String::new(),
+                        graded_path: String::new(),
+                        raw_root: String::new(),
+                        graded_root: String::new(),
+                        rows: *rows,
+                        dolphin_yes: *yes,
+                        dolphin_no: rows - yes,
+                        ambiguity_warnings: 0,
+                        collapsed_candidates: 0,
+                        id_collisions: 0,
+                        perceptual_matches: 0,
+                        graded_map_on_disk: false,
+                        track_path: None,
+                        graded_coverage: 1.0,
+                    })
+                    .collect(),
+                email_error: None,
+                postgres_error: None,
+                history_error: None,
+                cancelled: false,
+                resumed_surveys: 0,
+                resumed_rows: 0,
+                cached_file_ids: 0,
+            };
+            write_run_summary(&dir, &summary).expect("summary");
+            dir
+        };
+        let first = run("q1", &[("20240110_AB", 10, 5), ("20240220_CD", 4, 0)]);
+        let second = run("q2", &[("20240220_CD", 8, 2), ("20240225_AB", 10, 1)]);
+
+        let report = aggregate_runs(&[first, second], root.join("season"), Rules::default())
+            .expect("aggregate");
+        assert_eq!(report.superseded_surveys, vec!["20240220_CD".to_string()]);
+        assert_eq!((report.total.surveys, report.total.images), (3, 28));
+        assert_eq!(report.total.dolphin_yes, 8);
+        let months = report
+            .months
+            .iter()
+            .map(|row| (row.key.as_str(), row.images))
+            .collect::<Vec<_>>();
+        assert_eq!(months, vec![("2024-01", 10), ("2024-02", 18)]);
+        let area_ab = report.areas.iter().find(|row| row.key == "AB").expect("AB");
+        assert_eq!((area_ab.surveys, area_ab.dolphin_yes), (2, 6));
+        assert!((area_ab.dolphin_rate - 0.3).abs() < 1e-9);
+        let csv = fs::read_to_string(&report.csv_path).expect("csv");
+        assert!(csv.contains("month,2024-02,2,18,3,15,0.1667"));
+        assert!(Path::new(&report.json_path).exists());
+    }
+}
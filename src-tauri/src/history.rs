@@ -0,0 +1,312 @@
+//! Finished runs kept in the app data folder, and the workspaces of runs.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::rules::Rules;
+use crate::runs::{RunSummary, WorkspaceManifest, WORKSPACE_MANIFEST};
+use crate::{app_data_dir, AppError, Host};
+
+const RUNS_DIRNAME: &str = "runs";
+/// Runs kept in the history; recording a run drops the oldest beyond this.
+const MAX_RECORDED_RUNS: usize = 500;
+
+/// A finished run as kept in the run history: its summary plus the options
+/// and rules it ran with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run_id: String,
+    /// `root` or `single`.
+    pub kind: String,
+    pub started_at_ms: u64,
+    pub finished_at_ms: u64,
+    /// Raw and graded root of a root run, or the two folders of a single run.
+    pub raw_root: String,
+    pub graded_root: String,
+    pub options: serde_json::Value,
+    pub rules: Rules,
+    pub summary: RunSummary,
+}
+
+/// One entry of `list_runs`, without the options and rules snapshot.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunListItem {
+    pub run_id: String,
+    pub kind: String,
+    pub started_at_ms: u64,
+    pub finished_at_ms: u64,
+    pub raw_root: String,
+    pub graded_root: String,
+    pub output_dir: String,
+    pub processed_surveys: u64,
+    pub total_rows: u64,
+    /// Rows of `total_rows` taken over from an interrupted run.
+    #[serde(default)]
+    pub resumed_rows: u64,
+    pub problems_count: u64,
+    pub cancelled: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkspaceInfo {
+    pub run_id: String,
+    pub path: String,
+    pub kind: Option<String>,
+    pub created_at_ms: Option<u64>,
+    pub size_bytes: u64,
+    pub active: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkspaceCleanup {
+    pub removed: u64,
+    pub freed_bytes: u64,
+    pub remaining: Vec<WorkspaceInfo>,
+}
+
+fn runs_dir(host: &Host) -> Result<PathBuf, AppError> {
+    Ok(app_data_dir(host)?.join(RUNS_DIRNAME))
+}
+
+/// Adds a finished run to the history and drops the oldest runs beyond
+/// `MAX_RECORDED_RUNS`.
+pub(crate) fn record_run(host: &Host, record: &RunRecord) -> Result<(), AppError> {
+    let dir = runs_dir(host)?;
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join(format!("{}.json", record.run_id)),
+        serde_json::to_string_pretty(record)?,
+    )?;
+    prune_runs(&dir, MAX_RECORDED_RUNS)
+}
+
+/// Removes the oldest records in `dir` until `keep` are left. Run ids start
+/// with their start time, so they sort oldest first.
+fn prune_runs(dir: &Path, keep: usize) -> Result<(), AppError> {
+    let mut records = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+            records.push(path);
+        }
+    }
+    records.sort();
+    let excess = records.len().saturating_sub(keep);
+    for path in records.into_iter().take(excess) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Lists the recorded runs, newest first.
+pub fn list_runs(host: &Host) -> Result<Vec<RunListItem>, AppError> {
+    let dir = runs_dir(host)?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut runs = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(record) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|data| serde_json::from_str::<RunRecord>(&data).ok())
+        else {
+            continue;
+        };
+        runs.push(RunListItem {
+            run_id: record.run_id,
+            kind: record.kind,
+            started_at_ms: record.started_at_ms,
+            finished_at_ms: record.finished_at_ms,
+            raw_root: record.raw_root,
+            graded_root: record.graded_root,
+            output_dir: record.summary.output_dir,
+            processed_surveys: record.summary.processed_surveys,
+            total_rows: record.summary.total_rows,
+            resumed_rows: record.summary.resumed_rows,
+            problems_count: record.summary.problems_count,
+            cancelled: record.summary.cancelled,
+        });
+    }
+    runs.sort_by(|a, b| b.run_id.cmp(&a.run_id));
+    Ok(runs)
+}
+
+/// The recorded run with `run_id`, including its options and rules.
+pub fn get_run(host: &Host, run_id: &str) -> Result<RunRecord, AppError> {
+    let path = runs_dir(host)?.join(format!("{}.json", run_id));
+    let valid = !run_id.is_empty()
+        && run_id
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-');
+    if !valid || !path.is_file() {
+        return Err(AppError::Validation(format!(
+            "No recorded run with id '{}'.",
+            run_id
+        )));
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Lists run workspaces under app data with their size on disk.
+pub fn list_workspaces(host: &Host) -> Result<Vec<WorkspaceInfo>, AppError> {
+    let root = workspaces_root(host)?;
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+    let active = active_workspaces()
+        .lock()
+        .map(|set| set.clone())
+        .unwrap_or_default();
+    let mut workspaces = Vec::new();
+    for entry in fs::read_dir(&root)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let manifest: Option<WorkspaceManifest> = fs::read_to_string(path.join(WORKSPACE_MANIFEST))
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok());
+        let run_id = entry.file_name().to_string_lossy().to_string();
+        workspaces.push(WorkspaceInfo {
+            active: active.contains(&run_id),
+            kind: manifest.as_ref().map(|manifest| manifest.kind.clone()),
+            created_at_ms: manifest.as_ref().map(|manifest| manifest.created_at_ms),
+            size_bytes: dir_size(&path),
+            path: path.to_string_lossy().to_string(),
+            run_id,
+        });
+    }
+    workspaces.sort_by(|a, b| a.run_id.cmp(&b.run_id));
+    Ok(workspaces)
+}
+
+/// Removes every workspace that does not belong to a run in progress.
+pub fn cleanup_workspaces(host: &Host) -> Result<WorkspaceCleanup, AppError> {
+    let mut cleanup = WorkspaceCleanup {
+        removed: 0,
+        freed_bytes: 0,
+        remaining: Vec::new(),
+    };
+    for workspace in list_workspaces(host)? {
+        if workspace.active {
+            cleanup.remaining.push(workspace);
+            continue;
+        }
+        fs::remove_dir_all(&workspace.path)?;
+        cleanup.removed += 1;
+        cleanup.freed_bytes += workspace.size_bytes;
+    }
+    Ok(cleanup)
+}
+
+pub(crate) fn workspaces_root(host: &Host) -> Result<PathBuf, AppError> {
+    Ok(app_data_dir(host)?.join("workspaces"))
+}
+
+pub(crate) fn active_workspaces() -> &'static Mutex<HashSet<String>> {
+    static ACTIVE: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    ACTIVE.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::runs::{run_single_pair, SingleRunOptions, RUN_SUMMARY_FILENAME};
+    use crate::test_support::{root_options, TestTree};
+
+    #[test]
+    fn finished_runs_are_kept_in_the_history() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB");
+        let graded_dir = root.join("graded").join("20240101_AB").join("Dolphins");
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(&graded_dir).expect("create");
+        fs::write(raw_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        fs::write(graded_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        let host = tree.host();
+        assert!(list_runs(&host).expect("list").is_empty());
+
+        let options: SingleRunOptions =
+            serde_json::from_value(serde_json::json!({ "output_filename": "single.csv" }))
+                .expect("options");
+        let summary = run_single_pair(
+            &host,
+            root.join("graded").join("20240101_AB"),
+            raw_dir,
+            root.join("out"),
+            None,
+            options,
+            Rules::default(),
+        )
+        .expect("run");
+
+        let runs = list_runs(&host).expect("list");
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].run_id, summary.run_id);
+        assert_eq!(runs[0].kind, "single");
+        assert_eq!(runs[0].total_rows, 1);
+        let record = get_run(&host, &summary.run_id).expect("record");
+        assert_eq!(record.rules, Rules::default());
+        assert_eq!(record.options["output_filename"], "single.csv");
+        assert!(record.finished_at_ms >= record.started_at_ms);
+        assert!(get_run(&host, "../rules").is_err());
+    }
+
+    #[test]
+    fn a_history_that_cannot_be_written_does_not_fail_the_run() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/Dolphins/IMG_0001.JPG", "x");
+        // A file where the history folder should be.
+        tree.write("data/runs", "");
+        let host = tree.host();
+        let summary = tree
+            .run_root(&host, root_options(serde_json::json!({})), Rules::default())
+            .expect("run");
+        assert_eq!(summary.total_rows, 1);
+        assert!(summary.history_error.is_some());
+        let written =
+            fs::read_to_string(tree.join("out").join(RUN_SUMMARY_FILENAME)).expect("summary");
+        assert!(written.contains("\"history_error\": \""));
+    }
+
+    #[test]
+    fn the_history_keeps_the_newest_runs() {
+        let tree = TestTree::new();
+        let dir = tree.mkdir("runs");
+        for run_id in ["1000-0000", "1000-0001", "2000-0000", "3000-0000"] {
+            tree.write(format!("runs/{run_id}.json"), "{}");
+        }
+        tree.write("runs/notes.txt", "");
+        prune_runs(&dir, 2).expect("prune");
+        let mut left: Vec<_> = fs::read_dir(&dir)
+            .expect("runs")
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["2000-0000.json", "3000-0000.json", "notes.txt"]);
+    }
+}
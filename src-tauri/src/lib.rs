@@ -1,19 +1,116 @@
-use csv::WriterBuilder;
-use regex::Regex;
+mod archives;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+mod classify;
+mod control;
+mod export;
+mod history;
+mod matching;
+mod output;
+mod paths;
+mod problems;
+mod review;
+mod rules;
+mod runs;
+mod scan;
+mod self_check;
+mod settings;
+mod suggest;
+mod synthetic;
+#[cfg(test)]
+mod test_support;
+mod tracks;
+mod watch;
+
+use control::Generation;
+pub use control::{
+    cancel_runs, enqueue_single_run, list_run_queue, pause_runs, remove_queued_run, resume_runs,
+    QueueStatus, QueuedRun,
+};
+pub use export::{
+    aggregate_runs, diff_runs, export_dataset, AggregateReport, AggregateRow, DatasetExport,
+    DatasetExportOptions, DatasetFormat, DatasetLinkMode, LabelChange, RunDiff, SurveyDiff,
+};
+pub use history::{
+    cleanup_workspaces, get_run, list_runs, list_workspaces, RunListItem, RunRecord,
+    WorkspaceCleanup, WorkspaceInfo,
+};
+pub use output::{
+    CsvDelimiter, CsvQuoteStyle, CsvSettings, HyperlinkStyle, LineEnding, OnExisting, OutputFormat,
+    ThumbnailMode,
+};
+pub use paths::{checked_image_path, checked_reveal_path, normalize_root};
+pub use problems::{ProblemGroup, ProblemItem, ProblemSeverity};
+pub use review::{
+    get_review_queue, get_thumbnail, set_manual_label, ManualLabel, ReviewItem, ReviewQueue,
+    ReviewQueueOptions, ReviewReason, Thumbnail,
+};
+use rules::CompiledRules;
+pub use rules::{
+    activate_profile, delete_profile, get_or_init_rules, list_profiles, load_profile,
+    load_rules_history, reset_rules, save_profile, save_rules, validate_rules, BaseKeyScheme,
+    CandidateDedupe, CollisionPolicy, KeyCase, LabelRule, MatchingStrategy, RuleFieldChange,
+    RuleFieldError, RuleProfile, Rules, RulesUpdate, RulesValidation, RulesVersion,
+    DEFAULT_PROFILE,
+};
+use runs::CachedScan;
+pub use runs::{
+    estimate_run, preview_root_scan, run_root_scan, run_single_pair, PreviewOptions, PreviewScan,
+    RootRunOptions, RunEstimate, RunSummary, SingleRunOptions, SurveySummary,
+};
+pub use scan::{DuplicateResolution, PreviewItem, ScanSettings};
+pub use self_check::{run_self_check, SelfCheckMismatch, SelfCheckReport};
+pub use settings::{
+    get_settings, load_settings, save_settings, AppSettings, PostgresSettings, PostgresTls,
+    SmtpSecurity, SmtpSettings, REDACTED_PASSWORD,
+};
+pub use suggest::{
+    suggest_rules_from_samples, suggest_tokens, RulesSuggestion, SampleMatch, TokenSuggestion,
+};
+pub use synthetic::{generate_synthetic_tree, SyntheticTree, SyntheticTreeOptions};
+pub use tracks::TrackSettings;
+pub use watch::{start_watch, stop_watch, WatchOptions};
+
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::fs;
-use std::path::{Path, PathBuf};
-use tauri::{AppHandle, Emitter, Manager};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
-use walkdir::WalkDir;
 
-const DEFAULT_RULES_JSON: &str = include_str!("../assets/rules.default.json");
+const PREVIEW_COUNT_WORKERS: usize = 4;
+/// Threads that stat files for their ids; on network shares each stat is a
+/// round trip, so several in flight hide most of the latency.
+const STAT_WORKERS: usize = 8;
+/// Rows a survey hands to the result writers at a time; also bounds the rows
+/// in flight from a survey worker thread.
+const ROW_BATCH_SIZE: usize = 1024;
+const THUMBNAIL_PX: u32 = 96;
+/// Process exit codes for headless use; schedulers branch on these, so the
+/// values must stay stable.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_PROBLEMS: i32 = 2;
+pub const EXIT_VALIDATION: i32 = 3;
+pub const EXIT_IO: i32 = 4;
 
 #[derive(Debug, Error)]
 pub enum AppError {
     #[error("{0}")]
     Message(String),
+    /// Invalid input such as rules, options or a missing survey id.
+    #[error("{0}")]
+    Validation(String),
+    /// The run stopped because of problems with a blocking severity.
+    #[error("{0}")]
+    Blocked(String),
+    /// The run was stopped through `cancel_runs`.
+    #[error("Run cancelled.")]
+    Cancelled,
+    /// Another run holds the output folder.
+    #[error("{0}")]
+    Busy(String),
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
@@ -22,1003 +119,476 @@ pub enum AppError {
     Csv(#[from] csv::Error),
     #[error(transparent)]
     Json(#[from] serde_json::Error),
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Rules {
-    pub extensions: Vec<String>,
-    pub survey_id_regex_detected: String,
-    pub survey_id_regex_base: String,
-    #[serde(default = "default_image_id_regex")]
-    pub image_id_regex: String,
-    pub graded_priority_ind_regex: String,
-    pub graded_priority_secondary_tokens: Vec<String>,
-    pub graded_negative_contains_any: Vec<String>,
-    pub graded_positive_contains_any: Vec<String>,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct RootRunOptions {
-    pub write_per_survey: bool,
-    pub write_merged: bool,
-    pub merged_filename: String,
-    pub problems_filename: String,
-    pub per_survey_dirname: String,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct SingleRunOptions {
-    pub output_filename: String,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PreviewItem {
-    pub base_key: String,
-    pub raw_path: Option<String>,
-    pub graded_path: Option<String>,
-    pub status: String,
-    pub problem_type: Option<String>,
-    pub details: Option<String>,
-    pub raw_image_count: Option<u64>,
-    pub graded_image_count: Option<u64>,
-    pub survey_id_raw_detected: Option<String>,
-    pub survey_id_graded_detected: Option<String>,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct ProgressEvent {
-    pub survey_id_base: String,
-    pub processed: u64,
-    pub total: u64,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct RunSummary {
-    pub processed_surveys: u64,
-    pub total_rows: u64,
-    pub dolphin_yes: u64,
-    pub dolphin_no: u64,
-    pub ambiguity_warnings: u64,
-    pub problems_count: u64,
-    pub output_dir: String,
-    pub merged_csv_path: Option<String>,
-    pub problems_csv_path: Option<String>,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct ProblemItem {
-    pub survey_id_base: String,
-    pub survey_id_detected: Option<String>,
-    pub raw_path: Option<String>,
-    pub graded_path: Option<String>,
-    pub problem_type: String,
-    pub details: Option<String>,
-}
-
-#[derive(Clone, Debug)]
-struct SurveyFolder {
-    path: PathBuf,
-    detected_id: Option<String>,
-}
-
-#[derive(Clone, Debug)]
-struct CandidateWinner {
-    relpath: String,
-    winner_type: String,
-}
-
-#[derive(Clone, Debug)]
-struct PairResult {
-    rows: Vec<CsvRow>,
-    ambiguity_warnings: u64,
-}
-
-#[derive(Clone, Debug)]
-struct GradedMapResult {
-    map: HashMap<String, Vec<String>>,
-    ambiguity_warnings: u64,
-}
-
-#[derive(Clone, Debug)]
-struct CompiledRules {
-    extensions: HashSet<String>,
-    detected_re: Regex,
-    base_re: Regex,
-    image_id_re: Regex,
-    ind_re: Regex,
-    secondary_tokens: Vec<String>,
-    negative_tokens: Vec<String>,
-    positive_tokens: Vec<String>,
-}
-
-#[derive(Clone, Debug)]
-struct CsvRow {
-    survey_id_base: String,
-    raw_relpath: String,
-    filename: String,
-    dolphin: u8,
-    graded_relpath: String,
-    graded_hits: u64,
-    graded_winner_type: String,
-    survey_id_raw_detected: Option<String>,
-    survey_id_graded_detected: Option<String>,
-}
-
-pub fn get_or_init_rules(app: &AppHandle) -> Result<Rules, AppError> {
-    let path = rules_file_path(app)?;
-    if !path.exists() {
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
+    #[error(transparent)]
+    Xlsx(#[from] rust_xlsxwriter::XlsxError),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    SevenZ(#[from] sevenz_rust::Error),
+}
+
+impl AppError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AppError::Message(_) | AppError::Cancelled | AppError::Busy(_) => EXIT_FAILURE,
+            AppError::Blocked(_) => EXIT_PROBLEMS,
+            AppError::Validation(_) | AppError::Regex(_) | AppError::Json(_) => EXIT_VALIDATION,
+            AppError::Io(_)
+            | AppError::Csv(_)
+            | AppError::Xlsx(_)
+            | AppError::Sqlite(_)
+            | AppError::Parquet(_)
+            | AppError::Zip(_)
+            | AppError::SevenZ(_) => EXIT_IO,
         }
-        fs::write(&path, DEFAULT_RULES_JSON)?;
-    }
-    let data = fs::read_to_string(path)?;
-    let rules: Rules = serde_json::from_str(&data)?;
-    Ok(rules)
-}
-
-pub fn save_rules(app: &AppHandle, rules: Rules) -> Result<Rules, AppError> {
-    let path = rules_file_path(app)?;
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
     }
-    let data = serde_json::to_string_pretty(&rules)?;
-    fs::write(&path, data)?;
-    Ok(rules)
-}
-
-pub fn reset_rules(app: &AppHandle) -> Result<Rules, AppError> {
-    let rules: Rules = serde_json::from_str(DEFAULT_RULES_JSON)?;
-    save_rules(app, rules)
-}
 
-pub fn preview_root_scan(
-    graded_root: PathBuf,
-    raw_root: PathBuf,
-    rules: Rules,
-) -> Result<Vec<PreviewItem>, AppError> {
-    let compiled = compile_rules(&rules)?;
-    let scan = scan_roots(&raw_root, &graded_root, &compiled, true)?;
-    Ok(scan.preview)
-}
-
-pub fn run_root_scan(
-    app: &AppHandle,
-    graded_root: PathBuf,
-    raw_root: PathBuf,
-    output_dir: PathBuf,
-    options: RootRunOptions,
-    rules: Rules,
-) -> Result<RunSummary, AppError> {
-    let compiled = compile_rules(&rules)?;
-    let scan = scan_roots(&raw_root, &graded_root, &compiled, false)?;
-
-    if !output_dir.exists() {
-        fs::create_dir_all(&output_dir)?;
-    }
-
-    let per_survey_dir = output_dir.join(&options.per_survey_dirname);
-    if options.write_per_survey {
-        fs::create_dir_all(&per_survey_dir)?;
-    }
-
-    let problems_csv_path = output_dir.join(&options.problems_filename);
-    if !scan.problems.is_empty() {
-        write_problems_csv(&problems_csv_path, &scan.problems)?;
-    }
-
-    let mut merged_writer = if options.write_merged {
-        let path = output_dir.join(&options.merged_filename);
-        Some(init_csv_writer(&path)?)
-    } else {
-        None
-    };
-
-    let mut processed_surveys = 0u64;
-    let mut total_rows = 0u64;
-    let mut dolphin_yes = 0u64;
-    let mut dolphin_no = 0u64;
-    let mut ambiguity_warnings = 0u64;
-
-    for entry in scan.entries {
-        if entry.status != "OK" {
-            continue;
-        }
-        let raw = entry.raw.expect("raw required");
-        let graded = entry.graded.expect("graded required");
-
-        let pair_result = process_pair(app, &compiled, &entry.base_key, &raw, &graded)?;
-        let rows = pair_result.rows;
-        ambiguity_warnings += pair_result.ambiguity_warnings;
-
-        if options.write_per_survey {
-            let per_path = per_survey_dir.join(format!("{}.csv", entry.base_key));
-            write_csv_rows(&per_path, &rows)?;
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::Message(_) => "error",
+            AppError::Validation(_) => "validation",
+            AppError::Blocked(_) => "blocked",
+            AppError::Cancelled => "cancelled",
+            AppError::Busy(_) => "busy",
+            AppError::Io(_) => "io",
+            AppError::Regex(_) => "regex",
+            AppError::Csv(_) => "csv",
+            AppError::Json(_) => "json",
+            AppError::Xlsx(_) => "xlsx",
+            AppError::Sqlite(_) => "sqlite",
+            AppError::Parquet(_) => "parquet",
+            AppError::Zip(_) => "zip",
+            AppError::SevenZ(_) => "7z",
         }
+    }
 
-        if let Some(writer) = merged_writer.as_mut() {
-            write_rows_to_writer(writer, &rows)?;
-        }
-
-        processed_surveys += 1;
-        for row in rows {
-            total_rows += 1;
-            if row.dolphin == 1 {
-                dolphin_yes += 1;
-            } else {
-                dolphin_no += 1;
-            }
+    /// Machine-readable form for `--error-format json`.
+    pub fn report(&self) -> ErrorReport {
+        ErrorReport {
+            exit_code: self.exit_code(),
+            kind: self.kind().to_string(),
+            message: self.to_string(),
         }
     }
-
-    let merged_csv_path = if options.write_merged {
-        Some(
-            output_dir
-                .join(&options.merged_filename)
-                .to_string_lossy()
-                .to_string(),
-        )
-    } else {
-        None
-    };
-
-    let problems_csv_path = if !scan.problems.is_empty() {
-        Some(problems_csv_path.to_string_lossy().to_string())
-    } else {
-        None
-    };
-
-    Ok(RunSummary {
-        processed_surveys,
-        total_rows,
-        dolphin_yes,
-        dolphin_no,
-        ambiguity_warnings,
-        problems_count: scan.problems.len() as u64,
-        output_dir: output_dir.to_string_lossy().to_string(),
-        merged_csv_path,
-        problems_csv_path,
-    })
 }
 
-pub fn run_single_pair(
-    app: &AppHandle,
-    graded_dir: PathBuf,
-    raw_dir: PathBuf,
-    output_dir: PathBuf,
-    survey_id_override: Option<String>,
-    options: SingleRunOptions,
-    rules: Rules,
-) -> Result<RunSummary, AppError> {
-    let compiled = compile_rules(&rules)?;
-    if !output_dir.exists() {
-        fs::create_dir_all(&output_dir)?;
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ErrorReport {
+    pub exit_code: i32,
+    pub kind: String,
+    pub message: String,
+}
+
+/// State of a host caching the saved rules, the last compiled rule set and
+/// the latest preview scan, so commands neither re-read `rules.json` nor
+/// recompile regexes each time. Saving or resetting rules refreshes the cache.
+#[derive(Default)]
+struct AppState {
+    rules: Mutex<Option<Rules>>,
+    compiled: Mutex<Option<(Rules, Arc<CompiledRules>)>>,
+    last_scan: Mutex<Option<CachedScan>>,
+    /// Advanced by every preview, so counts of an older one are dropped.
+    preview_counts: Generation,
+}
+
+/// Receives the events of running scans, runs, watches and the run queue:
+/// the desktop app forwards them to the frontend, the CLI prints them to
+/// stderr, and library users or tests may collect them.
+pub trait ProgressSink: Send + Sync {
+    fn progress(&self, event: ProgressEvent);
+
+    /// A watched survey was matched again or disappeared.
+    fn survey_updated(&self, _event: SurveyUpdateEvent) {}
+
+    /// A queued run changed its status.
+    fn queue_updated(&self, _run: &QueuedRun) {}
+
+    /// Image counts of a preview item, counted after the preview returned.
+    fn counts_updated(&self, _event: CountUpdateEvent) {}
+
+    /// A run was paused or resumed.
+    fn run_paused(&self, _event: RunPauseEvent) {}
+}
+
+/// Progress lines on stderr, as the CLI prints them.
+pub struct StderrProgress;
+
+impl ProgressSink for StderrProgress {
+    fn progress(&self, event: ProgressEvent) {
+        let eta = event
+            .eta_seconds
+            .map(|secs| format!(", about {} s left", secs))
+            .unwrap_or_default();
+        eprintln!(
+            "[{}/{}] {} {}: {}/{}{}",
+            event.survey_index,
+            event.survey_count,
+            event.survey_id_base,
+            event.phase.as_str(),
+            event.processed,
+            event.total,
+            eta
+        );
     }
 
-    let detected = survey_id_override
-        .and_then(|value| extract_base_key(&value, &compiled.base_re).map(|base| (value, base)))
-        .or_else(|| {
-            extract_detected_id(&graded_dir, &compiled.detected_re).and_then(|detected| {
-                extract_base_key(&detected, &compiled.base_re).map(|base| (detected, base))
-            })
-        })
-        .ok_or_else(|| {
-            AppError::Message(
-                "Unable to derive survey id base; please provide an override.".to_string(),
-            )
-        })?;
-
-    let (detected_full, base_key) = detected;
-
-    let raw_detected = extract_detected_id(&raw_dir, &compiled.detected_re);
-    let raw_folder = SurveyFolder {
-        path: raw_dir,
-        detected_id: raw_detected,
-    };
-    let graded_folder = SurveyFolder {
-        path: graded_dir,
-        detected_id: Some(detected_full.clone()),
-    };
-
-    let pair_result = process_pair(app, &compiled, &base_key, &raw_folder, &graded_folder)?;
-    let rows = pair_result.rows;
-    let output_path = output_dir.join(&options.output_filename);
-    write_csv_rows(&output_path, &rows)?;
-
-    let mut dolphin_yes = 0u64;
-    let mut dolphin_no = 0u64;
-    for row in &rows {
-        if row.dolphin == 1 {
-            dolphin_yes += 1;
-        } else {
-            dolphin_no += 1;
+    fn survey_updated(&self, event: SurveyUpdateEvent) {
+        match event.error {
+            Some(error) => eprintln!("{}: {}: {}", event.survey_id_base, event.status, error),
+            None => eprintln!(
+                "{}: {} ({} rows)",
+                event.survey_id_base, event.status, event.rows
+            ),
         }
     }
 
-    Ok(RunSummary {
-        processed_surveys: 1,
-        total_rows: rows.len() as u64,
-        dolphin_yes,
-        dolphin_no,
-        ambiguity_warnings: pair_result.ambiguity_warnings,
-        problems_count: 0,
-        output_dir: output_dir.to_string_lossy().to_string(),
-        merged_csv_path: Some(output_path.to_string_lossy().to_string()),
-        problems_csv_path: None,
-    })
-}
-
-fn rules_file_path(app: &AppHandle) -> Result<PathBuf, AppError> {
-    let dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|err| AppError::Message(err.to_string()))?;
-    Ok(dir.join("rules.json"))
-}
-
-fn default_image_id_regex() -> String {
-    "^(.+?_\\d{3,5})(?:[ _][A-Za-z0-9]+)*$".to_string()
-}
-
-fn compile_rules(rules: &Rules) -> Result<CompiledRules, AppError> {
-    let mut extensions = HashSet::new();
-    for ext in &rules.extensions {
-        let normalized = normalize_extension(ext);
-        extensions.insert(normalized);
+    fn queue_updated(&self, run: &QueuedRun) {
+        eprintln!("queued run {}: {}", run.job_id, run.status.as_str());
     }
-    Ok(CompiledRules {
-        extensions,
-        detected_re: Regex::new(&rules.survey_id_regex_detected)?,
-        base_re: Regex::new(&rules.survey_id_regex_base)?,
-        image_id_re: Regex::new(&rules.image_id_regex)?,
-        ind_re: Regex::new(&rules.graded_priority_ind_regex)?,
-        secondary_tokens: normalize_tokens(&rules.graded_priority_secondary_tokens),
-        negative_tokens: normalize_tokens(&rules.graded_negative_contains_any),
-        positive_tokens: normalize_tokens(&rules.graded_positive_contains_any),
-    })
-}
 
-fn normalize_extension(ext: &str) -> String {
-    let trimmed = ext.trim().to_lowercase();
-    if trimmed.starts_with('.') {
-        trimmed
-    } else {
-        format!(".{}", trimmed)
+    fn run_paused(&self, event: RunPauseEvent) {
+        let state = if event.paused { "paused" } else { "resumed" };
+        eprintln!("run {}: {}", event.run_id, state);
     }
 }
 
-fn normalize_tokens(tokens: &[String]) -> Vec<String> {
-    tokens
-        .iter()
-        .map(|token| token.trim().to_lowercase())
-        .filter(|token| !token.is_empty())
-        .collect()
-}
-
-fn extract_detected_id(path: &Path, regex: &Regex) -> Option<String> {
-    let path_str = path.to_string_lossy();
-    regex
-        .captures_iter(&path_str)
-        .last()
-        .and_then(|captures| captures.get(1))
-        .map(|m| m.as_str().to_string())
-}
-
-fn extract_base_key(value: &str, regex: &Regex) -> Option<String> {
-    regex
-        .captures_iter(value)
-        .last()
-        .and_then(|captures| captures.get(1))
-        .map(|m| m.as_str().to_uppercase())
-}
-
-fn scan_roots(
-    raw_root: &Path,
-    graded_root: &Path,
-    rules: &CompiledRules,
-    include_counts: bool,
-) -> Result<ScanResult, AppError> {
-    let raw_map = discover_surveys(raw_root, rules)?;
-    let graded_map = discover_surveys(graded_root, rules)?;
-
-    let mut base_keys: HashSet<String> = raw_map.keys().cloned().collect();
-    base_keys.extend(graded_map.keys().cloned());
-
-    let mut entries = Vec::new();
-    let mut problems = Vec::new();
-    let mut preview = Vec::new();
-
-    for base_key in base_keys {
-        let raw_list = raw_map.get(&base_key).cloned().unwrap_or_default();
-        let graded_list = graded_map.get(&base_key).cloned().unwrap_or_default();
-
-        let raw_missing = raw_list.is_empty();
-        let graded_missing = graded_list.is_empty();
-
-        let (raw, raw_problem) = select_unique(&base_key, &raw_list, "DUPLICATE_RAW");
-        let (graded, graded_problem) = select_unique(&base_key, &graded_list, "DUPLICATE_GRADED");
-
-        if let Some(problem) = raw_problem.as_ref() {
-            problems.push(problem.clone());
-        }
-        if let Some(problem) = graded_problem.as_ref() {
-            problems.push(problem.clone());
-        }
-
-        let mut status = "OK".to_string();
-        let mut problem_type = None;
-        let mut details = None;
-
-        if raw_missing {
-            status = "PROBLEM".to_string();
-            problem_type = Some("RAW_MISSING".to_string());
-            details = Some("No raw survey folder found.".to_string());
-            problems.push(ProblemItem {
-                survey_id_base: base_key.clone(),
-                survey_id_detected: graded
-                    .as_ref()
-                    .and_then(|folder| folder.detected_id.clone()),
-                raw_path: None,
-                graded_path: graded
-                    .as_ref()
-                    .map(|folder| folder.path.to_string_lossy().to_string()),
-                problem_type: "RAW_MISSING".to_string(),
-                details: None,
-            });
-        }
-
-        if graded_missing {
-            status = "PROBLEM".to_string();
-            problem_type = Some("GRADED_MISSING".to_string());
-            details = Some("No graded survey folder found.".to_string());
-            problems.push(ProblemItem {
-                survey_id_base: base_key.clone(),
-                survey_id_detected: raw.as_ref().and_then(|folder| folder.detected_id.clone()),
-                raw_path: raw
-                    .as_ref()
-                    .map(|folder| folder.path.to_string_lossy().to_string()),
-                graded_path: None,
-                problem_type: "GRADED_MISSING".to_string(),
-                details: None,
-            });
-        }
-
-        if raw_problem.is_some() || graded_problem.is_some() {
-            status = "PROBLEM".to_string();
-            if problem_type.is_none() {
-                problem_type = raw_problem
-                    .as_ref()
-                    .map(|problem| problem.problem_type.clone())
-                    .or_else(|| {
-                        graded_problem
-                            .as_ref()
-                            .map(|problem| problem.problem_type.clone())
-                    });
-                details = raw_problem
-                    .as_ref()
-                    .and_then(|problem| problem.details.clone())
-                    .or_else(|| {
-                        graded_problem
-                            .as_ref()
-                            .and_then(|problem| problem.details.clone())
-                    });
-            }
-        }
-
-        let (raw_count, graded_count) = if include_counts {
-            let raw_count = raw
-                .as_ref()
-                .map(|folder| count_images(&folder.path, rules))
-                .transpose()?;
-            let graded_count = graded
-                .as_ref()
-                .map(|folder| count_images(&folder.path, rules))
-                .transpose()?;
-            (raw_count, graded_count)
+/// Where the pipeline runs: its data folder with rules, settings and
+/// workspaces, its cached state and the `ProgressSink` its events go to.
+/// The desktop app keeps one in Tauri's managed state; the CLI, benchmarks
+/// and tests create headless ones.
+#[derive(Clone)]
+pub struct Host(Arc<HostInner>);
+
+struct HostInner {
+    data_dir: PathBuf,
+    state: AppState,
+    sink: Option<Arc<dyn ProgressSink>>,
+    /// Preview counts are sent to the sink after the preview returned
+    /// instead of being filled in before.
+    background_counts: bool,
+}
+
+impl Host {
+    /// Headless host keeping rules, settings and workspaces in `data_dir`;
+    /// `progress` prints per-file progress lines to stderr.
+    pub fn headless(data_dir: PathBuf, progress: bool) -> Self {
+        let sink: Option<Arc<dyn ProgressSink>> = if progress {
+            Some(Arc::new(StderrProgress))
         } else {
-            (None, None)
+            None
         };
+        Host::new(data_dir, sink, false)
+    }
 
-        let preview_item = PreviewItem {
-            base_key: base_key.clone(),
-            raw_path: raw
-                .as_ref()
-                .map(|folder| folder.path.to_string_lossy().to_string()),
-            graded_path: graded
-                .as_ref()
-                .map(|folder| folder.path.to_string_lossy().to_string()),
-            status: status.clone(),
-            problem_type: problem_type.clone(),
-            details: details.clone(),
-            raw_image_count: raw_count,
-            graded_image_count: graded_count,
-            survey_id_raw_detected: raw.as_ref().and_then(|folder| folder.detected_id.clone()),
-            survey_id_graded_detected: graded
-                .as_ref()
-                .and_then(|folder| folder.detected_id.clone()),
-        };
+    /// Headless host handing its events to `sink`.
+    pub fn headless_with_sink(data_dir: PathBuf, sink: Arc<dyn ProgressSink>) -> Self {
+        Host::new(data_dir, Some(sink), false)
+    }
 
-        preview.push(preview_item);
-        entries.push(ScanEntry {
-            base_key,
-            raw,
-            graded,
-            status,
-        });
+    /// Host of an interactive frontend: like a headless one, but previews
+    /// return before their images are counted and the counts follow as
+    /// `ProgressSink::counts_updated` events.
+    pub fn interactive(data_dir: PathBuf, sink: Arc<dyn ProgressSink>) -> Self {
+        Host::new(data_dir, Some(sink), true)
     }
 
-    preview.sort_by(|a, b| a.base_key.cmp(&b.base_key));
-    entries.sort_by(|a, b| a.base_key.cmp(&b.base_key));
+    fn new(
+        data_dir: PathBuf,
+        sink: Option<Arc<dyn ProgressSink>>,
+        background_counts: bool,
+    ) -> Self {
+        Host(Arc::new(HostInner {
+            data_dir,
+            state: AppState::default(),
+            sink,
+            background_counts,
+        }))
+    }
 
-    Ok(ScanResult {
-        entries,
-        problems,
-        preview,
-    })
-}
+    fn state(&self) -> &AppState {
+        &self.0.state
+    }
 
-fn select_unique(
-    base_key: &str,
-    list: &[SurveyFolder],
-    problem_type: &str,
-) -> (Option<SurveyFolder>, Option<ProblemItem>) {
-    if list.len() <= 1 {
-        return (list.first().cloned(), None);
+    fn sink(&self) -> Option<&Arc<dyn ProgressSink>> {
+        self.0.sink.as_ref()
     }
-    let detail = list
-        .iter()
-        .map(|item| item.path.to_string_lossy().to_string())
-        .collect::<Vec<_>>()
-        .join("; ");
-    (
-        None,
-        Some(ProblemItem {
-            survey_id_base: base_key.to_string(),
-            survey_id_detected: list.first().and_then(|folder| folder.detected_id.clone()),
-            raw_path: None,
-            graded_path: None,
-            problem_type: problem_type.to_string(),
-            details: Some(detail),
-        }),
-    )
-}
 
-fn discover_surveys(
-    root: &Path,
-    rules: &CompiledRules,
-) -> Result<HashMap<String, Vec<SurveyFolder>>, AppError> {
-    let mut map: HashMap<String, Vec<SurveyFolder>> = HashMap::new();
-    let mut walker = WalkDir::new(root).into_iter();
-    while let Some(entry) = walker.next() {
-        let Ok(entry) = entry else {
-            continue;
-        };
-        if !entry.file_type().is_dir() {
-            continue;
-        }
-        let path = entry.path();
-        let detected_id = extract_detected_id(path, &rules.detected_re);
-        let base_key = detected_id
-            .as_ref()
-            .and_then(|detected| extract_base_key(detected, &rules.base_re))
-            .or_else(|| {
-                let path_str = path.to_string_lossy();
-                extract_base_key(&path_str, &rules.base_re)
-            });
-        if let Some(base_key) = base_key {
-            map.entry(base_key).or_default().push(SurveyFolder {
-                path: path.to_path_buf(),
-                detected_id,
-            });
-            walker.skip_current_dir();
+    fn progress(&self, event: ProgressEvent) {
+        if let Some(sink) = self.sink() {
+            sink.progress(event);
         }
     }
-    Ok(map)
-}
 
-fn count_images(root: &Path, rules: &CompiledRules) -> Result<u64, AppError> {
-    let mut count = 0u64;
-    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
-        if !entry.file_type().is_file() {
-            continue;
-        }
-        if is_supported_image(entry.path(), rules) {
-            count += 1;
+    fn queue_updated(&self, run: &QueuedRun) {
+        if let Some(sink) = self.sink() {
+            sink.queue_updated(run);
         }
     }
-    Ok(count)
-}
 
-fn is_supported_image(path: &Path, rules: &CompiledRules) -> bool {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| {
-            rules
-                .extensions
-                .contains(&format!(".{}", ext.to_lowercase()))
-        })
-        .unwrap_or(false)
-}
-
-fn process_pair(
-    app: &AppHandle,
-    rules: &CompiledRules,
-    base_key: &str,
-    raw: &SurveyFolder,
-    graded: &SurveyFolder,
-) -> Result<PairResult, AppError> {
-    let graded_result = build_graded_map(&graded.path, rules)?;
-    let graded_map = graded_result.map;
-    let raw_files = collect_images(&raw.path, rules)?;
-    let total = raw_files.len() as u64;
-
-    let mut rows = Vec::new();
-    let mut ambiguity_warnings = graded_result.ambiguity_warnings;
-    for (index, raw_path) in raw_files.into_iter().enumerate() {
-        let (file_id, ambiguous) = compute_file_id(&raw_path, rules);
-        if ambiguous {
-            ambiguity_warnings += 1;
+    fn survey_updated(&self, event: SurveyUpdateEvent) {
+        if let Some(sink) = self.sink() {
+            sink.survey_updated(event);
         }
-        let candidates = graded_map.get(&file_id).cloned().unwrap_or_default();
-        let winner = select_winner(&candidates, rules);
-        let (dolphin, graded_relpath, winner_type) = if candidates.is_empty() {
-            (0u8, "RAW".to_string(), "RAW".to_string())
-        } else {
-            let has_negative = any_token_match(&candidates, &rules.negative_tokens);
-            let positive_ok = if rules.positive_tokens.is_empty()
-                || rules.positive_tokens.iter().any(|token| token == "*")
-            {
-                true
-            } else {
-                any_token_match(&candidates, &rules.positive_tokens)
-            };
-            let dolphin = if !has_negative && positive_ok {
-                1u8
-            } else {
-                0u8
-            };
-            (
-                dolphin,
-                winner
-                    .as_ref()
-                    .map(|value| value.relpath.clone())
-                    .unwrap_or_else(|| "RAW".to_string()),
-                winner
-                    .as_ref()
-                    .map(|value| value.winner_type.clone())
-                    .unwrap_or_else(|| "RAW".to_string()),
-            )
-        };
-
-        let raw_relpath = normalize_relpath(&raw_path, &raw.path);
-        let filename = raw_path
-            .file_name()
-            .and_then(|name| name.to_str())
-            .unwrap_or_default()
-            .to_string();
-
-        rows.push(CsvRow {
-            survey_id_base: base_key.to_string(),
-            raw_relpath,
-            filename,
-            dolphin,
-            graded_relpath,
-            graded_hits: candidates.len() as u64,
-            graded_winner_type: winner_type,
-            survey_id_raw_detected: raw.detected_id.clone(),
-            survey_id_graded_detected: graded.detected_id.clone(),
-        });
-
-        let _ = app.emit(
-            "progress",
-            ProgressEvent {
-                survey_id_base: base_key.to_string(),
-                processed: (index as u64) + 1,
-                total,
-            },
-        );
     }
 
-    Ok(PairResult {
-        rows,
-        ambiguity_warnings,
-    })
-}
-
-fn collect_images(root: &Path, rules: &CompiledRules) -> Result<Vec<PathBuf>, AppError> {
-    let mut files = Vec::new();
-    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
-        if !entry.file_type().is_file() {
-            continue;
-        }
-        if is_supported_image(entry.path(), rules) {
-            files.push(entry.path().to_path_buf());
+    fn run_paused(&self, event: RunPauseEvent) {
+        if let Some(sink) = self.sink() {
+            sink.run_paused(event);
         }
     }
-    files.sort();
-    Ok(files)
 }
 
-fn build_graded_map(
-    graded_root: &Path,
-    rules: &CompiledRules,
-) -> Result<GradedMapResult, AppError> {
-    let mut map: HashMap<String, Vec<String>> = HashMap::new();
-    let mut ambiguity_warnings = 0u64;
-    for entry in WalkDir::new(graded_root).into_iter().filter_map(Result::ok) {
-        if !entry.file_type().is_file() {
-            continue;
+/// How often `progress` events are emitted while a survey is processed. The
+/// last file of a survey is always reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ProgressGranularity {
+    #[default]
+    EveryFile,
+    EveryN {
+        files: u64,
+    },
+    Percent {
+        step: u64,
+    },
+}
+
+impl ProgressGranularity {
+    fn should_emit(self, processed: u64, total: u64) -> bool {
+        if processed >= total {
+            return true;
         }
-        if !is_supported_image(entry.path(), rules) {
-            continue;
-        }
-        let (file_id, ambiguous) = compute_file_id(entry.path(), rules);
-        if ambiguous {
-            ambiguity_warnings += 1;
+        match self {
+            Self::EveryFile => true,
+            Self::EveryN { files } => processed.is_multiple_of(files.max(1)),
+            Self::Percent { step } => {
+                let step = step.max(1);
+                let bucket = |done: u64| done * 100 / total / step;
+                bucket(processed) > bucket(processed - 1)
+            }
         }
-        let relpath = normalize_relpath(entry.path(), graded_root);
-        map.entry(file_id).or_default().push(relpath);
     }
-    Ok(GradedMapResult {
-        map,
-        ambiguity_warnings,
-    })
 }
 
-fn compute_file_id(path: &Path, rules: &CompiledRules) -> (String, bool) {
-    let filename = path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or_default()
-        .to_string();
-    let stem = path
-        .file_stem()
-        .and_then(|name| name.to_str())
-        .unwrap_or_default();
-    if let Some(captures) = rules.image_id_re.captures(stem) {
-        if let Some(matched) = captures.get(1) {
-            return (matched.as_str().to_lowercase(), false);
+/// Stage of a run named by each `progress` event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunPhase {
+    /// Pairing the survey folders below the roots.
+    Discovering,
+    /// Listing the graded images of a survey.
+    IndexingGraded,
+    /// Labeling the raw images of a survey; `processed` counts them.
+    #[default]
+    Matching,
+    /// Writing the run's outputs after the last survey.
+    Writing,
+}
+
+impl RunPhase {
+    fn as_str(self) -> &'static str {
+        match self {
+            RunPhase::Discovering => "discovering",
+            RunPhase::IndexingGraded => "indexing graded",
+            RunPhase::Matching => "matching",
+            RunPhase::Writing => "writing",
         }
     }
-    let filename_lower = filename.to_lowercase();
-    match fs::metadata(path) {
-        Ok(metadata) => (format!("{}|{}", filename_lower, metadata.len()), false),
-        Err(_) => (filename_lower, true),
-    }
-}
-
-fn select_winner(candidates: &[String], rules: &CompiledRules) -> Option<CandidateWinner> {
-    if candidates.is_empty() {
-        return None;
-    }
-
-    let mut scored: Vec<(u8, usize, String, String)> = candidates
-        .iter()
-        .map(|candidate| {
-            let winner_type = classify_candidate(candidate, rules);
-            let priority = match winner_type.as_str() {
-                "IND" => 1u8,
-                "SECONDARY" => 2u8,
-                _ => 99u8,
-            };
-            (priority, candidate.len(), candidate.clone(), winner_type)
-        })
-        .collect();
-
-    scored.sort_by(|a, b| {
-        a.0.cmp(&b.0)
-            .then_with(|| a.1.cmp(&b.1))
-            .then_with(|| a.2.cmp(&b.2))
-    });
-
-    scored.first().map(|item| CandidateWinner {
-        relpath: item.2.clone(),
-        winner_type: item.3.clone(),
-    })
-}
-
-fn classify_candidate(candidate: &str, rules: &CompiledRules) -> String {
-    let lower = candidate.to_lowercase();
-    if rules.ind_re.is_match(&lower) {
-        return "IND".to_string();
-    }
-    if rules
-        .secondary_tokens
-        .iter()
-        .any(|token| lower.contains(token))
-    {
-        return "SECONDARY".to_string();
-    }
-    "OTHER".to_string()
 }
 
-fn any_token_match(candidates: &[String], tokens: &[String]) -> bool {
-    if tokens.is_empty() {
-        return false;
-    }
-    if tokens.iter().any(|token| token == "*") {
-        return true;
-    }
-    candidates.iter().any(|candidate| {
-        let lower = candidate.to_lowercase();
-        tokens.iter().any(|token| lower.contains(token))
-    })
-}
-
-fn normalize_relpath(path: &Path, root: &Path) -> String {
-    let rel = path.strip_prefix(root).unwrap_or(path);
-    let rel_str = rel.to_string_lossy().to_string();
-    rel_str.replace('\\', "/")
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProgressEvent {
+    /// Run the event belongs to; empty for watches.
+    #[serde(default)]
+    pub run_id: String,
+    #[serde(default)]
+    pub phase: RunPhase,
+    pub survey_id_base: String,
+    /// Files of the current survey done so far and in total.
+    pub processed: u64,
+    pub total: u64,
+    /// The current survey, counting from 1, and the surveys of the whole run.
+    #[serde(default)]
+    pub survey_index: u64,
+    #[serde(default)]
+    pub survey_count: u64,
+    /// Seconds until the last survey is done, once enough work is finished to
+    /// tell.
+    #[serde(default)]
+    pub eta_seconds: Option<u64>,
+}
+
+/// Status of one survey whose folders changed while a watch was running,
+/// emitted as `survey-updated`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SurveyUpdateEvent {
+    pub watch_id: String,
+    pub survey_id_base: String,
+    /// Pairing status from the scan (`OK` or `PROBLEM`), `REMOVED` once
+    /// neither root has a folder for the survey any more, or `SCAN_ERROR`
+    /// when a folder of the roots could not be read, with the reason in
+    /// `error`; `survey_id_base` is empty when the folder is not a survey's.
+    pub status: String,
+    pub raw_path: Option<String>,
+    pub graded_path: Option<String>,
+    /// Matching results; only filled in for `OK` surveys.
+    pub rows: u64,
+    pub dolphin_yes: u64,
+    pub dolphin_no: u64,
+    pub ambiguity_warnings: u64,
+    /// Set when matching the survey failed.
+    pub error: Option<String>,
 }
 
-fn init_csv_writer(path: &Path) -> Result<csv::Writer<fs::File>, AppError> {
-    let mut writer = WriterBuilder::new().has_headers(true).from_path(path)?;
-    writer.write_record([
-        "survey_id_base",
-        "raw_relpath",
-        "filename",
-        "dolphin",
-        "graded_relpath",
-        "graded_hits",
-        "graded_winner_type",
-        "survey_id_raw_detected",
-        "survey_id_graded_detected",
-    ])?;
-    Ok(writer)
+/// A run paused with `pause_runs` or resumed with `resume_runs`, emitted as
+/// `run-paused`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunPauseEvent {
+    pub run_id: String,
+    pub paused: bool,
 }
 
-fn write_rows_to_writer(
-    writer: &mut csv::Writer<fs::File>,
-    rows: &[CsvRow],
-) -> Result<(), AppError> {
-    for row in rows {
-        writer.write_record([
-            row.survey_id_base.as_str(),
-            row.raw_relpath.as_str(),
-            row.filename.as_str(),
-            &row.dolphin.to_string(),
-            row.graded_relpath.as_str(),
-            &row.graded_hits.to_string(),
-            row.graded_winner_type.as_str(),
-            row.survey_id_raw_detected.as_deref().unwrap_or(""),
-            row.survey_id_graded_detected.as_deref().unwrap_or(""),
-        ])?;
-    }
-    writer.flush()?;
-    Ok(())
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountUpdateEvent {
+    pub base_key: String,
+    pub raw_image_count: Option<u64>,
+    pub graded_image_count: Option<u64>,
 }
 
-fn write_csv_rows(path: &Path, rows: &[CsvRow]) -> Result<(), AppError> {
-    let mut writer = init_csv_writer(path)?;
-    write_rows_to_writer(&mut writer, rows)
+fn app_data_dir(host: &Host) -> Result<PathBuf, AppError> {
+    Ok(host.0.data_dir.clone())
 }
 
-fn write_problems_csv(path: &Path, problems: &[ProblemItem]) -> Result<(), AppError> {
-    let mut writer = WriterBuilder::new().has_headers(true).from_path(path)?;
-    writer.write_record([
-        "survey_id_base",
-        "survey_id_detected",
-        "raw_path",
-        "graded_path",
-        "problem_type",
-        "details",
-    ])?;
-    for problem in problems {
-        writer.write_record([
-            problem.survey_id_base.as_str(),
-            problem.survey_id_detected.as_deref().unwrap_or(""),
-            problem.raw_path.as_deref().unwrap_or(""),
-            problem.graded_path.as_deref().unwrap_or(""),
-            problem.problem_type.as_str(),
-            problem.details.as_deref().unwrap_or(""),
-        ])?;
-    }
-    writer.flush()?;
-    Ok(())
+/// Unique, sortable id for a run: start time in milliseconds plus a counter.
+fn new_run_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "{}-{:04}",
+        unix_millis(),
+        COUNTER.fetch_add(1, Ordering::Relaxed) % 10_000
+    )
 }
 
-#[derive(Clone, Debug)]
-struct ScanEntry {
-    base_key: String,
-    raw: Option<SurveyFolder>,
-    graded: Option<SurveyFolder>,
-    status: String,
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
 }
 
-#[derive(Clone, Debug)]
-struct ScanResult {
-    entries: Vec<ScanEntry>,
-    problems: Vec<ProblemItem>,
-    preview: Vec<PreviewItem>,
+fn default_true() -> bool {
+    true
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn extract_detected_and_base() {
-        let rules = Rules {
-            extensions: vec![".jpg".to_string()],
-            survey_id_regex_detected: "(?i)\\b(\\d{8}_[A-Z]{2}(?:_[A-Z]{2})?)\\b".to_string(),
-            survey_id_regex_base: "(?i)\\b(\\d{8}_[A-Z]{2})(?:_[A-Z]{2})?\\b".to_string(),
-            image_id_regex: default_image_id_regex(),
-            graded_priority_ind_regex: "(?i)\\bind".to_string(),
-            graded_priority_secondary_tokens: vec!["best".to_string()],
-            graded_negative_contains_any: vec![],
-            graded_positive_contains_any: vec!["*".to_string()],
-        };
-        let compiled = compile_rules(&rules).expect("compile");
+    use std::collections::BTreeMap;
+    use std::fs;
 
-        let path = PathBuf::from("/data/20250101_AB_CD/some");
-        let detected = extract_detected_id(&path, &compiled.detected_re).expect("detected");
-        let base = extract_base_key(&detected, &compiled.base_re).expect("base");
-        assert_eq!(detected, "20250101_AB_CD");
-        assert_eq!(base, "20250101_AB");
-    }
+    use crate::output::row_headers;
+    use crate::test_support::TestTree;
 
     #[test]
-    fn winner_selection_prefers_ind_then_secondary() {
-        let rules = Rules {
-            extensions: vec![".jpg".to_string()],
-            survey_id_regex_detected: "x".to_string(),
-            survey_id_regex_base: "x".to_string(),
-            image_id_regex: default_image_id_regex(),
-            graded_priority_ind_regex: "(?i)\\bind".to_string(),
-            graded_priority_secondary_tokens: vec!["best".to_string()],
-            graded_negative_contains_any: vec![],
-            graded_positive_contains_any: vec!["*".to_string()],
+    fn progress_granularity_limits_events() {
+        let emitted = |granularity: ProgressGranularity, total: u64| {
+            (1..=total)
+                .filter(|processed| granularity.should_emit(*processed, total))
+                .collect::<Vec<_>>()
         };
-        let compiled = compile_rules(&rules).expect("compile");
-        let candidates = vec![
-            "alpha/best/image.jpg".to_string(),
-            "beta/ind/image.jpg".to_string(),
-            "gamma/other/image.jpg".to_string(),
-        ];
-        let winner = select_winner(&candidates, &compiled).expect("winner");
-        assert_eq!(winner.relpath, "beta/ind/image.jpg");
-        assert_eq!(winner.winner_type, "IND");
+        assert_eq!(emitted(ProgressGranularity::EveryFile, 3), vec![1, 2, 3]);
+        assert_eq!(
+            emitted(ProgressGranularity::EveryN { files: 4 }, 10),
+            vec![4, 8, 10]
+        );
+        assert_eq!(
+            emitted(ProgressGranularity::Percent { step: 25 }, 8),
+            vec![2, 4, 6, 8]
+        );
+        assert_eq!(
+            emitted(ProgressGranularity::Percent { step: 50 }, 3).len(),
+            2
+        );
     }
 
     #[test]
-    fn file_id_uses_size_when_available() {
-        let rules = Rules {
-            extensions: vec![".jpg".to_string()],
-            survey_id_regex_detected: "x".to_string(),
-            survey_id_regex_base: "x".to_string(),
-            image_id_regex: "^no-match$".to_string(),
-            graded_priority_ind_regex: "(?i)\\bind".to_string(),
-            graded_priority_secondary_tokens: vec!["best".to_string()],
-            graded_negative_contains_any: vec![],
-            graded_positive_contains_any: vec!["*".to_string()],
-        };
-        let compiled = compile_rules(&rules).expect("compile");
-        let temp_dir = std::env::temp_dir().join("survey_labeler_test");
-        let _ = fs::create_dir_all(&temp_dir);
-        let file_path = temp_dir.join("sample.JPG");
-        fs::write(&file_path, b"testdata").expect("write");
+    fn errors_map_to_stable_exit_codes() {
+        let header_error = row_headers(&CsvSettings {
+            header_map: BTreeMap::from([("nope".to_string(), "x".to_string())]),
+            ..CsvSettings::default()
+        })
+        .expect_err("unknown column");
+        assert_eq!(header_error.exit_code(), EXIT_VALIDATION);
+        let io_error = AppError::from(std::io::Error::new(std::io::ErrorKind::NotFound, "gone"));
+        assert_eq!(io_error.exit_code(), EXIT_IO);
+        assert_eq!(
+            AppError::Blocked("blocked".to_string()).exit_code(),
+            EXIT_PROBLEMS
+        );
 
-        let (file_id, ambiguous) = compute_file_id(&file_path, &compiled);
-        assert!(file_id.starts_with("sample.jpg|"));
-        assert!(!ambiguous);
+        let report = serde_json::to_value(header_error.report()).expect("json");
+        assert_eq!(report["exit_code"], 3);
+        assert_eq!(report["kind"], "validation");
+        assert_eq!(
+            report["message"],
+            "Header map refers to unknown column 'nope'."
+        );
     }
 
     #[test]
-    fn file_id_strips_suffix_tokens() {
-        let rules = Rules {
-            extensions: vec![".jpg".to_string()],
-            survey_id_regex_detected: "x".to_string(),
-            survey_id_regex_base: "x".to_string(),
-            image_id_regex: "^(.+?_\\d{3,5})(?:_[A-Za-z0-9]+)*$".to_string(),
-            graded_priority_ind_regex: "(?i)\\bind".to_string(),
-            graded_priority_secondary_tokens: vec!["best".to_string()],
-            graded_negative_contains_any: vec![],
-            graded_positive_contains_any: vec!["*".to_string()],
-        };
-        let compiled = compile_rules(&rules).expect("compile");
-        let file_path = PathBuf::from("/data/20100428_ALA_0449_QP_D.jpg");
-        let (file_id, ambiguous) = compute_file_id(&file_path, &compiled);
-        assert_eq!(file_id, "20100428_ala_0449");
-        assert!(!ambiguous);
+    fn headless_hosts_hand_events_to_their_sink() {
+        #[derive(Default)]
+        struct Collected(Mutex<Vec<ProgressEvent>>);
+
+        impl ProgressSink for Collected {
+            fn progress(&self, event: ProgressEvent) {
+                self.0.lock().expect("events").push(event);
+            }
+        }
+
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB");
+        let graded_dir = root.join("graded").join("20240101_AB");
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(graded_dir.join("IND")).expect("create");
+        fs::write(raw_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        fs::write(raw_dir.join("IMG_0002.JPG"), b"y").expect("write");
+        fs::write(graded_dir.join("IND").join("IMG_0001.JPG"), b"x").expect("write");
+        let sink = Arc::new(Collected::default());
+        let host = Host::headless_with_sink(root.join("data"), sink.clone());
+
+        let options: SingleRunOptions =
+            serde_json::from_value(serde_json::json!({ "output_filename": "single.csv" }))
+                .expect("options");
+        let summary = run_single_pair(
+            &host,
+            graded_dir,
+            raw_dir,
+            root.join("out"),
+            None,
+            options,
+            Rules::default(),
+        )
+        .expect("run");
+
+        let events = sink.0.lock().expect("events");
+        assert!(events.iter().all(|event| event.run_id == summary.run_id));
+        let last_match = events
+            .iter()
+            .rfind(|event| event.phase == RunPhase::Matching)
+            .expect("matching events");
+        assert_eq!(
+            (last_match.survey_id_base.as_str(), last_match.processed),
+            ("20240101_AB", 2)
+        );
+        assert_eq!(
+            events.last().map(|event| event.phase),
+            Some(RunPhase::Writing)
+        );
     }
 }
@@ -1,57 +1,367 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use survey_labeler::{
-    get_or_init_rules, preview_root_scan, reset_rules, run_root_scan, run_single_pair, save_rules,
-    RootRunOptions, Rules, SingleRunOptions,
+    activate_profile, aggregate_runs, cancel_runs, checked_image_path, checked_reveal_path,
+    cleanup_workspaces, delete_profile, diff_runs, enqueue_single_run, estimate_run,
+    export_dataset, generate_synthetic_tree, get_or_init_rules, get_review_queue, get_run,
+    get_settings, get_thumbnail, list_profiles, list_run_queue, list_runs, list_workspaces,
+    load_rules_history, pause_runs, preview_root_scan, remove_queued_run, reset_rules, resume_runs,
+    run_root_scan, run_self_check, run_single_pair, save_profile, save_rules, save_settings,
+    set_manual_label, start_watch, stop_watch, suggest_rules_from_samples, suggest_tokens,
+    validate_rules, AggregateReport, AppSettings, CountUpdateEvent, DatasetExport,
+    DatasetExportOptions, Host, ManualLabel, PreviewOptions, ProgressEvent, ProgressSink,
+    QueuedRun, ReviewQueue, ReviewQueueOptions, RootRunOptions, RuleProfile, Rules, RulesUpdate,
+    RulesValidation, RulesVersion, RunDiff, RunEstimate, RunListItem, RunPauseEvent, RunRecord,
+    SelfCheckReport, SingleRunOptions, SurveyUpdateEvent, SyntheticTree, SyntheticTreeOptions,
+    Thumbnail, WatchOptions, WorkspaceCleanup, WorkspaceInfo,
 };
+use tauri::{Emitter, Manager};
+use tauri_plugin_opener::OpenerExt;
+
+/// Forwards pipeline events to the frontend as Tauri events.
+struct TauriProgress(tauri::AppHandle);
+
+impl ProgressSink for TauriProgress {
+    fn progress(&self, event: ProgressEvent) {
+        let _ = self.0.emit("progress", event);
+    }
+
+    fn survey_updated(&self, event: SurveyUpdateEvent) {
+        let _ = self.0.emit("survey-updated", event);
+    }
+
+    fn queue_updated(&self, run: &QueuedRun) {
+        let _ = self.0.emit("run-queue", run);
+    }
+
+    fn counts_updated(&self, event: CountUpdateEvent) {
+        let _ = self.0.emit("count_update", event);
+    }
+
+    fn run_paused(&self, event: RunPauseEvent) {
+        let _ = self.0.emit("run-paused", event);
+    }
+}
+
+#[tauri::command]
+fn get_config(host: tauri::State<'_, Host>) -> Result<Rules, String> {
+    get_or_init_rules(&host).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn save_config(host: tauri::State<'_, Host>, rules: Rules) -> Result<RulesUpdate, String> {
+    save_rules(&host, rules).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn validate_rules_cmd(rules: Rules) -> RulesValidation {
+    validate_rules(&rules)
+}
+
+#[tauri::command]
+fn reset_config(host: tauri::State<'_, Host>) -> Result<RulesUpdate, String> {
+    reset_rules(&host).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn list_profiles_cmd(host: tauri::State<'_, Host>) -> Result<Vec<RuleProfile>, String> {
+    list_profiles(&host).map_err(|err| err.to_string())
+}
 
 #[tauri::command]
-fn get_config(app: tauri::AppHandle) -> Result<Rules, String> {
-    get_or_init_rules(&app).map_err(|err| err.to_string())
+fn save_profile_cmd(
+    name: String,
+    rules: Rules,
+    host: tauri::State<'_, Host>,
+) -> Result<RulesUpdate, String> {
+    save_profile(&host, &name, rules).map_err(|err| err.to_string())
 }
 
 #[tauri::command]
-fn save_config(app: tauri::AppHandle, rules: Rules) -> Result<Rules, String> {
-    save_rules(&app, rules).map_err(|err| err.to_string())
+fn delete_profile_cmd(
+    name: String,
+    host: tauri::State<'_, Host>,
+) -> Result<Vec<RuleProfile>, String> {
+    delete_profile(&host, &name).map_err(|err| err.to_string())
 }
 
 #[tauri::command]
-fn reset_config(app: tauri::AppHandle) -> Result<Rules, String> {
-    reset_rules(&app).map_err(|err| err.to_string())
+fn activate_profile_cmd(name: String, host: tauri::State<'_, Host>) -> Result<Rules, String> {
+    activate_profile(&host, &name).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn get_settings_cmd(host: tauri::State<'_, Host>) -> Result<AppSettings, String> {
+    get_settings(&host).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn save_settings_cmd(
+    host: tauri::State<'_, Host>,
+    settings: AppSettings,
+) -> Result<AppSettings, String> {
+    save_settings(&host, settings).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn get_rules_history(host: tauri::State<'_, Host>) -> Result<Vec<RulesVersion>, String> {
+    load_rules_history(&host).map_err(|err| err.to_string())
 }
 
 #[tauri::command]
 fn preview_root_scan_cmd(
+    graded_roots: Vec<String>,
+    raw_roots: Vec<String>,
+    options: Option<PreviewOptions>,
+    config: Option<Rules>,
+    host: tauri::State<'_, Host>,
+) -> Result<survey_labeler::PreviewScan, String> {
+    let rules = match config {
+        Some(rules) => rules,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
+    };
+    preview_root_scan(
+        &host,
+        graded_roots.into_iter().map(PathBuf::from).collect(),
+        raw_roots.into_iter().map(PathBuf::from).collect(),
+        options.unwrap_or_default(),
+        rules,
+    )
+    .map_err(|err| err.to_string())
+}
+
+// Counts every image below the roots, so keep it off the main thread.
+#[tauri::command(async)]
+fn estimate_run_cmd(
+    graded_roots: Vec<String>,
+    raw_roots: Vec<String>,
+    options: RootRunOptions,
+    config: Option<Rules>,
+    host: tauri::State<'_, Host>,
+) -> Result<RunEstimate, String> {
+    let rules = match config {
+        Some(rules) => rules,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
+    };
+    estimate_run(
+        &host,
+        graded_roots.into_iter().map(PathBuf::from).collect(),
+        raw_roots.into_iter().map(PathBuf::from).collect(),
+        options,
+        rules,
+    )
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn suggest_tokens_cmd(
     graded_root: String,
-    raw_root: String,
+    limit: Option<usize>,
     config: Option<Rules>,
-    app: tauri::AppHandle,
-) -> Result<Vec<survey_labeler::PreviewItem>, String> {
+    host: tauri::State<'_, Host>,
+) -> Result<Vec<survey_labeler::TokenSuggestion>, String> {
     let rules = match config {
         Some(rules) => rules,
-        None => get_or_init_rules(&app).map_err(|err| err.to_string())?,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
     };
-    preview_root_scan(PathBuf::from(graded_root), PathBuf::from(raw_root), rules)
+    suggest_tokens(PathBuf::from(graded_root), rules, limit.unwrap_or(50))
         .map_err(|err| err.to_string())
 }
 
 #[tauri::command]
-fn run_root_scan_cmd(
+fn suggest_rules(sample_paths: Vec<String>) -> Result<survey_labeler::RulesSuggestion, String> {
+    suggest_rules_from_samples(&sample_paths).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn generate_synthetic_tree_cmd(options: SyntheticTreeOptions) -> Result<SyntheticTree, String> {
+    generate_synthetic_tree(options).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn aggregate_runs_cmd(
+    output_dirs: Vec<String>,
+    report_dir: String,
+    config: Option<Rules>,
+    host: tauri::State<'_, Host>,
+) -> Result<AggregateReport, String> {
+    let rules = match config {
+        Some(rules) => rules,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
+    };
+    let output_dirs = output_dirs
+        .into_iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    aggregate_runs(&output_dirs, PathBuf::from(report_dir), rules).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn cancel_run_cmd(run_id: Option<String>) -> usize {
+    cancel_runs(run_id.as_deref())
+}
+
+#[tauri::command]
+fn pause_run_cmd(host: tauri::State<'_, Host>, run_id: Option<String>) -> usize {
+    pause_runs(&host, run_id.as_deref())
+}
+
+#[tauri::command]
+fn resume_run_cmd(host: tauri::State<'_, Host>, run_id: Option<String>) -> usize {
+    resume_runs(&host, run_id.as_deref())
+}
+
+#[tauri::command]
+fn start_watch_cmd(
     graded_root: String,
     raw_root: String,
+    options: Option<WatchOptions>,
+    config: Option<Rules>,
+    host: tauri::State<'_, Host>,
+) -> Result<String, String> {
+    let rules = match config {
+        Some(rules) => rules,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
+    };
+    start_watch(
+        &host,
+        PathBuf::from(graded_root),
+        PathBuf::from(raw_root),
+        options.unwrap_or_default(),
+        rules,
+    )
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn stop_watch_cmd(watch_id: Option<String>) -> usize {
+    stop_watch(watch_id.as_deref())
+}
+
+#[tauri::command]
+fn run_self_check_cmd(host: tauri::State<'_, Host>) -> Result<SelfCheckReport, String> {
+    run_self_check(&host).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn diff_runs_cmd(
+    old_csv: String,
+    new_csv: String,
+    config: Option<Rules>,
+    host: tauri::State<'_, Host>,
+) -> Result<RunDiff, String> {
+    let rules = match config {
+        Some(rules) => rules,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
+    };
+    diff_runs(PathBuf::from(old_csv), PathBuf::from(new_csv), rules).map_err(|err| err.to_string())
+}
+
+// Copying a season's images takes a while; keep it off the main thread.
+#[tauri::command(async)]
+fn export_dataset_cmd(
+    merged_csv: String,
+    output_dir: String,
+    options: Option<DatasetExportOptions>,
+) -> Result<DatasetExport, String> {
+    export_dataset(
+        PathBuf::from(merged_csv),
+        PathBuf::from(output_dir),
+        options.unwrap_or_default(),
+    )
+    .map_err(|err| err.to_string())
+}
+
+// Decoding a large original takes a moment; keep it off the main thread.
+#[tauri::command(async)]
+fn get_thumbnail_cmd(
+    path: String,
+    max_px: u32,
+    inline: Option<bool>,
+    host: tauri::State<'_, Host>,
+) -> Result<Thumbnail, String> {
+    get_thumbnail(&host, PathBuf::from(path), max_px, inline.unwrap_or(false))
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn get_review_queue_cmd(
+    merged_csv: String,
+    options: Option<ReviewQueueOptions>,
+) -> Result<ReviewQueue, String> {
+    get_review_queue(PathBuf::from(merged_csv), options.unwrap_or_default())
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn set_manual_label_cmd(
+    merged_csv: String,
+    row_id: String,
+    label: Option<u8>,
+    note: Option<String>,
+) -> Result<Option<ManualLabel>, String> {
+    set_manual_label(PathBuf::from(merged_csv), &row_id, label, note).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn reveal_path_cmd(path: String, app: tauri::AppHandle) -> Result<(), String> {
+    let path = checked_reveal_path(&path).map_err(|err| err.to_string())?;
+    app.opener()
+        .reveal_item_in_dir(path)
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn open_image_cmd(
+    path: String,
+    host: tauri::State<'_, Host>,
+    app: tauri::AppHandle,
+) -> Result<(), String> {
+    let path = checked_image_path(&host, &path).map_err(|err| err.to_string())?;
+    app.opener()
+        .open_path(path.to_string_lossy(), None::<&str>)
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn list_runs_cmd(host: tauri::State<'_, Host>) -> Result<Vec<RunListItem>, String> {
+    list_runs(&host).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn get_run_cmd(run_id: String, host: tauri::State<'_, Host>) -> Result<RunRecord, String> {
+    get_run(&host, &run_id).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn list_workspaces_cmd(host: tauri::State<'_, Host>) -> Result<Vec<WorkspaceInfo>, String> {
+    list_workspaces(&host).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn cleanup_workspaces_cmd(host: tauri::State<'_, Host>) -> Result<WorkspaceCleanup, String> {
+    cleanup_workspaces(&host).map_err(|err| err.to_string())
+}
+
+// Runs off the main thread so `cancel_run_cmd` is handled while it works.
+#[tauri::command(async)]
+fn run_root_scan_cmd(
+    graded_roots: Vec<String>,
+    raw_roots: Vec<String>,
     output_dir: String,
     options: RootRunOptions,
     config: Option<Rules>,
-    app: tauri::AppHandle,
+    host: tauri::State<'_, Host>,
 ) -> Result<survey_labeler::RunSummary, String> {
     let rules = match config {
         Some(rules) => rules,
-        None => get_or_init_rules(&app).map_err(|err| err.to_string())?,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
     };
     run_root_scan(
-        &app,
-        PathBuf::from(graded_root),
-        PathBuf::from(raw_root),
+        &host,
+        graded_roots.into_iter().map(PathBuf::from).collect(),
+        raw_roots.into_iter().map(PathBuf::from).collect(),
         PathBuf::from(output_dir),
         options,
         rules,
@@ -59,7 +369,8 @@ fn run_root_scan_cmd(
     .map_err(|err| err.to_string())
 }
 
-#[tauri::command]
+// Runs off the main thread so `cancel_run_cmd` is handled while it works.
+#[tauri::command(async)]
 fn run_single_pair_cmd(
     graded_dir: String,
     raw_dir: String,
@@ -67,14 +378,40 @@ fn run_single_pair_cmd(
     survey_id_override: Option<String>,
     options: SingleRunOptions,
     config: Option<Rules>,
-    app: tauri::AppHandle,
+    host: tauri::State<'_, Host>,
 ) -> Result<survey_labeler::RunSummary, String> {
     let rules = match config {
         Some(rules) => rules,
-        None => get_or_init_rules(&app).map_err(|err| err.to_string())?,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
     };
     run_single_pair(
-        &app,
+        &host,
+        PathBuf::from(graded_dir),
+        PathBuf::from(raw_dir),
+        PathBuf::from(output_dir),
+        survey_id_override,
+        options,
+        rules,
+    )
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn enqueue_single_run_cmd(
+    graded_dir: String,
+    raw_dir: String,
+    output_dir: String,
+    survey_id_override: Option<String>,
+    options: SingleRunOptions,
+    config: Option<Rules>,
+    host: tauri::State<'_, Host>,
+) -> Result<QueuedRun, String> {
+    let rules = match config {
+        Some(rules) => rules,
+        None => get_or_init_rules(&host).map_err(|err| err.to_string())?,
+    };
+    enqueue_single_run(
+        &host,
         PathBuf::from(graded_dir),
         PathBuf::from(raw_dir),
         PathBuf::from(output_dir),
@@ -85,17 +422,67 @@ fn run_single_pair_cmd(
     .map_err(|err| err.to_string())
 }
 
+#[tauri::command]
+fn list_run_queue_cmd() -> Vec<QueuedRun> {
+    list_run_queue()
+}
+
+#[tauri::command]
+fn remove_queued_run_cmd(job_id: String) -> bool {
+    remove_queued_run(&job_id)
+}
+
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_opener::init())
+        .setup(|app| {
+            let data_dir = app.path().app_data_dir()?;
+            let sink = Arc::new(TauriProgress(app.handle().clone()));
+            app.manage(Host::interactive(data_dir, sink));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             get_config,
             save_config,
             reset_config,
+            validate_rules_cmd,
+            get_rules_history,
+            list_profiles_cmd,
+            save_profile_cmd,
+            delete_profile_cmd,
+            activate_profile_cmd,
+            get_settings_cmd,
+            save_settings_cmd,
             preview_root_scan_cmd,
+            estimate_run_cmd,
+            suggest_tokens_cmd,
+            suggest_rules,
+            generate_synthetic_tree_cmd,
             run_root_scan_cmd,
             run_single_pair_cmd,
+            enqueue_single_run_cmd,
+            list_run_queue_cmd,
+            remove_queued_run_cmd,
+            cancel_run_cmd,
+            pause_run_cmd,
+            resume_run_cmd,
+            start_watch_cmd,
+            stop_watch_cmd,
+            aggregate_runs_cmd,
+            run_self_check_cmd,
+            list_runs_cmd,
+            get_run_cmd,
+            diff_runs_cmd,
+            export_dataset_cmd,
+            get_thumbnail_cmd,
+            get_review_queue_cmd,
+            set_manual_label_cmd,
+            reveal_path_cmd,
+            open_image_cmd,
+            list_workspaces_cmd,
+            cleanup_workspaces_cmd,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,2550 @@
+//! Matching of raw images to their graded copies within one survey pair,
+//! producing one result row per raw image.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+
+use crate::classify::{candidate_labels, label_candidates, select_winner};
+use crate::control::CancelToken;
+use crate::output::{normalize_relpath, CsvRow, CsvSettings};
+use crate::paths::long_path;
+use crate::review::ManualLabels;
+use crate::rules::{CandidateDedupe, CollisionPolicy, CompiledRules, MatchingStrategy};
+use crate::runs::RunProgress;
+use crate::scan::{
+    collect_listed_images, drop_shadowed_camera_raws, is_excluded_dir, is_supported_image,
+    list_tree, tree_files, FileStat, ListedFile, ScanCache, SurveyFolder, SurveyPart, WalkError,
+};
+use crate::settings::AppSettings;
+use crate::tracks::SurveyTrack;
+use crate::{
+    archives, unix_millis, AppError, Host, ProgressGranularity, RunPhase, ROW_BATCH_SIZE,
+    STAT_WORKERS,
+};
+
+/// Receives the rows of a survey as `process_pair` produces them.
+type RowSink<'a> = dyn FnMut(CsvRow) -> Result<(), AppError> + 'a;
+
+/// Tallies of the rows a survey produced; the rows themselves are streamed
+/// to a `RowSink`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RowCounts {
+    pub(crate) rows: u64,
+    pub(crate) dolphin_yes: u64,
+    /// Rows with at least one graded candidate.
+    pub(crate) graded_covered: u64,
+}
+
+impl RowCounts {
+    fn add(&mut self, row: &CsvRow) {
+        self.rows += 1;
+        if row.dolphin == 1 {
+            self.dolphin_yes += 1;
+        }
+        if row.graded_hits > 0 {
+            self.graded_covered += 1;
+        }
+    }
+
+    pub(crate) fn dolphin_no(&self) -> u64 {
+        self.rows - self.dolphin_yes
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PairResult {
+    pub(crate) counts: RowCounts,
+    pub(crate) ambiguity_warnings: u64,
+    pub(crate) collapsed_candidates: u64,
+    pub(crate) id_collisions: Vec<FileIdCollision>,
+    pub(crate) perceptual_matches: u64,
+    pub(crate) graded_map_on_disk: bool,
+    pub(crate) walk_errors: Vec<WalkError>,
+    /// File id and relpath of graded images no raw image matched.
+    pub(crate) orphaned_graded: Vec<(String, String)>,
+}
+
+/// Raw images of one survey that share a filename+size fallback id.
+#[derive(Clone, Debug)]
+pub(crate) struct FileIdCollision {
+    pub(crate) file_id: String,
+    pub(crate) raw_relpaths: Vec<String>,
+}
+
+pub(crate) struct GradedMapResult {
+    pub(crate) map: GradedMap,
+    ambiguity_warnings: u64,
+    walk_errors: Vec<WalkError>,
+}
+
+/// Per-survey processing settings shared by root and single runs.
+#[derive(Clone, Debug)]
+pub(crate) struct PairOptions {
+    pub(crate) progress: ProgressGranularity,
+    /// Estimated bytes after which the graded map moves to disk.
+    pub(crate) graded_map_cap: Option<u64>,
+    /// Folder for the graded map's disk table: the run's workspace, or the
+    /// system temp folder when unset.
+    pub(crate) spill_dir: Option<PathBuf>,
+    /// Read EXIF from every raw image; only done when a column needs it.
+    pub(crate) read_exif: bool,
+    pub(crate) track: Option<Arc<SurveyTrack>>,
+    /// Listings and file ids of earlier runs (`incremental_cache`).
+    pub(crate) cache: Option<Arc<ScanCache>>,
+    /// Collect graded images no raw image matched (`write_orphaned_graded`).
+    pub(crate) find_orphans: bool,
+    /// Review-queue labels put over the automatic ones.
+    pub(crate) manual_labels: Option<Arc<ManualLabels>>,
+    /// Position of the survey in its run, for progress events.
+    pub(crate) run: RunProgress,
+    pub(crate) cancel: CancelToken,
+}
+
+impl PairOptions {
+    pub(crate) fn new(
+        progress: ProgressGranularity,
+        csv: &CsvSettings,
+        settings: &AppSettings,
+        cancel: &CancelToken,
+    ) -> Self {
+        Self {
+            progress,
+            graded_map_cap: settings
+                .graded_map_memory_mb
+                .map(|mb| mb.saturating_mul(1024 * 1024)),
+            spill_dir: None,
+            read_exif: csv.include_capture_time
+                || csv.include_exif_gps
+                || csv.include_track_position,
+            track: None,
+            cache: None,
+            find_orphans: false,
+            manual_labels: None,
+            run: RunProgress::default(),
+            cancel: cancel.clone(),
+        }
+    }
+}
+
+/// When and where a `GradedMap` moves to disk.
+#[derive(Clone, Debug)]
+pub(crate) struct GradedSpill {
+    /// Estimated bytes after which the map moves to disk.
+    pub(crate) cap: u64,
+    /// Folder the SQLite file is created in.
+    pub(crate) dir: PathBuf,
+}
+
+/// Graded file id -> candidate relpaths. Kept in memory until its estimated
+/// size passes the cap, then moved to a temporary SQLite file so surveys with
+/// hundreds of thousands of graded files fit on small machines.
+pub(crate) enum GradedMap {
+    Memory {
+        map: GradedCandidates,
+        bytes: u64,
+        spill: Option<GradedSpill>,
+    },
+    Disk(DiskGradedMap),
+}
+
+impl GradedMap {
+    fn new(spill: Option<GradedSpill>) -> Self {
+        GradedMap::Memory {
+            map: GradedCandidates::default(),
+            bytes: 0,
+            spill,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, file_id: String, relpath: String) -> Result<(), AppError> {
+        let spill = match self {
+            GradedMap::Memory { map, bytes, spill } => {
+                *bytes += map.insert(file_id, &relpath);
+                spill.as_ref().is_some_and(|spill| *bytes > spill.cap)
+            }
+            GradedMap::Disk(disk) => {
+                disk.insert(&file_id, &relpath)?;
+                false
+            }
+        };
+        if spill {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<(), AppError> {
+        let GradedMap::Memory {
+            map,
+            spill: Some(spill),
+            ..
+        } = self
+        else {
+            return Ok(());
+        };
+        let mut disk = DiskGradedMap::create(&spill.dir)?;
+        for (file_id, relpath) in map.iter() {
+            disk.insert(file_id, &relpath)?;
+        }
+        *self = GradedMap::Disk(disk);
+        Ok(())
+    }
+
+    /// Ends the bulk load; indexes the disk table.
+    fn finish(&mut self) -> Result<(), AppError> {
+        match self {
+            GradedMap::Memory { .. } => Ok(()),
+            GradedMap::Disk(disk) => disk.finish(),
+        }
+    }
+
+    pub(crate) fn candidates(&self, file_id: &str) -> Result<Vec<String>, AppError> {
+        match self {
+            GradedMap::Memory { map, .. } => Ok(map.candidates(file_id)),
+            GradedMap::Disk(disk) => disk.candidates(file_id),
+        }
+    }
+
+    pub(crate) fn take(&mut self, file_id: &str) -> Result<Vec<String>, AppError> {
+        match self {
+            GradedMap::Memory { map, .. } => Ok(map.remove(file_id)),
+            GradedMap::Disk(disk) => {
+                let candidates = disk.candidates(file_id)?;
+                disk.connection()?
+                    .execute("DELETE FROM graded WHERE file_id = ?1", [file_id])?;
+                Ok(candidates)
+            }
+        }
+    }
+
+    pub(crate) fn is_on_disk(&self) -> bool {
+        matches!(self, GradedMap::Disk(_))
+    }
+
+    /// Candidates held over all file ids.
+    #[cfg(feature = "bench")]
+    pub(crate) fn len(&self) -> Result<usize, AppError> {
+        match self {
+            GradedMap::Memory { map, .. } => Ok(map.len()),
+            GradedMap::Disk(disk) => {
+                Ok(disk
+                    .connection()?
+                    .query_row("SELECT COUNT(*) FROM graded", [], |row| {
+                        row.get::<_, i64>(0)
+                    })? as usize)
+            }
+        }
+    }
+
+    /// File id and relpath of every entry whose id is not in `ids`, sorted by
+    /// relpath.
+    fn entries_except(&self, ids: &HashSet<String>) -> Result<Vec<(String, String)>, AppError> {
+        let mut entries: Vec<(String, String)> = match self {
+            GradedMap::Memory { map, .. } => map
+                .iter()
+                .filter(|(file_id, _)| !ids.contains(*file_id))
+                .map(|(file_id, relpath)| (file_id.to_string(), relpath))
+                .collect(),
+            GradedMap::Disk(disk) => {
+                let conn = disk.connection()?;
+                let mut statement = conn.prepare("SELECT file_id, relpath FROM graded")?;
+                let rows = statement.query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?;
+                let mut entries = Vec::new();
+                for row in rows {
+                    let (file_id, relpath) = row?;
+                    if !ids.contains(&file_id) {
+                        entries.push((file_id, relpath));
+                    }
+                }
+                entries
+            }
+        };
+        entries.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(entries)
+    }
+}
+
+/// In-memory `GradedMap`. Relpaths are stored as an interned folder plus the
+/// file name, and the candidates of a file id are chained through one entry
+/// list instead of a `Vec` each, since most ids have a single candidate and
+/// a survey's graded files sit in a handful of folders.
+#[derive(Default)]
+pub(crate) struct GradedCandidates {
+    pub(crate) folders: Vec<Box<str>>,
+    folder_ids: HashMap<Box<str>, u32>,
+    entries: Vec<GradedEntry>,
+    /// First and last entry of each file id's chain.
+    pub(crate) chains: HashMap<Box<str>, (u32, u32)>,
+}
+
+struct GradedEntry {
+    folder: u32,
+    name: Box<str>,
+    /// Next candidate of the same file id, or `GradedCandidates::END`.
+    next: u32,
+}
+
+impl GradedCandidates {
+    const END: u32 = u32::MAX;
+    /// Rough size of an entry and its share of the hash maps, for the cap.
+    const ENTRY_OVERHEAD: u64 = 40;
+
+    /// Adds a candidate; returns the estimated bytes it takes.
+    pub(crate) fn insert(&mut self, file_id: String, relpath: &str) -> u64 {
+        let (folder, name) = relpath.rsplit_once('/').unwrap_or(("", relpath));
+        let mut bytes = name.len() as u64 + Self::ENTRY_OVERHEAD;
+        let folder = match self.folder_ids.get(folder) {
+            Some(&id) => id,
+            None => {
+                let id = self.folders.len() as u32;
+                self.folders.push(folder.into());
+                self.folder_ids.insert(folder.into(), id);
+                bytes += 2 * folder.len() as u64;
+                id
+            }
+        };
+        let index = self.entries.len() as u32;
+        self.entries.push(GradedEntry {
+            folder,
+            name: name.into(),
+            next: Self::END,
+        });
+        match self.chains.get_mut(file_id.as_str()) {
+            Some((_, last)) => {
+                self.entries[*last as usize].next = index;
+                *last = index;
+            }
+            None => {
+                bytes += file_id.len() as u64;
+                self.chains.insert(file_id.into_boxed_str(), (index, index));
+            }
+        }
+        bytes
+    }
+
+    fn chain_entries(&self, first: u32) -> impl Iterator<Item = &GradedEntry> + '_ {
+        std::iter::successors(self.entries.get(first as usize), |entry| {
+            self.entries.get(entry.next as usize)
+        })
+    }
+
+    fn chain(&self, first: u32) -> impl Iterator<Item = String> + '_ {
+        self.chain_entries(first)
+            .map(|entry| match &*self.folders[entry.folder as usize] {
+                "" => entry.name.to_string(),
+                folder => format!("{}/{}", folder, entry.name),
+            })
+    }
+
+    pub(crate) fn candidates(&self, file_id: &str) -> Vec<String> {
+        match self.chains.get(file_id) {
+            Some(&(first, _)) => self.chain(first).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Drops the chain of `file_id`; its entries stay unused until the map
+    /// is dropped, which only matters for the few ids of id collisions.
+    pub(crate) fn remove(&mut self, file_id: &str) -> Vec<String> {
+        match self.chains.remove(file_id) {
+            Some((first, _)) => self.chain(first).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "bench")]
+    fn len(&self) -> usize {
+        self.chains
+            .values()
+            .map(|&(first, _)| self.chain_entries(first).count())
+            .sum()
+    }
+
+    /// Every file id and candidate relpath, chains in insertion order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, String)> + '_ {
+        self.chains.iter().flat_map(|(file_id, &(first, _))| {
+            self.chain(first)
+                .map(move |relpath| (file_id.as_ref(), relpath))
+        })
+    }
+}
+
+/// Temporary SQLite table backing a spilled `GradedMap`; the file is removed
+/// on drop, and with the run's workspace if the app dies first.
+pub(crate) struct DiskGradedMap {
+    conn: Option<Connection>,
+    path: PathBuf,
+    loading: bool,
+}
+
+impl DiskGradedMap {
+    fn create(dir: &Path) -> Result<Self, AppError> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "survey_labeler_graded_{}_{}_{}.sqlite",
+            std::process::id(),
+            unix_millis(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let conn = Connection::open(&path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = OFF;
+             CREATE TABLE graded (file_id TEXT NOT NULL, relpath TEXT NOT NULL);
+             BEGIN;",
+        )?;
+        Ok(Self {
+            conn: Some(conn),
+            path,
+            loading: true,
+        })
+    }
+
+    pub(crate) fn connection(&self) -> Result<&Connection, AppError> {
+        self.conn
+            .as_ref()
+            .ok_or_else(|| AppError::Message("Graded map database is closed.".to_string()))
+    }
+
+    fn insert(&mut self, file_id: &str, relpath: &str) -> Result<(), AppError> {
+        self.connection()?
+            .prepare_cached("INSERT INTO graded (file_id, relpath) VALUES (?1, ?2)")?
+            .execute([file_id, relpath])?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), AppError> {
+        if self.loading {
+            self.connection()?
+                .execute_batch("COMMIT; CREATE INDEX graded_file_id ON graded (file_id);")?;
+            self.loading = false;
+        }
+        Ok(())
+    }
+
+    fn candidates(&self, file_id: &str) -> Result<Vec<String>, AppError> {
+        let conn = self.connection()?;
+        let mut statement =
+            conn.prepare_cached("SELECT relpath FROM graded WHERE file_id = ?1 ORDER BY rowid")?;
+        let rows = statement.query_map([file_id], |row| row.get::<_, String>(0))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+impl Drop for DiskGradedMap {
+    fn drop(&mut self) {
+        // Close the connection first; Windows cannot delete an open file.
+        drop(self.conn.take());
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The clip frame a video-mode row stands for.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct VideoFrame {
+    pub(crate) frame: u64,
+    /// Offset into the clip; unknown when its frame rate cannot be read.
+    pub(crate) seconds: Option<f64>,
+}
+
+/// What a survey worker thread of `process_pair_within` sends back.
+enum PairMessage {
+    Row(Box<CsvRow>),
+    Done(Result<PairResult, AppError>),
+}
+
+/// Runs `process_pair`, giving up after `timeout`. A timed-out survey yields
+/// `None`; its worker thread stops at its next row, or is left to hang in the
+/// background. Rows reach `sink` on the calling thread either way.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn process_pair_within(
+    host: &Host,
+    rules: &Arc<CompiledRules>,
+    base_key: &str,
+    raw: &SurveyFolder,
+    graded: &SurveyFolder,
+    pair_options: PairOptions,
+    timeout: Option<Duration>,
+    sink: &mut RowSink,
+) -> Result<Option<PairResult>, AppError> {
+    let mut pair_options = pair_options;
+    pair_options.run.ticket = pair_options.run.attempts.ticket();
+    let Some(timeout) = timeout else {
+        return process_pair(host, rules, base_key, raw, graded, pair_options, sink).map(Some);
+    };
+    let attempts = pair_options.run.attempts.clone();
+    let (sender, receiver) = std::sync::mpsc::sync_channel(ROW_BATCH_SIZE);
+    let cancel = pair_options.cancel.clone();
+    let host = host.clone();
+    let rules = Arc::clone(rules);
+    let base_key = base_key.to_string();
+    let raw = raw.clone();
+    let graded = graded.clone();
+    std::thread::spawn(move || {
+        let result = process_pair(
+            &host,
+            &rules,
+            &base_key,
+            &raw,
+            &graded,
+            pair_options,
+            &mut |row| {
+                sender
+                    .send(PairMessage::Row(Box::new(row)))
+                    .map_err(|_| AppError::Cancelled)
+            },
+        );
+        let _ = sender.send(PairMessage::Done(result));
+    });
+    // Time spent paused does not count against the timeout.
+    let started = Instant::now();
+    let paused_before = cancel.paused_for();
+    loop {
+        let paused = cancel.paused_for().saturating_sub(paused_before);
+        let Some(left) = timeout
+            .checked_sub(started.elapsed().saturating_sub(paused))
+            .filter(|left| !left.is_zero())
+        else {
+            // The worker runs on until its next row fails to send; its
+            // progress events are dropped from now on.
+            attempts.advance();
+            return Ok(None);
+        };
+        match receiver.recv_timeout(left) {
+            Ok(PairMessage::Row(row)) => sink(*row)?,
+            Ok(PairMessage::Done(result)) => return result.map(Some),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(AppError::Message(
+                    "Survey worker stopped without a result.".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+/// Labels the raw images of one survey from its graded folder. Rows go to
+/// `sink` one by one, so memory does not grow with the survey's size.
+pub(crate) fn process_pair(
+    host: &Host,
+    rules: &CompiledRules,
+    base_key: &str,
+    raw: &SurveyFolder,
+    graded: &SurveyFolder,
+    pair_options: PairOptions,
+    sink: &mut RowSink,
+) -> Result<PairResult, AppError> {
+    if rules.video_mode {
+        return process_video_pair(host, rules, base_key, raw, graded, pair_options, sink);
+    }
+    pair_options.cancel.check()?;
+    let progress = pair_options.progress;
+    pair_options
+        .run
+        .emit(host, RunPhase::IndexingGraded, base_key, 0, 0);
+    let spill = pair_options.graded_map_cap.map(|cap| GradedSpill {
+        cap,
+        dir: pair_options
+            .spill_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir),
+    });
+    let graded_result = build_graded_map(
+        &graded.path,
+        graded.part.as_ref(),
+        rules,
+        spill.as_ref(),
+        pair_options.cache.as_deref(),
+        &pair_options.cancel,
+    )?;
+    let mut graded_map = graded_result.map;
+    let mut raw_walk_errors = Vec::new();
+    let mut raw_listed = collect_listed_images(
+        &raw.path,
+        raw.part.as_ref(),
+        rules,
+        pair_options.cache.as_deref(),
+        &mut raw_walk_errors,
+    )?;
+    let mut raw_ids = listed_file_ids(&mut raw_listed, rules, pair_options.cache.as_deref());
+    let raw_files: Vec<PathBuf> = raw_listed.into_iter().map(|file| file.path).collect();
+    let total = raw_files.len() as u64;
+    let id_collisions = resolve_id_collisions(
+        &raw_files,
+        &mut raw_ids,
+        &raw.path,
+        &mut graded_map,
+        &graded.path,
+        rules,
+    )?;
+
+    let raw_id_set = if rules.perceptual_fallback || pair_options.find_orphans {
+        raw_ids.iter().map(|(id, _)| id.clone()).collect()
+    } else {
+        HashSet::new()
+    };
+    let mut orphaned_graded = if pair_options.find_orphans {
+        graded_map.entries_except(&raw_id_set)?
+    } else {
+        Vec::new()
+    };
+    let mut perceptual_claims = HashMap::new();
+    if rules.perceptual_fallback {
+        let mut raw_hashes = Vec::new();
+        for (index, (raw_path, (file_id, _))) in raw_files.iter().zip(&raw_ids).enumerate() {
+            pair_options.cancel.check()?;
+            if graded_map.candidates(file_id)?.is_empty() {
+                if let Some(hash) = dhash(raw_path) {
+                    raw_hashes.push((index, hash));
+                }
+            }
+        }
+        if !raw_hashes.is_empty() {
+            let index = build_perceptual_index(
+                graded_map.entries_except(&raw_id_set)?,
+                &graded.path,
+                &pair_options.cancel,
+            )?;
+            perceptual_claims =
+                claim_perceptual_matches(&raw_hashes, &index, rules.perceptual_max_distance);
+        }
+        // A graded copy matched by its picture has a raw image after all.
+        let claimed: HashSet<&String> = perceptual_claims.values().collect();
+        orphaned_graded.retain(|(_, relpath)| !claimed.contains(relpath));
+    }
+    let mut perceptual_matches = 0u64;
+
+    let mut counts = RowCounts::default();
+    let mut ambiguity_warnings = graded_result.ambiguity_warnings;
+    let mut walk_errors = raw_walk_errors;
+    walk_errors.extend(graded_result.walk_errors.iter().cloned());
+    let mut collapsed_candidates = 0u64;
+    let (survey_date, area) = survey_fields(base_key, rules);
+    let notes = load_survey_notes(&[&raw.path, &graded.path], rules)?;
+    for (index, (raw_path, (file_id, ambiguous))) in raw_files.into_iter().zip(raw_ids).enumerate()
+    {
+        pair_options.cancel.check()?;
+        if ambiguous {
+            ambiguity_warnings += 1;
+        }
+        let mut candidates = graded_map.candidates(&file_id)?;
+        let mut perceptual = false;
+        if let Some(relpath) = perceptual_claims.remove(&index) {
+            candidates = vec![relpath];
+            perceptual_matches += 1;
+            perceptual = true;
+        }
+        if rules.dedupe != CandidateDedupe::Off && candidates.len() > 1 {
+            let before = candidates.len();
+            candidates = dedupe_candidates(candidates, &graded.path, rules);
+            collapsed_candidates += (before - candidates.len()) as u64;
+        }
+        let labeling = label_candidates(&candidates, ambiguous || perceptual, &graded.path, rules);
+        let raw_relpath = normalize_relpath(&raw_path, &raw.path);
+        let filename = raw_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let note = frame_number(&raw_path).and_then(|frame| notes.get(&frame).cloned());
+        let exif = if pair_options.read_exif {
+            read_image_exif(&raw_path)
+        } else {
+            Vec::new()
+        };
+        let capture_datetime = exif.iter().find_map(exif_capture_datetime);
+
+        emit_row(
+            CsvRow {
+                survey_id_base: base_key.to_string(),
+                raw_relpath,
+                filename,
+                dolphin: labeling.dolphin,
+                labels: labeling.labels,
+                graded_relpath: labeling.graded_relpath,
+                graded_hits: candidates.len() as u64,
+                graded_winner_type: labeling.winner_type,
+                survey_id_raw_detected: raw.detected_id.clone(),
+                survey_id_graded_detected: graded.detected_id.clone(),
+                winner: labeling.winner,
+                raw_path,
+                survey_date: survey_date.clone(),
+                area: area.clone(),
+                notes: note,
+                quality: labeling.quality,
+                label_confidence: labeling.confidence,
+                decision_reason: labeling.decision_reason,
+                track_position: pair_options
+                    .track
+                    .as_ref()
+                    .zip(capture_datetime.as_deref())
+                    .and_then(|(track, time)| track.position_for(time)),
+                capture_datetime,
+                gps: exif.iter().find_map(exif_gps),
+                video_frame: None,
+                manual_label: false,
+            },
+            &pair_options,
+            &mut counts,
+            sink,
+        )?;
+
+        let processed = (index as u64) + 1;
+        if progress.should_emit(processed, total) {
+            pair_options
+                .run
+                .emit(host, RunPhase::Matching, base_key, processed, total);
+        }
+    }
+
+    Ok(PairResult {
+        counts,
+        ambiguity_warnings,
+        collapsed_candidates,
+        id_collisions,
+        perceptual_matches,
+        graded_map_on_disk: graded_map.is_on_disk(),
+        walk_errors,
+        orphaned_graded,
+    })
+}
+
+/// Puts the review-queue label over `row`, counts it and hands it to `sink`.
+fn emit_row(
+    mut row: CsvRow,
+    pair_options: &PairOptions,
+    counts: &mut RowCounts,
+    sink: &mut RowSink,
+) -> Result<(), AppError> {
+    if let Some(manual_labels) = &pair_options.manual_labels {
+        manual_labels.apply(&mut row);
+    }
+    counts.add(&row);
+    sink(row)
+}
+
+/// `process_pair` in video mode: one row per clip frame that graded stills
+/// were grabbed from, labeled from all stills of that frame, and a
+/// `dolphin` = 0 row for each clip without stills. Stills whose clip is not
+/// in the raw folder count as orphaned graded images.
+fn process_video_pair(
+    host: &Host,
+    rules: &CompiledRules,
+    base_key: &str,
+    raw: &SurveyFolder,
+    graded: &SurveyFolder,
+    pair_options: PairOptions,
+    sink: &mut RowSink,
+) -> Result<PairResult, AppError> {
+    let cache = pair_options.cache.as_deref();
+    let cancel = &pair_options.cancel;
+    let mut walk_errors = Vec::new();
+    let mut clips = HashMap::new();
+    let raw_excluded = |dir: &Path| is_excluded_dir(&raw.path, dir, rules);
+    for path in tree_files(
+        &raw.path,
+        rules,
+        cache,
+        raw_excluded,
+        cancel,
+        &mut walk_errors,
+    )? {
+        if !is_video(&path, rules)
+            || raw
+                .part
+                .as_ref()
+                .is_some_and(|part| !part.contains(&path, rules))
+        {
+            continue;
+        }
+        if let Some(stem) = path.file_stem() {
+            clips
+                .entry(stem.to_string_lossy().to_lowercase())
+                .or_insert(path);
+        }
+    }
+
+    pair_options
+        .run
+        .emit(host, RunPhase::IndexingGraded, base_key, 0, 0);
+    // `None` stands for a whole clip that no still was grabbed from.
+    let mut frames: BTreeMap<(String, Option<u64>), Vec<String>> = BTreeMap::new();
+    let mut orphaned_graded = Vec::new();
+    let graded_excluded = |dir: &Path| is_excluded_graded_dir(&graded.path, dir, rules);
+    for path in tree_files(
+        &graded.path,
+        rules,
+        cache,
+        graded_excluded,
+        cancel,
+        &mut walk_errors,
+    )? {
+        cancel.check()?;
+        if !is_supported_image(&path, rules)
+            || graded
+                .part
+                .as_ref()
+                .is_some_and(|part| !part.contains(&path, rules))
+        {
+            continue;
+        }
+        let relpath = normalize_relpath(&path, &graded.path);
+        match video_frame_of(&path, rules) {
+            Some((clip, frame)) if clips.contains_key(&clip) => {
+                frames.entry((clip, Some(frame))).or_default().push(relpath)
+            }
+            _ if pair_options.find_orphans => {
+                orphaned_graded.push((compute_file_id(&path, rules).0, relpath));
+            }
+            _ => {}
+        }
+    }
+
+    let graded_clips: HashSet<&String> = frames.keys().map(|(clip, _)| clip).collect();
+    let stillless: Vec<String> = clips
+        .keys()
+        .filter(|clip| !graded_clips.contains(clip))
+        .cloned()
+        .collect();
+    frames.extend(stillless.into_iter().map(|clip| ((clip, None), Vec::new())));
+
+    let total = frames.len() as u64;
+    let (survey_date, area) = survey_fields(base_key, rules);
+    let notes = load_survey_notes(&[&raw.path, &graded.path], rules)?;
+    let mut frame_rates: HashMap<String, Option<f64>> = HashMap::new();
+    let mut collapsed_candidates = 0u64;
+    let mut counts = RowCounts::default();
+    for (index, ((clip, frame), mut candidates)) in frames.into_iter().enumerate() {
+        cancel.check()?;
+        let clip_path = &clips[&clip];
+        if rules.dedupe != CandidateDedupe::Off && candidates.len() > 1 {
+            let before = candidates.len();
+            candidates = dedupe_candidates(candidates, &graded.path, rules);
+            collapsed_candidates += (before - candidates.len()) as u64;
+        }
+        let labeling = label_candidates(&candidates, false, &graded.path, rules);
+        let clip_relpath = normalize_relpath(clip_path, &raw.path);
+        let video_frame = frame.map(|frame| {
+            let frame_rate = *frame_rates
+                .entry(clip)
+                .or_insert_with(|| video_frame_rate(clip_path));
+            VideoFrame {
+                frame,
+                seconds: frame_rate.map(|fps| frame as f64 / fps),
+            }
+        });
+        emit_row(
+            CsvRow {
+                survey_id_base: base_key.to_string(),
+                raw_relpath: match frame {
+                    Some(frame) => format!("{}#frame={}", clip_relpath, frame),
+                    None => clip_relpath,
+                },
+                filename: clip_path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                dolphin: labeling.dolphin,
+                labels: labeling.labels,
+                graded_relpath: labeling.graded_relpath,
+                graded_hits: candidates.len() as u64,
+                graded_winner_type: labeling.winner_type,
+                survey_id_raw_detected: raw.detected_id.clone(),
+                survey_id_graded_detected: graded.detected_id.clone(),
+                winner: labeling.winner,
+                raw_path: clip_path.clone(),
+                survey_date: survey_date.clone(),
+                area: area.clone(),
+                notes: frame.and_then(|frame| notes.get(&frame).cloned()),
+                quality: labeling.quality,
+                label_confidence: labeling.confidence,
+                decision_reason: labeling.decision_reason,
+                capture_datetime: None,
+                gps: None,
+                track_position: None,
+                video_frame,
+                manual_label: false,
+            },
+            &pair_options,
+            &mut counts,
+            sink,
+        )?;
+
+        let processed = (index as u64) + 1;
+        if pair_options.progress.should_emit(processed, total) {
+            pair_options
+                .run
+                .emit(host, RunPhase::Matching, base_key, processed, total);
+        }
+    }
+
+    Ok(PairResult {
+        counts,
+        ambiguity_warnings: 0,
+        collapsed_candidates,
+        id_collisions: Vec::new(),
+        perceptual_matches: 0,
+        graded_map_on_disk: false,
+        walk_errors,
+        orphaned_graded,
+    })
+}
+
+pub(crate) fn is_video(path: &Path, rules: &CompiledRules) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            rules
+                .video_extensions
+                .contains(&format!(".{}", ext.to_lowercase()))
+        })
+}
+
+/// Lowercase clip stem and frame number of a graded still, from
+/// `video_frame_regex`.
+fn video_frame_of(path: &Path, rules: &CompiledRules) -> Option<(String, u64)> {
+    let stem = path.file_stem()?.to_str()?;
+    let captures = rules.video_frame_re.captures(stem)?;
+    let frame = captures.name("frame")?.as_str().parse().ok()?;
+    Some((captures.name("clip")?.as_str().to_lowercase(), frame))
+}
+
+/// Frames per second of the first video track of an MP4 or MOV clip, from
+/// the track's timescale and the duration of its first samples.
+pub(crate) fn video_frame_rate(path: &Path) -> Option<f64> {
+    let be_u32 = |bytes: Option<&[u8]>| Some(u32::from_be_bytes(bytes?.try_into().ok()?));
+    let moov = read_top_level_box(path, b"moov")?;
+    isobmff_boxes(&moov)
+        .into_iter()
+        .filter(|(kind, _)| kind == b"trak")
+        .find_map(|(_, trak)| {
+            let mdia = child_box(trak, b"mdia")?;
+            if child_box(mdia, b"hdlr")?.get(8..12)? != b"vide" {
+                return None;
+            }
+            // mdhd: version and flags, two dates (64-bit in version 1), timescale.
+            let mdhd = child_box(mdia, b"mdhd")?;
+            let at = if mdhd.first() == Some(&1) { 20 } else { 12 };
+            let timescale = be_u32(mdhd.get(at..at + 4))?;
+            // stts: version and flags, entry count, then sample count and delta.
+            let stbl = child_box(child_box(mdia, b"minf")?, b"stbl")?;
+            let delta = be_u32(child_box(stbl, b"stts")?.get(12..16))?;
+            (timescale > 0 && delta > 0).then(|| f64::from(timescale) / f64::from(delta))
+        })
+}
+
+pub(crate) fn read_exif(path: &Path) -> Option<exif::Exif> {
+    let file = fs::File::open(long_path(path)).ok()?;
+    exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .ok()
+}
+
+/// EXIF blocks of an image. CR2, NEF and ARW are TIFF containers and read
+/// like JPEGs; CR3 keeps its tags in separate TIFF blocks inside the file.
+pub(crate) fn read_image_exif(path: &Path) -> Vec<exif::Exif> {
+    let is_cr3 = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cr3"));
+    if is_cr3 {
+        return cr3_exif(path).unwrap_or_default();
+    }
+    read_exif(path).into_iter().collect()
+}
+
+/// Canon's `uuid` box in a CR3 `moov` box, holding the CMT metadata blocks.
+pub(crate) const CR3_CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+/// The IFD0 (`CMT1`), Exif (`CMT2`) and GPS (`CMT4`) blocks of a CR3 file.
+/// Each is a TIFF of its own whose first IFD holds the tags directly.
+fn cr3_exif(path: &Path) -> Option<Vec<exif::Exif>> {
+    let moov = read_top_level_box(path, b"moov")?;
+    let canon = isobmff_boxes(&moov)
+        .into_iter()
+        .find(|(kind, body)| kind == b"uuid" && body.starts_with(&CR3_CANON_UUID))?
+        .1;
+    Some(
+        isobmff_boxes(&canon[CR3_CANON_UUID.len()..])
+            .into_iter()
+            .filter(|(kind, _)| matches!(kind, b"CMT1" | b"CMT2" | b"CMT4"))
+            .filter_map(|(_, body)| exif::Reader::new().read_raw(body.to_vec()).ok())
+            .collect(),
+    )
+}
+
+/// Largest metadata box read into memory. `moov` boxes of photos and
+/// clips stay far below it; a larger size means a damaged file.
+const MAX_METADATA_BOX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Body of the first top-level ISO base media box of type `kind`, skipping
+/// over the others (such as the media data) without reading them. Boxes
+/// larger than the rest of the file or `MAX_METADATA_BOX_BYTES` are not read.
+fn read_top_level_box(path: &Path, kind: &[u8; 4]) -> Option<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = fs::File::open(long_path(path)).ok()?;
+    let file_len = file.metadata().ok()?.len();
+    loop {
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header).ok()?;
+        let size = u64::from(u32::from_be_bytes(header[..4].try_into().ok()?));
+        let (size, header_len) = match size {
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large).ok()?;
+                (u64::from_be_bytes(large), 16)
+            }
+            _ => (size, 8),
+        };
+        let body_len = size.checked_sub(header_len)?;
+        if &header[4..] == kind {
+            let left = file_len.saturating_sub(file.stream_position().ok()?);
+            if body_len > left.min(MAX_METADATA_BOX_BYTES) {
+                return None;
+            }
+            let mut body = vec![0u8; usize::try_from(body_len).ok()?];
+            file.read_exact(&mut body).ok()?;
+            return Some(body);
+        }
+        file.seek(SeekFrom::Current(i64::try_from(body_len).ok()?))
+            .ok()?;
+    }
+}
+
+fn child_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    isobmff_boxes(data)
+        .into_iter()
+        .find(|(found, _)| found == kind)
+        .map(|(_, body)| body)
+}
+
+/// Type and body of each ISO base media box in `data`.
+fn isobmff_boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 8 {
+        let size = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = [rest[4], rest[5], rest[6], rest[7]];
+        let size = if size == 0 { rest.len() } else { size };
+        if size < 8 || size > rest.len() {
+            break;
+        }
+        boxes.push((kind, &rest[8..size]));
+        rest = &rest[size..];
+    }
+    boxes
+}
+
+/// A field of the primary IFD. Blocks whose IFD0 holds Exif or GPS tags
+/// directly (CR3) are parsed as plain TIFF tags, so those are matched by
+/// number as well.
+fn exif_field(exif: &exif::Exif, tag: exif::Tag) -> Option<&exif::Field> {
+    exif.get_field(tag, exif::In::PRIMARY).or_else(|| {
+        exif.fields().find(|field| {
+            field.ifd_num == exif::In::PRIMARY
+                && field.tag.context() == exif::Context::Tiff
+                && field.tag.number() == tag.number()
+        })
+    })
+}
+
+/// The decoded image or, for formats the decoder does not know such as
+/// camera RAW, the first JPEG preview embedded in the file, which is all
+/// thumbnails and dHashes need.
+pub(crate) fn open_image(path: &Path) -> Option<image::DynamicImage> {
+    let path = long_path(path);
+    if let Ok(image) = image::open(&path) {
+        return Some(image);
+    }
+    let data = fs::read(&path).ok()?;
+    data.windows(3)
+        .enumerate()
+        .filter(|(_, window)| window == &[0xFF, 0xD8, 0xFF])
+        .take(16)
+        .find_map(|(start, _)| {
+            image::load_from_memory_with_format(&data[start..], image::ImageFormat::Jpeg).ok()
+        })
+}
+
+/// `DateTimeOriginal` as `YYYY-MM-DDTHH:MM:SS` in the camera's local time;
+/// the offset tags are ignored.
+pub(crate) fn exif_capture_datetime(exif: &exif::Exif) -> Option<String> {
+    let field = exif_field(exif, exif::Tag::DateTimeOriginal)?;
+    let exif::Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let time = exif::DateTime::from_ascii(values.first()?).ok()?;
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        time.year, time.month, time.day, time.hour, time.minute, time.second
+    ))
+}
+
+/// Latitude and longitude from the GPS IFD; southern and western references
+/// give negative values. Missing or malformed tags yield `None`.
+pub(crate) fn exif_gps(exif: &exif::Exif) -> Option<(f64, f64)> {
+    let coordinate = |tag: exif::Tag, ref_tag: exif::Tag, negative: u8| -> Option<f64> {
+        let exif::Value::Rational(parts) = &exif_field(exif, tag)?.value else {
+            return None;
+        };
+        let [degrees, minutes, seconds] = parts.get(..3)? else {
+            return None;
+        };
+        let value = degrees.to_f64() + minutes.to_f64() / 60.0 + seconds.to_f64() / 3600.0;
+        let exif::Value::Ascii(reference) = &exif_field(exif, ref_tag)?.value else {
+            return None;
+        };
+        let sign = match reference.first()?.first()? {
+            byte if *byte == negative => -1.0,
+            _ => 1.0,
+        };
+        Some(sign * value).filter(|value| value.is_finite())
+    };
+    Some((
+        coordinate(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, b'S')?,
+        coordinate(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, b'W')?,
+    ))
+}
+
+pub(crate) fn build_graded_map(
+    graded_root: &Path,
+    part: Option<&SurveyPart>,
+    rules: &CompiledRules,
+    spill: Option<&GradedSpill>,
+    cache: Option<&ScanCache>,
+    cancel: &CancelToken,
+) -> Result<GradedMapResult, AppError> {
+    let mut map = GradedMap::new(spill.cloned());
+    let mut ambiguity_warnings = 0u64;
+    if archives::is_archive(graded_root) && graded_root.is_file() {
+        let checksums = rules.matching == MatchingStrategy::Checksum;
+        for entry in archive_images(graded_root, checksums, part, rules)? {
+            cancel.check()?;
+            let (file_id, ambiguous) = match entry.sha256 {
+                Some(hash) => (format!("sha256:{}", hash), false),
+                None => file_id_with_size(Path::new(&entry.name), rules, || Some(entry.size)),
+            };
+            if ambiguous {
+                ambiguity_warnings += 1;
+            }
+            map.insert(file_id, entry.name)?;
+        }
+        map.finish()?;
+        return Ok(GradedMapResult {
+            map,
+            ambiguity_warnings,
+            walk_errors: Vec::new(),
+        });
+    }
+    let mut walk_errors = Vec::new();
+    let excluded = |dir: &Path| is_excluded_graded_dir(graded_root, dir, rules);
+    let mut listed = list_tree(
+        graded_root,
+        rules,
+        cache,
+        excluded,
+        cancel,
+        &mut walk_errors,
+    )?;
+    listed.retain(|file| is_supported_image(&file.path, rules));
+    drop_shadowed_camera_raws(&mut listed, rules);
+    listed.retain(|file| part.is_none_or(|part| part.contains(&file.path, rules)));
+    cancel.check()?;
+    let ids = listed_file_ids(&mut listed, rules, cache);
+    for (ListedFile { path, .. }, (file_id, ambiguous)) in listed.into_iter().zip(ids) {
+        cancel.check()?;
+        if ambiguous {
+            ambiguity_warnings += 1;
+        }
+        map.insert(file_id, normalize_relpath(&path, graded_root))?;
+    }
+    map.finish()?;
+    Ok(GradedMapResult {
+        map,
+        ambiguity_warnings,
+        walk_errors,
+    })
+}
+
+/// Graded folders never searched for candidates: `exclude_dir_globs` and
+/// `graded_excluded_subfolders`.
+fn is_excluded_graded_dir(graded_root: &Path, dir: &Path, rules: &CompiledRules) -> bool {
+    is_excluded_dir(graded_root, dir, rules)
+        || dir.file_name().is_some_and(|name| {
+            rules
+                .excluded_subfolders
+                .contains(&name.to_string_lossy().to_lowercase())
+        })
+}
+
+/// The supported images in a graded archive, leaving out entries below
+/// excluded folders and, for a combo survey part, other surveys' images.
+pub(crate) fn archive_images(
+    archive: &Path,
+    checksums: bool,
+    part: Option<&SurveyPart>,
+    rules: &CompiledRules,
+) -> Result<Vec<archives::ArchiveEntry>, AppError> {
+    let mut entries = archives::list_entries(archive, checksums)?;
+    entries.retain(|entry| {
+        let path = Path::new(&entry.name);
+        let folders: Vec<&str> = entry.name.split('/').collect();
+        let excluded = (1..folders.len()).any(|depth| {
+            let folder = folders[depth - 1].to_lowercase();
+            rules.excluded_subfolders.contains(&folder)
+                || is_excluded_dir(Path::new(""), Path::new(&folders[..depth].join("/")), rules)
+        });
+        !excluded
+            && is_supported_image(path, rules)
+            && part.is_none_or(|part| part.contains(path, rules))
+    });
+    Ok(entries)
+}
+
+/// 64-bit difference hash: the image shrunk to 9x8 grey pixels, one bit per
+/// pixel telling whether it is brighter than its right neighbour. Survives
+/// re-encoding and resizing; `None` for files the decoder cannot read.
+pub(crate) fn dhash(path: &Path) -> Option<u64> {
+    let grey = open_image(path)?
+        .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = grey.get_pixel(x, y)[0] > grey.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(brighter);
+        }
+    }
+    Some(hash)
+}
+
+/// dHashes of the graded images whose file id no raw image of the survey
+/// has, i.e. the ones a renamed copy could hide among. `unmatched` holds
+/// their file ids and relpaths below `graded_root`, as the graded map lists
+/// them, so the index needs no file list beside a spilled map.
+pub(crate) fn build_perceptual_index(
+    unmatched: Vec<(String, String)>,
+    graded_root: &Path,
+    cancel: &CancelToken,
+) -> Result<Vec<(u64, String)>, AppError> {
+    let mut index = Vec::new();
+    for (_, relpath) in unmatched {
+        cancel.check()?;
+        if let Some(hash) = dhash(&graded_root.join(&relpath)) {
+            index.push((hash, relpath));
+        }
+    }
+    Ok(index)
+}
+
+/// Pairs raw images, given by position and dHash, with the graded images
+/// of `index` within `max_distance`. The closest pairs go first and every
+/// raw and graded image is used once, so each raw image gets the nearest
+/// graded image no closer raw image claimed. Ties go to the earlier raw
+/// image and the smaller relpath.
+pub(crate) fn claim_perceptual_matches(
+    raw_hashes: &[(usize, u64)],
+    index: &[(u64, String)],
+    max_distance: u32,
+) -> HashMap<usize, String> {
+    let mut pairs = Vec::new();
+    for &(raw, raw_hash) in raw_hashes {
+        for (graded, (graded_hash, _)) in index.iter().enumerate() {
+            let distance = (graded_hash ^ raw_hash).count_ones();
+            if distance <= max_distance {
+                pairs.push((distance, raw, graded));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| (a.0, a.1, &index[a.2].1).cmp(&(b.0, b.1, &index[b.2].1)));
+    let mut claimed = HashSet::new();
+    let mut matches = HashMap::new();
+    for (_, raw, graded) in pairs {
+        if matches.contains_key(&raw) || !claimed.insert(graded) {
+            continue;
+        }
+        matches.insert(raw, index[graded].1.clone());
+    }
+    matches
+}
+
+/// File ids of `files` like `compute_file_id`, or from `cache`. Files whose
+/// id needs their size or mtime and whose listing did not have it are
+/// stat'ed up front by `STAT_WORKERS` threads instead of one at a time.
+pub(crate) fn listed_file_ids(
+    files: &mut [ListedFile],
+    rules: &CompiledRules,
+    cache: Option<&ScanCache>,
+) -> Vec<(String, bool)> {
+    let needs_stat = |file: &ListedFile| {
+        cache.is_some()
+            || (rules.matching != MatchingStrategy::Checksum
+                && regex_file_id(&file.path, rules).is_none())
+    };
+    let mut missing: Vec<&mut ListedFile> = files
+        .iter_mut()
+        .filter(|file| file.stat.is_none() && needs_stat(file))
+        .collect();
+    if !missing.is_empty() {
+        let chunk = missing.len().div_ceil(STAT_WORKERS);
+        std::thread::scope(|scope| {
+            for part in missing.chunks_mut(chunk) {
+                scope.spawn(move || {
+                    for file in part {
+                        file.stat = fs::metadata(long_path(&file.path))
+                            .ok()
+                            .map(|metadata| FileStat::of(&metadata));
+                    }
+                });
+            }
+        });
+    }
+    files
+        .iter()
+        .map(|file| match cache {
+            Some(cache) => cache.file_id(&file.path, file.stat, rules),
+            None => file_id_with_size(&file.path, rules, || file.stat.map(|stat| stat.size)),
+        })
+        .collect()
+}
+
+pub(crate) fn compute_file_id(path: &Path, rules: &CompiledRules) -> (String, bool) {
+    file_id_with_size(path, rules, || {
+        fs::metadata(long_path(path))
+            .ok()
+            .map(|metadata| metadata.len())
+    })
+}
+
+/// `compute_file_id` with the file size from `size`, which is only asked
+/// when the id falls back to filename and size; `None` makes it ambiguous.
+pub(crate) fn file_id_with_size(
+    path: &Path,
+    rules: &CompiledRules,
+    size: impl FnOnce() -> Option<u64>,
+) -> (String, bool) {
+    if rules.matching == MatchingStrategy::Checksum {
+        if let Some(hash) = file_sha256(path) {
+            return (format!("sha256:{}", hash), false);
+        }
+    }
+    let filename = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_string();
+    if let Some(id) = regex_file_id(path, rules) {
+        return (id, false);
+    }
+    let filename_lower = filename.to_lowercase();
+    match size() {
+        Some(size) => (format!("{}|{}", filename_lower, size), false),
+        None => (filename_lower, true),
+    }
+}
+
+pub(crate) fn regex_file_id(path: &Path, rules: &CompiledRules) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    let captures = rules.image_id_re.captures(stem)?;
+    Some(captures.get(1)?.as_str().to_lowercase())
+}
+
+/// Finds raw images sharing a filename+size fallback id and applies the
+/// collision policy: `hash` re-keys those ids on both sides with a partial
+/// content hash, `refuse` blanks the raw ids so they match nothing.
+pub(crate) fn resolve_id_collisions(
+    raw_files: &[PathBuf],
+    raw_ids: &mut [(String, bool)],
+    raw_root: &Path,
+    graded_map: &mut GradedMap,
+    graded_root: &Path,
+    rules: &CompiledRules,
+) -> Result<Vec<FileIdCollision>, AppError> {
+    // Equal checksums mean equal content; such raw images rightly share candidates.
+    if rules.matching == MatchingStrategy::Checksum {
+        return Ok(Vec::new());
+    }
+    let mut by_id: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (index, (file_id, ambiguous)) in raw_ids.iter().enumerate() {
+        if !ambiguous && regex_file_id(&raw_files[index], rules).is_none() {
+            by_id.entry(file_id.as_str()).or_default().push(index);
+        }
+    }
+    let colliding = by_id
+        .into_iter()
+        .filter(|(_, indices)| indices.len() > 1)
+        .map(|(file_id, indices)| (file_id.to_string(), indices))
+        .collect::<Vec<_>>();
+
+    let mut collisions = Vec::new();
+    for (file_id, indices) in colliding {
+        match rules.collisions {
+            CollisionPolicy::Warn => {}
+            CollisionPolicy::Hash => {
+                for &index in &indices {
+                    if let Some(hash) = partial_content_hash(&raw_files[index]) {
+                        raw_ids[index].0 = format!("{}#{}", file_id, hash);
+                    }
+                }
+                for candidate in graded_map.take(&file_id)? {
+                    let key = match partial_content_hash(&graded_root.join(&candidate)) {
+                        Some(hash) => format!("{}#{}", file_id, hash),
+                        None => file_id.clone(),
+                    };
+                    graded_map.insert(key, candidate)?;
+                }
+            }
+            CollisionPolicy::Refuse => {
+                for &index in &indices {
+                    raw_ids[index].0 = String::new();
+                }
+            }
+        }
+        collisions.push(FileIdCollision {
+            file_id,
+            raw_relpaths: indices
+                .iter()
+                .map(|&index| normalize_relpath(&raw_files[index], raw_root))
+                .collect(),
+        });
+    }
+    Ok(collisions)
+}
+
+/// SHA-256 of the first 64 KiB; enough to tell burst shots of equal size
+/// apart without reading whole files.
+fn partial_content_hash(path: &Path) -> Option<String> {
+    use std::io::Read;
+    let mut buffer = Vec::with_capacity(64 * 1024);
+    fs::File::open(long_path(path))
+        .ok()?
+        .take(64 * 1024)
+        .read_to_end(&mut buffer)
+        .ok()?;
+    Some(format!("{:x}", Sha256::digest(&buffer))[..16].to_string())
+}
+
+/// Keeps one candidate per content group (the one `select_winner` would pick),
+/// preserving the order in which groups first appear. Candidates are
+/// classified first and only copies with the same labels collapse. Unreadable
+/// files are never collapsed.
+pub(crate) fn dedupe_candidates(
+    candidates: Vec<String>,
+    graded_root: &Path,
+    rules: &CompiledRules,
+) -> Vec<String> {
+    let mut groups: Vec<(Option<String>, Vec<String>)> = Vec::new();
+    for candidate in candidates {
+        let key = candidate_content_key(&graded_root.join(&candidate), rules.dedupe)
+            .map(|content| format!("{:?} {}", candidate_labels(&candidate, rules), content));
+        match groups
+            .iter_mut()
+            .find(|(existing, _)| key.is_some() && *existing == key)
+        {
+            Some((_, members)) => members.push(candidate),
+            None => groups.push((key, vec![candidate])),
+        }
+    }
+    groups
+        .into_iter()
+        .filter_map(|(_, members)| select_winner(&members, rules).map(|winner| winner.relpath))
+        .collect()
+}
+
+fn candidate_content_key(path: &Path, dedupe: CandidateDedupe) -> Option<String> {
+    match dedupe {
+        CandidateDedupe::Off => None,
+        CandidateDedupe::Size => fs::metadata(long_path(path))
+            .ok()
+            .map(|metadata| metadata.len().to_string()),
+        CandidateDedupe::Hash => file_sha256(path),
+    }
+}
+
+fn file_sha256(path: &Path) -> Option<String> {
+    let mut file = fs::File::open(long_path(path)).ok()?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).ok()?;
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+/// Survey date (ISO formatted when it is `YYYYMMDD`) and area code parsed
+/// from the base key with `survey_fields_regex`.
+pub(crate) fn survey_fields(
+    base_key: &str,
+    rules: &CompiledRules,
+) -> (Option<String>, Option<String>) {
+    let Some(captures) = rules.fields_re.captures(base_key) else {
+        return (None, None);
+    };
+    let date = captures.name("date").map(|date| {
+        let date = date.as_str();
+        if date.len() == 8 && date.chars().all(|ch| ch.is_ascii_digit()) {
+            format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..])
+        } else {
+            date.to_string()
+        }
+    });
+    let area = captures.name("area").map(|area| area.as_str().to_string());
+    (date, area)
+}
+
+/// Notes keyed by frame number from the notes files directly inside the
+/// given survey folders; several notes for one frame are joined with ` | `.
+pub(crate) fn load_survey_notes(
+    folders: &[&Path],
+    rules: &CompiledRules,
+) -> Result<HashMap<u64, String>, AppError> {
+    let mut notes: HashMap<u64, String> = HashMap::new();
+    if rules.notes_filenames.is_empty() {
+        return Ok(notes);
+    }
+    for folder in folders {
+        // Graded archives carry no notes file we would read.
+        if !folder.is_dir() {
+            continue;
+        }
+        let mut files = fs::read_dir(folder)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file()
+                    && path.file_name().is_some_and(|name| {
+                        rules
+                            .notes_filenames
+                            .contains(&name.to_string_lossy().to_lowercase())
+                    })
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        for file in files {
+            // Notes are often saved by Windows editors, so tolerate non-UTF-8 text.
+            let data = fs::read(&file)?;
+            for line in String::from_utf8_lossy(&data).lines() {
+                let Some(captures) = rules.notes_re.captures(line) else {
+                    continue;
+                };
+                let (Some(frame), Some(note)) = (captures.name("frame"), captures.name("note"))
+                else {
+                    continue;
+                };
+                let Ok(frame) = frame.as_str().parse::<u64>() else {
+                    continue;
+                };
+                let note = note.as_str().trim();
+                if note.is_empty() {
+                    continue;
+                }
+                notes
+                    .entry(frame)
+                    .and_modify(|existing| {
+                        existing.push_str(" | ");
+                        existing.push_str(note);
+                    })
+                    .or_insert_with(|| note.to_string());
+            }
+        }
+    }
+    Ok(notes)
+}
+
+/// Frame number of a raw image: the last run of digits in its file stem
+/// (`DSC_0123.JPG` -> 123).
+pub(crate) fn frame_number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let end = stem.rfind(|ch: char| ch.is_ascii_digit())? + 1;
+    let start = stem[..end]
+        .rfind(|ch: char| !ch.is_ascii_digit())
+        .map_or(0, |index| index + 1);
+    stem[start..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    use crate::output::{row_columns, row_record, CAPTURE_TIME_COLUMN};
+    use crate::rules::{compile_rules, Rules};
+    use crate::runs::preview_root_scan;
+    use crate::scan::{collect_images, discover_surveys};
+    use crate::test_support::{csv_row, default_compiled, root_options, TestTree};
+
+    #[test]
+    fn an_abandoned_survey_stops_reporting_progress() {
+        #[derive(Default)]
+        struct Phases(std::sync::Mutex<Vec<(String, RunPhase)>>);
+
+        impl crate::ProgressSink for Phases {
+            fn progress(&self, event: crate::ProgressEvent) {
+                let mut phases = self.0.lock().expect("phases");
+                phases.push((event.survey_id_base, event.phase));
+            }
+        }
+
+        let tree = TestTree::new();
+        for key in ["20240101_AB", "20240102_AB"] {
+            for index in 1..=3 {
+                tree.write(format!("raw/{key}/IMG_000{index}.JPG"), "x");
+            }
+            tree.write(format!("graded/{key}/IND/IMG_0001.JPG"), "x");
+        }
+        let sink = Arc::new(Phases::default());
+        let host = Host::headless_with_sink(tree.join("data"), sink.clone());
+        let rules = Arc::new(compile_rules(&Rules::default()).expect("compile"));
+        let folder = |side: &str, key: &str| SurveyFolder {
+            path: tree.join(side).join(key),
+            detected_id: None,
+            part: None,
+        };
+        let cancel = CancelToken::default();
+        let pair_options = PairOptions::new(
+            ProgressGranularity::default(),
+            &CsvSettings::default(),
+            &AppSettings::default(),
+            &cancel,
+        );
+        let events = |key: &str, matching: bool| {
+            sink.0
+                .lock()
+                .expect("phases")
+                .iter()
+                .filter(|(base, phase)| base == key && (!matching || *phase == RunPhase::Matching))
+                .count()
+        };
+
+        // The worker waits out the pause while the survey times out.
+        cancel.pause();
+        let abandoned = process_pair_within(
+            &host,
+            &rules,
+            "20240101_AB",
+            &folder("raw", "20240101_AB"),
+            &folder("graded", "20240101_AB"),
+            pair_options.clone(),
+            Some(Duration::ZERO),
+            &mut |_| Ok(()),
+        )
+        .expect("abandoned");
+        assert!(abandoned.is_none());
+        cancel.resume();
+
+        let result = process_pair_within(
+            &host,
+            &rules,
+            "20240102_AB",
+            &folder("raw", "20240102_AB"),
+            &folder("graded", "20240102_AB"),
+            pair_options,
+            Some(Duration::from_secs(60)),
+            &mut |_| Ok(()),
+        )
+        .expect("survey");
+        assert_eq!(result.map(|result| result.counts.rows), Some(3));
+        assert_eq!(events("20240102_AB", true), 3);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(events("20240101_AB", false), 0);
+    }
+
+    #[test]
+    fn oversized_metadata_boxes_are_not_read() {
+        let tree = TestTree::new();
+        // A 64-bit box size of 1 TiB in a file of a few bytes.
+        let mut clip = 1u32.to_be_bytes().to_vec();
+        clip.extend(b"moov");
+        clip.extend((1u64 << 40).to_be_bytes());
+        clip.extend([0u8; 32]);
+        let path = tree.write("CLIP01.MP4", &clip);
+        assert_eq!(read_top_level_box(&path, b"moov"), None);
+        assert_eq!(video_frame_rate(&path), None);
+
+        let mut clip = 40u32.to_be_bytes().to_vec();
+        clip.extend(b"moov");
+        clip.extend([7u8; 32]);
+        let path = tree.write("CLIP02.MP4", &clip);
+        assert_eq!(read_top_level_box(&path, b"moov"), Some(vec![7u8; 32]));
+    }
+
+    fn stat_test_tree(files: usize) -> TestTree {
+        let tree = TestTree::new();
+        for index in 0..files {
+            // No `_NNN` suffix, so the ids fall back to filename and size.
+            tree.write(
+                Path::new("IND").join(format!("DSC{:05}.JPG", index)),
+                "x".repeat(index % 7),
+            );
+        }
+        tree.write("IND/img_001.jpg", "x");
+        tree
+    }
+
+    /// Minimal JPEG whose EXIF holds only `DateTimeOriginal`.
+    fn jpeg_with_capture_time(path: &Path, value: &str) {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        // IFD0: ExifIFDPointer -> offset 26.
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend([0x69, 0x87, 4, 0]);
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(26u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        // Exif IFD: DateTimeOriginal -> offset 44.
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend([0x03, 0x90, 2, 0]);
+        tiff.extend(20u32.to_le_bytes());
+        tiff.extend(44u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(value.as_bytes());
+        tiff.push(0);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(tiff);
+        jpeg.extend([0xFF, 0xD9]);
+        fs::write(path, jpeg).expect("write jpeg");
+    }
+
+    fn graded_ids(map: &GradedMap) -> Vec<String> {
+        match map {
+            GradedMap::Memory { map, .. } => map.chains.keys().map(|id| id.to_string()).collect(),
+            GradedMap::Disk(disk) => {
+                let conn = disk.connection().expect("connection");
+                let mut statement = conn
+                    .prepare("SELECT DISTINCT file_id FROM graded ORDER BY file_id")
+                    .expect("prepare");
+                statement
+                    .query_map([], |row| row.get(0))
+                    .expect("query")
+                    .map(|id| id.expect("id"))
+                    .collect()
+            }
+        }
+    }
+
+    #[test]
+    fn file_id_uses_size_when_available() {
+        let rules = Rules {
+            extensions: vec![".jpg".to_string()],
+            survey_id_regex_detected: "x".to_string(),
+            survey_id_regex_base: "x".to_string(),
+            image_id_regex: "^no-match$".to_string(),
+            graded_priority_ind_regex: "(?i)\\bind".to_string(),
+            graded_priority_secondary_tokens: vec!["best".to_string()],
+            graded_negative_contains_any: vec![],
+            graded_positive_contains_any: vec!["*".to_string()],
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let file_path = tree.join("sample.JPG");
+        fs::write(&file_path, b"testdata").expect("write");
+
+        let (file_id, ambiguous) = compute_file_id(&file_path, &compiled);
+        assert!(file_id.starts_with("sample.jpg|"));
+        assert!(!ambiguous);
+    }
+
+    #[test]
+    fn checksum_strategy_matches_renamed_copies() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw = root.join("20240101_AB_0001.jpg");
+        let renamed = root.join("best shot.jpg");
+        let edited = root.join("20240101_AB_0001 crop.jpg");
+        fs::write(&raw, b"frame one").expect("write");
+        fs::write(&renamed, b"frame one").expect("write");
+        fs::write(&edited, b"frame one, cropped").expect("write");
+
+        let by_name = compile_rules(&Rules::default()).expect("compile");
+        assert_ne!(
+            compute_file_id(&raw, &by_name).0,
+            compute_file_id(&renamed, &by_name).0
+        );
+        assert_eq!(
+            compute_file_id(&raw, &by_name).0,
+            compute_file_id(&edited, &by_name).0
+        );
+
+        let by_checksum = compile_rules(&Rules {
+            matching_strategy: MatchingStrategy::Checksum,
+            ..Rules::default()
+        })
+        .expect("compile");
+        let (raw_id, ambiguous) = compute_file_id(&raw, &by_checksum);
+        assert!(raw_id.starts_with("sha256:"));
+        assert!(!ambiguous);
+        assert_eq!(raw_id, compute_file_id(&renamed, &by_checksum).0);
+        assert_ne!(raw_id, compute_file_id(&edited, &by_checksum).0);
+    }
+
+    #[test]
+    fn file_id_strips_suffix_tokens() {
+        let rules = Rules {
+            extensions: vec![".jpg".to_string()],
+            survey_id_regex_detected: "x".to_string(),
+            survey_id_regex_base: "x".to_string(),
+            image_id_regex: "^(.+?_\\d{3,5})(?:_[A-Za-z0-9]+)*$".to_string(),
+            graded_priority_ind_regex: "(?i)\\bind".to_string(),
+            graded_priority_secondary_tokens: vec!["best".to_string()],
+            graded_negative_contains_any: vec![],
+            graded_positive_contains_any: vec!["*".to_string()],
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let file_path = PathBuf::from("/data/20100428_ALA_0449_QP_D.jpg");
+        let (file_id, ambiguous) = compute_file_id(&file_path, &compiled);
+        assert_eq!(file_id, "20100428_ala_0449");
+        assert!(!ambiguous);
+    }
+
+    #[test]
+    fn graded_map_skips_excluded_subfolders() {
+        let rules = Rules {
+            graded_excluded_subfolders: vec!["Rejects".to_string()],
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let root = tree.path();
+        fs::create_dir_all(root.join("IND")).expect("create");
+        fs::create_dir_all(root.join("rejects").join("old")).expect("create");
+        fs::write(root.join("IND").join("img_001.jpg"), "a").expect("write");
+        fs::write(root.join("rejects").join("old").join("img_002.jpg"), "b").expect("write");
+
+        let result = build_graded_map(root, None, &compiled, None, None, &CancelToken::default())
+            .expect("map");
+        assert!(!result.map.candidates("img_001").expect("lookup").is_empty());
+        assert!(result.map.candidates("img_002").expect("lookup").is_empty());
+    }
+
+    #[test]
+    fn graded_archives_are_read_as_survey_folders() {
+        use std::io::Write;
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB");
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(root.join("graded")).expect("create");
+        for index in 1..=3 {
+            fs::write(raw_dir.join(format!("IMG_000{}.JPG", index)), b"x").expect("write");
+        }
+        let file = fs::File::create(root.join("graded").join("20240101_AB.zip")).expect("zip");
+        let mut zip = zip::ZipWriter::new(file);
+        let options = zip::write::SimpleFileOptions::default();
+        for name in ["Dolphins/IMG_0001.JPG", "Rejects/IMG_0002.JPG", "notes.txt"] {
+            zip.start_file(name, options).expect("entry");
+            zip.write_all(b"graded").expect("write");
+        }
+        zip.finish().expect("finish");
+
+        let host = tree.host();
+        let rules = Rules {
+            scan_archives: true,
+            graded_excluded_subfolders: vec!["Rejects".to_string()],
+            ..Rules::default()
+        };
+        let preview = preview_root_scan(
+            &host,
+            vec![root.join("graded")],
+            vec![root.join("raw")],
+            serde_json::from_value(serde_json::json!({ "include_counts": true })).expect("options"),
+            rules.clone(),
+        )
+        .expect("preview");
+        assert_eq!(preview.items.len(), 1);
+        assert_eq!(preview.items[0].status, "OK");
+        assert_eq!(preview.items[0].graded_image_count, Some(1));
+
+        let options = root_options(serde_json::json!({}));
+        let summary = tree.run_root(&host, options, rules.clone()).expect("run");
+        assert_eq!(summary.total_rows, 3);
+        assert_eq!(summary.surveys[0].dolphin_yes, 1);
+        assert!(summary.surveys[0].graded_path.ends_with("20240101_AB.zip"));
+
+        // Perceptual matching would read entries as files and find nothing.
+        let perceptual = Rules {
+            perceptual_fallback: true,
+            ..rules
+        };
+        assert!(matches!(
+            tree.run_root(&host, root_options(serde_json::json!({})), perceptual),
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn video_stills_become_rows_for_their_clip_frames() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB");
+        let graded_dir = root.join("graded").join("20240101_AB");
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(graded_dir.join("Dolphins")).expect("create");
+        fs::create_dir_all(graded_dir.join("Empty")).expect("create");
+        let isobmff = |kind: &[u8], body: &[u8]| {
+            let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            data.extend(kind);
+            data.extend(body);
+            data
+        };
+        // 30000/1001 fps: mdhd timescale 30000, stts sample delta 1001.
+        let mut hdlr = vec![0u8; 8];
+        hdlr.extend(b"vide");
+        hdlr.extend([0u8; 12]);
+        let mut mdhd = vec![0u8; 12];
+        mdhd.extend(30000u32.to_be_bytes());
+        mdhd.extend([0u8; 8]);
+        let mut stts = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stts.extend(900u32.to_be_bytes());
+        stts.extend(1001u32.to_be_bytes());
+        let stbl = isobmff(b"stbl", &isobmff(b"stts", &stts));
+        let mut mdia = isobmff(b"hdlr", &hdlr);
+        mdia.extend(isobmff(b"mdhd", &mdhd));
+        mdia.extend(isobmff(b"minf", &stbl));
+        let mut clip = isobmff(b"ftyp", b"isom\0\0\0\0");
+        clip.extend(isobmff(b"mdat", &[0u8; 64]));
+        clip.extend(isobmff(
+            b"moov",
+            &isobmff(b"trak", &isobmff(b"mdia", &mdia)),
+        ));
+        fs::write(raw_dir.join("CLIP01.MP4"), clip).expect("write");
+        fs::write(raw_dir.join("CLIP03.MP4"), b"no stills").expect("write");
+        assert!((video_frame_rate(&raw_dir.join("CLIP01.MP4")).expect("fps") - 29.97).abs() < 0.01);
+        for still in [
+            "Dolphins/CLIP01_fram0300.jpg",
+            "Empty/CLIP01_fram0600.jpg",
+            "Dolphins/CLIP02_fram0001.jpg",
+        ] {
+            fs::write(graded_dir.join(still), b"still").expect("write");
+        }
+
+        let host = tree.host();
+        let rules = Rules {
+            video_mode: true,
+            graded_negative_contains_any: vec!["empty".to_string()],
+            ..Rules::default()
+        };
+        let options = root_options(serde_json::json!({
+            "include_video_frame": true
+        }));
+        let summary = tree.run_root(&host, options, rules).expect("run");
+        assert_eq!(summary.total_rows, 3);
+        let merged = fs::read_to_string(root.join("out").join("merged.csv")).expect("merged");
+        let lines: Vec<&str> = merged.lines().collect();
+        assert!(lines[0].ends_with("video_frame,video_time_s"));
+        assert!(lines[1].starts_with("20240101_AB,CLIP01.MP4#frame=300,CLIP01.MP4,1,"));
+        assert!(lines[1].ends_with(",300,10.010"));
+        assert!(lines[2].starts_with("20240101_AB,CLIP01.MP4#frame=600,CLIP01.MP4,0,"));
+        assert!(lines[2].ends_with(",600,20.020"));
+        // A clip without stills still gets its row.
+        assert!(lines[3].starts_with("20240101_AB,CLIP03.MP4,CLIP03.MP4,0,RAW,"));
+        assert!(lines[3].ends_with(",,"));
+    }
+
+    #[test]
+    fn dedupe_collapses_identical_candidates() {
+        let rules = Rules {
+            graded_dedupe: CandidateDedupe::Hash,
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let root = tree.path();
+        for folder in ["IND", "mirror", "other"] {
+            fs::create_dir_all(root.join(folder)).expect("create");
+        }
+        fs::write(root.join("IND").join("img_001.jpg"), "same").expect("write");
+        fs::write(root.join("mirror").join("img_001.jpg"), "same").expect("write");
+        fs::write(root.join("other").join("img_001.jpg"), "different").expect("write");
+
+        let candidates = vec![
+            "mirror/img_001.jpg".to_string(),
+            "IND/img_001.jpg".to_string(),
+            "other/img_001.jpg".to_string(),
+        ];
+        let deduped = dedupe_candidates(candidates.clone(), root, &compiled);
+        assert_eq!(deduped, vec!["IND/img_001.jpg", "other/img_001.jpg"]);
+
+        let by_size = compile_rules(&Rules {
+            graded_dedupe: CandidateDedupe::Size,
+            ..Rules::default()
+        })
+        .expect("compile");
+        assert_eq!(dedupe_candidates(candidates, root, &by_size).len(), 2);
+    }
+
+    #[test]
+    fn dedupe_keeps_a_negative_copy_of_a_positive_candidate() {
+        let rules = Rules {
+            graded_dedupe: CandidateDedupe::Hash,
+            graded_positive_contains_any: vec!["dolphin".to_string()],
+            graded_negative_contains_any: vec!["empty".to_string()],
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        tree.write("IND dolphin/img_001.jpg", "same");
+        tree.write("empty/img_001.jpg", "same");
+
+        let candidates = vec![
+            "IND dolphin/img_001.jpg".to_string(),
+            "empty/img_001.jpg".to_string(),
+        ];
+        let deduped = dedupe_candidates(candidates, tree.path(), &compiled);
+        assert_eq!(
+            deduped,
+            vec!["IND dolphin/img_001.jpg", "empty/img_001.jpg"]
+        );
+        let labeling = label_candidates(&deduped, false, tree.path(), &compiled);
+        assert_eq!(labeling.dolphin, 0);
+        assert!(labeling.decision_reason.contains("negative token 'empty'"));
+    }
+
+    #[test]
+    fn combo_graded_folders_split_by_filename_id() {
+        let rules = Rules {
+            split_combo_graded_folders: true,
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let root = tree.path();
+        let combo = root.join("20240101_AB");
+        fs::create_dir_all(&combo).expect("create");
+        fs::write(combo.join("20240101_AB-img_001.jpg"), "a").expect("write");
+        fs::write(combo.join("20240102_AB-img_001.jpg"), "b").expect("write");
+        fs::write(combo.join("img_003.jpg"), "c").expect("write");
+
+        let map =
+            discover_surveys(root, &compiled, true, false, &mut Vec::new()).expect("discover");
+        assert_eq!(map.len(), 2);
+        let second = &map["20240102_AB"][0];
+        let graded = build_graded_map(
+            &second.path,
+            second.part.as_ref(),
+            &compiled,
+            None,
+            None,
+            &CancelToken::default(),
+        )
+        .expect("map");
+        assert_eq!(graded_ids(&graded.map).len(), 1);
+        let first = &map["20240101_AB"][0];
+        let graded = build_graded_map(
+            &first.path,
+            first.part.as_ref(),
+            &compiled,
+            None,
+            None,
+            &CancelToken::default(),
+        )
+        .expect("map");
+        assert_eq!(graded_ids(&graded.map).len(), 2);
+
+        let unsplit =
+            discover_surveys(root, &compiled, false, false, &mut Vec::new()).expect("discover");
+        assert_eq!(unsplit.len(), 1);
+    }
+
+    #[test]
+    fn combo_raw_images_follow_filename_survey_id() {
+        let rules = Rules {
+            split_combo_raw_folders: true,
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let root = tree.path();
+        let dump = root.join("2024").join("20240101_AB");
+        fs::create_dir_all(&dump).expect("create");
+        fs::write(dump.join("20240101_AB_CD-img_001.jpg"), "a").expect("write");
+        fs::write(dump.join("20240102_EF-img_002.jpg"), "b").expect("write");
+
+        let map = discover_surveys(
+            root,
+            &compiled,
+            compiled.split_combo_raw,
+            false,
+            &mut Vec::new(),
+        )
+        .expect("discover");
+        let second = &map["20240102_EF"][0];
+        let files = collect_images(
+            &second.path,
+            second.part.as_ref(),
+            &compiled,
+            None,
+            &mut Vec::new(),
+        )
+        .expect("collect");
+        assert_eq!(files, vec![dump.join("20240102_EF-img_002.jpg")]);
+        let first = &map["20240101_AB"][0];
+        let files = collect_images(
+            &first.path,
+            first.part.as_ref(),
+            &compiled,
+            None,
+            &mut Vec::new(),
+        )
+        .expect("collect");
+        assert_eq!(files, vec![dump.join("20240101_AB_CD-img_001.jpg")]);
+    }
+
+    #[test]
+    fn graded_map_spills_to_disk_above_cap() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        for folder in ["IND", "best", "other"] {
+            fs::create_dir_all(root.join(folder)).expect("create");
+            for index in 1..=20 {
+                fs::write(root.join(folder).join(format!("img_{:03}.jpg", index)), "x")
+                    .expect("write");
+            }
+        }
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+        let in_memory =
+            build_graded_map(root, None, &compiled, None, None, &CancelToken::default())
+                .expect("map");
+        let spill_dir = tree.join("workspace");
+        let mut spilled = build_graded_map(
+            root,
+            None,
+            &compiled,
+            Some(&GradedSpill {
+                cap: 1024,
+                dir: spill_dir.clone(),
+            }),
+            None,
+            &CancelToken::default(),
+        )
+        .expect("map");
+        assert!(!in_memory.map.is_on_disk());
+        assert!(spilled.map.is_on_disk());
+        assert_eq!(fs::read_dir(&spill_dir).expect("spill dir").count(), 1);
+
+        let mut ids = graded_ids(&in_memory.map);
+        ids.sort();
+        assert_eq!(graded_ids(&spilled.map), ids);
+        let mut expected = in_memory.map.candidates("img_007").expect("lookup");
+        expected.sort();
+        let mut actual = spilled.map.candidates("img_007").expect("lookup");
+        actual.sort();
+        assert_eq!(actual.len(), 3);
+        assert_eq!(actual, expected);
+
+        let taken = spilled.map.take("img_007").expect("take");
+        assert_eq!(taken.len(), 3);
+        assert!(spilled
+            .map
+            .candidates("img_007")
+            .expect("lookup")
+            .is_empty());
+        spilled
+            .map
+            .insert("img_007#a".to_string(), taken[0].clone())
+            .expect("insert");
+        assert_eq!(
+            spilled.map.candidates("img_007#a").expect("lookup"),
+            vec![taken[0].clone()]
+        );
+        drop(spilled);
+        assert_eq!(fs::read_dir(&spill_dir).expect("spill dir").count(), 0);
+    }
+
+    #[test]
+    fn size_fallback_collisions_follow_policy() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw = root.join("raw");
+        let graded = root.join("graded");
+        for dir in [raw.join("card1"), raw.join("card2"), graded.join("best")] {
+            fs::create_dir_all(&dir).expect("create");
+        }
+        fs::write(raw.join("card1/burst.jpg"), "frame-aaaa").expect("write");
+        fs::write(raw.join("card2/burst.jpg"), "frame-bbbb").expect("write");
+        fs::write(raw.join("card2/single.jpg"), "only").expect("write");
+        fs::write(graded.join("best/burst.jpg"), "frame-bbbb").expect("write");
+
+        let run = |policy: CollisionPolicy| {
+            let rules = compile_rules(&Rules {
+                image_id_regex: "^no-match$".to_string(),
+                file_id_collisions: policy,
+                ..Rules::default()
+            })
+            .expect("compile");
+            let raw_files = collect_images(&raw, None, &rules, None, &mut Vec::new()).expect("raw");
+            let mut raw_ids = raw_files
+                .iter()
+                .map(|path| compute_file_id(path, &rules))
+                .collect::<Vec<_>>();
+            let mut graded_map =
+                build_graded_map(&graded, None, &rules, None, None, &CancelToken::default())
+                    .expect("map")
+                    .map;
+            let collisions = resolve_id_collisions(
+                &raw_files,
+                &mut raw_ids,
+                &raw,
+                &mut graded_map,
+                &graded,
+                &rules,
+            )
+            .expect("collisions");
+            let matched = raw_ids
+                .iter()
+                .map(|(file_id, _)| !graded_map.candidates(file_id).expect("lookup").is_empty())
+                .collect::<Vec<_>>();
+            (collisions, matched)
+        };
+
+        let (collisions, matched) = run(CollisionPolicy::Warn);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(
+            collisions[0].raw_relpaths,
+            vec!["card1/burst.jpg".to_string(), "card2/burst.jpg".to_string()]
+        );
+        assert_eq!(matched, vec![true, true, false]);
+        assert_eq!(run(CollisionPolicy::Hash).1, vec![false, true, false]);
+        assert_eq!(run(CollisionPolicy::Refuse).1, vec![false, false, false]);
+    }
+
+    #[test]
+    fn notes_attach_to_frames() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw = root.join("raw");
+        let graded = root.join("graded");
+        fs::create_dir_all(&raw).expect("create");
+        fs::create_dir_all(&graded).expect("create");
+        fs::write(
+            raw.join("Notes.txt"),
+            "Frame 12: two animals, one calf\nweather got worse\n0013 - blurry\n",
+        )
+        .expect("write");
+        fs::write(
+            graded.join("notes.csv"),
+            b"frame,note\n12,dorsal fin nick\n7;caf\xe9\n",
+        )
+        .expect("write");
+
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+        let notes = load_survey_notes(&[&raw, &graded], &compiled).expect("notes");
+        assert_eq!(
+            notes.get(&12).map(String::as_str),
+            Some("two animals, one calf | dorsal fin nick")
+        );
+        assert_eq!(notes.get(&13).map(String::as_str), Some("blurry"));
+        assert_eq!(notes.get(&7).map(String::as_str), Some("caf\u{fffd}"));
+        assert_eq!(notes.len(), 3);
+
+        assert_eq!(frame_number(Path::new("DSC_0012.JPG")), Some(12));
+        assert_eq!(
+            frame_number(Path::new("20240101_AB_0013 best.jpg")),
+            Some(13)
+        );
+        assert_eq!(frame_number(Path::new("cover.jpg")), None);
+    }
+
+    #[test]
+    fn perceptual_fallback_finds_renamed_graded_copy() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        fs::create_dir_all(root.join("graded/IND")).expect("create");
+        fs::create_dir_all(root.join("raw")).expect("create");
+        let gradient = image::RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+        });
+        let mirrored = image::imageops::flip_horizontal(&gradient);
+        let raw = root.join("raw/20240101_AB_0001.jpg");
+        gradient.save(&raw).expect("save raw");
+        // Re-encoded at another size, as graders' exports usually are.
+        image::imageops::resize(&gradient, 48, 36, image::imageops::FilterType::Triangle)
+            .save(root.join("graded/IND/dolphin_best.jpg"))
+            .expect("save graded");
+        mirrored
+            .save(root.join("graded/IND/other_shot.jpg"))
+            .expect("save other");
+        fs::copy(&raw, root.join("graded/IND/20240101_AB_0002.jpg")).expect("copy");
+
+        let rules = compile_rules(&Rules {
+            perceptual_fallback: true,
+            ..Rules::default()
+        })
+        .expect("compile");
+        let graded = build_graded_map(
+            &root.join("graded"),
+            None,
+            &rules,
+            None,
+            None,
+            &CancelToken::default(),
+        )
+        .expect("graded map");
+        // The 0002 copy has a filename match of its own and stays out.
+        let raw_ids = [raw.clone(), root.join("raw/20240101_AB_0002.jpg")]
+            .iter()
+            .map(|path| compute_file_id(path, &rules).0)
+            .collect::<HashSet<_>>();
+        let index = build_perceptual_index(
+            graded.map.entries_except(&raw_ids).expect("entries"),
+            &root.join("graded"),
+            &CancelToken::default(),
+        )
+        .expect("index");
+        assert_eq!(index.len(), 2);
+        let hash = dhash(&raw).expect("hash");
+        let claims = claim_perceptual_matches(&[(0, hash)], &index, rules.perceptual_max_distance);
+        assert_eq!(
+            claims,
+            HashMap::from([(0, "IND/dolphin_best.jpg".to_string())])
+        );
+        assert!(dhash(&root.join("missing.jpg")).is_none());
+    }
+
+    #[test]
+    fn perceptual_claims_pick_the_nearest_image_once() {
+        let index = vec![
+            (0b0000_0000, "IND/a.jpg".to_string()),
+            (0b0000_0111, "IND/b.jpg".to_string()),
+            (0b1111_0000, "IND/c.jpg".to_string()),
+        ];
+        // Raw 2 is exactly a and raw 0 is one bit from b. Raw 1 is one bit
+        // from a and two from b, but closer raw images claimed both, so it
+        // gets none instead of sharing one.
+        let raw_hashes = [(0, 0b0000_0011), (1, 0b0000_0001), (2, 0b0000_0000)];
+        let claims = claim_perceptual_matches(&raw_hashes, &index, 2);
+        assert_eq!(
+            claims,
+            HashMap::from([(0, "IND/b.jpg".to_string()), (2, "IND/a.jpg".to_string()),])
+        );
+    }
+
+    #[test]
+    fn capture_time_comes_from_exif() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let tagged = root.join("tagged.jpg");
+        jpeg_with_capture_time(&tagged, "2024:05:01 10:20:30");
+        let exif = read_exif(&tagged).expect("exif");
+        assert_eq!(
+            exif_capture_datetime(&exif).as_deref(),
+            Some("2024-05-01T10:20:30")
+        );
+        let blank = root.join("blank.jpg");
+        jpeg_with_capture_time(&blank, "    :  :     :  :  ");
+        let exif = read_exif(&blank).expect("exif");
+        assert_eq!(exif_capture_datetime(&exif), None);
+        let plain = root.join("plain.jpg");
+        fs::write(&plain, [0xFF, 0xD8, 0xFF, 0xD9]).expect("write");
+        assert!(read_exif(&plain).is_none());
+
+        let settings = CsvSettings {
+            include_capture_time: true,
+            ..CsvSettings::default()
+        };
+        assert_eq!(row_columns(&settings).last(), Some(&CAPTURE_TIME_COLUMN));
+    }
+
+    #[test]
+    fn gps_coordinates_come_from_exif() {
+        let rational = |num: u32, denom: u32| [num.to_le_bytes(), denom.to_le_bytes()].concat();
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        // IFD0: GPSInfoIFDPointer -> offset 26.
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend([0x25, 0x88, 4, 0]);
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(26u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        // GPS IFD: refs inline, latitude at 80, longitude at 104.
+        tiff.extend(4u16.to_le_bytes());
+        for (tag, kind, count, value) in [
+            (1u16, 2u16, 2u32, *b"S\0\0\0"),
+            (2, 5, 3, 80u32.to_le_bytes()),
+            (3, 2, 2, *b"E\0\0\0"),
+            (4, 5, 3, 104u32.to_le_bytes()),
+        ] {
+            tiff.extend(tag.to_le_bytes());
+            tiff.extend(kind.to_le_bytes());
+            tiff.extend(count.to_le_bytes());
+            tiff.extend(value);
+        }
+        tiff.extend(0u32.to_le_bytes());
+        for (num, denom) in [(43, 1), (30, 1), (36, 1), (12, 1), (15, 1), (1, 2)] {
+            tiff.extend(rational(num, denom));
+        }
+        let exif = exif::Reader::new().read_raw(tiff).expect("exif");
+        let (lat, lon) = exif_gps(&exif).expect("gps");
+        assert!((lat + 43.51).abs() < 1e-9);
+        assert!((lon - (12.25 + 0.5 / 3600.0)).abs() < 1e-9);
+
+        let settings = CsvSettings {
+            include_exif_gps: true,
+            ..CsvSettings::default()
+        };
+        let row = CsvRow {
+            gps: Some((lat, lon)),
+            ..csv_row("20240101_AB", "img_001.jpg", 1)
+        };
+        let record = row_record(&row, &settings);
+        assert_eq!(record[record.len() - 2..], ["-43.510000", "12.250139"]);
+        assert_eq!(row_columns(&settings).len(), record.len());
+    }
+
+    #[test]
+    fn listed_file_ids_match_per_file_ids() {
+        let tree = stat_test_tree(40);
+        let root = tree.path();
+        let rules = compile_rules(&Rules::default()).expect("compile");
+        let mut errors = Vec::new();
+        let mut listed =
+            collect_listed_images(root, None, &rules, None, &mut errors).expect("list");
+        let ids = listed_file_ids(&mut listed, &rules, None);
+        assert_eq!(ids.len(), 41);
+        for (file, id) in listed.iter().zip(&ids) {
+            assert_eq!(*id, compute_file_id(&file.path, &rules));
+        }
+        // Regex ids need no stat.
+        let regex_file = listed
+            .iter()
+            .find(|file| file.path.ends_with("img_001.jpg"))
+            .expect("listed");
+        assert!(cfg!(windows) || regex_file.stat.is_none());
+        assert!(listed.iter().filter(|file| file.stat.is_some()).count() >= 40);
+
+        let cache = ScanCache::load(root.join("cache.json"), "rules");
+        let cached = listed_file_ids(&mut listed, &rules, Some(&cache));
+        assert_eq!(cached, ids);
+    }
+
+    /// Graded indexing cost of file ids, one stat per file against the
+    /// batched stats of `listed_file_ids`. The gap is widest on SMB and NFS
+    /// shares; point `SURVEY_LABELER_BENCH_DIR` at one to measure there.
+    /// Run with `cargo test --release bench_file_id_stats -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_file_id_stats() {
+        let bench_dir = std::env::var_os("SURVEY_LABELER_BENCH_DIR").map(PathBuf::from);
+        let tree = bench_dir.is_none().then(|| stat_test_tree(20_000));
+        let root = bench_dir
+            .as_deref()
+            .or(tree.as_ref().map(TestTree::path))
+            .expect("bench root");
+        let rules = compile_rules(&Rules::default()).expect("compile");
+        let listed =
+            collect_listed_images(root, None, &rules, None, &mut Vec::new()).expect("list");
+
+        let started = Instant::now();
+        let serial: Vec<_> = listed
+            .iter()
+            .map(|file| compute_file_id(&file.path, &rules))
+            .collect();
+        let serial_time = started.elapsed();
+
+        let mut batched_files = listed.clone();
+        let started = Instant::now();
+        let batched = listed_file_ids(&mut batched_files, &rules, None);
+        let batched_time = started.elapsed();
+
+        assert_eq!(batched, serial);
+        println!(
+            "{} files: one stat each {:?}, batched {:?} ({:.1}x)",
+            listed.len(),
+            serial_time,
+            batched_time,
+            serial_time.as_secs_f64() / batched_time.as_secs_f64().max(1e-9)
+        );
+    }
+
+    #[test]
+    fn graded_candidates_are_interned_in_insertion_order() {
+        let mut map = GradedCandidates::default();
+        map.insert("img_001".to_string(), "IND/img_001.jpg");
+        map.insert("img_002".to_string(), "IND/img_002.jpg");
+        map.insert("img_001".to_string(), "img_001.jpg");
+        map.insert("img_001".to_string(), "IND/crops/img_001_a.jpg");
+        assert_eq!(map.folders.len(), 3);
+        assert_eq!(
+            map.candidates("img_001"),
+            vec!["IND/img_001.jpg", "img_001.jpg", "IND/crops/img_001_a.jpg"]
+        );
+        assert_eq!(map.remove("img_002"), vec!["IND/img_002.jpg"]);
+        assert!(map.candidates("img_002").is_empty());
+        assert_eq!(map.iter().count(), 3);
+    }
+
+    #[test]
+    fn survey_fields_come_from_base_key() {
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+        assert_eq!(
+            survey_fields("20240315_AB", &compiled),
+            (Some("2024-03-15".to_string()), Some("AB".to_string()))
+        );
+        assert_eq!(survey_fields("TRIP-0421-N", &compiled), (None, None));
+
+        let custom = compile_rules(&Rules {
+            survey_fields_regex: "^TRIP-(?P<area>\\d{4})".to_string(),
+            ..Rules::default()
+        })
+        .expect("compile");
+        assert_eq!(
+            survey_fields("TRIP-0421-N", &custom),
+            (None, Some("0421".to_string()))
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn file_ids_are_total_and_stable(
+            name in "[^/\\\\\u{0}]{0,40}",
+            size in prop::option::of(any::<u64>()),
+        ) {
+            let compiled = default_compiled(false);
+            let path = Path::new("survey").join(&name);
+            let (file_id, ambiguous) = file_id_with_size(&path, compiled, || size);
+            prop_assert_eq!(
+                file_id_with_size(&path, compiled, || size),
+                (file_id.clone(), ambiguous)
+            );
+            match regex_file_id(&path, compiled) {
+                Some(id) => prop_assert_eq!((file_id, ambiguous), (id, false)),
+                None => prop_assert_eq!(ambiguous, size.is_none()),
+            }
+        }
+
+        #[test]
+        fn file_ids_ignore_edit_suffixes(
+            stem in "[\\p{L}\\p{M} .-]{1,12}",
+            frame in "[0-9]{3,5}",
+            suffixes in prop::collection::vec("[ _][A-Za-z0-9]{1,6}", 0..3),
+            extension in "(jpg|JPG|jpeg|png|cr3)",
+        ) {
+            let compiled = default_compiled(false);
+            let expected = format!("{}_{}", stem, frame).to_lowercase();
+            let path = PathBuf::from(format!(
+                "{}_{}{}.{}",
+                stem,
+                frame,
+                suffixes.concat(),
+                extension
+            ));
+            prop_assert_eq!(
+                file_id_with_size(&path, compiled, || None),
+                (expected, false)
+            );
+        }
+    }
+}
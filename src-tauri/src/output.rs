@@ -0,0 +1,3355 @@
+//! Result files: CSV and the other row formats, reports, exports and the
+//! output folder lock.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array, UInt8Array};
+use arrow_schema::{DataType, Field, Schema};
+use csv::{Terminator, WriterBuilder};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message as EmailMessage, SmtpTransport, Transport};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use postgres::config::SslMode;
+use postgres::NoTls;
+use rusqlite::Connection;
+use rust_xlsxwriter::{ExcelDateTime, Format, Image as XlsxImage, Workbook, Worksheet};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use serde::{Deserialize, Serialize};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use walkdir::DirEntry;
+
+use crate::classify::WinnerDetails;
+use crate::control::CancelToken;
+use crate::matching::{open_image, VideoFrame};
+use crate::paths::long_path;
+use crate::problems::ProblemItem;
+use crate::rules::Rules;
+use crate::runs::{RootRunOptions, RunProvenance, RunSummary, RUN_SUMMARY_FILENAME};
+use crate::settings::{PostgresSettings, PostgresTls, SmtpSecurity, SmtpSettings};
+use crate::tracks::TrackFix;
+use crate::{AppError, ROW_BATCH_SIZE, THUMBNAIL_PX};
+
+pub(crate) const OUTPUT_MARKER_FILENAME: &str = ".survey-labeler-output";
+const OUTPUT_LOCK_FILENAME: &str = ".survey-labeler.lock";
+/// File format of the result rows (merged, per-survey and single-pair output).
+/// Problems, pairings and the other reports stay CSV.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// Workbook with text, number and date cells, so ids keep their leading
+    /// zeros and no delimiter has to be guessed.
+    Xlsx,
+    /// Snappy-compressed Apache Parquet with typed, nullable columns, for
+    /// loading millions of rows into pandas or DuckDB.
+    Parquet,
+    /// One JSON object per row, keyed by column header, with numbers as
+    /// numbers and empty values as `null`.
+    Jsonl,
+}
+
+impl OutputFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// `path` with the extension of this format; CSV names are kept as given.
+    pub(crate) fn output_path(self, path: PathBuf) -> PathBuf {
+        match self {
+            OutputFormat::Csv => path,
+            _ => path.with_extension(self.extension()),
+        }
+    }
+
+    /// Whether rows can be added to an existing file of this format.
+    pub(crate) fn appendable(self) -> bool {
+        matches!(self, OutputFormat::Csv | OutputFormat::Jsonl)
+    }
+}
+
+/// What a run does with a result file that already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnExisting {
+    #[default]
+    Overwrite,
+    /// Leave the file untouched and write nothing to it.
+    Skip,
+    /// Add the new rows below the existing ones; CSV with the same columns only.
+    Append,
+    /// Refuse to run before any result file is written.
+    Error,
+    /// Write next to it with a `_YYYYMMDD-HHMMSS` (UTC) suffix.
+    TimestampSuffix,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailMode {
+    #[default]
+    None,
+    All,
+    /// Only rows labeled dolphin = 1.
+    Dolphin,
+}
+
+/// Formatting applied to every CSV a run writes.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CsvSettings {
+    #[serde(default)]
+    pub line_ending: LineEnding,
+    /// Field separator; `semicolon` suits Excel on European locales.
+    #[serde(default)]
+    pub delimiter: CsvDelimiter,
+    #[serde(default)]
+    pub quote_style: CsvQuoteStyle,
+    /// Start every CSV with a UTF-8 byte order mark so Excel reads umlauts in
+    /// folder names correctly.
+    #[serde(default)]
+    pub utf8_bom: bool,
+    /// Renames result columns, keyed by the built-in column name
+    /// (e.g. `dolphin` -> `presence`). Unlisted columns keep their name.
+    #[serde(default)]
+    pub header_map: BTreeMap<String, String>,
+    /// Built-in names of the columns to write, in this order. Empty writes
+    /// every available column in the default order; optional columns still
+    /// need their `include_*` switch. `survey_id_base`, `raw_relpath` and
+    /// `dolphin` must be kept.
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// Append the `winner_*` columns describing the winning graded candidate.
+    #[serde(default)]
+    pub include_winner_details: bool,
+    /// Append `survey_date` and `area` parsed from the base key.
+    #[serde(default)]
+    pub include_survey_fields: bool,
+    /// Append a `notes` column with grader notes for the image's frame.
+    #[serde(default)]
+    pub include_notes: bool,
+    /// Append a `quality` column from the rules' `quality_tokens`.
+    #[serde(default)]
+    pub include_quality: bool,
+    /// Append `label_confidence`, a 0-1 score for how firmly the `dolphin`
+    /// value is backed by the graded candidates.
+    #[serde(default)]
+    pub include_confidence: bool,
+    /// Append `decision_reason`, naming the token or rule that set `dolphin`.
+    #[serde(default)]
+    pub include_decision_reason: bool,
+    /// Append `label_source`: `manual` for rows whose `dolphin` was set in
+    /// the review queue, `auto` for the rest.
+    #[serde(default)]
+    pub include_label_source: bool,
+    /// Append `capture_datetime` from the raw image's EXIF `DateTimeOriginal`.
+    #[serde(default)]
+    pub include_capture_time: bool,
+    /// Append `gps_lat`/`gps_lon` in decimal degrees from the raw image's EXIF
+    /// GPS tags.
+    #[serde(default)]
+    pub include_exif_gps: bool,
+    /// Append `track_lat`/`track_lon`/`track_error_m` interpolated from the
+    /// survey's GPX track at the image's capture time (see `TrackSettings`).
+    #[serde(default)]
+    pub include_track_position: bool,
+    /// Append `video_frame`/`video_time_s`, the clip frame a graded still was
+    /// grabbed from and its offset into the clip (video mode).
+    #[serde(default)]
+    pub include_video_frame: bool,
+    /// Append `raw_hyperlink`/`graded_hyperlink` columns pointing at the images.
+    #[serde(default)]
+    pub hyperlinks: HyperlinkStyle,
+    /// Names of the rules' extra label columns, filled in when a run starts.
+    #[serde(skip)]
+    pub(crate) label_columns: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HyperlinkStyle {
+    #[default]
+    None,
+    /// `=HYPERLINK("file:///...", "name")`, clickable in Excel and LibreOffice.
+    Formula,
+    /// Plain `file:///...` URI.
+    Uri,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsvDelimiter {
+    #[default]
+    Comma,
+    Semicolon,
+    Tab,
+}
+
+impl CsvDelimiter {
+    fn byte(self) -> u8 {
+        match self {
+            CsvDelimiter::Comma => b',',
+            CsvDelimiter::Semicolon => b';',
+            CsvDelimiter::Tab => b'\t',
+        }
+    }
+}
+
+/// When fields are wrapped in double quotes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvQuoteStyle {
+    /// Only fields containing the delimiter, quotes or line breaks.
+    #[default]
+    Necessary,
+    Always,
+    /// Every field that is not a number.
+    NonNumeric,
+    /// No quoting at all. A field containing the delimiter or a line break
+    /// fails the run instead of shifting the columns of its row.
+    Never,
+}
+
+impl CsvQuoteStyle {
+    fn to_csv(self) -> csv::QuoteStyle {
+        match self {
+            CsvQuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            CsvQuoteStyle::Always => csv::QuoteStyle::Always,
+            CsvQuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            CsvQuoteStyle::Never => csv::QuoteStyle::Never,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CsvRow {
+    pub(crate) survey_id_base: String,
+    pub(crate) raw_relpath: String,
+    pub(crate) filename: String,
+    pub(crate) dolphin: u8,
+    /// One value per entry of `Rules::labels`, in the same order.
+    pub(crate) labels: Vec<u8>,
+    pub(crate) graded_relpath: String,
+    pub(crate) graded_hits: u64,
+    pub(crate) graded_winner_type: String,
+    pub(crate) survey_id_raw_detected: Option<String>,
+    pub(crate) survey_id_graded_detected: Option<String>,
+    pub(crate) winner: Option<WinnerDetails>,
+    pub(crate) raw_path: PathBuf,
+    pub(crate) survey_date: Option<String>,
+    pub(crate) area: Option<String>,
+    pub(crate) notes: Option<String>,
+    pub(crate) quality: Option<String>,
+    pub(crate) label_confidence: f64,
+    pub(crate) decision_reason: String,
+    pub(crate) capture_datetime: Option<String>,
+    /// Latitude and longitude in decimal degrees.
+    pub(crate) gps: Option<(f64, f64)>,
+    pub(crate) track_position: Option<TrackFix>,
+    pub(crate) video_frame: Option<VideoFrame>,
+    /// `dolphin` comes from a manual label of the review queue.
+    pub(crate) manual_label: bool,
+}
+
+pub(crate) fn is_output_dir(entry: &DirEntry) -> bool {
+    entry.file_type().is_dir() && entry.path().join(OUTPUT_MARKER_FILENAME).is_file()
+}
+
+pub(crate) fn mark_output_dir(output_dir: &Path) -> Result<(), AppError> {
+    let marker = output_dir.join(OUTPUT_MARKER_FILENAME);
+    if !long_path(&marker).exists() {
+        fs::write(
+            long_path(&marker),
+            "Created by Survey Label Exporter. Folders containing this file are skipped when scanning.\n",
+        )?;
+    }
+    Ok(())
+}
+
+/// Exclusive lock on an output folder while a run writes to it. The OS drops
+/// the lock when the process ends, so a crashed run leaves no stale lock, and
+/// the lock file is removed when the run finishes.
+pub(crate) struct OutputLock {
+    path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl OutputLock {
+    pub(crate) fn acquire(output_dir: &Path, run_id: &str) -> Result<Self, AppError> {
+        let path = long_path(&output_dir.join(OUTPUT_LOCK_FILENAME)).into_owned();
+        loop {
+            let mut file = open_output_lock(&path)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(fs::TryLockError::WouldBlock) => {
+                    return Err(AppError::Busy(format!(
+                "{} is in use by another run; wait for it to finish or pick another output folder.",
+                output_dir.to_string_lossy()
+            )))
+                }
+                Err(fs::TryLockError::Error(err)) => return Err(err.into()),
+            }
+            use std::io::Write;
+            file.set_len(0)?;
+            writeln!(file, "{}", run_id)?;
+            // The run before may have removed the file between our open and
+            // our lock; then the lock is on a file nobody else sees.
+            if fs::read_to_string(&path).is_ok_and(|holder| holder.trim() == run_id) {
+                return Ok(Self {
+                    path,
+                    file: Some(file),
+                });
+            }
+        }
+    }
+
+    /// Blocks until no run holds the lock on `output_dir`, or `cancel` is
+    /// set.
+    pub(crate) fn wait(output_dir: &Path, cancel: &CancelToken) -> Result<(), AppError> {
+        let path = long_path(&output_dir.join(OUTPUT_LOCK_FILENAME)).into_owned();
+        loop {
+            cancel.check()?;
+            if !path.exists() {
+                return Ok(());
+            }
+            match open_output_lock(&path)?.try_lock() {
+                Ok(()) => return Ok(()),
+                Err(fs::TryLockError::WouldBlock) => {
+                    std::thread::sleep(std::time::Duration::from_millis(200))
+                }
+                Err(fs::TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so no other run locks the old file
+        // after it is gone.
+        let _ = fs::remove_file(&self.path);
+        self.file.take();
+    }
+}
+
+fn open_output_lock(path: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+}
+
+pub(crate) fn hyperlink(path: &Path, style: HyperlinkStyle) -> String {
+    let uri = file_uri(path);
+    match style {
+        HyperlinkStyle::None => String::new(),
+        HyperlinkStyle::Uri => uri,
+        HyperlinkStyle::Formula => {
+            let label = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            format!(
+                "=HYPERLINK(\"{}\", \"{}\")",
+                uri.replace('"', "\"\""),
+                label.replace('"', "\"\"")
+            )
+        }
+    }
+}
+
+/// `file://` URI for an absolute path; UNC paths become `file://server/share/...`.
+pub(crate) fn file_uri(path: &Path) -> String {
+    let text = path.to_string_lossy().replace('\\', "/");
+    let (prefix, rest) = match text.strip_prefix("//") {
+        Some(unc) => ("file://", unc.to_string()),
+        None if text.starts_with('/') => ("file://", text.clone()),
+        None => ("file:///", text.clone()),
+    };
+    let mut encoded = String::from(prefix);
+    for byte in rest.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+pub(crate) fn normalize_relpath(path: &Path, root: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    let rel_str = rel.to_string_lossy().to_string();
+    rel_str.replace('\\', "/")
+}
+
+pub(crate) fn csv_writer(
+    path: &Path,
+    settings: &CsvSettings,
+) -> Result<csv::Writer<fs::File>, AppError> {
+    use std::io::Write;
+    let mut file = fs::File::create(long_path(path))?;
+    if settings.utf8_bom {
+        file.write_all("\u{feff}".as_bytes())?;
+    }
+    Ok(csv_builder(settings).has_headers(true).from_writer(file))
+}
+
+/// Writes one record, refusing fields that quote style `never` cannot write
+/// so that the file still reads back with the right columns.
+pub(crate) fn write_csv_record<I, T>(
+    writer: &mut csv::Writer<fs::File>,
+    record: I,
+    settings: &CsvSettings,
+) -> Result<(), AppError>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let record: Vec<T> = record.into_iter().collect();
+    if settings.quote_style == CsvQuoteStyle::Never {
+        let delimiter = settings.delimiter.byte();
+        if let Some(field) = record.iter().map(AsRef::as_ref).find(|field| {
+            field
+                .iter()
+                .any(|byte| matches!(*byte, b'\n' | b'\r') || *byte == delimiter)
+        }) {
+            return Err(AppError::Validation(format!(
+                "The field \"{}\" contains the delimiter or a line break, which quote style never cannot write. Choose another quote style.",
+                String::from_utf8_lossy(field)
+            )));
+        }
+    }
+    writer.write_record(record)?;
+    Ok(())
+}
+
+fn csv_builder(settings: &CsvSettings) -> WriterBuilder {
+    let terminator = match settings.line_ending {
+        LineEnding::Lf => Terminator::Any(b'\n'),
+        LineEnding::Crlf => Terminator::CRLF,
+    };
+    let mut builder = WriterBuilder::new();
+    builder
+        .terminator(terminator)
+        .delimiter(settings.delimiter.byte())
+        .quote_style(settings.quote_style.to_csv());
+    builder
+}
+
+/// Opens a CSV written by a run, taking the delimiter from its header line
+/// since the run may have used other `CsvSettings`. A UTF-8 BOM is skipped
+/// by the reader.
+pub(crate) fn run_csv_reader(path: &Path) -> Result<csv::Reader<fs::File>, AppError> {
+    use std::io::BufRead;
+    let mut header = String::new();
+    let path = long_path(path);
+    std::io::BufReader::new(fs::File::open(&path)?).read_line(&mut header)?;
+    let delimiter = [b'\t', b';', b',']
+        .into_iter()
+        .max_by_key(|delimiter| header.bytes().filter(|byte| byte == delimiter).count())
+        .unwrap_or(b',');
+    Ok(csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_path(&path)?)
+}
+
+const WINNER_DETAIL_COLUMNS: [&str; 4] = [
+    "winner_top_folder",
+    "winner_depth",
+    "winner_matched_tokens",
+    "winner_full_path",
+];
+
+const SURVEY_FIELD_COLUMNS: [&str; 2] = ["survey_date", "area"];
+
+const NOTES_COLUMN: &str = "notes";
+
+const QUALITY_COLUMN: &str = "quality";
+
+pub(crate) const CONFIDENCE_COLUMN: &str = "label_confidence";
+
+pub(crate) const DECISION_REASON_COLUMN: &str = "decision_reason";
+
+pub(crate) const LABEL_SOURCE_COLUMN: &str = "label_source";
+
+pub(crate) const CAPTURE_TIME_COLUMN: &str = "capture_datetime";
+
+const GPS_COLUMNS: [&str; 2] = ["gps_lat", "gps_lon"];
+
+const TRACK_COLUMNS: [&str; 3] = ["track_lat", "track_lon", "track_error_m"];
+
+const VIDEO_COLUMNS: [&str; 2] = ["video_frame", "video_time_s"];
+
+const HYPERLINK_COLUMNS: [&str; 2] = ["raw_hyperlink", "graded_hyperlink"];
+
+/// Columns a `columns` selection may not drop.
+const REQUIRED_COLUMNS: [&str; 3] = ["survey_id_base", "raw_relpath", "dolphin"];
+
+const ROW_COLUMNS: [&str; 9] = [
+    "survey_id_base",
+    "raw_relpath",
+    "filename",
+    "dolphin",
+    "graded_relpath",
+    "graded_hits",
+    "graded_winner_type",
+    "survey_id_raw_detected",
+    "survey_id_graded_detected",
+];
+
+/// Columns written, in order: the `columns` selection or every available one.
+pub(crate) fn row_columns(settings: &CsvSettings) -> Vec<&str> {
+    if settings.columns.is_empty() {
+        available_columns(settings)
+    } else {
+        settings.columns.iter().map(String::as_str).collect()
+    }
+}
+
+/// Every column the settings can produce, in the default order.
+fn available_columns(settings: &CsvSettings) -> Vec<&str> {
+    let mut columns = ROW_COLUMNS[..4].to_vec();
+    columns.extend(settings.label_columns.iter().map(String::as_str));
+    columns.extend(&ROW_COLUMNS[4..]);
+    if settings.include_survey_fields {
+        columns.extend(SURVEY_FIELD_COLUMNS);
+    }
+    if settings.include_notes {
+        columns.push(NOTES_COLUMN);
+    }
+    if settings.include_quality {
+        columns.push(QUALITY_COLUMN);
+    }
+    if settings.include_confidence {
+        columns.push(CONFIDENCE_COLUMN);
+    }
+    if settings.include_decision_reason {
+        columns.push(DECISION_REASON_COLUMN);
+    }
+    if settings.include_label_source {
+        columns.push(LABEL_SOURCE_COLUMN);
+    }
+    if settings.include_capture_time {
+        columns.push(CAPTURE_TIME_COLUMN);
+    }
+    if settings.include_exif_gps {
+        columns.extend(GPS_COLUMNS);
+    }
+    if settings.include_track_position {
+        columns.extend(TRACK_COLUMNS);
+    }
+    if settings.include_video_frame {
+        columns.extend(VIDEO_COLUMNS);
+    }
+    if settings.include_winner_details {
+        columns.extend(WINNER_DETAIL_COLUMNS);
+    }
+    if settings.hyperlinks != HyperlinkStyle::None {
+        columns.extend(HYPERLINK_COLUMNS);
+    }
+    columns
+}
+
+pub(crate) fn label_columns(rules: &Rules) -> Vec<String> {
+    rules
+        .labels
+        .iter()
+        .map(|label| label.name.trim().to_string())
+        .collect()
+}
+
+/// Resolves the result CSV header, validating `columns` and applying
+/// `header_map`.
+pub(crate) fn row_headers(settings: &CsvSettings) -> Result<Vec<String>, AppError> {
+    let available = available_columns(settings);
+    let mut selected = HashSet::new();
+    for column in &settings.columns {
+        if !available.contains(&column.as_str()) {
+            return Err(AppError::Validation(format!(
+                "Column list refers to unknown or disabled column '{}'.",
+                column
+            )));
+        }
+        if !selected.insert(column.as_str()) {
+            return Err(AppError::Validation(format!(
+                "Column list names '{}' twice.",
+                column
+            )));
+        }
+    }
+    if !settings.columns.is_empty() {
+        // Resume, diff, review and the dataset export find rows by these.
+        for required in REQUIRED_COLUMNS {
+            if !selected.contains(required) {
+                return Err(AppError::Validation(format!(
+                    "Column list must keep '{}'.",
+                    required
+                )));
+            }
+        }
+    }
+    let columns = row_columns(settings);
+    for (column, header) in &settings.header_map {
+        if !available.contains(&column.as_str()) {
+            return Err(AppError::Validation(format!(
+                "Header map refers to unknown column '{}'.",
+                column
+            )));
+        }
+        if header.trim().is_empty() {
+            return Err(AppError::Validation(format!(
+                "Header map gives column '{}' an empty name.",
+                column
+            )));
+        }
+    }
+    let headers: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            settings
+                .header_map
+                .get(*column)
+                .map(|header| header.trim().to_string())
+                .unwrap_or_else(|| column.to_string())
+        })
+        .collect();
+    let mut seen = HashSet::new();
+    for header in &headers {
+        if !seen.insert(header.as_str()) {
+            return Err(AppError::Validation(format!(
+                "Header map produces duplicate column name '{}'.",
+                header
+            )));
+        }
+    }
+    Ok(headers)
+}
+
+pub(crate) fn init_csv_writer(
+    path: &Path,
+    settings: &CsvSettings,
+) -> Result<csv::Writer<fs::File>, AppError> {
+    let headers = row_headers(settings)?;
+    let mut writer = csv_writer(path, settings)?;
+    write_csv_record(&mut writer, &headers, settings)?;
+    Ok(writer)
+}
+
+/// Reopens a CSV written by an earlier run, cut back to `len` bytes, for
+/// appending rows without repeating the header.
+pub(crate) fn append_csv_writer(
+    path: &Path,
+    len: u64,
+    settings: &CsvSettings,
+) -> Result<csv::Writer<fs::File>, AppError> {
+    let file = fs::OpenOptions::new().append(true).open(long_path(path))?;
+    file.set_len(len)?;
+    Ok(csv_builder(settings).has_headers(false).from_writer(file))
+}
+
+pub(crate) fn write_rows_to_writer(
+    writer: &mut csv::Writer<fs::File>,
+    rows: &[CsvRow],
+    settings: &CsvSettings,
+) -> Result<(), AppError> {
+    for row in rows {
+        write_csv_record(writer, row_record(row, settings), settings)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub(crate) fn row_record(row: &CsvRow, settings: &CsvSettings) -> Vec<String> {
+    let record = full_record(row, settings);
+    if settings.columns.is_empty() {
+        return record;
+    }
+    let available = available_columns(settings);
+    settings
+        .columns
+        .iter()
+        .map(|column| {
+            available
+                .iter()
+                .position(|name| name == column)
+                .map(|index| record[index].clone())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Values of every available column, in `available_columns` order.
+fn full_record(row: &CsvRow, settings: &CsvSettings) -> Vec<String> {
+    let mut record = vec![
+        row.survey_id_base.clone(),
+        row.raw_relpath.clone(),
+        row.filename.clone(),
+        row.dolphin.to_string(),
+    ];
+    record.extend(row.labels.iter().map(u8::to_string));
+    record.extend([
+        row.graded_relpath.clone(),
+        row.graded_hits.to_string(),
+        row.graded_winner_type.clone(),
+        row.survey_id_raw_detected.clone().unwrap_or_default(),
+        row.survey_id_graded_detected.clone().unwrap_or_default(),
+    ]);
+    if settings.include_survey_fields {
+        record.push(row.survey_date.clone().unwrap_or_default());
+        record.push(row.area.clone().unwrap_or_default());
+    }
+    if settings.include_notes {
+        record.push(row.notes.clone().unwrap_or_default());
+    }
+    if settings.include_quality {
+        record.push(row.quality.clone().unwrap_or_default());
+    }
+    if settings.include_confidence {
+        record.push(format!("{:.2}", row.label_confidence));
+    }
+    if settings.include_decision_reason {
+        record.push(row.decision_reason.clone());
+    }
+    if settings.include_label_source {
+        record.push(if row.manual_label { "manual" } else { "auto" }.to_string());
+    }
+    if settings.include_capture_time {
+        record.push(row.capture_datetime.clone().unwrap_or_default());
+    }
+    if settings.include_exif_gps {
+        match row.gps {
+            Some((lat, lon)) => {
+                record.push(format!("{:.6}", lat));
+                record.push(format!("{:.6}", lon));
+            }
+            None => record.extend([String::new(), String::new()]),
+        }
+    }
+    if settings.include_track_position {
+        match row.track_position {
+            Some(fix) => {
+                record.push(format!("{:.6}", fix.lat));
+                record.push(format!("{:.6}", fix.lon));
+                record.push(format!("{:.1}", fix.error_m));
+            }
+            None => record.extend([String::new(), String::new(), String::new()]),
+        }
+    }
+    if settings.include_video_frame {
+        match row.video_frame {
+            Some(video) => {
+                record.push(video.frame.to_string());
+                record.push(
+                    video
+                        .seconds
+                        .map(|seconds| format!("{:.3}", seconds))
+                        .unwrap_or_default(),
+                );
+            }
+            None => record.extend([String::new(), String::new()]),
+        }
+    }
+    if settings.include_winner_details {
+        match &row.winner {
+            Some(winner) => record.extend([
+                winner.top_folder.clone(),
+                winner.depth.to_string(),
+                winner.matched_tokens.join(";"),
+                winner.full_path.clone(),
+            ]),
+            None => record.extend(std::iter::repeat_n(String::new(), 4)),
+        }
+    }
+    if settings.hyperlinks != HyperlinkStyle::None {
+        record.push(hyperlink(&row.raw_path, settings.hyperlinks));
+        record.push(
+            row.winner
+                .as_ref()
+                .map(|winner| hyperlink(Path::new(&winner.full_path), settings.hyperlinks))
+                .unwrap_or_default(),
+        );
+    }
+    record
+}
+
+/// Rows of one worksheet, the header row included.
+const XLSX_MAX_ROWS: u32 = 1_048_576;
+
+/// A workbook whose rows are written in constant memory mode: a row goes to
+/// a temporary file as soon as the next one starts, so only the current row
+/// is held. Rows past Excel's limit of 1,048,576 per sheet continue on
+/// `<name> (2)`, `<name> (3)` and so on, each starting with the header row.
+pub(crate) struct SheetWriter {
+    workbook: Workbook,
+    name: String,
+    headers: Vec<String>,
+    /// Column index and width in pixels, set on every sheet.
+    column_widths: Vec<(u16, u16)>,
+    sheets: usize,
+    next_row: u32,
+    rows_per_sheet: u32,
+}
+
+impl SheetWriter {
+    pub(crate) fn new(
+        name: &str,
+        headers: Vec<String>,
+        column_widths: Vec<(u16, u16)>,
+    ) -> Result<Self, AppError> {
+        let mut writer = Self {
+            workbook: Workbook::new(),
+            name: name.to_string(),
+            headers,
+            column_widths,
+            sheets: 0,
+            next_row: 0,
+            rows_per_sheet: XLSX_MAX_ROWS,
+        };
+        writer.add_sheet()?;
+        Ok(writer)
+    }
+
+    fn add_sheet(&mut self) -> Result<(), AppError> {
+        self.sheets += 1;
+        let name = match self.sheets {
+            1 => self.name.clone(),
+            count => format!("{} ({})", self.name, count),
+        };
+        let sheet = self.workbook.add_worksheet_with_constant_memory();
+        sheet.set_name(name)?;
+        for &(col, width) in &self.column_widths {
+            sheet.set_column_width_pixels(col, width)?;
+        }
+        for (col, header) in self.headers.iter().enumerate() {
+            sheet.write_string(0, col as u16, header)?;
+        }
+        self.next_row = 1;
+        Ok(())
+    }
+
+    /// The sheet and index of the next row, on a new sheet once the current
+    /// one is full.
+    pub(crate) fn next_row(&mut self) -> Result<(&mut Worksheet, u32), AppError> {
+        if self.next_row >= self.rows_per_sheet {
+            self.add_sheet()?;
+        }
+        let row = self.next_row;
+        self.next_row += 1;
+        Ok((self.workbook.worksheet_from_index(self.sheets - 1)?, row))
+    }
+
+    pub(crate) fn save(mut self, path: &Path) -> Result<(), AppError> {
+        self.workbook.save(long_path(path))?;
+        Ok(())
+    }
+}
+
+/// The QA workbook of a run: the result rows with a leading thumbnail
+/// column when `thumbnails` is set, filled survey by survey as they finish.
+/// Images that cannot be decoded are skipped.
+pub(crate) struct QaWorkbook {
+    sheets: SheetWriter,
+    thumbnails: ThumbnailMode,
+    thumbnail_limit: usize,
+    embedded: usize,
+}
+
+impl QaWorkbook {
+    pub(crate) fn new(options: &RootRunOptions) -> Result<Self, AppError> {
+        let mut headers = row_headers(&options.csv)?;
+        let mut column_widths = Vec::new();
+        if options.thumbnails != ThumbnailMode::None {
+            headers.insert(0, "thumbnail".to_string());
+            column_widths.push((0, THUMBNAIL_PX as u16));
+        }
+        Ok(Self {
+            sheets: SheetWriter::new("Results", headers, column_widths)?,
+            thumbnails: options.thumbnails,
+            thumbnail_limit: options.thumbnail_limit,
+            embedded: 0,
+        })
+    }
+
+    pub(crate) fn write_rows(
+        &mut self,
+        rows: &[CsvRow],
+        settings: &CsvSettings,
+    ) -> Result<(), AppError> {
+        let columns = row_columns(settings);
+        let first_col = u16::from(self.thumbnails != ThumbnailMode::None);
+        let date_format = Format::new().set_num_format("yyyy-mm-dd");
+        for row in rows {
+            let (sheet, excel_row) = self.sheets.next_row()?;
+            let wants_thumbnail = match self.thumbnails {
+                ThumbnailMode::None => false,
+                ThumbnailMode::All => true,
+                ThumbnailMode::Dolphin => row.dolphin == 1,
+            };
+            // Rows are flushed in order, so the thumbnail goes in first.
+            if wants_thumbnail && self.embedded < self.thumbnail_limit {
+                if let Some(png) = thumbnail_png(&row.raw_path) {
+                    let image = XlsxImage::new_from_buffer(&png)?;
+                    sheet.set_row_height_pixels(excel_row, THUMBNAIL_PX as u16)?;
+                    sheet.insert_image_fit_to_cell(excel_row, 0, &image, true)?;
+                    self.embedded += 1;
+                }
+            }
+            for (col, value) in row_record(row, settings).into_iter().enumerate() {
+                write_result_cell(
+                    sheet,
+                    (excel_row, first_col + col as u16),
+                    columns[col],
+                    value,
+                    settings,
+                    &date_format,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn save(self, path: &Path) -> Result<(), AppError> {
+        self.sheets.save(path)
+    }
+}
+
+/// Column names of the Access import sheet; they match the `Sightings`
+/// table, so keep them stable when result columns change.
+pub(crate) const ACCESS_COLUMNS: [&str; 11] = [
+    "SurveyID",
+    "SurveyDate",
+    "Area",
+    "RawPath",
+    "FileName",
+    "Dolphin",
+    "GradedPath",
+    "GradedHits",
+    "WinnerType",
+    "DetectedID",
+    "RunID",
+];
+
+/// The Access export of a run, filled survey by survey as they finish. Rows
+/// are written with native cell types (date, boolean, number) so the Access
+/// import wizard infers Date/Time, Yes/No and Number fields. Past a sheet's
+/// row limit the rows continue on `Sightings (2)`, to be imported as well.
+pub(crate) struct AccessExport {
+    sheets: SheetWriter,
+    date_format: Format,
+    run_id: String,
+}
+
+impl AccessExport {
+    pub(crate) fn new(run_id: &str) -> Result<Self, AppError> {
+        let headers = ACCESS_COLUMNS
+            .iter()
+            .map(|header| header.to_string())
+            .collect();
+        Ok(Self {
+            sheets: SheetWriter::new("Sightings", headers, Vec::new())?,
+            date_format: Format::new().set_num_format("yyyy-mm-dd"),
+            run_id: run_id.to_string(),
+        })
+    }
+
+    pub(crate) fn write_rows(&mut self, rows: &[CsvRow]) -> Result<(), AppError> {
+        for row in rows {
+            self.write_row(row)?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &CsvRow) -> Result<(), AppError> {
+        let (sheet, excel_row) = self.sheets.next_row()?;
+        sheet.write_string(excel_row, 0, &row.survey_id_base)?;
+        if let Some(date) = row.survey_date.as_deref().and_then(excel_date) {
+            sheet.write_datetime_with_format(excel_row, 1, &date, &self.date_format)?;
+        }
+        if let Some(area) = &row.area {
+            sheet.write_string(excel_row, 2, area)?;
+        }
+        sheet.write_string(excel_row, 3, &row.raw_relpath)?;
+        sheet.write_string(excel_row, 4, &row.filename)?;
+        sheet.write_boolean(excel_row, 5, row.dolphin == 1)?;
+        sheet.write_string(excel_row, 6, &row.graded_relpath)?;
+        sheet.write_number(excel_row, 7, row.graded_hits as f64)?;
+        sheet.write_string(excel_row, 8, &row.graded_winner_type)?;
+        if let Some(detected) = &row.survey_id_raw_detected {
+            sheet.write_string(excel_row, 9, detected)?;
+        }
+        sheet.write_string(excel_row, 10, &self.run_id)?;
+        Ok(())
+    }
+
+    pub(crate) fn save(self, path: &Path) -> Result<(), AppError> {
+        self.sheets.save(path)
+    }
+}
+
+/// Parses a `YYYY-MM-DD` survey date; other formats are left out of the
+/// typed date column.
+pub(crate) fn excel_date(value: &str) -> Option<ExcelDateTime> {
+    let mut parts = value.splitn(3, '-').map(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    ExcelDateTime::from_ymd(
+        u16::try_from(year).ok()?,
+        u8::try_from(month).ok()?,
+        u8::try_from(day).ok()?,
+    )
+    .ok()
+}
+
+pub(crate) fn thumbnail_png(path: &Path) -> Option<Vec<u8>> {
+    let thumbnail = open_image(path)?.thumbnail(THUMBNAIL_PX, THUMBNAIL_PX);
+    let mut png = std::io::Cursor::new(Vec::new());
+    thumbnail.write_to(&mut png, image::ImageFormat::Png).ok()?;
+    Some(png.into_inner())
+}
+
+/// The Access, SQLite and PostgreSQL exports of a run, given the rows of each
+/// survey once it finished. The PostgreSQL export is best effort: when it
+/// fails, it is dropped and the run goes on without it.
+#[derive(Default)]
+pub(crate) struct RunExports {
+    pub(crate) access: Option<AccessExport>,
+    pub(crate) sqlite: Option<SqliteExport>,
+    pub(crate) postgres: Option<PostgresExport>,
+    /// Why the PostgreSQL export was given up.
+    pub(crate) postgres_error: Option<String>,
+}
+
+impl RunExports {
+    pub(crate) fn write_rows(&mut self, rows: &[CsvRow]) -> Result<(), AppError> {
+        if let Some(access) = &mut self.access {
+            access.write_rows(rows)?;
+        }
+        if let Some(sqlite) = &mut self.sqlite {
+            sqlite.write_rows(rows)?;
+        }
+        if let Some(Err(err)) = self.postgres.as_mut().map(|export| export.write_rows(rows)) {
+            self.postgres = None;
+            self.postgres_error = Some(err.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Result files the rows of one survey are streamed to while it runs, in
+/// batches of `ROW_BATCH_SIZE`. `rollback` takes back what a survey that
+/// fails or times out wrote, so only finished surveys reach the output.
+///
+/// Only CSV and JSON Lines output can be cut back. The rows of a survey going
+/// to a Parquet or workbook merged file, to the QA workbook or to the
+/// `exports` are held until it finishes.
+pub(crate) struct SurveyOutput<'a> {
+    settings: &'a CsvSettings,
+    /// The survey's own result file, with its length before the survey when
+    /// appended to. A new or overwritten file is written to its `part_path`
+    /// and only takes the place of the old one on `commit`.
+    file: Option<(PathBuf, RowWriter, Option<u64>)>,
+    merged: Option<&'a mut RowWriter>,
+    /// Length of the merged file before the survey. Without one the merged
+    /// file cannot be cut back, so its rows are held until `commit`.
+    merged_len: Option<u64>,
+    qa: Option<&'a mut QaWorkbook>,
+    held: Vec<CsvRow>,
+    exports: Option<&'a mut RunExports>,
+    batch: Vec<CsvRow>,
+}
+
+impl<'a> SurveyOutput<'a> {
+    pub(crate) fn new(
+        settings: &'a CsvSettings,
+        mut merged: Option<&'a mut RowWriter>,
+        qa: Option<&'a mut QaWorkbook>,
+        exports: Option<&'a mut RunExports>,
+    ) -> Result<Self, AppError> {
+        let merged_len = match merged.as_deref_mut() {
+            Some(writer) => writer.csv_len()?,
+            None => None,
+        };
+        Ok(Self {
+            settings,
+            file: None,
+            merged,
+            merged_len,
+            qa,
+            held: Vec::new(),
+            exports,
+            batch: Vec::with_capacity(ROW_BATCH_SIZE),
+        })
+    }
+
+    pub(crate) fn open_file(
+        &mut self,
+        path: &Path,
+        append: bool,
+        format: OutputFormat,
+    ) -> Result<(), AppError> {
+        let (len, writer) = match append {
+            true => (
+                Some(fs::metadata(long_path(path))?.len()),
+                RowWriter::open(path, true, format, self.settings)?,
+            ),
+            false => (
+                None,
+                RowWriter::create(&part_path(path), format, self.settings)?,
+            ),
+        };
+        self.file = Some((path.to_path_buf(), writer, len));
+        Ok(())
+    }
+
+    pub(crate) fn push(&mut self, row: CsvRow) -> Result<(), AppError> {
+        self.batch.push(row);
+        if self.batch.len() >= ROW_BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), AppError> {
+        if let Some((_, writer, _)) = &mut self.file {
+            writer.write_rows(&self.batch, self.settings)?;
+        }
+        if let (Some(merged), Some(_)) = (self.merged.as_deref_mut(), self.merged_len) {
+            merged.write_rows(&self.batch, self.settings)?;
+        }
+        match self.holds_rows() {
+            true => self.held.append(&mut self.batch),
+            false => self.batch.clear(),
+        }
+        Ok(())
+    }
+
+    /// Writes the rest of a finished survey and closes its own file.
+    pub(crate) fn commit(&mut self) -> Result<(), AppError> {
+        self.flush()?;
+        if let Some((path, writer, len)) = self.file.take() {
+            writer.finish()?;
+            if len.is_none() {
+                fs::rename(long_path(&part_path(&path)), long_path(&path))?;
+            }
+        }
+        if let (Some(merged), None) = (self.merged.as_deref_mut(), self.merged_len) {
+            merged.write_rows(&self.held, self.settings)?;
+        }
+        if let Some(qa) = self.qa.as_deref_mut() {
+            qa.write_rows(&self.held, self.settings)?;
+        }
+        if let Some(exports) = self.exports.as_deref_mut() {
+            exports.write_rows(&self.held)?;
+        }
+        self.held.clear();
+        Ok(())
+    }
+
+    /// Whether rows wait in `held` for `commit`: for a merged file that
+    /// cannot be cut back, the QA workbook and the exports.
+    fn holds_rows(&self) -> bool {
+        (self.merged.is_some() && self.merged_len.is_none())
+            || self.qa.is_some()
+            || self.exports.is_some()
+    }
+
+    /// Removes the survey's partial file, or cuts its own file back when
+    /// appended to, and cuts its rows from the merged file. A file the survey
+    /// was to overwrite is left as it was.
+    pub(crate) fn rollback(&mut self) -> Result<(), AppError> {
+        self.batch.clear();
+        self.held.clear();
+        if let Some((path, writer, len)) = self.file.take() {
+            drop(writer);
+            match len {
+                Some(len) => fs::OpenOptions::new()
+                    .write(true)
+                    .open(long_path(&path))?
+                    .set_len(len)?,
+                // Workbooks are only saved on `finish`.
+                None => match fs::remove_file(long_path(&part_path(&path))) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(err.into())
+                    }
+                    _ => {}
+                },
+            }
+        }
+        if let (Some(merged), Some(len)) = (self.merged.as_deref_mut(), self.merged_len) {
+            merged.rewind(len)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a result file is written until its survey finished: `merged.csv`
+/// becomes `merged.csv.part`, next to it so the final rename stays on one
+/// volume.
+pub(crate) fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+pub(crate) fn check_on_existing(policy: OnExisting, format: OutputFormat) -> Result<(), AppError> {
+    if policy == OnExisting::Append && !format.appendable() {
+        return Err(AppError::Validation(format!(
+            "Appending needs CSV or JSON Lines output; .{} result files cannot be appended to.",
+            format.extension()
+        )));
+    }
+    Ok(())
+}
+
+/// Applies the `on_existing` policy to a result file: `None` when it is to
+/// be left alone, otherwise the path to write and whether to append to it.
+pub(crate) fn output_target(
+    path: PathBuf,
+    policy: OnExisting,
+    stamp: &str,
+) -> Result<Option<(PathBuf, bool)>, AppError> {
+    if !path.exists() {
+        return Ok(Some((path, false)));
+    }
+    match policy {
+        OnExisting::Overwrite => Ok(Some((path, false))),
+        OnExisting::Skip => Ok(None),
+        OnExisting::Append => Ok(Some((path, true))),
+        OnExisting::Error => Err(existing_output_error(&path)),
+        OnExisting::TimestampSuffix => {
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let extension = path
+                .extension()
+                .map(|extension| format!(".{}", extension.to_string_lossy()))
+                .unwrap_or_default();
+            let mut suffixed = path.with_file_name(format!("{}_{}{}", stem, stamp, extension));
+            let mut counter = 2u32;
+            while suffixed.exists() {
+                suffixed =
+                    path.with_file_name(format!("{}_{}-{}{}", stem, stamp, counter, extension));
+                counter += 1;
+            }
+            Ok(Some((suffixed, false)))
+        }
+    }
+}
+
+/// [`output_target`] for a report of the run: the problem, pairing and
+/// orphan CSVs, the QA workbook, the Access export and the Markdown and
+/// HTML summaries. They describe one run and cannot take a second one's
+/// rows, so `append` writes them with a timestamp suffix instead.
+pub(crate) fn report_target(
+    path: PathBuf,
+    policy: OnExisting,
+    stamp: &str,
+) -> Result<Option<PathBuf>, AppError> {
+    let policy = match policy {
+        OnExisting::Append => OnExisting::TimestampSuffix,
+        policy => policy,
+    };
+    Ok(output_target(path, policy, stamp)?.map(|(path, _)| path))
+}
+
+pub(crate) fn existing_output_error(path: &Path) -> AppError {
+    AppError::Validation(format!(
+        "{} already exists and on_existing is 'error'.",
+        path.to_string_lossy()
+    ))
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC, for file names.
+pub(crate) fn file_timestamp(millis: u64) -> String {
+    let digits: String = format_utc_timestamp(millis)
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect();
+    format!("{}-{}", &digits[..8], &digits[8..])
+}
+
+/// Destination of result rows: a CSV streamed to disk, or a workbook whose
+/// rows go to temporary files until it is saved on `finish`.
+pub(crate) enum RowWriter {
+    Csv(Box<csv::Writer<fs::File>>),
+    Xlsx {
+        path: PathBuf,
+        sheets: Box<SheetWriter>,
+    },
+    Parquet {
+        writer: Box<ArrowWriter<fs::File>>,
+        schema: Arc<Schema>,
+    },
+    Jsonl(Box<std::io::BufWriter<fs::File>>),
+}
+
+impl RowWriter {
+    /// Like `create`, or continues an existing CSV with the same columns
+    /// when `append` is set.
+    pub(crate) fn open(
+        path: &Path,
+        append: bool,
+        format: OutputFormat,
+        settings: &CsvSettings,
+    ) -> Result<Self, AppError> {
+        if !append {
+            return Self::create(path, format, settings);
+        }
+        if format == OutputFormat::Jsonl {
+            return Self::resume(path, fs::metadata(long_path(path))?.len(), format, settings);
+        }
+        let headers = row_headers(settings)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(settings.delimiter.byte())
+            .from_path(long_path(path))?;
+        if reader
+            .headers()?
+            .iter()
+            .ne(headers.iter().map(String::as_str))
+        {
+            return Err(AppError::Validation(format!(
+                "{} has other columns than this run; cannot append to it.",
+                path.to_string_lossy()
+            )));
+        }
+        let len = fs::metadata(long_path(path))?.len();
+        Self::resume(path, len, format, settings)
+    }
+
+    /// Continues a CSV or JSON Lines file written by an earlier run, cut
+    /// back to `len` bytes.
+    pub(crate) fn resume(
+        path: &Path,
+        len: u64,
+        format: OutputFormat,
+        settings: &CsvSettings,
+    ) -> Result<Self, AppError> {
+        match format {
+            OutputFormat::Jsonl => {
+                let file = fs::OpenOptions::new().append(true).open(long_path(path))?;
+                file.set_len(len)?;
+                Ok(RowWriter::Jsonl(Box::new(std::io::BufWriter::new(file))))
+            }
+            _ => Ok(RowWriter::Csv(Box::new(append_csv_writer(
+                path, len, settings,
+            )?))),
+        }
+    }
+
+    pub(crate) fn create(
+        path: &Path,
+        format: OutputFormat,
+        settings: &CsvSettings,
+    ) -> Result<Self, AppError> {
+        match format {
+            OutputFormat::Csv => Ok(RowWriter::Csv(Box::new(init_csv_writer(path, settings)?))),
+            OutputFormat::Xlsx => Ok(RowWriter::Xlsx {
+                path: path.to_path_buf(),
+                sheets: Box::new(SheetWriter::new(
+                    "Results",
+                    row_headers(settings)?,
+                    Vec::new(),
+                )?),
+            }),
+            OutputFormat::Parquet => {
+                let fields: Vec<Field> = row_columns(settings)
+                    .into_iter()
+                    .zip(row_headers(settings)?)
+                    .map(|(column, header)| Field::new(header, column_type(column, settings), true))
+                    .collect();
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                let schema = Arc::new(Schema::new(fields));
+                let writer = ArrowWriter::try_new(
+                    fs::File::create(long_path(path))?,
+                    schema.clone(),
+                    Some(properties),
+                )?;
+                Ok(RowWriter::Parquet {
+                    writer: Box::new(writer),
+                    schema,
+                })
+            }
+            OutputFormat::Jsonl => {
+                row_headers(settings)?;
+                Ok(RowWriter::Jsonl(Box::new(std::io::BufWriter::new(
+                    fs::File::create(long_path(path))?,
+                ))))
+            }
+        }
+    }
+
+    pub(crate) fn write_rows(
+        &mut self,
+        rows: &[CsvRow],
+        settings: &CsvSettings,
+    ) -> Result<(), AppError> {
+        match self {
+            RowWriter::Csv(writer) => write_rows_to_writer(writer, rows, settings),
+            RowWriter::Xlsx { sheets, .. } => {
+                let columns = row_columns(settings);
+                let date_format = Format::new().set_num_format("yyyy-mm-dd");
+                for row in rows {
+                    let (sheet, excel_row) = sheets.next_row()?;
+                    for (col, value) in row_record(row, settings).into_iter().enumerate() {
+                        write_result_cell(
+                            sheet,
+                            (excel_row, col as u16),
+                            columns[col],
+                            value,
+                            settings,
+                            &date_format,
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+            RowWriter::Parquet { writer, schema } => {
+                if rows.is_empty() {
+                    return Ok(());
+                }
+                let records: Vec<Vec<String>> =
+                    rows.iter().map(|row| row_record(row, settings)).collect();
+                let columns = row_columns(settings)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, column)| {
+                        let values = records.iter().map(|record| {
+                            Some(record[index].as_str()).filter(|value| !value.is_empty())
+                        });
+                        parquet_array(column_type(column, settings), values)
+                    })
+                    .collect();
+                let batch = RecordBatch::try_new(schema.clone(), columns)
+                    .map_err(parquet::errors::ParquetError::from)?;
+                writer.write(&batch)?;
+                Ok(())
+            }
+            RowWriter::Jsonl(writer) => {
+                use std::io::Write;
+                let columns = row_columns(settings);
+                let headers = row_headers(settings)?;
+                for row in rows {
+                    let record = row_record(row, settings);
+                    writeln!(
+                        writer,
+                        "{}",
+                        json_line(&columns, &headers, &record, settings)?
+                    )?;
+                }
+                writer.flush()?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Bytes written so far for CSV output, used by the run checkpoint.
+    pub(crate) fn csv_len(&mut self) -> Result<Option<u64>, AppError> {
+        use std::io::Write;
+        match self {
+            RowWriter::Csv(writer) => {
+                writer.flush()?;
+                Ok(Some(writer.get_ref().metadata()?.len()))
+            }
+            RowWriter::Jsonl(writer) => {
+                writer.flush()?;
+                Ok(Some(writer.get_ref().metadata()?.len()))
+            }
+            RowWriter::Xlsx { .. } | RowWriter::Parquet { .. } => Ok(None),
+        }
+    }
+
+    /// Cuts CSV output back to `len` bytes from `csv_len`.
+    fn rewind(&mut self, len: u64) -> Result<(), AppError> {
+        use std::io::{Seek, Write};
+        let mut file = match self {
+            RowWriter::Csv(writer) => {
+                writer.flush()?;
+                writer.get_ref()
+            }
+            RowWriter::Jsonl(writer) => {
+                writer.flush()?;
+                writer.get_ref()
+            }
+            RowWriter::Xlsx { .. } | RowWriter::Parquet { .. } => return Ok(()),
+        };
+        file.set_len(len)?;
+        file.seek(std::io::SeekFrom::Start(len))?;
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Result<(), AppError> {
+        match self {
+            RowWriter::Csv(mut writer) => writer.flush()?,
+            RowWriter::Xlsx { path, sheets } => sheets.save(&path)?,
+            RowWriter::Parquet { writer, .. } => {
+                writer.close()?;
+            }
+            RowWriter::Jsonl(mut writer) => {
+                use std::io::Write;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Type of a result column in Parquet and JSON Lines output, following the
+/// cell types of `write_result_cell`; dates and everything else stay strings.
+fn column_type(column: &str, settings: &CsvSettings) -> DataType {
+    match column {
+        "dolphin" => DataType::UInt8,
+        _ if settings.label_columns.iter().any(|label| label == column) => DataType::UInt8,
+        "graded_hits" | "winner_depth" | "video_frame" => DataType::UInt64,
+        CONFIDENCE_COLUMN | "gps_lat" | "gps_lon" | "track_lat" | "track_lon" | "track_error_m"
+        | "video_time_s" => DataType::Float64,
+        _ => DataType::Utf8,
+    }
+}
+
+/// One JSON Lines object with the keys in column order; empty values become
+/// `null` and numeric columns numbers.
+fn json_line(
+    columns: &[&str],
+    headers: &[String],
+    record: &[String],
+    settings: &CsvSettings,
+) -> Result<String, AppError> {
+    let mut line = String::from("{");
+    for (index, (column, value)) in columns.iter().zip(record).enumerate() {
+        if index > 0 {
+            line.push(',');
+        }
+        line.push_str(&serde_json::to_string(&headers[index])?);
+        line.push(':');
+        let value = match column_type(column, settings) {
+            _ if value.is_empty() => serde_json::Value::Null,
+            DataType::UInt8 | DataType::UInt64 => value
+                .parse::<u64>()
+                .map(serde_json::Value::from)
+                .unwrap_or_else(|_| serde_json::Value::from(value.as_str())),
+            DataType::Float64 => value
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(serde_json::Value::Number)
+                .unwrap_or_else(|| serde_json::Value::from(value.as_str())),
+            _ => serde_json::Value::from(value.as_str()),
+        };
+        line.push_str(&value.to_string());
+    }
+    line.push('}');
+    Ok(line)
+}
+
+/// Column of `data_type` from formatted values; empty values become nulls.
+fn parquet_array<'a>(
+    data_type: DataType,
+    values: impl Iterator<Item = Option<&'a str>>,
+) -> ArrayRef {
+    match data_type {
+        DataType::UInt8 => Arc::new(
+            values
+                .map(|value| value.and_then(|value| value.parse().ok()))
+                .collect::<UInt8Array>(),
+        ),
+        DataType::UInt64 => Arc::new(
+            values
+                .map(|value| value.and_then(|value| value.parse().ok()))
+                .collect::<UInt64Array>(),
+        ),
+        DataType::Float64 => Arc::new(
+            values
+                .map(|value| value.and_then(|value| value.parse().ok()))
+                .collect::<Float64Array>(),
+        ),
+        _ => Arc::new(values.collect::<StringArray>()),
+    }
+}
+
+/// Writes one result value with a cell type matching its column: numbers for
+/// counts and flags, dates for `survey_date` and `capture_datetime`, formulas for `HYPERLINK` links
+/// and text for everything else. Empty values stay blank.
+fn write_result_cell(
+    sheet: &mut Worksheet,
+    (row, col): (u32, u16),
+    column: &str,
+    value: String,
+    settings: &CsvSettings,
+    date_format: &Format,
+) -> Result<(), AppError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    match column {
+        "dolphin" | "graded_hits" | "winner_depth" | CONFIDENCE_COLUMN | "gps_lat" | "gps_lon"
+        | "track_lat" | "track_lon" | "track_error_m" | "video_frame" | "video_time_s" => {
+            match value.parse::<f64>() {
+                Ok(number) => sheet.write_number(row, col, number)?,
+                Err(_) => sheet.write_string(row, col, value)?,
+            }
+        }
+        _ if settings.label_columns.iter().any(|label| label == column) => {
+            match value.parse::<f64>() {
+                Ok(number) => sheet.write_number(row, col, number)?,
+                Err(_) => sheet.write_string(row, col, value)?,
+            }
+        }
+        "survey_date" => match excel_date(&value) {
+            Some(date) => sheet.write_datetime_with_format(row, col, &date, date_format)?,
+            None => sheet.write_string(row, col, value)?,
+        },
+        CAPTURE_TIME_COLUMN => match ExcelDateTime::parse_from_str(&value) {
+            Ok(time) => sheet.write_datetime_with_format(
+                row,
+                col,
+                &time,
+                &Format::new().set_num_format("yyyy-mm-dd hh:mm:ss"),
+            )?,
+            Err(_) => sheet.write_string(row, col, value)?,
+        },
+        _ if settings.hyperlinks == HyperlinkStyle::Formula
+            && HYPERLINK_COLUMNS.contains(&column) =>
+        {
+            sheet.write_formula(row, col, value.as_str())?
+        }
+        _ => sheet.write_string(row, col, value)?,
+    };
+    Ok(())
+}
+
+pub(crate) fn write_run_summary(output_dir: &Path, summary: &RunSummary) -> Result<(), AppError> {
+    fs::write(
+        long_path(&output_dir.join(RUN_SUMMARY_FILENAME)),
+        serde_json::to_string_pretty(summary)?,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn render_summary_markdown(
+    summary: &RunSummary,
+    problems: &[ProblemItem],
+    provenance: &RunProvenance,
+) -> String {
+    let mut md = String::new();
+    md.push_str("# Survey labeling run\n\n");
+    if summary.cancelled {
+        md.push_str(
+            "> **Incomplete:** the run was cancelled. Only the surveys listed below were \
+             processed; see the `RUN_CANCELLED` problem.\n\n",
+        );
+    }
+    md.push_str("| Metric | Value |\n|---|---:|\n");
+    for (label, value) in [
+        ("Processed surveys", summary.processed_surveys),
+        ("Total rows", summary.total_rows),
+        ("Dolphin yes", summary.dolphin_yes),
+        ("Dolphin no", summary.dolphin_no),
+        ("Ambiguity warnings", summary.ambiguity_warnings),
+        (
+            "Collapsed duplicate candidates",
+            summary.collapsed_candidates,
+        ),
+        ("File id collisions", summary.id_collisions),
+        ("Perceptual matches", summary.perceptual_matches),
+        ("Problems", summary.problems_count),
+        ("Errors", summary.error_count),
+        ("Warnings", summary.warning_count),
+    ] {
+        md.push_str(&format!("| {} | {} |\n", label, value));
+    }
+
+    md.push_str("\n## Surveys\n\n");
+    if summary.surveys.is_empty() {
+        md.push_str("No surveys were processed.\n");
+    } else {
+        md.push_str(
+            "| Survey | Rows | Dolphin yes | Dolphin no | Yes rate | Ambiguity warnings |\n",
+        );
+        md.push_str("|---|---:|---:|---:|---:|---:|\n");
+        for survey in &summary.surveys {
+            let rate = if survey.rows > 0 {
+                format!(
+                    "{:.1}%",
+                    survey.dolphin_yes as f64 * 100.0 / survey.rows as f64
+                )
+            } else {
+                "-".to_string()
+            };
+            md.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} |\n",
+                markdown_cell(&survey.survey_id_base),
+                survey.rows,
+                survey.dolphin_yes,
+                survey.dolphin_no,
+                rate,
+                survey.ambiguity_warnings
+            ));
+        }
+    }
+
+    md.push_str("\n## Problems\n\n");
+    if problems.is_empty() {
+        md.push_str("No problems found.\n");
+    } else {
+        md.push_str("| Survey | Type | Severity | Details |\n|---|---|---|---|\n");
+        for problem in problems {
+            md.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                markdown_cell(&problem.survey_id_base),
+                problem.problem_type,
+                problem.severity.as_str(),
+                markdown_cell(problem.details.as_deref().unwrap_or(""))
+            ));
+        }
+    }
+
+    md.push_str("\n## Provenance\n\n");
+    md.push_str(&format!("- Tool version: {}\n", env!("CARGO_PKG_VERSION")));
+    md.push_str(&format!(
+        "- Started: {}\n- Finished: {}\n",
+        format_utc_timestamp(provenance.started_at_ms),
+        format_utc_timestamp(provenance.finished_at_ms)
+    ));
+    md.push_str(&format!(
+        "- Raw root: `{}`\n- Graded root: `{}`\n- Output folder: `{}`\n",
+        provenance.raw_root, provenance.graded_root, summary.output_dir
+    ));
+    if let Some(path) = summary.merged_csv_path.as_deref() {
+        md.push_str(&format!("- Merged CSV: `{}`\n", path));
+    }
+    if let Some(path) = summary.problems_csv_path.as_deref() {
+        md.push_str(&format!("- Problems CSV: `{}`\n", path));
+    }
+    md.push_str("\n### Rules\n\n```json\n");
+    md.push_str(&serde_json::to_string_pretty(provenance.rules).unwrap_or_default());
+    md.push_str("\n```\n");
+    md
+}
+
+fn markdown_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', " ")
+}
+
+const HTML_REPORT_STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2rem;color:#1d2733}\
+    h1{margin-top:0}table{border-collapse:collapse;margin-bottom:1.5rem}\
+    th,td{border-bottom:1px solid #d8dee6;padding:.3rem .7rem;text-align:left}\
+    td.num{text-align:right}.bar{display:flex;width:12rem;height:.8rem;background:#e4e9ef}\
+    .yes{background:#2f7fc1}.no{background:#b8c4d0}.error{color:#b3261e}\
+    .warning{color:#8a5a00}.info{color:#4b5563}.note{background:#fdecea;padding:.6rem}";
+
+/// Same content as `render_summary_markdown` as a single HTML page with
+/// inline styles and a yes/no bar per survey, so it opens from any folder.
+pub(crate) fn render_html_report(
+    summary: &RunSummary,
+    problems: &[ProblemItem],
+    provenance: &RunProvenance,
+) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">");
+    html.push_str("<title>Survey labeling run</title>");
+    html.push_str(&format!(
+        "<style>{}</style></head><body>\n",
+        HTML_REPORT_STYLE
+    ));
+    html.push_str("<h1>Survey labeling run</h1>\n");
+    html.push_str(&format!(
+        "<p>{} &ndash; {}</p>\n",
+        format_utc_timestamp(provenance.started_at_ms),
+        format_utc_timestamp(provenance.finished_at_ms)
+    ));
+    if summary.cancelled {
+        html.push_str(
+            "<p class=\"note\"><strong>Incomplete:</strong> the run was cancelled. Only the \
+             surveys listed below were processed.</p>\n",
+        );
+    }
+    html.push_str("<table>\n");
+    for (label, value) in [
+        ("Processed surveys", summary.processed_surveys),
+        ("Total rows", summary.total_rows),
+        ("Dolphin yes", summary.dolphin_yes),
+        ("Dolphin no", summary.dolphin_no),
+        ("Ambiguity warnings", summary.ambiguity_warnings),
+        ("Problems", summary.problems_count),
+        ("Errors", summary.error_count),
+        ("Warnings", summary.warning_count),
+    ] {
+        html.push_str(&format!(
+            "<tr><th>{}</th><td class=\"num\">{}</td></tr>\n",
+            label, value
+        ));
+    }
+    html.push_str("</table>\n<h2>Surveys</h2>\n");
+    if summary.surveys.is_empty() {
+        html.push_str("<p>No surveys were processed.</p>\n");
+    } else {
+        html.push_str(
+            "<table>\n<tr><th>Survey</th><th>Rows</th><th>Dolphin yes</th><th>Dolphin no</th>\
+             <th>Yes rate</th><th></th><th>Ambiguity warnings</th></tr>\n",
+        );
+        for survey in &summary.surveys {
+            let rate = if survey.rows > 0 {
+                survey.dolphin_yes as f64 * 100.0 / survey.rows as f64
+            } else {
+                0.0
+            };
+            html.push_str(&format!(
+                "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+                 <td class=\"num\">{}</td><td class=\"num\">{:.1}%</td><td><div class=\"bar\">\
+                 <div class=\"yes\" style=\"width:{:.1}%\"></div><div class=\"no\" \
+                 style=\"width:{:.1}%\"></div></div></td><td class=\"num\">{}</td></tr>\n",
+                html_escape(&survey.survey_id_base),
+                survey.rows,
+                survey.dolphin_yes,
+                survey.dolphin_no,
+                rate,
+                rate,
+                if survey.rows > 0 { 100.0 - rate } else { 0.0 },
+                survey.ambiguity_warnings
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h2>Problems</h2>\n");
+    if problems.is_empty() {
+        html.push_str("<p>No problems found.</p>\n");
+    } else {
+        html.push_str(
+            "<table>\n<tr><th>Survey</th><th>Type</th><th>Severity</th><th>Details</th></tr>\n",
+        );
+        for problem in problems {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td>{}</td></tr>\n",
+                html_escape(&problem.survey_id_base),
+                html_escape(&problem.problem_type),
+                problem.severity.as_str(),
+                problem.severity.as_str(),
+                html_escape(problem.details.as_deref().unwrap_or(""))
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("<h2>Provenance</h2>\n<ul>\n");
+    html.push_str(&format!(
+        "<li>Tool version: {}</li>\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    for (label, value) in [
+        ("Raw root", Some(provenance.raw_root.as_str())),
+        ("Graded root", Some(provenance.graded_root.as_str())),
+        ("Output folder", Some(summary.output_dir.as_str())),
+        ("Merged CSV", summary.merged_csv_path.as_deref()),
+        ("Problems CSV", summary.problems_csv_path.as_deref()),
+    ] {
+        if let Some(value) = value {
+            html.push_str(&format!(
+                "<li>{}: <code>{}</code></li>\n",
+                label,
+                html_escape(value)
+            ));
+        }
+    }
+    html.push_str("</ul>\n</body></html>\n");
+    html
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats unix milliseconds as `YYYY-MM-DD HH:MM:SS UTC`.
+pub(crate) fn format_utc_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+/// Days since 1970-01-01 to a proleptic Gregorian date (Howard Hinnant's
+/// `civil_from_days`).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+pub(crate) fn send_email_report(
+    smtp: &SmtpSettings,
+    password: Option<String>,
+    summary: &RunSummary,
+) -> Result<(), AppError> {
+    let mail_error =
+        |err: &dyn std::fmt::Display| AppError::Message(format!("Email report: {}", err));
+    let from: Mailbox = smtp.from.parse().map_err(|err| mail_error(&err))?;
+    let mut builder = EmailMessage::builder().from(from).subject(format!(
+        "Survey labeling run: {} surveys, {} problems",
+        summary.processed_surveys, summary.problems_count
+    ));
+    for recipient in &smtp.to {
+        let mailbox: Mailbox = recipient.parse().map_err(|err| mail_error(&err))?;
+        builder = builder.to(mailbox);
+    }
+
+    let body = format!(
+        "Output folder: {}\nProcessed surveys: {}\nTotal rows: {}\nDolphin yes: {}\nDolphin no: {}\nAmbiguity warnings: {}\nProblems: {}\n",
+        summary.output_dir,
+        summary.processed_surveys,
+        summary.total_rows,
+        summary.dolphin_yes,
+        summary.dolphin_no,
+        summary.ambiguity_warnings,
+        summary.problems_count,
+    );
+    let mut parts = MultiPart::mixed()
+        .singlepart(SinglePart::plain(body))
+        .singlepart(Attachment::new("summary.json".to_string()).body(
+            serde_json::to_string_pretty(summary)?,
+            ContentType::parse("application/json").map_err(|err| mail_error(&err))?,
+        ));
+    if let Some(path) = summary.problems_csv_path.as_deref() {
+        let filename = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "problems.csv".to_string());
+        parts = parts.singlepart(Attachment::new(filename).body(
+            fs::read(path)?,
+            ContentType::parse("text/csv").map_err(|err| mail_error(&err))?,
+        ));
+    }
+    let email = builder.multipart(parts).map_err(|err| mail_error(&err))?;
+
+    let mut transport = match smtp.security {
+        SmtpSecurity::Tls => SmtpTransport::relay(&smtp.host).map_err(|err| mail_error(&err))?,
+        SmtpSecurity::Starttls => {
+            SmtpTransport::starttls_relay(&smtp.host).map_err(|err| mail_error(&err))?
+        }
+        SmtpSecurity::None => SmtpTransport::builder_dangerous(&smtp.host),
+    }
+    .port(smtp.port);
+    if let Some(username) = smtp.username.as_ref() {
+        transport = transport.credentials(Credentials::new(
+            username.clone(),
+            password.unwrap_or_default(),
+        ));
+    }
+    transport
+        .build()
+        .send(&email)
+        .map_err(|err| mail_error(&err))?;
+    Ok(())
+}
+
+/// Columns of the PostgreSQL export tables after `run_id`. Columns missing
+/// from existing tables are added, so tables created by an older version
+/// take the new columns instead of failing the export.
+const POSTGRES_RUNS_COLUMNS: &[(&str, &str)] = &[
+    ("started_at", "TIMESTAMPTZ NOT NULL"),
+    ("finished_at", "TIMESTAMPTZ NOT NULL"),
+    ("raw_root", "TEXT NOT NULL"),
+    ("graded_root", "TEXT NOT NULL"),
+    ("processed_surveys", "BIGINT NOT NULL"),
+    ("total_rows", "BIGINT NOT NULL"),
+    ("dolphin_yes", "BIGINT NOT NULL"),
+    ("dolphin_no", "BIGINT NOT NULL"),
+    ("problems_count", "BIGINT NOT NULL"),
+    ("rules", "JSONB NOT NULL"),
+];
+const POSTGRES_ROW_COLUMNS: &[(&str, &str)] = &[
+    ("survey_id_base", "TEXT NOT NULL"),
+    ("raw_relpath", "TEXT NOT NULL"),
+    ("filename", "TEXT NOT NULL"),
+    ("dolphin", "SMALLINT NOT NULL"),
+    ("graded_relpath", "TEXT NOT NULL"),
+    ("graded_hits", "BIGINT NOT NULL"),
+    ("graded_winner_type", "TEXT NOT NULL"),
+    ("survey_id_raw_detected", "TEXT"),
+    ("survey_id_graded_detected", "TEXT"),
+    ("survey_date", "TEXT"),
+    ("area", "TEXT"),
+];
+const POSTGRES_PROBLEM_COLUMNS: &[(&str, &str)] = &[
+    ("survey_id_base", "TEXT NOT NULL"),
+    ("survey_id_detected", "TEXT"),
+    ("raw_path", "TEXT"),
+    ("graded_path", "TEXT"),
+    ("problem_type", "TEXT NOT NULL"),
+    ("severity", "TEXT NOT NULL"),
+    ("details", "TEXT"),
+];
+
+/// Creates the schema and tables of the PostgreSQL export and adds the
+/// columns older tables lack. Added columns are nullable, since existing
+/// rows have no value for them.
+fn postgres_schema_sql(schema: &str) -> String {
+    let tables = [
+        ("runs", "run_id TEXT PRIMARY KEY", POSTGRES_RUNS_COLUMNS),
+        (
+            "result_rows",
+            "run_id TEXT NOT NULL REFERENCES {schema}.runs (run_id) ON DELETE CASCADE",
+            POSTGRES_ROW_COLUMNS,
+        ),
+        (
+            "problems",
+            "run_id TEXT NOT NULL REFERENCES {schema}.runs (run_id) ON DELETE CASCADE",
+            POSTGRES_PROBLEM_COLUMNS,
+        ),
+    ];
+    let mut sql = format!("CREATE SCHEMA IF NOT EXISTS {schema};\n");
+    for (table, key, columns) in tables {
+        let key = key.replace("{schema}", schema);
+        let definitions = columns
+            .iter()
+            .map(|(name, kind)| format!(",\n    {name} {kind}"))
+            .collect::<String>();
+        sql.push_str(&format!(
+            "CREATE TABLE IF NOT EXISTS {schema}.{table} (\n    {key}{definitions}\n);\n"
+        ));
+        for (name, kind) in columns {
+            let kind = kind.trim_end_matches(" NOT NULL");
+            sql.push_str(&format!(
+                "ALTER TABLE {schema}.{table} ADD COLUMN IF NOT EXISTS {name} {kind};\n"
+            ));
+        }
+    }
+    sql
+}
+
+/// Column list of `columns` after `run_id`, for `INSERT` and `COPY`.
+fn postgres_column_list(columns: &[(&str, &str)]) -> String {
+    std::iter::once("run_id")
+        .chain(columns.iter().map(|(name, _)| *name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// TLS setup for [`PostgresTls::Require`]: the server's certificate is
+/// checked against `ca_certificate` or the public web roots.
+fn postgres_tls(settings: &PostgresSettings) -> Result<MakeRustlsConnect, AppError> {
+    let mut roots = rustls::RootCertStore::empty();
+    match settings.ca_certificate.as_deref() {
+        Some(path) => {
+            let pem_error = |err: &dyn std::fmt::Display| {
+                AppError::Validation(format!(
+                    "Cannot read the PostgreSQL CA certificate {}: {}",
+                    path.display(),
+                    err
+                ))
+            };
+            let certificates = CertificateDer::pem_file_iter(long_path(path))
+                .map_err(|err| pem_error(&err))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| pem_error(&err))?;
+            if certificates.is_empty() {
+                return Err(pem_error(&"no certificates in the file"));
+            }
+            for certificate in certificates {
+                roots.add(certificate).map_err(|err| pem_error(&err))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|err| AppError::Message(format!("PostgreSQL export: {}", err)))?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    Ok(MakeRustlsConnect::new(config))
+}
+
+fn postgres_export_error(err: &dyn std::fmt::Display) -> AppError {
+    AppError::Message(format!("PostgreSQL export: {}", err))
+}
+
+/// A run's PostgreSQL export, written inside a single transaction opened
+/// when the run starts. The run's previous export is deleted first, so
+/// exporting is idempotent per `run_id`; the rows of each finished survey
+/// are bulk loaded with their own `COPY`. `finish` fills in the run's totals,
+/// loads its problems and commits. Dropped before that, the connection
+/// closes and the server rolls the transaction back.
+pub(crate) struct PostgresExport {
+    client: postgres::Client,
+    schema: String,
+    run_id: String,
+}
+
+impl PostgresExport {
+    /// Connects and adds the run with the totals still at zero, so the rows
+    /// referencing it can follow.
+    pub(crate) fn open(
+        settings: &PostgresSettings,
+        password: Option<String>,
+        run_id: &str,
+        provenance: &RunProvenance,
+    ) -> Result<Self, AppError> {
+        let mut config = postgres::Config::new();
+        config
+            .host(&settings.host)
+            .port(settings.port)
+            .user(&settings.user)
+            .dbname(&settings.dbname);
+        if let Some(password) = password.as_deref() {
+            config.password(password);
+        }
+        let mut client = match settings.tls {
+            PostgresTls::Require => config
+                .ssl_mode(SslMode::Require)
+                .connect(postgres_tls(settings)?),
+            PostgresTls::Disable => config.ssl_mode(SslMode::Disable).connect(NoTls),
+        }
+        .map_err(|err| postgres_export_error(&err))?;
+        let schema = quote_ident(&settings.schema);
+        client
+            .batch_execute(&format!("BEGIN;\n{}", postgres_schema_sql(&schema)))
+            .map_err(|err| postgres_export_error(&err))?;
+        client
+            .execute(
+                &format!("DELETE FROM {schema}.runs WHERE run_id = $1"),
+                &[&run_id],
+            )
+            .map_err(|err| postgres_export_error(&err))?;
+        client
+            .execute(
+                &format!(
+                    "INSERT INTO {schema}.runs ({columns}) VALUES ($1,
+                     to_timestamp($2::bigint / 1000.0), to_timestamp($2::bigint / 1000.0),
+                     $3, $4, 0, 0, 0, 0, 0, $5::text::jsonb)",
+                    columns = postgres_column_list(POSTGRES_RUNS_COLUMNS)
+                ),
+                &[
+                    &run_id,
+                    &(provenance.started_at_ms as i64),
+                    &provenance.raw_root,
+                    &provenance.graded_root,
+                    &serde_json::to_string(provenance.rules)?,
+                ],
+            )
+            .map_err(|err| postgres_export_error(&err))?;
+        Ok(Self {
+            client,
+            schema,
+            run_id: run_id.to_string(),
+        })
+    }
+
+    pub(crate) fn write_rows(&mut self, rows: &[CsvRow]) -> Result<(), AppError> {
+        let records = rows.iter().map(|row| {
+            vec![
+                Some(self.run_id.clone()),
+                Some(row.survey_id_base.clone()),
+                Some(row.raw_relpath.clone()),
+                Some(row.filename.clone()),
+                Some(row.dolphin.to_string()),
+                Some(row.graded_relpath.clone()),
+                Some(row.graded_hits.to_string()),
+                Some(row.graded_winner_type.clone()),
+                row.survey_id_raw_detected.clone(),
+                row.survey_id_graded_detected.clone(),
+                row.survey_date.clone(),
+                row.area.clone(),
+            ]
+        });
+        copy_records(
+            &mut self.client,
+            &format!(
+                "{}.result_rows ({})",
+                self.schema,
+                postgres_column_list(POSTGRES_ROW_COLUMNS)
+            ),
+            records,
+        )
+        .map_err(|err| postgres_export_error(&err))
+    }
+
+    pub(crate) fn finish(
+        mut self,
+        summary: &RunSummary,
+        problems: &[ProblemItem],
+        provenance: &RunProvenance,
+    ) -> Result<(), AppError> {
+        let schema = &self.schema;
+        self.client
+            .execute(
+                &format!(
+                    "UPDATE {schema}.runs SET finished_at = to_timestamp($2::bigint / 1000.0),
+                     processed_surveys = $3, total_rows = $4, dolphin_yes = $5,
+                     dolphin_no = $6, problems_count = $7 WHERE run_id = $1"
+                ),
+                &[
+                    &self.run_id,
+                    &(provenance.finished_at_ms as i64),
+                    &(summary.processed_surveys as i64),
+                    &(summary.total_rows as i64),
+                    &(summary.dolphin_yes as i64),
+                    &(summary.dolphin_no as i64),
+                    &(summary.problems_count as i64),
+                ],
+            )
+            .map_err(|err| postgres_export_error(&err))?;
+        let records = problems.iter().map(|problem| {
+            vec![
+                Some(self.run_id.clone()),
+                Some(problem.survey_id_base.clone()),
+                problem.survey_id_detected.clone(),
+                problem.raw_path.clone(),
+                problem.graded_path.clone(),
+                Some(problem.problem_type.clone()),
+                Some(problem.severity.as_str().to_string()),
+                problem.details.clone(),
+            ]
+        });
+        copy_records(
+            &mut self.client,
+            &format!(
+                "{schema}.problems ({})",
+                postgres_column_list(POSTGRES_PROBLEM_COLUMNS)
+            ),
+            records,
+        )
+        .map_err(|err| postgres_export_error(&err))?;
+        self.client
+            .batch_execute("COMMIT")
+            .map_err(|err| postgres_export_error(&err))
+    }
+}
+
+/// A run's SQLite export: the same tables as the PostgreSQL export plus
+/// `surveys`, with the full run summary as JSON in `runs.summary`. Runs
+/// accumulate across output runs. Like the PostgreSQL export it is written
+/// in one transaction from the start of the run, with the foreign keys
+/// checked on `finish`, which adds the run itself and commits.
+pub(crate) struct SqliteExport {
+    conn: Connection,
+    run_id: String,
+}
+
+impl SqliteExport {
+    pub(crate) fn open(path: &Path, run_id: &str) -> Result<Self, AppError> {
+        let conn = Connection::open(long_path(path))?;
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+             CREATE TABLE IF NOT EXISTS runs (
+                 run_id TEXT PRIMARY KEY,
+                 started_at_ms INTEGER NOT NULL,
+                 finished_at_ms INTEGER NOT NULL,
+                 raw_root TEXT NOT NULL,
+                 graded_root TEXT NOT NULL,
+                 processed_surveys INTEGER NOT NULL,
+                 total_rows INTEGER NOT NULL,
+                 dolphin_yes INTEGER NOT NULL,
+                 dolphin_no INTEGER NOT NULL,
+                 problems_count INTEGER NOT NULL,
+                 cancelled INTEGER NOT NULL,
+                 rules TEXT NOT NULL,
+                 summary TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS surveys (
+                 run_id TEXT NOT NULL REFERENCES runs (run_id) ON DELETE CASCADE,
+                 survey_id_base TEXT NOT NULL,
+                 raw_path TEXT NOT NULL,
+                 graded_path TEXT NOT NULL,
+                 rows INTEGER NOT NULL,
+                 dolphin_yes INTEGER NOT NULL,
+                 dolphin_no INTEGER NOT NULL,
+                 ambiguity_warnings INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS result_rows (
+                 run_id TEXT NOT NULL REFERENCES runs (run_id) ON DELETE CASCADE,
+                 survey_id_base TEXT NOT NULL,
+                 raw_relpath TEXT NOT NULL,
+                 filename TEXT NOT NULL,
+                 dolphin INTEGER NOT NULL,
+                 graded_relpath TEXT NOT NULL,
+                 graded_hits INTEGER NOT NULL,
+                 graded_winner_type TEXT NOT NULL,
+                 survey_id_raw_detected TEXT,
+                 survey_id_graded_detected TEXT,
+                 survey_date TEXT,
+                 area TEXT
+             );
+             CREATE INDEX IF NOT EXISTS result_rows_run ON result_rows (run_id);
+             CREATE INDEX IF NOT EXISTS result_rows_image ON result_rows (survey_id_base, raw_relpath);
+             CREATE TABLE IF NOT EXISTS problems (
+                 run_id TEXT NOT NULL REFERENCES runs (run_id) ON DELETE CASCADE,
+                 survey_id_base TEXT NOT NULL,
+                 survey_id_detected TEXT,
+                 raw_path TEXT,
+                 graded_path TEXT,
+                 problem_type TEXT NOT NULL,
+                 severity TEXT NOT NULL,
+                 details TEXT
+             );",
+        )?;
+        // Exporting a run again replaces its rows.
+        conn.execute_batch("BEGIN IMMEDIATE; PRAGMA defer_foreign_keys = ON;")?;
+        conn.execute("DELETE FROM runs WHERE run_id = ?1", [run_id])?;
+        Ok(Self {
+            conn,
+            run_id: run_id.to_string(),
+        })
+    }
+
+    pub(crate) fn write_rows(&mut self, rows: &[CsvRow]) -> Result<(), AppError> {
+        let mut insert = self.conn.prepare_cached(
+            "INSERT INTO result_rows VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        )?;
+        for row in rows {
+            insert.execute(rusqlite::params![
+                self.run_id,
+                row.survey_id_base,
+                row.raw_relpath,
+                row.filename,
+                row.dolphin,
+                row.graded_relpath,
+                row.graded_hits as i64,
+                row.graded_winner_type,
+                row.survey_id_raw_detected,
+                row.survey_id_graded_detected,
+                row.survey_date,
+                row.area,
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(
+        self,
+        summary: &RunSummary,
+        problems: &[ProblemItem],
+        provenance: &RunProvenance,
+    ) -> Result<(), AppError> {
+        self.conn.execute(
+            "INSERT INTO runs VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            rusqlite::params![
+                self.run_id,
+                provenance.started_at_ms as i64,
+                provenance.finished_at_ms as i64,
+                provenance.raw_root,
+                provenance.graded_root,
+                summary.processed_surveys as i64,
+                summary.total_rows as i64,
+                summary.dolphin_yes as i64,
+                summary.dolphin_no as i64,
+                summary.problems_count as i64,
+                summary.cancelled,
+                serde_json::to_string(provenance.rules)?,
+                serde_json::to_string(summary)?,
+            ],
+        )?;
+        {
+            let mut insert = self
+                .conn
+                .prepare("INSERT INTO surveys VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+            for survey in &summary.surveys {
+                insert.execute(rusqlite::params![
+                    self.run_id,
+                    survey.survey_id_base,
+                    survey.raw_path,
+                    survey.graded_path,
+                    survey.rows as i64,
+                    survey.dolphin_yes as i64,
+                    survey.dolphin_no as i64,
+                    survey.ambiguity_warnings as i64,
+                ])?;
+            }
+            let mut insert = self
+                .conn
+                .prepare("INSERT INTO problems VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+            for problem in problems {
+                insert.execute(rusqlite::params![
+                    self.run_id,
+                    problem.survey_id_base,
+                    problem.survey_id_detected,
+                    problem.raw_path,
+                    problem.graded_path,
+                    problem.problem_type,
+                    problem.severity.as_str(),
+                    problem.details,
+                ])?;
+            }
+        }
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+/// Streams `records` into `table`, which names its columns, with
+/// `COPY ... FROM STDIN` in CSV format; `None` fields are sent as `\N` and
+/// arrive as NULL.
+fn copy_records(
+    client: &mut postgres::Client,
+    table: &str,
+    records: impl Iterator<Item = Vec<Option<String>>>,
+) -> Result<(), AppError> {
+    let sink = client
+        .copy_in(&format!(
+            "COPY {table} FROM STDIN WITH (FORMAT csv, NULL '\\N')"
+        ))
+        .map_err(|err| AppError::Message(err.to_string()))?;
+    let mut writer = WriterBuilder::new().has_headers(false).from_writer(sink);
+    for record in records {
+        writer.write_record(record.iter().map(|field| field.as_deref().unwrap_or("\\N")))?;
+    }
+    let sink = writer
+        .into_inner()
+        .map_err(|err| AppError::Message(err.to_string()))?;
+    sink.finish()
+        .map_err(|err| AppError::Message(err.to_string()))?;
+    Ok(())
+}
+
+pub(crate) fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::problems::problem_severity;
+    use crate::settings::AppSettings;
+    use crate::test_support::{csv_row, TestTree};
+
+    fn write_result_rows(
+        path: &Path,
+        append: bool,
+        format: OutputFormat,
+        rows: &[CsvRow],
+        settings: &CsvSettings,
+    ) -> Result<(), AppError> {
+        let mut writer = RowWriter::open(path, append, format, settings)?;
+        writer.write_rows(rows, settings)?;
+        writer.finish()
+    }
+
+    /// Cells of every sheet of a saved workbook, by sheet name and cell
+    /// reference (`B2`). Values are as stored: numbers and booleans (`1`)
+    /// as written, text unescaped.
+    fn xlsx_cells(path: &Path) -> Vec<(String, BTreeMap<String, String>)> {
+        let mut archive = zip::ZipArchive::new(fs::File::open(path).expect("open")).expect("zip");
+        let mut read = |name: &str| {
+            let mut xml = String::new();
+            std::io::Read::read_to_string(&mut archive.by_name(name).expect(name), &mut xml)
+                .expect("read");
+            xml
+        };
+        let unescape = |text: &str| {
+            text.replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&amp;", "&")
+        };
+        let sheet_re = regex::Regex::new(r#"<sheet name="([^"]*)""#).expect("regex");
+        let cell_re =
+            regex::Regex::new(r#"<c r="([A-Z]+[0-9]+)"[^>]*?(?:/>|>(.*?)</c>)"#).expect("regex");
+        let value_re = regex::Regex::new(r"<(?:t|v)[^>]*>(.*?)</(?:t|v)>").expect("regex");
+        let workbook = read("xl/workbook.xml");
+        sheet_re
+            .captures_iter(&workbook)
+            .enumerate()
+            .map(|(index, sheet)| {
+                let xml = read(&format!("xl/worksheets/sheet{}.xml", index + 1));
+                let cells = cell_re
+                    .captures_iter(&xml)
+                    .filter_map(|cell| {
+                        let value = value_re.captures(cell.get(2)?.as_str())?;
+                        Some((cell[1].to_string(), unescape(&value[1])))
+                    })
+                    .collect();
+                (unescape(&sheet[1]), cells)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sheet_writer_continues_full_sheets_on_new_ones() {
+        let tree = TestTree::new();
+        let path = tree.join("rows.xlsx");
+        let mut sheets =
+            SheetWriter::new("Rows", vec!["value".to_string()], Vec::new()).expect("sheets");
+        sheets.rows_per_sheet = 3;
+        for value in ["a", "b", "c", "d", "e"] {
+            let (sheet, row) = sheets.next_row().expect("row");
+            sheet.write_string(row, 0, value).expect("write");
+        }
+        sheets.save(&path).expect("save");
+
+        let cells = xlsx_cells(&path);
+        let names: Vec<&str> = cells.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Rows", "Rows (2)", "Rows (3)"]);
+        let column: Vec<Vec<&str>> = cells
+            .iter()
+            .map(|(_, cells)| cells.values().map(String::as_str).collect())
+            .collect();
+        assert_eq!(
+            column,
+            [
+                vec!["value", "a", "b"],
+                vec!["value", "c", "d"],
+                vec!["value", "e"]
+            ]
+        );
+    }
+
+    #[test]
+    fn utc_timestamps_format_calendar_dates() {
+        assert_eq!(format_utc_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(
+            format_utc_timestamp(1_709_210_096_000),
+            "2024-02-29 12:34:56 UTC"
+        );
+    }
+
+    #[test]
+    fn on_existing_policies_protect_result_files() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let path = root.join("merged.csv");
+        let stamp = file_timestamp(1_709_210_096_000);
+        assert_eq!(stamp, "20240229-123456");
+        let fresh = output_target(path.clone(), OnExisting::Error, &stamp).expect("target");
+        assert_eq!(fresh, Some((path.clone(), false)));
+
+        let settings = CsvSettings::default();
+        write_result_rows(&path, false, OutputFormat::Csv, &[], &settings).expect("write");
+        let curated = fs::read_to_string(&path).expect("read");
+        assert_eq!(
+            output_target(path.clone(), OnExisting::Skip, &stamp).expect("target"),
+            None
+        );
+        assert!(output_target(path.clone(), OnExisting::Error, &stamp).is_err());
+        assert_eq!(
+            output_target(path.clone(), OnExisting::TimestampSuffix, &stamp).expect("target"),
+            Some((root.join("merged_20240229-123456.csv"), false))
+        );
+        fs::write(root.join("merged_20240229-123456.csv"), "taken").expect("write");
+        assert_eq!(
+            output_target(path.clone(), OnExisting::TimestampSuffix, &stamp).expect("target"),
+            Some((root.join("merged_20240229-123456-2.csv"), false))
+        );
+
+        let (append_path, append) = output_target(path.clone(), OnExisting::Append, &stamp)
+            .expect("target")
+            .expect("append");
+        assert!(append);
+        write_result_rows(&append_path, true, OutputFormat::Csv, &[], &settings).expect("append");
+        assert_eq!(fs::read_to_string(&path).expect("read"), curated);
+        let wider = CsvSettings {
+            include_notes: true,
+            ..CsvSettings::default()
+        };
+        assert!(RowWriter::open(&path, true, OutputFormat::Csv, &wider).is_err());
+        assert!(check_on_existing(OnExisting::Append, OutputFormat::Xlsx).is_err());
+    }
+
+    #[test]
+    fn csv_line_endings_follow_settings() {
+        let tree = TestTree::new();
+        let path = tree.join("crlf.csv");
+        let settings = CsvSettings {
+            line_ending: LineEnding::Crlf,
+            ..CsvSettings::default()
+        };
+        write_result_rows(&path, false, OutputFormat::Csv, &[], &settings).expect("write");
+        let data = fs::read_to_string(&path).expect("read");
+        assert!(data.ends_with("survey_id_graded_detected\r\n"));
+
+        write_result_rows(
+            &path,
+            false,
+            OutputFormat::Csv,
+            &[],
+            &CsvSettings::default(),
+        )
+        .expect("write");
+        let data = fs::read_to_string(&path).expect("read");
+        assert!(data.ends_with("survey_id_graded_detected\n"));
+        assert!(!data.contains('\r'));
+    }
+
+    #[test]
+    fn header_map_renames_and_validates_columns() {
+        let mut settings = CsvSettings::default();
+        settings
+            .header_map
+            .insert("dolphin".to_string(), "presence".to_string());
+        let headers = row_headers(&settings).expect("headers");
+        assert_eq!(headers[3], "presence");
+        assert_eq!(headers[0], "survey_id_base");
+
+        settings
+            .header_map
+            .insert("filename".to_string(), "presence".to_string());
+        assert!(row_headers(&settings).is_err());
+
+        let mut unknown = CsvSettings::default();
+        unknown
+            .header_map
+            .insert("delfin".to_string(), "Delfin".to_string());
+        assert!(row_headers(&unknown).is_err());
+    }
+
+    #[test]
+    fn column_selection_orders_and_validates_columns() {
+        let mut settings = CsvSettings {
+            include_confidence: true,
+            columns: vec![
+                "filename".to_string(),
+                "survey_id_base".to_string(),
+                "raw_relpath".to_string(),
+                "dolphin".to_string(),
+                "label_confidence".to_string(),
+            ],
+            ..CsvSettings::default()
+        };
+        settings
+            .header_map
+            .insert("graded_hits".to_string(), "hits".to_string());
+        assert_eq!(
+            row_headers(&settings).expect("headers"),
+            [
+                "filename",
+                "survey_id_base",
+                "raw_relpath",
+                "dolphin",
+                "label_confidence"
+            ]
+        );
+        let row = CsvRow {
+            label_confidence: 0.75,
+            ..csv_row("20240101_AB", "sub/img_001.jpg", 1)
+        };
+        assert_eq!(
+            row_record(&row, &settings),
+            ["img_001.jpg", "20240101_AB", "sub/img_001.jpg", "1", "0.75"]
+        );
+
+        settings.include_confidence = false;
+        assert!(row_headers(&settings).is_err());
+        settings.columns = vec!["dolphin".to_string(), "dolphin".to_string()];
+        assert!(row_headers(&settings).is_err());
+        settings.columns = vec!["survey_id_base".to_string(), "dolphin".to_string()];
+        assert!(row_headers(&settings).is_err());
+    }
+
+    #[test]
+    fn hyperlinks_use_file_uris() {
+        assert_eq!(
+            file_uri(Path::new("/data/raw/img 001.jpg")),
+            "file:///data/raw/img%20001.jpg"
+        );
+        assert_eq!(
+            file_uri(Path::new(r"C:\Surveys\Raw\img_001.jpg")),
+            "file:///C:/Surveys/Raw/img_001.jpg"
+        );
+        assert_eq!(
+            file_uri(Path::new(r"\\nas\surveys\schön.jpg")),
+            "file://nas/surveys/sch%C3%B6n.jpg"
+        );
+        assert_eq!(
+            hyperlink(Path::new("/raw/img_001.jpg"), HyperlinkStyle::Formula),
+            "=HYPERLINK(\"file:///raw/img_001.jpg\", \"img_001.jpg\")"
+        );
+    }
+
+    #[test]
+    fn quote_ident_escapes_quotes() {
+        assert_eq!(quote_ident("public"), "\"public\"");
+        assert_eq!(quote_ident("lab\"s"), "\"lab\"\"s\"");
+        let settings: AppSettings = serde_json::from_str(
+            r#"{"postgres": {"host": "db", "user": "lab", "dbname": "surveys"}}"#,
+        )
+        .expect("settings");
+        let postgres = settings.postgres.expect("postgres");
+        assert_eq!((postgres.port, postgres.schema.as_str()), (5432, "public"));
+    }
+
+    #[test]
+    fn qa_workbook_embeds_thumbnails() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let image_path = root.join("img_001.jpg");
+        image::RgbImage::from_pixel(320, 200, image::Rgb([20, 90, 160]))
+            .save(&image_path)
+            .expect("save image");
+        fs::write(root.join("broken.jpg"), "not an image").expect("write");
+        assert!(thumbnail_png(&image_path).is_some());
+        assert!(thumbnail_png(&root.join("broken.jpg")).is_none());
+
+        let options: RootRunOptions = serde_json::from_str(
+            r#"{"write_per_survey": false, "write_merged": false, "merged_filename": "m.csv",
+                "problems_filename": "p.csv", "per_survey_dirname": "per",
+                "write_qa_workbook": true, "thumbnails": "dolphin", "thumbnail_limit": 1}"#,
+        )
+        .expect("options");
+        let row = |raw_path: PathBuf, dolphin: u8| CsvRow {
+            raw_path,
+            ..csv_row("20240101_AB", "img_001.jpg", dolphin)
+        };
+        let rows = vec![
+            row(image_path.clone(), 1),
+            row(root.join("broken.jpg"), 1),
+            row(image_path, 0),
+        ];
+        let path = root.join(&options.qa_workbook_filename);
+        let mut workbook = QaWorkbook::new(&options).expect("workbook");
+        workbook.write_rows(&rows, &options.csv).expect("rows");
+        assert_eq!(workbook.embedded, 1);
+        workbook.save(&path).expect("save");
+        let cells = xlsx_cells(&path);
+        assert_eq!(cells.len(), 1);
+        let (name, cells) = &cells[0];
+        assert_eq!(name, "Results");
+        let cell = |reference: &str| cells.get(reference).map(String::as_str);
+        assert_eq!(cell("A1"), Some("thumbnail"));
+        assert_eq!(cell("B1"), Some("survey_id_base"));
+        assert_eq!(cell("B2"), Some("20240101_AB"));
+        assert_eq!(cell("C4"), Some("img_001.jpg"));
+        assert_eq!(cell("E2"), Some("1"));
+        assert_eq!(cell("E4"), Some("0"));
+        assert_eq!(cell("F2"), Some("IND/img_001.jpg"));
+
+        let access_path = root.join(&options.access_export_filename);
+        let rows = [CsvRow {
+            survey_date: Some("2024-03-15".to_string()),
+            area: Some("AB".to_string()),
+            ..rows[0].clone()
+        }];
+        let mut access = AccessExport::new("run-1").expect("access export");
+        access.write_rows(&rows).expect("rows");
+        access.save(&access_path).expect("save");
+        let cells = xlsx_cells(&access_path);
+        let (name, cells) = &cells[0];
+        assert_eq!(name, "Sightings");
+        let row: Vec<&str> = ["A", "B", "C", "D", "F", "H", "K"]
+            .iter()
+            .map(|col| cells[&format!("{col}2")].as_str())
+            .collect();
+        // 45366 is 2024-03-15 as an Excel date; the dolphin flag is a boolean.
+        assert_eq!(
+            row,
+            [
+                "20240101_AB",
+                "45366",
+                "AB",
+                "img_001.jpg",
+                "1",
+                "1",
+                "run-1"
+            ]
+        );
+        assert_eq!(cells["A1"], "SurveyID");
+        assert!(excel_date("2024-03-15").is_some());
+        assert!(excel_date("2024-13-01").is_none());
+        assert!(excel_date("15.03.2024").is_none());
+    }
+
+    #[test]
+    fn xlsx_output_replaces_csv_extension() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        assert_eq!(
+            OutputFormat::Xlsx.output_path(root.join("merged.csv")),
+            root.join("merged.xlsx")
+        );
+        assert_eq!(
+            OutputFormat::Csv.output_path(root.join("merged.txt")),
+            root.join("merged.txt")
+        );
+        let settings: CsvSettings = serde_json::from_str(
+            r#"{"include_survey_fields": true, "include_winner_details": true}"#,
+        )
+        .expect("settings");
+        let row = CsvRow {
+            graded_hits: 2,
+            raw_path: root.join("0012.jpg"),
+            survey_date: Some("2024-01-01".to_string()),
+            area: Some("AB".to_string()),
+            ..csv_row("20240101_AB", "card 1/0012.jpg", 1)
+        };
+        let path = OutputFormat::Xlsx.output_path(root.join("merged.csv"));
+        write_result_rows(
+            &path,
+            false,
+            OutputFormat::Xlsx,
+            &[row.clone(), row],
+            &settings,
+        )
+        .expect("workbook");
+        let cells = xlsx_cells(&path);
+        let (name, cells) = &cells[0];
+        assert_eq!(name, "Results");
+        let headers = row_headers(&settings).expect("headers");
+        let column = |header: &str| {
+            let index = headers
+                .iter()
+                .position(|name| name == header)
+                .expect(header);
+            char::from(b'A' + index as u8)
+        };
+        for row in [2, 3] {
+            let cell = |header: &str| cells[&format!("{}{}", column(header), row)].as_str();
+            assert_eq!(cell("survey_id_base"), "20240101_AB");
+            assert_eq!(cell("raw_relpath"), "card 1/0012.jpg");
+            // Text keeps leading zeros; counts and dates are numbers.
+            assert_eq!(cell("filename"), "0012.jpg");
+            assert_eq!(cell("graded_hits"), "2");
+            assert_eq!(cell("survey_date"), "45292");
+            assert_eq!(cell("area"), "AB");
+        }
+        assert!(!cells.contains_key("A4"));
+    }
+
+    #[test]
+    fn parquet_output_has_typed_nullable_columns() {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        let tree = TestTree::new();
+        let root = tree.path();
+        let settings = CsvSettings {
+            include_survey_fields: true,
+            include_confidence: true,
+            ..CsvSettings::default()
+        };
+        let row = |dolphin: u8, area: Option<&str>| CsvRow {
+            graded_relpath: "IND/0012.jpg".to_string(),
+            graded_hits: 2,
+            graded_winner_type: "IND".to_string(),
+            raw_path: root.join("0012.jpg"),
+            survey_date: Some("2024-01-01".to_string()),
+            area: area.map(str::to_string),
+            label_confidence: 0.85,
+            ..csv_row("20240101_AB", "0012.jpg", dolphin)
+        };
+        let path = OutputFormat::Parquet.output_path(root.join("merged.csv"));
+        assert_eq!(path, root.join("merged.parquet"));
+        write_result_rows(
+            &path,
+            false,
+            OutputFormat::Parquet,
+            &[row(1, Some("AB")), row(0, None)],
+            &settings,
+        )
+        .expect("parquet");
+        assert!(check_on_existing(OnExisting::Append, OutputFormat::Parquet).is_err());
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&path).expect("open"))
+            .expect("reader")
+            .build()
+            .expect("build");
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.expect("batch")).collect();
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        let schema = batch.schema();
+        assert_eq!(
+            schema
+                .field_with_name("dolphin")
+                .expect("dolphin")
+                .data_type(),
+            &DataType::UInt8
+        );
+        assert_eq!(
+            schema
+                .field_with_name(CONFIDENCE_COLUMN)
+                .expect("confidence")
+                .data_type(),
+            &DataType::Float64
+        );
+        let area = batch.column(schema.index_of("area").expect("area"));
+        assert_eq!(area.null_count(), 1);
+    }
+
+    #[test]
+    fn jsonl_output_keeps_types_and_nulls() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let settings = CsvSettings {
+            include_survey_fields: true,
+            include_confidence: true,
+            columns: vec![
+                "survey_id_base".to_string(),
+                "raw_relpath".to_string(),
+                "dolphin".to_string(),
+                "graded_hits".to_string(),
+                "area".to_string(),
+                "label_confidence".to_string(),
+            ],
+            ..CsvSettings::default()
+        };
+        let row = |area: Option<&str>| CsvRow {
+            graded_hits: 2,
+            raw_path: root.join("0012.jpg"),
+            area: area.map(str::to_string),
+            label_confidence: 0.5,
+            ..csv_row("20240101_AB", "0012.jpg", 1)
+        };
+        let path = OutputFormat::Jsonl.output_path(root.join("merged.csv"));
+        write_result_rows(
+            &path,
+            false,
+            OutputFormat::Jsonl,
+            &[row(Some("AB"))],
+            &settings,
+        )
+        .expect("jsonl");
+        write_result_rows(&path, true, OutputFormat::Jsonl, &[row(None)], &settings)
+            .expect("append");
+        let data = fs::read_to_string(&path).expect("read");
+        let lines: Vec<&str> = data.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"survey_id_base":"20240101_AB","raw_relpath":"0012.jpg","dolphin":1,"graded_hits":2,"area":"AB","label_confidence":0.5}"#,
+                r#"{"survey_id_base":"20240101_AB","raw_relpath":"0012.jpg","dolphin":1,"graded_hits":2,"area":null,"label_confidence":0.5}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn html_report_is_self_contained_and_escaped() {
+        let rules = Rules::default();
+        let provenance = RunProvenance {
+            started_at_ms: 0,
+            finished_at_ms: 1_000,
+            raw_root: "/data/raw".to_string(),
+            graded_root: "/data/graded".to_string(),
+            rules: &rules,
+        };
+        let summary: RunSummary = serde_json::from_value(serde_json::json!({
+            "processed_surveys": 1,
+            "total_rows": 4,
+            "dolphin_yes": 1,
+            "dolphin_no": 3,
+            "ambiguity_warnings": 0,
+            "problems_count": 1,
+            "output_dir": "/data/out",
+            "merged_csv_path": null,
+            "problems_csv_path": null,
+            "surveys": [{
+                "survey_id_base": "20240101_AB",
+                "raw_path": "/data/raw/20240101_AB",
+                "graded_path": "/data/graded/20240101_AB",
+                "rows": 4,
+                "dolphin_yes": 1,
+                "dolphin_no": 3,
+                "ambiguity_warnings": 0
+            }]
+        }))
+        .expect("summary");
+        let problems = vec![ProblemItem {
+            survey_id_base: "20240102_<CD>".to_string(),
+            survey_id_detected: None,
+            raw_path: None,
+            graded_path: None,
+            problem_type: "GRADED_MISSING".to_string(),
+            severity: problem_severity("GRADED_MISSING"),
+            details: Some("No graded folder & no notes".to_string()),
+            suggested_path: None,
+        }];
+        let html = render_html_report(&summary, &problems, &provenance);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(!html.contains("<script") && !html.contains("<link"));
+        assert!(html.contains("style=\"width:25.0%\""));
+        assert!(html.contains("20240102_&lt;CD&gt;"));
+        assert!(html.contains("No graded folder &amp; no notes"));
+    }
+
+    #[test]
+    fn streamed_rows_of_failed_surveys_are_taken_back() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let settings = CsvSettings::default();
+        let row = |survey: &str, index: usize| CsvRow {
+            raw_path: root.join(format!("img_{:05}.jpg", index)),
+            ..csv_row(survey, &format!("img_{:05}.jpg", index), 0)
+        };
+        let merged_path = root.join("merged.csv");
+        let mut merged =
+            RowWriter::create(&merged_path, OutputFormat::Csv, &settings).expect("merged");
+        let mut exports = RunExports {
+            access: Some(AccessExport::new("run-1").expect("access")),
+            ..RunExports::default()
+        };
+        // Result files of an earlier run, overwritten by this one.
+        tree.write("A.csv", "old\n");
+        tree.write("B.csv", "old\n");
+
+        let mut output = SurveyOutput::new(&settings, Some(&mut merged), None, Some(&mut exports))
+            .expect("output");
+        output
+            .open_file(&root.join("A.csv"), false, OutputFormat::Csv)
+            .expect("open");
+        for index in 0..3 {
+            output.push(row("A", index)).expect("push");
+        }
+        output.commit().expect("commit");
+
+        let mut output = SurveyOutput::new(&settings, Some(&mut merged), None, Some(&mut exports))
+            .expect("output");
+        output
+            .open_file(&root.join("B.csv"), false, OutputFormat::Csv)
+            .expect("open");
+        for index in 0..ROW_BATCH_SIZE + 5 {
+            output.push(row("B", index)).expect("push");
+        }
+        assert!(root.join("B.csv.part").exists());
+        output.rollback().expect("rollback");
+        merged.finish().expect("finish");
+
+        let surveys = |path: &Path| -> Vec<String> {
+            run_csv_reader(path)
+                .expect("reader")
+                .records()
+                .map(|record| record.expect("record")[0].to_string())
+                .collect()
+        };
+        assert_eq!(surveys(&merged_path), vec!["A", "A", "A"]);
+        assert_eq!(surveys(&root.join("A.csv")).len(), 3);
+        assert!(!root.join("A.csv.part").exists());
+        assert!(!root.join("B.csv.part").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("B.csv")).expect("read"),
+            "old\n"
+        );
+        let access_path = root.join("access.xlsx");
+        exports
+            .access
+            .take()
+            .expect("access")
+            .save(&access_path)
+            .expect("save");
+        let cells = xlsx_cells(&access_path);
+        assert!(cells[0].1.contains_key("A4"));
+        assert!(!cells[0].1.contains_key("A5"));
+    }
+
+    #[test]
+    fn sqlite_export_keeps_earlier_runs() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let path = root.join("results.sqlite");
+        let rules = Rules::default();
+        let provenance = RunProvenance {
+            started_at_ms: 1,
+            finished_at_ms: 2,
+            raw_root: "raw".to_string(),
+            graded_root: "graded".to_string(),
+            rules: &rules,
+        };
+        let row = |dolphin: u8| CsvRow {
+            raw_path: root.join("img_001.jpg"),
+            ..csv_row("20240101_AB", "img_001.jpg", dolphin)
+        };
+        let summary = |run_id: &str| -> RunSummary {
+            serde_json::from_value(serde_json::json!({
+                "run_id": run_id,
+                "processed_surveys": 1,
+                "total_rows": 1,
+                "dolphin_yes": 0,
+                "dolphin_no": 1,
+                "ambiguity_warnings": 0,
+                "problems_count": 0,
+                "output_dir": root.to_string_lossy(),
+            }))
+            .expect("summary")
+        };
+        let export = |run_id: &str, dolphin: u8| {
+            let mut sqlite = SqliteExport::open(&path, run_id).expect("open");
+            sqlite.write_rows(&[row(dolphin)]).expect("rows");
+            sqlite
+        };
+        export("run-a", 0)
+            .finish(&summary("run-a"), &[], &provenance)
+            .expect("a");
+        export("run-b", 1)
+            .finish(&summary("run-b"), &[], &provenance)
+            .expect("b");
+        // Exporting a run again replaces its rows, and one given up before
+        // `finish` leaves the database as it was.
+        export("run-b", 1)
+            .finish(&summary("run-b"), &[], &provenance)
+            .expect("b");
+        drop(export("run-c", 1));
+
+        let conn = Connection::open(&path).expect("open");
+        let flipped: Vec<String> = conn
+            .prepare(
+                "SELECT b.raw_relpath FROM result_rows a JOIN result_rows b
+                 ON a.survey_id_base = b.survey_id_base AND a.raw_relpath = b.raw_relpath
+                 WHERE a.run_id = 'run-a' AND b.run_id = 'run-b'
+                 AND a.dolphin = 0 AND b.dolphin = 1",
+            )
+            .expect("prepare")
+            .query_map([], |row| row.get(0))
+            .expect("query")
+            .collect::<Result<_, _>>()
+            .expect("rows");
+        assert_eq!(flipped, vec!["img_001.jpg"]);
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .expect("count")
+        };
+        assert_eq!(count("runs"), 2);
+        assert_eq!(count("result_rows"), 2);
+    }
+
+    fn postgres_test_summary(run_id: &str) -> RunSummary {
+        serde_json::from_value(serde_json::json!({
+            "run_id": run_id,
+            "processed_surveys": 1,
+            "total_rows": 1,
+            "dolphin_yes": 1,
+            "dolphin_no": 0,
+            "ambiguity_warnings": 0,
+            "problems_count": 0,
+            "output_dir": "out",
+        }))
+        .expect("summary")
+    }
+
+    #[test]
+    fn postgres_schema_adds_columns_missing_from_older_tables() {
+        let sql = postgres_schema_sql("\"lab\"");
+        assert!(sql.contains("CREATE TABLE IF NOT EXISTS \"lab\".runs (\n    run_id TEXT PRIMARY KEY,\n    started_at TIMESTAMPTZ NOT NULL,"));
+        for (table, columns) in [
+            ("runs", POSTGRES_RUNS_COLUMNS),
+            ("result_rows", POSTGRES_ROW_COLUMNS),
+            ("problems", POSTGRES_PROBLEM_COLUMNS),
+        ] {
+            for (name, kind) in columns {
+                let kind = kind.trim_end_matches(" NOT NULL");
+                let alter = format!(
+                    "ALTER TABLE \"lab\".{table} ADD COLUMN IF NOT EXISTS {name} {kind};\n"
+                );
+                assert!(sql.contains(&alter), "{alter}");
+            }
+        }
+        assert_eq!(
+            postgres_column_list(POSTGRES_PROBLEM_COLUMNS),
+            "run_id, survey_id_base, survey_id_detected, raw_path, graded_path, problem_type, severity, details"
+        );
+    }
+
+    #[test]
+    fn postgres_export_reports_connection_and_certificate_errors() {
+        let tree = TestTree::new();
+        let rules = Rules::default();
+        let provenance = RunProvenance {
+            started_at_ms: 1,
+            finished_at_ms: 2,
+            raw_root: "raw".to_string(),
+            graded_root: "graded".to_string(),
+            rules: &rules,
+        };
+        // A port nobody listens on any more.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("port")
+            .port();
+        let mut settings: PostgresSettings = serde_json::from_value(serde_json::json!({
+            "host": "127.0.0.1",
+            "port": port,
+            "user": "lab",
+            "dbname": "surveys",
+        }))
+        .expect("settings");
+        assert_eq!(settings.tls, PostgresTls::Require);
+        let Err(err) = PostgresExport::open(&settings, None, "run-a", &provenance) else {
+            panic!("refused");
+        };
+        assert!(err.to_string().starts_with("PostgreSQL export: "), "{err}");
+
+        settings.ca_certificate = Some(tree.write("ca.pem", "not a certificate"));
+        let Err(err) = PostgresExport::open(&settings, None, "run-a", &provenance) else {
+            panic!("no certificate");
+        };
+        assert!(matches!(err, AppError::Validation(_)), "{err}");
+        assert!(err.to_string().contains("ca.pem"), "{err}");
+    }
+
+    /// Needs a scratch database: run with
+    /// `SURVEY_LABELER_TEST_POSTGRES='{"host": …, "user": …, "dbname": …}'
+    /// cargo test postgres_export_round_trip -- --ignored`. The settings are
+    /// the `postgres` block of `settings.json`; the test uses its own schema
+    /// and drops it again.
+    #[test]
+    #[ignore]
+    fn postgres_export_round_trip() {
+        let Some(json) = std::env::var_os("SURVEY_LABELER_TEST_POSTGRES") else {
+            return;
+        };
+        let mut settings: PostgresSettings =
+            serde_json::from_str(&json.to_string_lossy()).expect("settings");
+        settings.schema = format!("survey_labeler_test_{}", std::process::id());
+        let password = settings.password.take();
+        let schema = quote_ident(&settings.schema);
+        let rules = Rules::default();
+        let provenance = RunProvenance {
+            started_at_ms: 1,
+            finished_at_ms: 2,
+            raw_root: "raw".to_string(),
+            graded_root: "graded".to_string(),
+            rules: &rules,
+        };
+        let mut config = postgres::Config::new();
+        config
+            .host(&settings.host)
+            .port(settings.port)
+            .user(&settings.user)
+            .dbname(&settings.dbname);
+        if let Some(password) = password.as_deref() {
+            config.password(password);
+        }
+        let mut client = match settings.tls {
+            PostgresTls::Require => config.connect(postgres_tls(&settings).expect("tls")),
+            PostgresTls::Disable => config.connect(NoTls),
+        }
+        .expect("connect");
+        // A table from an older version without the `area` column.
+        client
+            .batch_execute(&format!(
+                "CREATE SCHEMA {schema};
+                 CREATE TABLE {schema}.runs (run_id TEXT PRIMARY KEY);
+                 CREATE TABLE {schema}.result_rows (
+                     run_id TEXT NOT NULL REFERENCES {schema}.runs (run_id) ON DELETE CASCADE,
+                     survey_id_base TEXT NOT NULL,
+                     raw_relpath TEXT NOT NULL
+                 );"
+            ))
+            .expect("old tables");
+
+        let rows = [CsvRow {
+            area: Some("North".to_string()),
+            ..csv_row("20240101_AB", "img_001.jpg", 1)
+        }];
+        let summary = postgres_test_summary("run-a");
+        let export = || {
+            let mut export =
+                PostgresExport::open(&settings, password.clone(), "run-a", &provenance)?;
+            export.write_rows(&rows)?;
+            export.finish(&summary, &[], &provenance)
+        };
+        let result = export().and_then(|()| export());
+        let exported = client.query(
+            &format!("SELECT raw_relpath, area FROM {schema}.result_rows"),
+            &[],
+        );
+        client
+            .batch_execute(&format!("DROP SCHEMA {schema} CASCADE"))
+            .expect("drop");
+        result.expect("export");
+        let exported: Vec<(String, Option<String>)> = exported
+            .expect("query")
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        assert_eq!(
+            exported,
+            vec![("img_001.jpg".to_string(), Some("North".to_string()))]
+        );
+    }
+
+    #[test]
+    fn csv_dialect_options_are_written_and_read_back() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let settings = CsvSettings {
+            delimiter: CsvDelimiter::Semicolon,
+            quote_style: CsvQuoteStyle::Always,
+            utf8_bom: true,
+            ..CsvSettings::default()
+        };
+        let path = root.join("merged.csv");
+        drop(init_csv_writer(&path, &settings).expect("writer"));
+        let data = fs::read_to_string(&path).expect("read");
+        assert!(data.starts_with("\u{feff}\"survey_id_base\";\"raw_relpath\";"));
+
+        let mut reader = run_csv_reader(&path).expect("reader");
+        assert_eq!(&reader.headers().expect("headers")[0], "survey_id_base");
+        assert!(RowWriter::open(&path, true, OutputFormat::Csv, &settings).is_ok());
+        assert!(RowWriter::open(&path, true, OutputFormat::Csv, &CsvSettings::default()).is_err());
+    }
+
+    #[test]
+    fn unquoted_csv_refuses_fields_with_the_delimiter() {
+        let tree = TestTree::new();
+        let path = tree.join("merged.csv");
+        let settings = CsvSettings {
+            quote_style: CsvQuoteStyle::Never,
+            ..CsvSettings::default()
+        };
+        let mut writer = init_csv_writer(&path, &settings).expect("writer");
+        let row = csv_row("20240101_AB", "IMG_0001.JPG", 1);
+        write_rows_to_writer(&mut writer, std::slice::from_ref(&row), &settings)
+            .expect("plain row");
+        for raw_relpath in ["Left, Right/IMG_0002.JPG", "Left\nRight/IMG_0002.JPG"] {
+            let row = CsvRow {
+                raw_relpath: raw_relpath.to_string(),
+                ..row.clone()
+            };
+            let error = write_rows_to_writer(&mut writer, &[row], &settings).expect_err("field");
+            assert!(matches!(error, AppError::Validation(_)));
+        }
+        drop(writer);
+        assert_eq!(fs::read_to_string(&path).expect("read").lines().count(), 2);
+
+        let settings = CsvSettings {
+            delimiter: CsvDelimiter::Semicolon,
+            ..settings
+        };
+        let mut writer = init_csv_writer(&path, &settings).expect("writer");
+        let row = CsvRow {
+            raw_relpath: "Left, Right/IMG_0002.JPG".to_string(),
+            ..row
+        };
+        write_rows_to_writer(&mut writer, &[row], &settings).expect("comma with semicolons");
+    }
+}
@@ -0,0 +1,346 @@
+//! Root folders and file paths: normalizing the roots a run is given,
+//! Windows extended-length and mapped-drive forms, and the checks before the
+//! app opens a path.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::matching::is_video;
+use crate::rules::{compile_rules, get_or_init_rules};
+use crate::scan::is_supported_image;
+use crate::{AppError, Host};
+
+/// A path from the UI that is about to be handed to the system file manager:
+/// absolute, free of `..` and existing, returned in canonical form.
+pub fn checked_reveal_path(path: &str) -> Result<PathBuf, AppError> {
+    let path = Path::new(path.trim());
+    if path.as_os_str().is_empty() {
+        return Err(AppError::Validation("No path given.".to_string()));
+    }
+    if !path.is_absolute()
+        || path
+            .components()
+            .any(|part| matches!(part, std::path::Component::ParentDir))
+    {
+        return Err(AppError::Validation(format!(
+            "{} is not an absolute path.",
+            path.to_string_lossy()
+        )));
+    }
+    if !long_path(path).exists() {
+        return Err(AppError::Validation(format!(
+            "{} does not exist.",
+            path.to_string_lossy()
+        )));
+    }
+    Ok(short_path(&fs::canonicalize(long_path(path))?).into_owned())
+}
+
+/// Like [`checked_reveal_path`], but the path must be an image or video file
+/// of the current rules, so opening it with the default app never runs a
+/// script or program.
+pub fn checked_image_path(host: &Host, path: &str) -> Result<PathBuf, AppError> {
+    let path = checked_reveal_path(path)?;
+    let rules = compile_rules(&get_or_init_rules(host)?)?;
+    if !path.is_file() || !(is_supported_image(&path, &rules) || is_video(&path, &rules)) {
+        return Err(AppError::Validation(format!(
+            "{} is not an image file.",
+            path.to_string_lossy()
+        )));
+    }
+    Ok(path)
+}
+
+/// Normalizes every root of one side, sorted and without duplicates, so the
+/// same roots picked in another order make the same run and resume its
+/// checkpoint. A run needs at least one root per side, and a root inside
+/// another one of the same side is refused since its images would be read
+/// twice.
+pub(crate) fn normalize_roots(
+    side: &str,
+    roots: &[PathBuf],
+    resolve_mapped_drives: bool,
+) -> Result<Vec<PathBuf>, AppError> {
+    if roots.is_empty() {
+        return Err(AppError::Validation(format!(
+            "At least one {} root is required.",
+            side
+        )));
+    }
+    let mut normalized: Vec<PathBuf> = roots
+        .iter()
+        .map(|root| normalize_root(root, resolve_mapped_drives))
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    // Sorted, a folder comes right before the folders inside it.
+    for pair in normalized.windows(2) {
+        if pair[1].starts_with(&pair[0]) {
+            return Err(AppError::Validation(format!(
+                "The {} root {} lies inside the {} root {}; pick only one of them.",
+                side,
+                pair[1].to_string_lossy(),
+                side,
+                pair[0].to_string_lossy()
+            )));
+        }
+    }
+    Ok(normalized)
+}
+
+/// Roots as stored in checkpoints, provenance and the run history.
+pub(crate) fn join_roots(roots: &[PathBuf]) -> String {
+    roots
+        .iter()
+        .map(|root| root.to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The root `path` was found under.
+pub(crate) fn root_of(roots: &[PathBuf], path: &Path) -> String {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.components().count())
+        .map(|root| root.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Canonical form of a picked root folder: symlinks and `..` resolved, no
+/// `\\?\` verbatim prefix and, optionally, mapped drives replaced by their
+/// UNC share. Folders that cannot be resolved are returned unchanged.
+pub fn normalize_root(path: &Path, resolve_mapped_drives: bool) -> PathBuf {
+    let canonical = fs::canonicalize(path)
+        .map(|canonical| strip_verbatim_prefix(&canonical))
+        .unwrap_or_else(|_| path.to_path_buf());
+    if resolve_mapped_drives {
+        mapped_drive_to_unc(&canonical).unwrap_or(canonical)
+    } else {
+        canonical
+    }
+}
+
+fn strip_verbatim_prefix(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if let Some(rest) = text.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", rest))
+    } else if let Some(rest) = text.strip_prefix(r"\\?\") {
+        PathBuf::from(rest)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// `path` in extended-length form (`\\?\C:\...`, `\\?\UNC\server\share\...`)
+/// so Windows accepts it beyond 260 characters. Used where files are opened
+/// and roots are walked; other platforms get the path back unchanged.
+pub(crate) fn long_path(path: &Path) -> std::borrow::Cow<'_, Path> {
+    if cfg!(windows) {
+        if let Some(extended) = extended_length_path(&path.to_string_lossy()) {
+            return std::borrow::Cow::Owned(PathBuf::from(extended));
+        }
+    }
+    std::borrow::Cow::Borrowed(path)
+}
+
+/// Undoes `long_path` for paths found while walking a long root, so results
+/// and relpaths keep the form the user entered.
+pub(crate) fn short_path(path: &Path) -> std::borrow::Cow<'_, Path> {
+    if cfg!(windows) && path.to_string_lossy().starts_with(r"\\?\") {
+        std::borrow::Cow::Owned(strip_verbatim_prefix(path))
+    } else {
+        std::borrow::Cow::Borrowed(path)
+    }
+}
+
+/// Extended-length spelling of an absolute drive or UNC path. `None` for
+/// paths that are already verbatim, relative or contain `.`/`..`, which the
+/// verbatim form would not resolve.
+fn extended_length_path(path: &str) -> Option<String> {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        return None;
+    }
+    let path = path.replace('/', "\\");
+    if path
+        .split('\\')
+        .skip(1)
+        .any(|component| component == "." || component == "..")
+    {
+        return None;
+    }
+    if let Some(share) = path.strip_prefix(r"\\") {
+        return (!share.is_empty()).then(|| format!(r"\\?\UNC\{}", share));
+    }
+    let bytes = path.as_bytes();
+    let is_drive_path =
+        bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'\\';
+    is_drive_path.then(|| format!(r"\\?\{}", path))
+}
+
+#[cfg(windows)]
+fn mapped_drive_to_unc(path: &Path) -> Option<PathBuf> {
+    use windows_sys::Win32::NetworkManagement::WNet::WNetGetConnectionW;
+
+    let text = path.to_string_lossy();
+    let mut chars = text.chars();
+    let drive = chars.next().filter(|ch| ch.is_ascii_alphabetic())?;
+    if chars.next() != Some(':') {
+        return None;
+    }
+    let local: Vec<u16> = format!("{}:", drive).encode_utf16().chain([0]).collect();
+    let mut length = 1024u32;
+    let mut remote = vec![0u16; length as usize];
+    // SAFETY: `local` is NUL-terminated and `remote` holds `length` u16s.
+    let status = unsafe { WNetGetConnectionW(local.as_ptr(), remote.as_mut_ptr(), &mut length) };
+    if status != 0 {
+        return None;
+    }
+    let end = remote
+        .iter()
+        .position(|ch| *ch == 0)
+        .unwrap_or(remote.len());
+    let share = PathBuf::from(String::from_utf16_lossy(&remote[..end]));
+    let rest = text[2..].trim_start_matches(['\\', '/']);
+    Some(if rest.is_empty() {
+        share
+    } else {
+        share.join(rest)
+    })
+}
+
+#[cfg(not(windows))]
+fn mapped_drive_to_unc(_path: &Path) -> Option<PathBuf> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rules::Rules;
+    use crate::runs::run_root_scan;
+    use crate::test_support::{root_options, TestTree};
+
+    #[test]
+    fn only_existing_absolute_paths_and_images_are_opened() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        fs::create_dir_all(root.join("survey")).expect("create");
+        fs::write(root.join("survey").join("IMG_0001.JPG"), b"raw").expect("write");
+        fs::write(root.join("survey").join("run.sh"), b"echo").expect("write");
+        let host = tree.host();
+        let image = root.join("survey").join("IMG_0001.JPG");
+        let canonical = fs::canonicalize(&image).expect("canonical");
+
+        let revealed = checked_reveal_path(&format!(" {} ", image.to_string_lossy()));
+        assert_eq!(revealed.expect("reveal"), canonical);
+        assert!(checked_reveal_path(&root.join("survey").to_string_lossy()).is_ok());
+        assert!(checked_reveal_path("").is_err());
+        assert!(checked_reveal_path("survey/IMG_0001.JPG").is_err());
+        let dotted = root.join("survey").join("..").join("survey");
+        assert!(checked_reveal_path(&dotted.to_string_lossy()).is_err());
+        assert!(checked_reveal_path(&root.join("missing").to_string_lossy()).is_err());
+
+        let opened = checked_image_path(&host, &image.to_string_lossy());
+        assert_eq!(opened.expect("open"), canonical);
+        let script = root.join("survey").join("run.sh");
+        assert!(checked_image_path(&host, &script.to_string_lossy()).is_err());
+        assert!(checked_image_path(&host, &root.join("survey").to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn roots_are_canonicalized_without_verbatim_prefix() {
+        assert_eq!(
+            strip_verbatim_prefix(Path::new(r"\\?\UNC\nas\surveys")),
+            PathBuf::from(r"\\nas\surveys")
+        );
+        assert_eq!(
+            strip_verbatim_prefix(Path::new(r"\\?\Z:\surveys")),
+            PathBuf::from(r"Z:\surveys")
+        );
+
+        let tree = TestTree::new();
+        let root = tree.path();
+        tree.mkdir("raw");
+        let messy = root.join("raw").join("..").join("raw");
+        assert_eq!(
+            normalize_root(&messy, false),
+            fs::canonicalize(root.join("raw")).expect("canonical")
+        );
+        let missing = root.join("missing");
+        assert_eq!(normalize_root(&missing, true), missing);
+    }
+
+    #[test]
+    fn roots_are_sorted_deduplicated_and_not_nested() {
+        let tree = TestTree::new();
+        let north = tree.mkdir("north");
+        let south = tree.mkdir("south");
+        let canonical = |path: &Path| fs::canonicalize(path).expect("canonical");
+        let roots = normalize_roots(
+            "raw",
+            &[south.clone(), north.clone(), north.join("..").join("north")],
+            false,
+        )
+        .expect("roots");
+        assert_eq!(roots, vec![canonical(&north), canonical(&south)]);
+        assert_eq!(
+            join_roots(&roots),
+            join_roots(&normalize_roots("raw", &[north.clone(), south], false).expect("roots"))
+        );
+
+        let nested = tree.mkdir("north/2024");
+        assert!(matches!(
+            normalize_roots("raw", &[nested, north], false),
+            Err(AppError::Validation(_))
+        ));
+        assert!(normalize_roots("raw", &[], false).is_err());
+    }
+
+    #[test]
+    fn long_paths_are_written_in_extended_length_form() {
+        assert_eq!(
+            extended_length_path(r"\\nas\surveys\Raw").as_deref(),
+            Some(r"\\?\UNC\nas\surveys\Raw")
+        );
+        assert_eq!(
+            extended_length_path("Z:/surveys/Raw").as_deref(),
+            Some(r"\\?\Z:\surveys\Raw")
+        );
+        assert_eq!(extended_length_path(r"\\?\Z:\surveys"), None);
+        assert_eq!(extended_length_path(r"Z:\surveys\..\Raw"), None);
+        assert_eq!(extended_length_path(r"surveys\Raw"), None);
+        assert_eq!(extended_length_path(r"\\"), None);
+
+        // Nest the survey folders well past the 260-character limit.
+        let tree = TestTree::new();
+        let root = tree.path();
+        let deep = (0..12).fold(root.to_path_buf(), |path, level| {
+            path.join(format!("archive_level_{:02}_with_a_long_name", level))
+        });
+        let raw_dir = deep.join("raw").join("20240101_AB");
+        let graded_dir = deep.join("graded").join("20240101_AB").join("Dolphins");
+        assert!(raw_dir.to_string_lossy().len() > 260);
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(&graded_dir).expect("create");
+        fs::write(raw_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        fs::write(graded_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        let host = tree.host();
+        let options = root_options(serde_json::json!({
+            "write_per_survey": true
+        }));
+        let summary = run_root_scan(
+            &host,
+            vec![deep.join("graded")],
+            vec![deep.join("raw")],
+            deep.join("out"),
+            options,
+            Rules::default(),
+        )
+        .expect("run");
+        assert_eq!(summary.total_rows, 1);
+        assert_eq!(summary.surveys[0].dolphin_yes, 1);
+        assert!(deep.join("out").join("merged.csv").is_file());
+    }
+}
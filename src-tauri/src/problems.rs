@@ -0,0 +1,686 @@
+//! Problems found while pairing and matching surveys, and the files
+//! listing them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::{csv_writer, write_csv_record, CsvSettings};
+use crate::runs::RootRunOptions;
+use crate::scan::{ScanEntry, SurveyFolder, AUTO_RESOLVED_NOTE};
+use crate::AppError;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProblemItem {
+    pub survey_id_base: String,
+    pub survey_id_detected: Option<String>,
+    pub raw_path: Option<String>,
+    pub graded_path: Option<String>,
+    pub problem_type: String,
+    pub severity: ProblemSeverity,
+    pub details: Option<String>,
+    /// For duplicate folders, the candidate the resolver scored best.
+    #[serde(default)]
+    pub suggested_path: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProblemSeverity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl ProblemSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProblemSeverity::Error => "error",
+            ProblemSeverity::Warning => "warning",
+            ProblemSeverity::Info => "info",
+        }
+    }
+}
+
+/// Problems of one type that share a parent folder, i.e. most likely a single
+/// root cause such as a misnamed parent directory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProblemGroup {
+    pub problem_type: String,
+    pub severity: ProblemSeverity,
+    pub ancestor_path: Option<String>,
+    pub occurrences: u64,
+    pub survey_id_bases: Vec<String>,
+    pub details: Option<String>,
+}
+
+pub(crate) fn problem_severity(problem_type: &str) -> ProblemSeverity {
+    match problem_type {
+        "DUPLICATE_RAW" | "DUPLICATE_GRADED" | "SURVEY_ERROR" | "SURVEY_TIMEOUT"
+        | "RUN_CANCELLED" | "SCAN_ERROR" => ProblemSeverity::Error,
+        "UNEXPECTED_SURVEY" => ProblemSeverity::Info,
+        "RAW_MISSING" | "GRADED_MISSING" | "EXPECTED_MISSING" | "FILE_ID_COLLISION" => {
+            ProblemSeverity::Warning
+        }
+        _ => ProblemSeverity::Warning,
+    }
+}
+
+pub(crate) fn severity_count(problems: &[ProblemItem], severity: ProblemSeverity) -> u64 {
+    problems
+        .iter()
+        .filter(|problem| problem.severity == severity)
+        .count() as u64
+}
+
+pub(crate) fn group_problems(problems: &[ProblemItem]) -> Vec<ProblemGroup> {
+    let mut groups: Vec<ProblemGroup> = Vec::new();
+    let mut index: HashMap<(String, Option<String>), usize> = HashMap::new();
+    for problem in problems {
+        let ancestor = problem_ancestor(problem).map(|path| path.to_string_lossy().to_string());
+        let key = (problem.problem_type.clone(), ancestor.clone());
+        match index.get(&key) {
+            Some(position) => {
+                let group = &mut groups[*position];
+                group.occurrences += 1;
+                group.survey_id_bases.push(problem.survey_id_base.clone());
+            }
+            None => {
+                index.insert(key, groups.len());
+                groups.push(ProblemGroup {
+                    problem_type: problem.problem_type.clone(),
+                    severity: problem.severity,
+                    ancestor_path: ancestor,
+                    occurrences: 1,
+                    survey_id_bases: vec![problem.survey_id_base.clone()],
+                    details: problem.details.clone(),
+                });
+            }
+        }
+    }
+    for group in &mut groups {
+        group.survey_id_bases.sort();
+        group.survey_id_bases.dedup();
+    }
+    groups.sort_by(|a, b| {
+        a.severity
+            .cmp(&b.severity)
+            .then_with(|| b.occurrences.cmp(&a.occurrences))
+            .then_with(|| a.problem_type.cmp(&b.problem_type))
+    });
+    groups
+}
+
+/// Folder shared by the paths of a problem: the parent of its raw or graded
+/// folder, or for duplicates, which list their folders in `details`, the
+/// deepest folder holding all of them.
+fn problem_ancestor(problem: &ProblemItem) -> Option<PathBuf> {
+    let paths: Vec<&Path> = match problem
+        .raw_path
+        .as_deref()
+        .or(problem.graded_path.as_deref())
+    {
+        Some(path) => vec![Path::new(path)],
+        None => {
+            let details = problem.details.as_deref()?;
+            details
+                .strip_suffix(AUTO_RESOLVED_NOTE)
+                .unwrap_or(details)
+                .split("; ")
+                .map(Path::new)
+                .filter(|path| path.is_absolute())
+                .collect()
+        }
+    };
+    let mut parents = paths.iter().filter_map(|path| path.parent());
+    let mut ancestor = parents.next()?.to_path_buf();
+    for parent in parents {
+        while !parent.starts_with(&ancestor) {
+            if !ancestor.pop() {
+                return None;
+            }
+        }
+    }
+    Some(ancestor)
+}
+
+/// Writes `problems.csv` (grouped or flat, per the run options) plus the
+/// detailed list when grouping. Nothing is written without problems.
+pub(crate) fn write_problem_files(
+    problems: &[ProblemItem],
+    problems_csv_path: Option<&Path>,
+    problems_detail_csv_path: Option<&Path>,
+    options: &RootRunOptions,
+) -> Result<(), AppError> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    if let Some(path) = problems_csv_path {
+        match options.group_problems {
+            true => write_problem_groups_csv(path, &group_problems(problems), &options.csv)?,
+            false => write_problems_csv(path, problems, &options.csv)?,
+        }
+    }
+    if let Some(path) = problems_detail_csv_path.filter(|_| options.group_problems) {
+        write_problems_csv(path, problems, &options.csv)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_problems_csv(
+    path: &Path,
+    problems: &[ProblemItem],
+    settings: &CsvSettings,
+) -> Result<(), AppError> {
+    let mut writer = csv_writer(path, settings)?;
+    writer.write_record([
+        "survey_id_base",
+        "survey_id_detected",
+        "raw_path",
+        "graded_path",
+        "problem_type",
+        "severity",
+        "details",
+        "suggested_path",
+    ])?;
+    for problem in problems {
+        write_csv_record(
+            &mut writer,
+            [
+                problem.survey_id_base.as_str(),
+                problem.survey_id_detected.as_deref().unwrap_or(""),
+                problem.raw_path.as_deref().unwrap_or(""),
+                problem.graded_path.as_deref().unwrap_or(""),
+                problem.problem_type.as_str(),
+                problem.severity.as_str(),
+                problem.details.as_deref().unwrap_or(""),
+                problem.suggested_path.as_deref().unwrap_or(""),
+            ],
+            settings,
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_problem_groups_csv(
+    path: &Path,
+    groups: &[ProblemGroup],
+    settings: &CsvSettings,
+) -> Result<(), AppError> {
+    const MAX_LISTED_SURVEYS: usize = 20;
+    let mut writer = csv_writer(path, settings)?;
+    writer.write_record([
+        "problem_type",
+        "severity",
+        "ancestor_path",
+        "occurrences",
+        "survey_id_bases",
+        "details",
+    ])?;
+    for group in groups {
+        let mut surveys = group
+            .survey_id_bases
+            .iter()
+            .take(MAX_LISTED_SURVEYS)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("; ");
+        if group.survey_id_bases.len() > MAX_LISTED_SURVEYS {
+            surveys.push_str(&format!(
+                "; +{} more",
+                group.survey_id_bases.len() - MAX_LISTED_SURVEYS
+            ));
+        }
+        write_csv_record(
+            &mut writer,
+            [
+                group.problem_type.as_str(),
+                group.severity.as_str(),
+                group.ancestor_path.as_deref().unwrap_or(""),
+                &group.occurrences.to_string(),
+                surveys.as_str(),
+                group.details.as_deref().unwrap_or(""),
+            ],
+            settings,
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// `orphaned_graded.csv`, listing the graded images whose file id matched
+/// no raw image of their survey. Surveys are written as they finish, so the
+/// list never has to fit in memory.
+pub(crate) struct OrphanedGradedWriter {
+    writer: csv::Writer<fs::File>,
+    pub(crate) count: u64,
+}
+
+impl OrphanedGradedWriter {
+    pub(crate) fn create(path: &Path, settings: &CsvSettings) -> Result<Self, AppError> {
+        let mut writer = csv_writer(path, settings)?;
+        writer.write_record(["survey_id_base", "graded_path", "graded_relpath", "file_id"])?;
+        Ok(Self { writer, count: 0 })
+    }
+
+    /// Adds the orphans of one survey as `(file_id, graded_relpath)` pairs.
+    pub(crate) fn write_survey(
+        &mut self,
+        survey_id_base: &str,
+        graded_path: &Path,
+        orphans: &[(String, String)],
+        settings: &CsvSettings,
+    ) -> Result<(), AppError> {
+        let graded_path = graded_path.to_string_lossy();
+        for (file_id, graded_relpath) in orphans {
+            write_csv_record(
+                &mut self.writer,
+                [
+                    survey_id_base,
+                    graded_path.as_ref(),
+                    graded_relpath.as_str(),
+                    file_id.as_str(),
+                ],
+                settings,
+            )?;
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), AppError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub(crate) fn write_pairings_csv(
+    path: &Path,
+    entries: &[ScanEntry],
+    settings: &CsvSettings,
+) -> Result<(), AppError> {
+    let mut writer = csv_writer(path, settings)?;
+    writer.write_record([
+        "survey_id_base",
+        "status",
+        "raw_path",
+        "survey_id_raw_detected",
+        "raw_alternatives",
+        "raw_reason",
+        "graded_path",
+        "survey_id_graded_detected",
+        "graded_alternatives",
+        "graded_reason",
+    ])?;
+    for entry in entries {
+        let (raw_path, raw_detected, raw_reason) = pairing_side(
+            entry.raw.as_ref(),
+            &entry.raw_candidates,
+            entry.raw_decided_by,
+            "raw",
+        );
+        let (graded_path, graded_detected, graded_reason) = pairing_side(
+            entry.graded.as_ref(),
+            &entry.graded_candidates,
+            entry.graded_decided_by,
+            "graded",
+        );
+        write_csv_record(
+            &mut writer,
+            [
+                entry.base_key.as_str(),
+                entry.status.as_str(),
+                raw_path.as_str(),
+                raw_detected.as_str(),
+                &entry.raw_candidates.len().saturating_sub(1).to_string(),
+                raw_reason.as_str(),
+                graded_path.as_str(),
+                graded_detected.as_str(),
+                &entry.graded_candidates.len().saturating_sub(1).to_string(),
+                graded_reason.as_str(),
+            ],
+            settings,
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Chosen path, its detected id and the reason for one side of a pairing.
+fn pairing_side(
+    chosen: Option<&SurveyFolder>,
+    candidates: &[SurveyFolder],
+    decided_by: Option<&str>,
+    side: &str,
+) -> (String, String, String) {
+    let reason = match (chosen, candidates.len()) {
+        (_, 0) => format!("No {} folder matches the base key.", side),
+        (Some(folder), 1) => match folder.part.as_ref() {
+            Some(part) => format!(
+                "Only candidate; split from the folder of survey {} by filename ids.",
+                part.folder_key
+            ),
+            None => "Only candidate.".to_string(),
+        },
+        (Some(_), count) => format!(
+            "Best of {} {} folders sharing the base key, decided by {} (resolve_duplicates: auto).",
+            count,
+            side,
+            decided_by.unwrap_or("first path in sort order")
+        ),
+        (None, count) => format!(
+            "{} {} folders share the base key, none chosen: {}",
+            count,
+            side,
+            candidates
+                .iter()
+                .map(|folder| folder.path.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join("; ")
+        ),
+    };
+    (
+        chosen
+            .map(|folder| folder.path.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        chosen
+            .and_then(|folder| folder.detected_id.clone())
+            .unwrap_or_default(),
+        reason,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::rules::{compile_rules, Rules};
+    use crate::scan::{
+        load_scan_options, scan_roots, DuplicateResolution, ScanOptions, ScanSettings,
+    };
+    use crate::test_support::{root_options, TestTree};
+
+    #[test]
+    fn low_graded_coverage_is_reported() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB");
+        let graded_dir = root.join("graded").join("20240101_AB").join("Dolphins");
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(&graded_dir).expect("create");
+        for index in 1..=4 {
+            fs::write(raw_dir.join(format!("IMG_000{}.JPG", index)), b"x").expect("write");
+        }
+        fs::write(graded_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        let host = tree.host();
+        let options = root_options(serde_json::json!({
+            "min_graded_coverage_pct": 50.0
+        }));
+        let summary = tree
+            .run_root(&host, options, Rules::default())
+            .expect("run");
+        assert_eq!(summary.surveys.len(), 1);
+        assert!((summary.surveys[0].graded_coverage - 0.25).abs() < 1e-9);
+        let problems = fs::read_to_string(root.join("out").join("problems.csv")).expect("read");
+        assert!(problems.contains("LOW_COVERAGE"));
+        assert_eq!(summary.warning_count, 1);
+    }
+
+    #[test]
+    fn graded_copies_matched_by_picture_are_no_orphans() {
+        let tree = TestTree::new();
+        let gradient = image::RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+        });
+        tree.mkdir("raw/20240101_AB");
+        tree.mkdir("graded/20240101_AB/Dolphins");
+        gradient
+            .save(tree.join("raw/20240101_AB/IMG_0001.jpg"))
+            .expect("save raw");
+        gradient
+            .save(tree.join("graded/20240101_AB/Dolphins/dolphin_best.jpg"))
+            .expect("save graded");
+        image::imageops::flip_horizontal(&gradient)
+            .save(tree.join("graded/20240101_AB/Dolphins/IMG_0099.jpg"))
+            .expect("save other");
+        tree.write("raw/20240102_AB/IMG_0001.jpg", "x");
+        tree.write("graded/20240102_AB/Dolphins/IMG_0098.jpg", "y");
+        let host = tree.host();
+        let options = root_options(serde_json::json!({
+            "write_merged": false,
+            "write_orphaned_graded": true
+        }));
+        let rules = Rules {
+            perceptual_fallback: true,
+            ..Rules::default()
+        };
+        let summary = tree.run_root(&host, options, rules).expect("run");
+        assert_eq!(summary.perceptual_matches, 1);
+        assert_eq!(summary.orphaned_graded, 2);
+        let path = summary.orphaned_graded_path.expect("path");
+        let mut reader = csv::Reader::from_path(path).expect("read");
+        let orphans: Vec<(String, String)> = reader
+            .records()
+            .map(|record| record.expect("record"))
+            .map(|record| (record[0].to_string(), record[2].to_string()))
+            .collect();
+        assert_eq!(
+            orphans,
+            [
+                (
+                    "20240101_AB".to_string(),
+                    "Dolphins/IMG_0099.jpg".to_string()
+                ),
+                (
+                    "20240102_AB".to_string(),
+                    "Dolphins/IMG_0098.jpg".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn orphaned_graded_images_are_listed() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB");
+        let graded_dir = root.join("graded").join("20240101_AB").join("Dolphins");
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(&graded_dir).expect("create");
+        fs::write(raw_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        fs::write(graded_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        fs::write(graded_dir.join("IMG_0099.JPG"), b"y").expect("write");
+        let host = tree.host();
+        let options = root_options(serde_json::json!({
+            "write_merged": false,
+            "write_orphaned_graded": true
+        }));
+        let summary = tree
+            .run_root(&host, options, Rules::default())
+            .expect("run");
+        assert_eq!(summary.orphaned_graded, 1);
+        let path = summary.orphaned_graded_path.expect("path");
+        let mut reader = csv::Reader::from_path(path).expect("read");
+        let records: Vec<csv::StringRecord> = reader
+            .records()
+            .map(|record| record.expect("record"))
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(&records[0][0], "20240101_AB");
+        assert_eq!(&records[0][2], "Dolphins/IMG_0099.JPG");
+    }
+
+    #[test]
+    fn problems_group_by_type_and_parent() {
+        let problem = |base: &str, raw_path: &str, problem_type: &str| ProblemItem {
+            survey_id_base: base.to_string(),
+            survey_id_detected: None,
+            raw_path: Some(raw_path.to_string()),
+            graded_path: None,
+            problem_type: problem_type.to_string(),
+            severity: problem_severity(problem_type),
+            details: None,
+            suggested_path: None,
+        };
+        let problems = vec![
+            problem("20240101_AB", "/raw/2024/bad/20240101_AB", "GRADED_MISSING"),
+            problem("20240102_AB", "/raw/2024/bad/20240102_AB", "GRADED_MISSING"),
+            problem("20240103_AB", "/raw/2024/ok/20240103_AB", "GRADED_MISSING"),
+            problem("20240104_AB", "/raw/2024/bad/20240104_AB", "DUPLICATE_RAW"),
+        ];
+        let groups = group_problems(&problems);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].problem_type, "DUPLICATE_RAW");
+        assert_eq!(groups[1].occurrences, 2);
+        assert_eq!(groups[1].ancestor_path.as_deref(), Some("/raw/2024/bad"));
+        assert_eq!(
+            groups[1].survey_id_bases,
+            vec!["20240101_AB".to_string(), "20240102_AB".to_string()]
+        );
+    }
+
+    #[test]
+    fn problems_are_counted_per_severity() {
+        let problem = |problem_type: &str| ProblemItem {
+            survey_id_base: "20240101_AB".to_string(),
+            survey_id_detected: None,
+            raw_path: None,
+            graded_path: None,
+            problem_type: problem_type.to_string(),
+            severity: problem_severity(problem_type),
+            details: None,
+            suggested_path: None,
+        };
+        let problems: Vec<ProblemItem> = [
+            "GRADED_MISSING",
+            "RAW_MISSING",
+            "FILE_ID_COLLISION",
+            "DUPLICATE_RAW",
+            "SCAN_ERROR",
+            "UNEXPECTED_SURVEY",
+        ]
+        .into_iter()
+        .map(problem)
+        .collect();
+        assert_eq!(severity_count(&problems, ProblemSeverity::Error), 2);
+        assert_eq!(severity_count(&problems, ProblemSeverity::Warning), 3);
+        assert_eq!(severity_count(&problems, ProblemSeverity::Info), 1);
+    }
+
+    #[test]
+    fn duplicates_group_by_the_folders_in_their_details() {
+        let duplicate = |base: &str, details: String| ProblemItem {
+            survey_id_base: base.to_string(),
+            survey_id_detected: None,
+            raw_path: None,
+            graded_path: None,
+            problem_type: "DUPLICATE_RAW".to_string(),
+            severity: problem_severity("DUPLICATE_RAW"),
+            details: Some(details),
+            suggested_path: None,
+        };
+        let problems = vec![
+            duplicate(
+                "20240101_AB",
+                "/raw/copy/2024/20240101_AB; /raw/copy/2024-old/20240101_AB".to_string(),
+            ),
+            duplicate(
+                "20240102_AB",
+                format!(
+                    "/raw/copy/2024/20240102_AB; /raw/copy/2024-old/20240102_AB{}",
+                    AUTO_RESOLVED_NOTE
+                ),
+            ),
+            duplicate(
+                "20240103_AB",
+                "/raw/other/20240103_AB; /raw/other/20240103_AB (2)".to_string(),
+            ),
+            ProblemItem {
+                problem_type: "FILTER_NO_MATCH".to_string(),
+                ..duplicate("2023*", "No survey below the roots matches it.".to_string())
+            },
+        ];
+        let groups = group_problems(&problems);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].ancestor_path.as_deref(), Some("/raw/copy"));
+        assert_eq!(
+            groups[0].survey_id_bases,
+            vec!["20240101_AB".to_string(), "20240102_AB".to_string()]
+        );
+        assert_eq!(groups[1].ancestor_path.as_deref(), Some("/raw/other"));
+        assert_eq!(groups[2].problem_type, "FILTER_NO_MATCH");
+        assert_eq!(groups[2].ancestor_path, None);
+    }
+
+    #[test]
+    fn pairings_explain_folder_choices() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_root = root.join("raw");
+        let graded_root = root.join("graded");
+        for dir in [
+            raw_root.join("20240101_AB"),
+            raw_root.join("20240102_CD"),
+            raw_root.join("old/20240102_CD"),
+            graded_root.join("20240101_AB_XY"),
+            graded_root.join("20240102_CD"),
+        ] {
+            fs::create_dir_all(&dir).expect("create");
+        }
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+        let options = load_scan_options(&ScanSettings::default(), &compiled).expect("options");
+        let scan = scan_roots(
+            std::slice::from_ref(&raw_root),
+            std::slice::from_ref(&graded_root),
+            &compiled,
+            &options,
+        )
+        .expect("scan");
+        let path = root.join("pairings.csv");
+        write_pairings_csv(&path, &scan.entries, &CsvSettings::default()).expect("pairings");
+
+        let mut reader = csv::Reader::from_path(&path).expect("read");
+        let records = reader
+            .records()
+            .map(|record| record.expect("record"))
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[0][0], "20240101_AB");
+        assert_eq!(&records[0][1], "OK");
+        assert_eq!(&records[0][7], "20240101_AB_XY");
+        assert_eq!(&records[0][9], "Only candidate.");
+        assert_eq!(&records[1][2], "");
+        assert_eq!(&records[1][4], "1");
+        assert!(records[1][5].starts_with("2 raw folders share the base key, none chosen"));
+
+        tree.write("raw/old/20240102_CD/IMG_0001.jpg", "cd-1");
+        let options = ScanOptions {
+            resolve_duplicates: DuplicateResolution::Auto,
+            ..options
+        };
+        let scan = scan_roots(
+            std::slice::from_ref(&raw_root),
+            std::slice::from_ref(&graded_root),
+            &compiled,
+            &options,
+        )
+        .expect("scan");
+        write_pairings_csv(&path, &scan.entries, &CsvSettings::default()).expect("pairings");
+        let mut reader = csv::Reader::from_path(&path).expect("read");
+        let record = reader.records().nth(1).expect("row").expect("record");
+        assert_eq!(
+            &record[2],
+            raw_root.join("old/20240102_CD").to_string_lossy()
+        );
+        assert_eq!(
+            &record[5],
+            "Best of 2 raw folders sharing the base key, decided by most images \
+             (resolve_duplicates: auto)."
+        );
+    }
+}
@@ -0,0 +1,594 @@
+//! The review queue of uncertain rows, the manual labels set from it, and
+//! image thumbnails.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::matching::open_image;
+use crate::output::{run_csv_reader, CsvRow, CONFIDENCE_COLUMN, DECISION_REASON_COLUMN};
+use crate::paths::long_path;
+use crate::runs::{RunSummary, RUN_SUMMARY_FILENAME};
+use crate::{app_data_dir, unix_millis, AppError, Host};
+
+const THUMBNAIL_CACHE_DIRNAME: &str = "thumbnails";
+/// Size the thumbnail cache is pruned to, least recently shown first.
+const THUMBNAIL_CACHE_MAX_BYTES: u64 = 256 * 1024 * 1024;
+const MANUAL_LABELS_FILENAME: &str = "manual_labels.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReviewQueueOptions {
+    /// Rows with a `label_confidence` below this are queued.
+    #[serde(default = "default_review_max_confidence")]
+    pub max_confidence: f64,
+    /// Keep rows that already have a manual label in the queue.
+    #[serde(default)]
+    pub include_reviewed: bool,
+    /// Most rows returned, lowest confidence first.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl Default for ReviewQueueOptions {
+    fn default() -> Self {
+        Self {
+            max_confidence: default_review_max_confidence(),
+            include_reviewed: false,
+            limit: None,
+        }
+    }
+}
+
+fn default_review_max_confidence() -> f64 {
+    0.7
+}
+
+/// Rows of a merged CSV that need a human look.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReviewQueue {
+    pub merged_csv: String,
+    pub total_rows: u64,
+    /// Rows matching the queue criteria, before `limit`.
+    pub queued_rows: u64,
+    pub items: Vec<ReviewItem>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewReason {
+    /// `label_confidence` below `max_confidence`.
+    LowConfidence,
+    /// Several graded candidates; used when the CSV has no
+    /// `label_confidence` column.
+    SeveralCandidates,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReviewItem {
+    /// `<survey_id_base>/<raw_relpath>`, the key of manual labels.
+    pub row_id: String,
+    pub survey_id_base: String,
+    pub raw_relpath: String,
+    /// `dolphin` as written in the CSV.
+    pub dolphin: u8,
+    pub label_confidence: Option<f64>,
+    pub graded_hits: u64,
+    pub graded_relpath: String,
+    pub decision_reason: Option<String>,
+    pub reason: ReviewReason,
+    /// Raw and winning graded image, when the run's `run_summary.json` is
+    /// next to the CSV.
+    pub raw_path: Option<String>,
+    pub graded_path: Option<String>,
+    pub manual: Option<ManualLabel>,
+}
+
+/// A `dolphin` value set by a reviewer, put over the automatic one by later
+/// runs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManualLabel {
+    pub row_id: String,
+    pub label: u8,
+    #[serde(default)]
+    pub note: String,
+    pub updated_at_ms: u64,
+}
+
+/// Manual labels of one output folder by row id.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ManualLabels(BTreeMap<String, ManualLabel>);
+
+impl ManualLabels {
+    pub(crate) fn get(&self, base_key: &str, raw_relpath: &str) -> Option<&ManualLabel> {
+        self.0.get(&review_row_id(base_key, raw_relpath))
+    }
+
+    pub(crate) fn apply(&self, row: &mut CsvRow) {
+        let Some(manual) = self.get(&row.survey_id_base, &row.raw_relpath) else {
+            return;
+        };
+        row.dolphin = manual.label;
+        row.manual_label = true;
+        row.label_confidence = 1.0;
+        row.decision_reason = if manual.note.is_empty() {
+            "manual label".to_string()
+        } else {
+            format!("manual label: {}", manual.note)
+        };
+    }
+}
+
+/// A downsized copy of an image for the preview UI.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Thumbnail {
+    /// Cached JPEG in the app data folder.
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    /// `data:image/jpeg;base64,...` of the JPEG, when asked for.
+    pub data_url: Option<String>,
+}
+
+/// Rows of a merged CSV for manual review: those with a `label_confidence`
+/// below `max_confidence`, lowest first, or, for CSVs written without that
+/// column, those with several graded candidates. Rows with a manual label
+/// are left out unless `include_reviewed` is set.
+pub fn get_review_queue(
+    merged_csv: PathBuf,
+    options: ReviewQueueOptions,
+) -> Result<ReviewQueue, AppError> {
+    let output_dir = merged_csv.parent().unwrap_or(Path::new(""));
+    let manual_labels = load_manual_labels(output_dir)?.unwrap_or_default();
+    let summary_path = output_dir.join(RUN_SUMMARY_FILENAME);
+    let survey_dirs: HashMap<String, (PathBuf, PathBuf)> = if summary_path.exists() {
+        let summary: RunSummary = serde_json::from_str(&fs::read_to_string(&summary_path)?)?;
+        summary
+            .surveys
+            .into_iter()
+            .map(|survey| {
+                (
+                    survey.survey_id_base,
+                    (
+                        PathBuf::from(survey.raw_path),
+                        PathBuf::from(survey.graded_path),
+                    ),
+                )
+            })
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let mut reader = run_csv_reader(&merged_csv)?;
+    let headers = reader.headers()?.clone();
+    let position = |name: &str| headers.iter().position(|header| header == name);
+    let column = |name: &str| {
+        position(name).ok_or_else(|| {
+            AppError::Validation(format!(
+                "Column {} missing in {}.",
+                name,
+                merged_csv.to_string_lossy()
+            ))
+        })
+    };
+    let base_column = column("survey_id_base")?;
+    let relpath_column = column("raw_relpath")?;
+    let dolphin_column = column("dolphin")?;
+    let graded_column = column("graded_relpath")?;
+    let hits_column = column("graded_hits")?;
+    let confidence_column = position(CONFIDENCE_COLUMN);
+    let reason_column = position(DECISION_REASON_COLUMN);
+
+    let mut queue = ReviewQueue {
+        merged_csv: merged_csv.to_string_lossy().to_string(),
+        total_rows: 0,
+        queued_rows: 0,
+        items: Vec::new(),
+    };
+    for record in reader.records() {
+        let record = record?;
+        queue.total_rows += 1;
+        let field = |index: usize| record.get(index).unwrap_or_default();
+        let confidence = confidence_column.and_then(|index| field(index).parse::<f64>().ok());
+        let graded_hits = field(hits_column).parse().unwrap_or(0);
+        let reason = match confidence {
+            Some(confidence) if confidence < options.max_confidence => ReviewReason::LowConfidence,
+            None if confidence_column.is_none() && graded_hits > 1 => {
+                ReviewReason::SeveralCandidates
+            }
+            _ => continue,
+        };
+        let (base_key, relpath) = (field(base_column), field(relpath_column));
+        let row_id = review_row_id(base_key, relpath);
+        let manual = manual_labels.get(base_key, relpath).cloned();
+        if manual.is_some() && !options.include_reviewed {
+            continue;
+        }
+        let graded_relpath = field(graded_column).to_string();
+        let dirs = survey_dirs.get(base_key);
+        // Video rows point at a frame of the clip.
+        let raw_file = relpath
+            .split_once("#frame=")
+            .map_or(relpath, |(clip, _)| clip);
+        queue.items.push(ReviewItem {
+            row_id,
+            survey_id_base: base_key.to_string(),
+            raw_relpath: relpath.to_string(),
+            dolphin: field(dolphin_column).parse().unwrap_or(0),
+            label_confidence: confidence,
+            graded_hits,
+            decision_reason: reason_column.map(|index| field(index).to_string()),
+            reason,
+            raw_path: dirs.map(|(raw, _)| raw.join(raw_file).to_string_lossy().to_string()),
+            graded_path: dirs
+                .filter(|_| graded_hits > 0)
+                .map(|(_, graded)| graded.join(&graded_relpath).to_string_lossy().to_string()),
+            graded_relpath,
+            manual,
+        });
+    }
+    queue.queued_rows = queue.items.len() as u64;
+    queue.items.sort_by(|a, b| {
+        a.label_confidence
+            .unwrap_or(0.0)
+            .total_cmp(&b.label_confidence.unwrap_or(0.0))
+            .then_with(|| a.row_id.cmp(&b.row_id))
+    });
+    if let Some(limit) = options.limit {
+        queue.items.truncate(limit);
+    }
+    Ok(queue)
+}
+
+/// Stores `label` (0 or 1) as the manual `dolphin` value of a review row of
+/// `merged_csv`, or removes the row's manual label when `label` is `None`.
+/// Returns the stored label. Labels are kept per output folder, so they
+/// apply to later runs into the folder of `merged_csv` only.
+pub fn set_manual_label(
+    merged_csv: PathBuf,
+    row_id: &str,
+    label: Option<u8>,
+    note: Option<String>,
+) -> Result<Option<ManualLabel>, AppError> {
+    if !row_id.contains('/') {
+        return Err(AppError::Validation(format!(
+            "'{}' is not a review row id (<survey_id_base>/<raw_relpath>).",
+            row_id
+        )));
+    }
+    let output_dir = merged_csv.parent().unwrap_or(Path::new(""));
+    let mut manual_labels = load_manual_labels(output_dir)?.unwrap_or_default();
+    let stored = match label {
+        Some(label @ (0 | 1)) => {
+            let manual = ManualLabel {
+                row_id: row_id.to_string(),
+                label,
+                note: note.map(|note| note.trim().to_string()).unwrap_or_default(),
+                updated_at_ms: unix_millis(),
+            };
+            manual_labels.0.insert(row_id.to_string(), manual.clone());
+            Some(manual)
+        }
+        Some(other) => {
+            return Err(AppError::Validation(format!(
+                "Manual label must be 0 or 1, not {}.",
+                other
+            )))
+        }
+        None => {
+            manual_labels.0.remove(row_id);
+            None
+        }
+    };
+    let path = output_dir.join(MANUAL_LABELS_FILENAME);
+    let labels: Vec<&ManualLabel> = manual_labels.0.values().collect();
+    let partial = path.with_extension("json.part");
+    fs::write(&partial, serde_json::to_string_pretty(&labels)?)?;
+    fs::rename(&partial, &path)?;
+    Ok(stored)
+}
+
+/// The manual labels stored in `output_dir`; `None` when there are none yet.
+pub(crate) fn load_manual_labels(output_dir: &Path) -> Result<Option<ManualLabels>, AppError> {
+    let path = output_dir.join(MANUAL_LABELS_FILENAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let labels: Vec<ManualLabel> = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    Ok(Some(ManualLabels(
+        labels
+            .into_iter()
+            .map(|label| (label.row_id.clone(), label))
+            .collect(),
+    )))
+}
+
+fn review_row_id(base_key: &str, raw_relpath: &str) -> String {
+    format!("{}/{}", base_key, raw_relpath)
+}
+
+/// The image at `path` scaled down to fit `max_px` on its longer side, so
+/// the webview need not decode full-size originals. Thumbnails are cached
+/// as JPEGs in the app data folder, keyed by path, size, modification time
+/// and `max_px`, so an edited image gets a new one. Camera RAW files use
+/// their embedded preview. Adding a thumbnail prunes the cache to
+/// `THUMBNAIL_CACHE_MAX_BYTES`.
+pub fn get_thumbnail(
+    host: &Host,
+    path: PathBuf,
+    max_px: u32,
+    inline: bool,
+) -> Result<Thumbnail, AppError> {
+    if !(16..=4096).contains(&max_px) {
+        return Err(AppError::Validation(
+            "Thumbnail size must be between 16 and 4096 pixels.".to_string(),
+        ));
+    }
+    let metadata = fs::metadata(long_path(&path))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let key = Sha256::digest(format!(
+        "{}|{}|{}|{}",
+        path.to_string_lossy(),
+        metadata.len(),
+        modified.as_nanos(),
+        max_px
+    ));
+    let cache_dir = app_data_dir(host)?.join(THUMBNAIL_CACHE_DIRNAME);
+    let cached = cache_dir.join(format!("{}.jpg", &format!("{:x}", key)[..32]));
+    let (width, height) = match image::image_dimensions(&cached) {
+        Ok(dimensions) => {
+            // The modification time of a cached thumbnail is when it was
+            // last shown, so pruning drops the ones not needed for longest.
+            let _ = fs::File::options()
+                .write(true)
+                .open(&cached)
+                .and_then(|file| file.set_modified(SystemTime::now()));
+            dimensions
+        }
+        Err(_) => {
+            let image = open_image(&long_path(&path)).ok_or_else(|| {
+                AppError::Validation(format!(
+                    "Cannot decode {} as an image.",
+                    path.to_string_lossy()
+                ))
+            })?;
+            let thumbnail = image.thumbnail(max_px, max_px).to_rgb8();
+            fs::create_dir_all(&cache_dir)?;
+            // Written aside under a name of its own and renamed, so a
+            // concurrent request never reads half a file and two requests
+            // for the same thumbnail do not write into one file.
+            static COUNTER: AtomicU64 = AtomicU64::new(0);
+            let partial = cached.with_extension(format!(
+                "{}_{}.part",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let saved = thumbnail
+                .save_with_format(&partial, image::ImageFormat::Jpeg)
+                .map_err(|err| AppError::Message(err.to_string()))
+                .and_then(|()| Ok(fs::rename(&partial, &cached)?));
+            if saved.is_err() {
+                let _ = fs::remove_file(&partial);
+            }
+            saved?;
+            prune_thumbnail_cache(&cache_dir, &cached, THUMBNAIL_CACHE_MAX_BYTES);
+            thumbnail.dimensions()
+        }
+    };
+    let data_url = if inline {
+        use base64::Engine;
+        Some(format!(
+            "data:image/jpeg;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(fs::read(&cached)?)
+        ))
+    } else {
+        None
+    };
+    Ok(Thumbnail {
+        path: cached.to_string_lossy().to_string(),
+        width,
+        height,
+        data_url,
+    })
+}
+
+/// Removes the least recently shown thumbnails until the cache holds at
+/// most `max_bytes`, keeping `keep`, the one just added. Files another
+/// request removes meanwhile are skipped.
+fn prune_thumbnail_cache(cache_dir: &Path, keep: &Path, max_bytes: u64) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+    let mut thumbnails: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "jpg"))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect();
+    let mut total: u64 = thumbnails.iter().map(|(_, len, _)| len).sum();
+    thumbnails.sort();
+    for (_, len, path) in thumbnails {
+        if total <= max_bytes {
+            break;
+        }
+        if path != keep && fs::remove_file(&path).is_ok() {
+            total = total.saturating_sub(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rules::Rules;
+    use crate::runs::run_root_scan;
+    use crate::test_support::{root_options, TestTree};
+
+    #[test]
+    fn thumbnails_are_downsized_and_cached() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let original = root.join("IMG_0001.png");
+        image::DynamicImage::new_rgb8(200, 100)
+            .save(&original)
+            .expect("save");
+        let host = tree.host();
+        let thumbnail = get_thumbnail(&host, original.clone(), 50, false).expect("thumbnail");
+        assert_eq!((thumbnail.width, thumbnail.height), (50, 25));
+        assert!(thumbnail.data_url.is_none());
+        assert!(Path::new(&thumbnail.path).starts_with(root.join("data").join("thumbnails")));
+        let again = get_thumbnail(&host, original.clone(), 50, true).expect("cached");
+        assert_eq!(again.path, thumbnail.path);
+        assert!(again
+            .data_url
+            .is_some_and(|url| url.starts_with("data:image/jpeg;base64,/9j/")));
+        let larger = get_thumbnail(&host, original.clone(), 80, false).expect("larger");
+        assert_ne!(larger.path, thumbnail.path);
+
+        assert!(get_thumbnail(&host, original, 4, false).is_err());
+        fs::write(root.join("broken.jpg"), b"not an image").expect("write");
+        assert!(get_thumbnail(&host, root.join("broken.jpg"), 50, false).is_err());
+        assert!(get_thumbnail(&host, root.join("missing.jpg"), 50, false).is_err());
+        let cache_dir = root.join("data").join("thumbnails");
+        let names: Vec<_> = fs::read_dir(&cache_dir)
+            .expect("cache")
+            .flatten()
+            .map(|entry| entry.file_name())
+            .collect();
+        assert_eq!(names.len(), 2, "no partial files left: {names:?}");
+    }
+
+    #[test]
+    fn the_thumbnail_cache_drops_the_least_recently_shown_first() {
+        let tree = TestTree::new();
+        let cache_dir = tree.mkdir("thumbnails");
+        let shown = |name: &str, secs_ago: u64| {
+            let path = tree.write(format!("thumbnails/{name}"), [0u8; 100]);
+            let file = fs::File::options().write(true).open(&path).expect("open");
+            file.set_modified(SystemTime::now() - std::time::Duration::from_secs(secs_ago))
+                .expect("touch");
+            path
+        };
+        let oldest = shown("a.jpg", 30);
+        let older = shown("b.jpg", 20);
+        let recent = shown("c.jpg", 10);
+        let added = shown("d.jpg", 40);
+        let other = tree.write("thumbnails/e.part", [0u8; 100]);
+
+        prune_thumbnail_cache(&cache_dir, &added, 250);
+        assert!(!oldest.exists());
+        assert!(!older.exists());
+        assert!(recent.exists());
+        assert!(added.exists());
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn manual_labels_from_the_review_queue_override_later_runs() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_dir = root.join("raw").join("20240101_AB");
+        let graded_dir = root.join("graded").join("20240101_AB").join("IND");
+        fs::create_dir_all(&raw_dir).expect("create");
+        fs::create_dir_all(&graded_dir).expect("create");
+        for name in ["IMG_0001.JPG", "IMG_0002.JPG"] {
+            fs::write(raw_dir.join(name), b"raw").expect("write");
+        }
+        fs::write(graded_dir.join("IMG_0001.JPG"), b"raw").expect("write");
+        let host = tree.host();
+        let run = || {
+            let options = root_options(serde_json::json!({
+                "on_existing": "overwrite",
+                "include_confidence": true,
+                "include_label_source": true
+            }));
+            tree.run_root(&host, options, Rules::default())
+                .expect("run")
+        };
+        assert_eq!(run().dolphin_yes, 1);
+        let merged = root.join("out").join("merged.csv");
+
+        let queue = get_review_queue(merged.clone(), ReviewQueueOptions::default()).expect("queue");
+        assert_eq!((queue.total_rows, queue.queued_rows), (2, 1));
+        let item = &queue.items[0];
+        assert_eq!(item.row_id, "20240101_AB/IMG_0002.JPG");
+        assert_eq!(item.reason, ReviewReason::LowConfidence);
+        assert_eq!(item.dolphin, 0);
+        assert_eq!(
+            item.raw_path.as_deref().map(PathBuf::from),
+            Some(raw_dir.join("IMG_0002.JPG"))
+        );
+        assert!(item.graded_path.is_none());
+
+        let stored = set_manual_label(
+            merged.clone(),
+            &item.row_id,
+            Some(1),
+            Some(" fin at the edge ".into()),
+        )
+        .expect("set")
+        .expect("stored");
+        assert_eq!(stored.note, "fin at the edge");
+        assert!(set_manual_label(merged.clone(), &item.row_id, Some(2), None).is_err());
+        assert!(set_manual_label(merged.clone(), "no-row-id", Some(1), None).is_err());
+        let queue = get_review_queue(merged.clone(), ReviewQueueOptions::default()).expect("queue");
+        assert!(queue.items.is_empty());
+        let reviewed = get_review_queue(
+            merged.clone(),
+            ReviewQueueOptions {
+                include_reviewed: true,
+                ..ReviewQueueOptions::default()
+            },
+        )
+        .expect("queue");
+        assert_eq!(
+            reviewed.items[0].manual.as_ref().map(|manual| manual.label),
+            Some(1)
+        );
+
+        assert_eq!(run().dolphin_yes, 2);
+        let csv = fs::read_to_string(&merged).expect("merged");
+        assert!(csv
+            .lines()
+            .next()
+            .expect("header")
+            .ends_with("label_confidence,label_source"));
+        let row = csv
+            .lines()
+            .find(|line| line.contains("IMG_0002"))
+            .expect("row");
+        assert!(row.ends_with(",1.00,manual"));
+        assert!(csv
+            .lines()
+            .any(|line| line.contains("IMG_0001") && line.ends_with(",auto")));
+
+        // Labels belong to their output folder, not to every run.
+        let other = run_root_scan(
+            &host,
+            vec![tree.join("graded")],
+            vec![tree.join("raw")],
+            tree.join("other"),
+            root_options(serde_json::json!({})),
+            Rules::default(),
+        )
+        .expect("run");
+        assert_eq!(other.dolphin_yes, 1);
+
+        assert!(set_manual_label(merged.clone(), &item.row_id, None, None)
+            .expect("clear")
+            .is_none());
+        assert_eq!(run().dolphin_yes, 1);
+    }
+}
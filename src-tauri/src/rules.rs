@@ -0,0 +1,1536 @@
+//! Labeling rules: the stored `rules.json` and its profiles, history and
+//! validation, and the compiled form used by scanning and matching.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{app_data_dir, unix_millis, AppError, Host};
+
+const DEFAULT_RULES_JSON: &str = include_str!("../assets/rules.default.json");
+pub(crate) const RULES_BACKUP_DIRNAME: &str = "rules_backups";
+pub(crate) const RULES_HISTORY_FILENAME: &str = "rules_history.json";
+const RULES_HISTORY_LIMIT: usize = 200;
+const PROFILES_DIRNAME: &str = "profiles";
+const ACTIVE_PROFILE_FILENAME: &str = "active";
+/// Profile kept in the data folder's own `rules.json`.
+pub const DEFAULT_PROFILE: &str = "default";
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rules {
+    pub extensions: Vec<String>,
+    /// Camera RAW formats taken as images next to `extensions`. A RAW file
+    /// with a JPEG (or other `extensions`) sibling of the same name is left
+    /// to the sibling, so RAW+JPEG shoots are not labeled twice.
+    #[serde(default = "default_camera_raw_extensions")]
+    pub camera_raw_extensions: Vec<String>,
+    pub survey_id_regex_detected: String,
+    pub survey_id_regex_base: String,
+    #[serde(default = "default_image_id_regex")]
+    pub image_id_regex: String,
+    /// How raw and graded images are identified as the same picture.
+    #[serde(default)]
+    pub matching_strategy: MatchingStrategy,
+    pub graded_priority_ind_regex: String,
+    pub graded_priority_secondary_tokens: Vec<String>,
+    pub graded_negative_contains_any: Vec<String>,
+    pub graded_positive_contains_any: Vec<String>,
+    /// Canonical token -> spelling variants. Variants are rewritten to the
+    /// canonical token in candidate paths and the positive and negative token
+    /// lists before those are matched; the IND regex and the secondary and
+    /// quality tokens see the paths as they are.
+    #[serde(default)]
+    pub token_synonyms: BTreeMap<String, Vec<String>>,
+    /// Graded subfolder names (case-insensitive) whose contents never count
+    /// as candidates, e.g. `rejects` or `duplicates`.
+    #[serde(default)]
+    pub graded_excluded_subfolders: Vec<String>,
+    /// Folders skipped on both sides while discovering surveys and collecting
+    /// images, as globs over the folder path below the scanned folder
+    /// (`**/thumbnails/**`, `**/backup*/**`); `*` stays within one folder
+    /// name, `**` spans several.
+    #[serde(default)]
+    pub exclude_dir_globs: Vec<String>,
+    /// Folder levels walked below a root or survey folder (1 = only what is
+    /// directly inside it); unlimited when unset. Keeps a scan from
+    /// descending into mounted backups.
+    #[serde(default)]
+    pub max_scan_depth: Option<usize>,
+    /// Walk into symlinked folders and read symlinked images. Symlink loops
+    /// are reported as scan errors.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Read `.zip` and `.7z` files below the graded root whose name carries a
+    /// survey id as that survey's graded folder, without extracting them.
+    /// Cannot be combined with perceptual matching, candidate dedupe or
+    /// `file_id_collisions: hash`.
+    #[serde(default)]
+    pub scan_archives: bool,
+    /// Label frame grabs of video clips: raw survey folders are searched for
+    /// `video_extensions` clips instead of images, and every graded still
+    /// whose name `video_frame_regex` matches becomes a row for its clip frame.
+    #[serde(default)]
+    pub video_mode: bool,
+    #[serde(default = "default_video_extensions")]
+    pub video_extensions: Vec<String>,
+    /// Applied to graded still file stems in video mode; named groups `clip`
+    /// (file stem of the clip) and `frame` (frame number, counted from 0).
+    #[serde(default = "default_video_frame_regex")]
+    pub video_frame_regex: String,
+    /// Collapse graded candidates with identical content before picking a winner.
+    #[serde(default)]
+    pub graded_dedupe: CandidateDedupe,
+    /// What to do when raw images of one survey share the filename+size
+    /// fallback id (images whose stem `image_id_regex` does not match).
+    #[serde(default)]
+    pub file_id_collisions: CollisionPolicy,
+    /// Split a graded folder whose filenames carry several survey ids into one
+    /// part per survey instead of treating it as a single survey.
+    #[serde(default)]
+    pub split_combo_graded_folders: bool,
+    /// Same for raw dumps: each image is assigned to the survey id in its
+    /// filename rather than the one in the folder path.
+    #[serde(default)]
+    pub split_combo_raw_folders: bool,
+    /// Casing of survey base keys in discovery, outputs and per-survey filenames.
+    #[serde(default)]
+    pub base_key_case: KeyCase,
+    /// Fold umlauts, `ß` and accented letters to ASCII (`Süd` -> `Sued`)
+    /// before survey ids are extracted and tokens are matched.
+    #[serde(default)]
+    pub transliterate: bool,
+    /// Named alternatives to the two survey id regexes, e.g. for `TRIP-0421-N`
+    /// style ids without a date.
+    #[serde(default)]
+    pub base_key_schemes: Vec<BaseKeyScheme>,
+    /// Name of the scheme in `base_key_schemes` to use instead of the default
+    /// `survey_id_regex_detected`/`survey_id_regex_base` pair.
+    #[serde(default)]
+    pub active_base_key_scheme: Option<String>,
+    /// Applied to the base key to fill the `survey_date` and `area` columns;
+    /// uses the named groups `date` and `area`.
+    #[serde(default = "default_survey_fields_regex")]
+    pub survey_fields_regex: String,
+    /// Grader notes files looked up in the raw and graded survey folders.
+    #[serde(default = "default_notes_filenames")]
+    pub notes_filenames: Vec<String>,
+    /// One note per matching line; named groups `frame` (number of the raw
+    /// image) and `note`. Lines that do not match are ignored.
+    #[serde(default = "default_notes_line_regex")]
+    pub notes_line_regex: String,
+    /// Photo-quality tokens (e.g. `Q1`, `Q2`, `Q3`) in priority order; the
+    /// first one found as a whole word in the winning candidate's path fills
+    /// the `quality` column.
+    #[serde(default)]
+    pub quality_tokens: Vec<String>,
+    /// Match a raw image without a filename match to graded images that look
+    /// the same (64-bit dHash), for graded copies that were renamed.
+    #[serde(default)]
+    pub perceptual_fallback: bool,
+    /// Largest number of differing dHash bits that still counts as the same image.
+    #[serde(default = "default_perceptual_max_distance")]
+    pub perceptual_max_distance: u32,
+    /// Extra labels (e.g. other species), each written as its own 0/1 column
+    /// after `dolphin` and decided from the candidates like `dolphin` is.
+    #[serde(default)]
+    pub labels: Vec<LabelRule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelRule {
+    /// Column name, e.g. `turtle`.
+    pub name: String,
+    pub positive_contains_any: Vec<String>,
+    #[serde(default)]
+    pub negative_contains_any: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BaseKeyScheme {
+    pub name: String,
+    pub survey_id_regex_detected: String,
+    pub survey_id_regex_base: String,
+    /// Overrides `base_key_case` for this scheme.
+    #[serde(default)]
+    pub case: Option<KeyCase>,
+    /// Replaces every run of separators (spaces, `_`, `-`, `.`) in the key,
+    /// so `TRIP 0421 N` and `TRIP_0421_N` both become `TRIP-0421-N`.
+    #[serde(default)]
+    pub separator: Option<String>,
+}
+
+/// How an extracted base key is normalized.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct KeyNormalization {
+    pub(crate) case: KeyCase,
+    pub(crate) separator: Option<String>,
+    pub(crate) transliterate: bool,
+}
+
+impl KeyNormalization {
+    /// Input for the id regexes: ASCII-folded when transliteration is on.
+    pub(crate) fn prepare<'a>(&self, value: &'a str) -> std::borrow::Cow<'a, str> {
+        if self.transliterate {
+            std::borrow::Cow::Owned(fold_to_ascii(value))
+        } else {
+            std::borrow::Cow::Borrowed(value)
+        }
+    }
+
+    pub(crate) fn apply(&self, value: &str) -> String {
+        let value = self.case.apply(value);
+        match self.separator.as_deref() {
+            Some(separator) => value
+                .split([' ', '_', '-', '.'])
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(separator),
+            None => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyCase {
+    #[default]
+    Upper,
+    Lower,
+    /// Keep the casing found in the folder or file name.
+    Preserve,
+}
+
+impl KeyCase {
+    fn apply(self, value: &str) -> String {
+        match self {
+            Self::Upper => value.to_uppercase(),
+            Self::Lower => value.to_lowercase(),
+            Self::Preserve => value.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchingStrategy {
+    /// Image id from `image_id_regex`, else filename plus file size.
+    #[default]
+    Filename,
+    /// SHA-256 of the whole file, so untouched copies match under any name.
+    Checksum,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateDedupe {
+    #[default]
+    Off,
+    /// Same file size counts as the same image.
+    Size,
+    /// Same SHA-256 of the file content counts as the same image.
+    Hash,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    /// Match as before and report the collision.
+    #[default]
+    Warn,
+    /// Append a hash of the first 64 KiB to colliding ids on both sides.
+    Hash,
+    /// Leave colliding raw images unmatched.
+    Refuse,
+}
+
+impl CollisionPolicy {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            CollisionPolicy::Warn => "warn",
+            CollisionPolicy::Hash => "hash",
+            CollisionPolicy::Refuse => "refuse",
+        }
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        serde_json::from_str(DEFAULT_RULES_JSON).expect("bundled default rules are valid")
+    }
+}
+
+/// A named rules preset, e.g. one per project or dataset.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RuleProfile {
+    pub name: String,
+    pub active: bool,
+    pub saved_at_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RulesUpdate {
+    pub rules: Rules,
+    pub backup_path: Option<String>,
+}
+
+/// A problem with one field of the rules, as shown next to that field.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RuleFieldError {
+    /// Rules field, e.g. `image_id_regex` or `base_key_schemes[1].survey_id_regex_base`.
+    pub field: String,
+    pub message: String,
+    /// Character offset in the pattern where a regex error starts.
+    pub position: Option<usize>,
+    /// What usually fixes this kind of error.
+    pub hint: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RulesValidation {
+    pub valid: bool,
+    pub errors: Vec<RuleFieldError>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RulesVersion {
+    pub version: u64,
+    pub saved_at_ms: u64,
+    /// Oldest recorded version: it has no predecessor to diff against, so
+    /// `changes` is empty and `rules` holds the whole version instead.
+    pub initial: bool,
+    pub rules: Option<serde_json::Value>,
+    pub changes: Vec<RuleFieldChange>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RuleFieldChange {
+    pub field: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct RulesHistoryEntry {
+    version: u64,
+    saved_at_ms: u64,
+    rules: serde_json::Value,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CompiledRules {
+    pub(crate) extensions: HashSet<String>,
+    pub(crate) camera_raw_extensions: HashSet<String>,
+    pub(crate) detected_re: Regex,
+    pub(crate) base_re: Regex,
+    pub(crate) image_id_re: Regex,
+    pub(crate) matching: MatchingStrategy,
+    pub(crate) ind_re: Regex,
+    pub(crate) secondary_tokens: Vec<String>,
+    pub(crate) negative_tokens: Vec<String>,
+    pub(crate) positive_tokens: Vec<String>,
+    synonyms: Vec<(String, String)>,
+    pub(crate) excluded_subfolders: HashSet<String>,
+    pub(crate) excluded_dirs: Vec<Regex>,
+    pub(crate) max_scan_depth: Option<usize>,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) scan_archives: bool,
+    pub(crate) video_mode: bool,
+    pub(crate) video_extensions: HashSet<String>,
+    pub(crate) video_frame_re: Regex,
+    pub(crate) dedupe: CandidateDedupe,
+    pub(crate) collisions: CollisionPolicy,
+    pub(crate) split_combo_graded: bool,
+    pub(crate) split_combo_raw: bool,
+    pub(crate) key_norm: KeyNormalization,
+    pub(crate) fields_re: Regex,
+    pub(crate) notes_filenames: HashSet<String>,
+    pub(crate) notes_re: Regex,
+    pub(crate) quality_tokens: Vec<String>,
+    pub(crate) perceptual_fallback: bool,
+    pub(crate) perceptual_max_distance: u32,
+    pub(crate) labels: Vec<CompiledLabel>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CompiledLabel {
+    pub(crate) positive_tokens: Vec<String>,
+    pub(crate) negative_tokens: Vec<String>,
+}
+
+pub fn get_or_init_rules(host: &Host) -> Result<Rules, AppError> {
+    let state = host.state();
+    if let Ok(cached) = state.rules.lock() {
+        if let Some(rules) = cached.as_ref() {
+            return Ok(rules.clone());
+        }
+    }
+    let path = rules_file_path(host)?;
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, DEFAULT_RULES_JSON)?;
+    }
+    let data = fs::read_to_string(path)?;
+    let rules: Rules = serde_json::from_str(&data)?;
+    if let Ok(mut cached) = state.rules.lock() {
+        *cached = Some(rules.clone());
+    }
+    Ok(rules)
+}
+
+/// Compiles `rules`, reusing the cached compilation when the rules are
+/// unchanged since the previous call.
+pub(crate) fn compiled_rules(host: &Host, rules: &Rules) -> Result<Arc<CompiledRules>, AppError> {
+    let state = host.state();
+    if let Ok(cached) = state.compiled.lock() {
+        if let Some((cached_rules, compiled)) = cached.as_ref() {
+            if cached_rules == rules {
+                return Ok(Arc::clone(compiled));
+            }
+        }
+    }
+    let compiled = Arc::new(compile_rules(rules)?);
+    if let Ok(mut cached) = state.compiled.lock() {
+        *cached = Some((rules.clone(), Arc::clone(&compiled)));
+    }
+    Ok(compiled)
+}
+
+pub fn save_rules(host: &Host, rules: Rules) -> Result<RulesUpdate, AppError> {
+    let backup_path = write_rules_file(&profile_dir(host, &active_profile(host)?)?, &rules)?;
+    clear_rules_cache(host, Some(rules.clone()));
+    Ok(RulesUpdate {
+        rules,
+        backup_path: backup_path.map(|path| path.to_string_lossy().to_string()),
+    })
+}
+
+/// Checks every pattern of the rules on its own and reports the broken ones
+/// per field, with the error position and a hint. Problems outside the
+/// patterns (labels, unknown base key scheme) are reported under `rules`.
+pub fn validate_rules(rules: &Rules) -> RulesValidation {
+    let mut patterns = vec![
+        (
+            "survey_id_regex_detected".to_string(),
+            &rules.survey_id_regex_detected,
+            &[][..],
+        ),
+        (
+            "survey_id_regex_base".to_string(),
+            &rules.survey_id_regex_base,
+            &[][..],
+        ),
+        ("image_id_regex".to_string(), &rules.image_id_regex, &[][..]),
+        (
+            "graded_priority_ind_regex".to_string(),
+            &rules.graded_priority_ind_regex,
+            &[][..],
+        ),
+        (
+            "survey_fields_regex".to_string(),
+            &rules.survey_fields_regex,
+            &[][..],
+        ),
+        (
+            "notes_line_regex".to_string(),
+            &rules.notes_line_regex,
+            &["frame", "note"][..],
+        ),
+        (
+            "video_frame_regex".to_string(),
+            &rules.video_frame_regex,
+            &["clip", "frame"][..],
+        ),
+    ];
+    for (index, scheme) in rules.base_key_schemes.iter().enumerate() {
+        patterns.push((
+            format!("base_key_schemes[{}].survey_id_regex_detected", index),
+            &scheme.survey_id_regex_detected,
+            &[][..],
+        ));
+        patterns.push((
+            format!("base_key_schemes[{}].survey_id_regex_base", index),
+            &scheme.survey_id_regex_base,
+            &[][..],
+        ));
+    }
+
+    let mut errors = Vec::new();
+    for (field, pattern, groups) in patterns {
+        match Regex::new(pattern) {
+            Ok(re) => {
+                let missing: Vec<&str> = groups
+                    .iter()
+                    .copied()
+                    .filter(|group| !re.capture_names().any(|name| name == Some(group)))
+                    .collect();
+                if !missing.is_empty() {
+                    errors.push(RuleFieldError {
+                        message: format!("Named group {} missing.", missing.join(", ")),
+                        position: None,
+                        hint: Some(format!(
+                            "Wrap the parts in (?P<name>...) groups, e.g. {}.",
+                            missing
+                                .iter()
+                                .map(|group| format!("(?P<{}>...)", group))
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                        )),
+                        field,
+                    });
+                } else if field == "image_id_regex" && re.captures_len() < 2 {
+                    errors.push(RuleFieldError {
+                        field,
+                        message: "The pattern has no capture group.".to_string(),
+                        position: None,
+                        hint: Some(
+                            "Group 1 is the image id; put the id part in parentheses, e.g. (\\d+)."
+                                .to_string(),
+                        ),
+                    });
+                }
+            }
+            Err(err) => errors.push(regex_field_error(field, pattern, err)),
+        }
+    }
+    for (index, glob) in rules.exclude_dir_globs.iter().enumerate() {
+        if let Err(err) = dir_glob_regex(glob) {
+            errors.push(RuleFieldError {
+                field: format!("exclude_dir_globs[{}]", index),
+                message: err.to_string(),
+                position: None,
+                hint: None,
+            });
+        }
+    }
+    if errors.is_empty() {
+        if let Err(err) = compile_rules(rules) {
+            errors.push(RuleFieldError {
+                field: "rules".to_string(),
+                message: err.to_string(),
+                position: None,
+                hint: None,
+            });
+        }
+    }
+    RulesValidation {
+        valid: errors.is_empty(),
+        errors,
+    }
+}
+
+/// Locates a regex error with `regex_syntax`, which reports spans, and
+/// explains the common mistakes.
+fn regex_field_error(field: String, pattern: &str, err: regex::Error) -> RuleFieldError {
+    use regex_syntax::ast::ErrorKind;
+    let (offset, message, hint) = match regex_syntax::Parser::new().parse(pattern) {
+        Err(regex_syntax::Error::Parse(err)) => {
+            let hint = match err.kind() {
+                ErrorKind::GroupUnclosed => "A ( is never closed.",
+                ErrorKind::GroupUnopened => "A ) has no matching (; write \\) for a literal one.",
+                ErrorKind::ClassUnclosed => "A [ is never closed; write \\[ for a literal one.",
+                ErrorKind::RepetitionMissing => {
+                    "*, + and ? repeat what precedes them; escape them as \\*, \\+ or \\?."
+                }
+                ErrorKind::RepetitionCountInvalid
+                | ErrorKind::RepetitionCountUnclosed
+                | ErrorKind::RepetitionCountDecimalEmpty => {
+                    "Counted repetitions look like {2}, {2,} or {2,5}."
+                }
+                ErrorKind::EscapeUnrecognized => {
+                    "Unknown escape; write \\\\ for a backslash, e.g. in Windows paths."
+                }
+                ErrorKind::ClassRangeInvalid => "Ranges go from low to high, e.g. [a-z].",
+                ErrorKind::GroupNameDuplicate { .. } => "Each group name may only be used once.",
+                ErrorKind::GroupNameEmpty | ErrorKind::GroupNameInvalid => {
+                    "Group names use letters, digits and _, e.g. (?P<frame>...)."
+                }
+                ErrorKind::UnsupportedLookAround => {
+                    "Look-ahead and look-behind like (?=...) are not supported."
+                }
+                ErrorKind::UnsupportedBackreference => "Backreferences like \\1 are not supported.",
+                _ => "",
+            };
+            (
+                Some(err.span().start.offset),
+                err.kind().to_string(),
+                Some(hint),
+            )
+        }
+        Err(regex_syntax::Error::Translate(err)) => {
+            (Some(err.span().start.offset), err.kind().to_string(), None)
+        }
+        // Valid syntax that still fails, e.g. a pattern over the size limit.
+        _ => (None, err.to_string(), None),
+    };
+    RuleFieldError {
+        field,
+        message,
+        position: offset.map(|offset| pattern[..offset].chars().count()),
+        hint: hint.filter(|hint| !hint.is_empty()).map(str::to_string),
+    }
+}
+
+/// Writes `rules.json` in `dir`, keeping a backup of the previous file and
+/// recording the new version in the folder's history.
+fn write_rules_file(dir: &Path, rules: &Rules) -> Result<Option<PathBuf>, AppError> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join("rules.json");
+    let data = serde_json::to_string_pretty(rules)?;
+    let backup_path = backup_rules_file(&path, &data)?;
+    fs::write(&path, data)?;
+    record_rules_history(&dir.join(RULES_HISTORY_FILENAME), rules)?;
+    Ok(backup_path)
+}
+
+fn clear_rules_cache(host: &Host, rules: Option<Rules>) {
+    let state = host.state();
+    if let Ok(mut cached) = state.rules.lock() {
+        *cached = rules;
+    }
+    if let Ok(mut cached) = state.compiled.lock() {
+        *cached = None;
+    }
+    if let Ok(mut cached) = state.last_scan.lock() {
+        *cached = None;
+    }
+}
+
+/// Lists the default profile followed by the stored profiles by name.
+pub fn list_profiles(host: &Host) -> Result<Vec<RuleProfile>, AppError> {
+    let active = active_profile(host)?;
+    let saved_at = |dir: &Path| {
+        fs::metadata(dir.join("rules.json"))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64)
+    };
+    let mut names = Vec::new();
+    let profiles_dir = app_data_dir(host)?.join(PROFILES_DIRNAME);
+    if profiles_dir.is_dir() {
+        for entry in fs::read_dir(&profiles_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().join("rules.json").is_file() && valid_profile_name(&name).is_ok() {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    let mut profiles = vec![RuleProfile {
+        name: DEFAULT_PROFILE.to_string(),
+        active: active == DEFAULT_PROFILE,
+        saved_at_ms: saved_at(&app_data_dir(host)?),
+    }];
+    for name in names {
+        profiles.push(RuleProfile {
+            saved_at_ms: saved_at(&profiles_dir.join(&name)),
+            active: name == active,
+            name,
+        });
+    }
+    Ok(profiles)
+}
+
+/// Stores `rules` under the profile `name`, creating it if needed. Saving the
+/// active profile is the same as `save_rules`.
+pub fn save_profile(host: &Host, name: &str, rules: Rules) -> Result<RulesUpdate, AppError> {
+    let name = valid_profile_name(name)?;
+    if name == active_profile(host)? {
+        return save_rules(host, rules);
+    }
+    let backup_path = write_rules_file(&profile_dir(host, name)?, &rules)?;
+    Ok(RulesUpdate {
+        rules,
+        backup_path: backup_path.map(|path| path.to_string_lossy().to_string()),
+    })
+}
+
+pub fn delete_profile(host: &Host, name: &str) -> Result<Vec<RuleProfile>, AppError> {
+    let name = valid_profile_name(name)?;
+    if name == DEFAULT_PROFILE {
+        return Err(AppError::Validation(
+            "The default profile cannot be deleted.".to_string(),
+        ));
+    }
+    if name == active_profile(host)? {
+        return Err(AppError::Validation(format!(
+            "Activate another profile before deleting '{}'.",
+            name
+        )));
+    }
+    let dir = profile_dir(host, name)?;
+    if !dir.is_dir() {
+        return Err(AppError::Validation(format!(
+            "Unknown rule profile '{}'.",
+            name
+        )));
+    }
+    fs::remove_dir_all(dir)?;
+    list_profiles(host)
+}
+
+/// Makes `name` the profile that `get_or_init_rules` and `save_rules` use,
+/// in the app and the CLI alike, and returns its rules.
+pub fn activate_profile(host: &Host, name: &str) -> Result<Rules, AppError> {
+    let rules = load_profile(host, name)?;
+    let name = valid_profile_name(name)?;
+    let marker = app_data_dir(host)?
+        .join(PROFILES_DIRNAME)
+        .join(ACTIVE_PROFILE_FILENAME);
+    if name == DEFAULT_PROFILE {
+        if marker.exists() {
+            fs::remove_file(&marker)?;
+        }
+    } else {
+        fs::write(&marker, name)?;
+    }
+    clear_rules_cache(host, Some(rules.clone()));
+    Ok(rules)
+}
+
+/// Reads the rules of profile `name` without activating it.
+pub fn load_profile(host: &Host, name: &str) -> Result<Rules, AppError> {
+    let name = valid_profile_name(name)?;
+    let path = profile_dir(host, name)?.join("rules.json");
+    if !path.is_file() {
+        if name == DEFAULT_PROFILE {
+            return Ok(serde_json::from_str(DEFAULT_RULES_JSON)?);
+        }
+        return Err(AppError::Validation(format!(
+            "Unknown rule profile '{}'.",
+            name
+        )));
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+pub fn reset_rules(host: &Host) -> Result<RulesUpdate, AppError> {
+    save_rules(host, Rules::default())
+}
+
+/// Returns every recorded rules version, oldest first, each with the field
+/// changes relative to the version before it.
+pub fn load_rules_history(host: &Host) -> Result<Vec<RulesVersion>, AppError> {
+    let history = read_rules_history(&rules_history_path(host)?)?;
+    Ok(diff_rules_history(&history))
+}
+
+fn rules_file_path(host: &Host) -> Result<PathBuf, AppError> {
+    Ok(profile_dir(host, &active_profile(host)?)?.join("rules.json"))
+}
+
+/// The active profile's name; the default profile when none was activated
+/// or the activated one has since disappeared.
+fn active_profile(host: &Host) -> Result<String, AppError> {
+    let profiles_dir = app_data_dir(host)?.join(PROFILES_DIRNAME);
+    let name = fs::read_to_string(profiles_dir.join(ACTIVE_PROFILE_FILENAME)).unwrap_or_default();
+    let name = name.trim();
+    if valid_profile_name(name).is_ok() && profiles_dir.join(name).is_dir() {
+        Ok(name.to_string())
+    } else {
+        Ok(DEFAULT_PROFILE.to_string())
+    }
+}
+
+/// Folder holding a profile's `rules.json`, history and backups. The default
+/// profile lives directly in the data folder.
+fn profile_dir(host: &Host, name: &str) -> Result<PathBuf, AppError> {
+    let data_dir = app_data_dir(host)?;
+    if name == DEFAULT_PROFILE {
+        Ok(data_dir)
+    } else {
+        Ok(data_dir.join(PROFILES_DIRNAME).join(name))
+    }
+}
+
+/// Profile names double as folder names: letters, digits, spaces, `-` and `_`.
+fn valid_profile_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name != ACTIVE_PROFILE_FILENAME
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+    if valid {
+        Ok(name)
+    } else {
+        Err(AppError::Validation(format!(
+            "Invalid profile name '{}'.",
+            name
+        )))
+    }
+}
+
+fn rules_history_path(host: &Host) -> Result<PathBuf, AppError> {
+    Ok(profile_dir(host, &active_profile(host)?)?.join(RULES_HISTORY_FILENAME))
+}
+
+pub(crate) fn read_rules_history(path: &Path) -> Result<Vec<RulesHistoryEntry>, AppError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+/// Appends `rules` as a new version unless it equals the latest one. Rules are
+/// stored as plain JSON so older versions stay readable when fields change.
+pub(crate) fn record_rules_history(path: &Path, rules: &Rules) -> Result<(), AppError> {
+    let mut history = read_rules_history(path)?;
+    let value = serde_json::to_value(rules)?;
+    if history.last().map(|entry| &entry.rules) == Some(&value) {
+        return Ok(());
+    }
+    let version = history.last().map(|entry| entry.version + 1).unwrap_or(1);
+    history.push(RulesHistoryEntry {
+        version,
+        saved_at_ms: unix_millis(),
+        rules: value,
+    });
+    if history.len() > RULES_HISTORY_LIMIT {
+        let excess = history.len() - RULES_HISTORY_LIMIT;
+        history.drain(..excess);
+    }
+    fs::write(path, serde_json::to_string_pretty(&history)?)?;
+    Ok(())
+}
+
+pub(crate) fn diff_rules_history(history: &[RulesHistoryEntry]) -> Vec<RulesVersion> {
+    let mut previous: Option<&serde_json::Value> = None;
+    let mut versions = Vec::new();
+    for entry in history {
+        versions.push(match previous {
+            Some(previous) => RulesVersion {
+                version: entry.version,
+                saved_at_ms: entry.saved_at_ms,
+                initial: false,
+                rules: None,
+                changes: diff_rules_values(previous, &entry.rules),
+            },
+            None => RulesVersion {
+                version: entry.version,
+                saved_at_ms: entry.saved_at_ms,
+                initial: true,
+                rules: Some(entry.rules.clone()),
+                changes: Vec::new(),
+            },
+        });
+        previous = Some(&entry.rules);
+    }
+    versions
+}
+
+fn diff_rules_values(
+    before: &serde_json::Value,
+    after: &serde_json::Value,
+) -> Vec<RuleFieldChange> {
+    let empty = serde_json::Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    let mut changes = Vec::new();
+    for field in fields {
+        let old = before.get(field);
+        let new = after.get(field);
+        if old == new {
+            continue;
+        }
+        let (added, removed) = match (old, new) {
+            (Some(serde_json::Value::Array(old)), Some(serde_json::Value::Array(new))) => (
+                new.iter()
+                    .filter(|item| !old.contains(item))
+                    .map(json_display)
+                    .collect(),
+                old.iter()
+                    .filter(|item| !new.contains(item))
+                    .map(json_display)
+                    .collect(),
+            ),
+            _ => (Vec::new(), Vec::new()),
+        };
+        changes.push(RuleFieldChange {
+            field: field.clone(),
+            before: old.cloned(),
+            after: new.cloned(),
+            added,
+            removed,
+        });
+    }
+    changes
+}
+
+fn json_display(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Copies the current rules file into `rules_backups/` before it gets replaced.
+/// Nothing is written when there is no file yet or the content is unchanged.
+pub(crate) fn backup_rules_file(path: &Path, new_data: &str) -> Result<Option<PathBuf>, AppError> {
+    let Ok(current) = fs::read_to_string(path) else {
+        return Ok(None);
+    };
+    if current == new_data {
+        return Ok(None);
+    }
+    let backup_dir = path
+        .parent()
+        .map(|parent| parent.join(RULES_BACKUP_DIRNAME))
+        .unwrap_or_else(|| PathBuf::from(RULES_BACKUP_DIRNAME));
+    fs::create_dir_all(&backup_dir)?;
+    let mut backup_path = backup_dir.join(format!("rules-{}.json", unix_millis()));
+    let mut suffix = 1u32;
+    while backup_path.exists() {
+        backup_path = backup_dir.join(format!("rules-{}-{}.json", unix_millis(), suffix));
+        suffix += 1;
+    }
+    fs::write(&backup_path, current)?;
+    Ok(Some(backup_path))
+}
+
+fn default_survey_fields_regex() -> String {
+    "^(?P<date>\\d{8})[_-](?P<area>[A-Za-z]{2})".to_string()
+}
+
+fn default_notes_filenames() -> Vec<String> {
+    vec!["notes.txt".to_string(), "notes.csv".to_string()]
+}
+
+fn default_notes_line_regex() -> String {
+    "(?i)^\\s*(?:frame|img|image)?[\\s_#]*(?P<frame>\\d+)\\s*[:;,\\t-]\\s*(?P<note>.*\\S)\\s*$"
+        .to_string()
+}
+
+fn default_camera_raw_extensions() -> Vec<String> {
+    [".cr2", ".cr3", ".nef", ".arw"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+fn default_video_extensions() -> Vec<String> {
+    [".mp4", ".mov"].into_iter().map(String::from).collect()
+}
+
+fn default_video_frame_regex() -> String {
+    "(?i)^(?P<clip>.+)_frame?(?P<frame>\\d+)$".to_string()
+}
+
+fn default_perceptual_max_distance() -> u32 {
+    10
+}
+
+pub(crate) fn default_image_id_regex() -> String {
+    "^(.+?_\\d{3,5})(?:[ _][A-Za-z0-9]+)*$".to_string()
+}
+
+pub(crate) fn compile_rules(rules: &Rules) -> Result<CompiledRules, AppError> {
+    let mut extensions = HashSet::new();
+    for ext in &rules.extensions {
+        let normalized = normalize_extension(ext);
+        extensions.insert(normalized);
+    }
+    let camera_raw_extensions = rules
+        .camera_raw_extensions
+        .iter()
+        .map(|ext| normalize_extension(ext))
+        .filter(|ext| !extensions.contains(ext))
+        .collect();
+    let video_frame_re = Regex::new(&rules.video_frame_regex)?;
+    if ["clip", "frame"].iter().any(|group| {
+        !video_frame_re
+            .capture_names()
+            .any(|name| name == Some(group))
+    }) {
+        return Err(AppError::Validation(
+            "video_frame_regex needs the named groups 'clip' and 'frame'.".to_string(),
+        ));
+    }
+    if rules.scan_archives
+        && (rules.perceptual_fallback
+            || rules.graded_dedupe != CandidateDedupe::Off
+            || rules.file_id_collisions == CollisionPolicy::Hash)
+    {
+        // These read graded images from disk, which archive entries are not.
+        return Err(AppError::Validation(
+            "scan_archives cannot be combined with perceptual_fallback, graded_dedupe or file_id_collisions: hash.".to_string(),
+        ));
+    }
+    let synonyms = compile_synonyms(&rules.token_synonyms, rules.transliterate);
+    let scheme = match rules.active_base_key_scheme.as_deref() {
+        Some(name) => Some(
+            rules
+                .base_key_schemes
+                .iter()
+                .find(|scheme| scheme.name == name)
+                .ok_or_else(|| {
+                    AppError::Validation(format!("Unknown base key scheme '{}'.", name))
+                })?,
+        ),
+        None => None,
+    };
+    let (detected_regex, base_regex) = match scheme {
+        Some(scheme) => (
+            &scheme.survey_id_regex_detected,
+            &scheme.survey_id_regex_base,
+        ),
+        None => (&rules.survey_id_regex_detected, &rules.survey_id_regex_base),
+    };
+    let mut label_names = HashSet::new();
+    for label in &rules.labels {
+        let name = label.name.trim();
+        if name.is_empty() {
+            return Err(AppError::Validation("Labels need a name.".to_string()));
+        }
+        if !label_names.insert(name) {
+            return Err(AppError::Validation(format!(
+                "Label '{}' is defined twice.",
+                name
+            )));
+        }
+        if label.positive_contains_any.is_empty() {
+            return Err(AppError::Validation(format!(
+                "Label '{}' has no positive tokens.",
+                name
+            )));
+        }
+    }
+    let labels = rules
+        .labels
+        .iter()
+        .map(|label| CompiledLabel {
+            positive_tokens: normalize_tokens(
+                &label.positive_contains_any,
+                &synonyms,
+                rules.transliterate,
+            ),
+            negative_tokens: normalize_tokens(
+                &label.negative_contains_any,
+                &synonyms,
+                rules.transliterate,
+            ),
+        })
+        .collect();
+    Ok(CompiledRules {
+        extensions,
+        camera_raw_extensions,
+        detected_re: Regex::new(detected_regex)?,
+        base_re: Regex::new(base_regex)?,
+        image_id_re: Regex::new(&rules.image_id_regex)?,
+        matching: rules.matching_strategy,
+        ind_re: Regex::new(&rules.graded_priority_ind_regex)?,
+        secondary_tokens: normalize_tokens(
+            &rules.graded_priority_secondary_tokens,
+            &[],
+            rules.transliterate,
+        ),
+        negative_tokens: normalize_tokens(
+            &rules.graded_negative_contains_any,
+            &synonyms,
+            rules.transliterate,
+        ),
+        positive_tokens: normalize_tokens(
+            &rules.graded_positive_contains_any,
+            &synonyms,
+            rules.transliterate,
+        ),
+        synonyms,
+        excluded_subfolders: rules
+            .graded_excluded_subfolders
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect(),
+        excluded_dirs: rules
+            .exclude_dir_globs
+            .iter()
+            .map(|glob| dir_glob_regex(glob))
+            .collect::<Result<_, _>>()?,
+        max_scan_depth: rules.max_scan_depth,
+        follow_symlinks: rules.follow_symlinks,
+        scan_archives: rules.scan_archives,
+        video_mode: rules.video_mode,
+        video_extensions: rules
+            .video_extensions
+            .iter()
+            .map(|ext| normalize_extension(ext))
+            .collect(),
+        video_frame_re,
+        dedupe: rules.graded_dedupe,
+        collisions: rules.file_id_collisions,
+        split_combo_graded: rules.split_combo_graded_folders,
+        split_combo_raw: rules.split_combo_raw_folders,
+        key_norm: KeyNormalization {
+            case: scheme
+                .and_then(|scheme| scheme.case)
+                .unwrap_or(rules.base_key_case),
+            separator: scheme.and_then(|scheme| scheme.separator.clone()),
+            transliterate: rules.transliterate,
+        },
+        fields_re: Regex::new(&rules.survey_fields_regex)?,
+        notes_filenames: rules
+            .notes_filenames
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect(),
+        notes_re: Regex::new(&rules.notes_line_regex)?,
+        quality_tokens: rules
+            .quality_tokens
+            .iter()
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .collect(),
+        perceptual_fallback: rules.perceptual_fallback,
+        perceptual_max_distance: rules.perceptual_max_distance,
+        labels,
+    })
+}
+
+/// Case-insensitive regex for one of `exclude_dir_globs`, matched against a
+/// folder path with `/` separators and a trailing `/`.
+pub(crate) fn dir_glob_regex(glob: &str) -> Result<Regex, AppError> {
+    let glob = glob.trim().replace('\\', "/");
+    let glob = glob.trim_end_matches('/');
+    if glob.is_empty() {
+        return Err(AppError::Validation(
+            "Excluded folder globs must not be empty.".to_string(),
+        ));
+    }
+    let mut pattern = String::from("(?i)^");
+    let mut rest = glob;
+    while let Some(c) = rest.chars().next() {
+        if let Some(tail) = rest.strip_prefix("**/") {
+            pattern.push_str("(?:.*/)?");
+            rest = tail;
+            continue;
+        }
+        if let Some(tail) = rest.strip_prefix("**") {
+            pattern.push_str(".*");
+            rest = tail;
+            continue;
+        }
+        match c {
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    if !glob.ends_with("/**") {
+        pattern.push('/');
+    }
+    pattern.push('$');
+    Ok(Regex::new(&pattern)?)
+}
+
+fn normalize_extension(ext: &str) -> String {
+    let trimmed = ext.trim().to_lowercase();
+    if trimmed.starts_with('.') {
+        trimmed
+    } else {
+        format!(".{}", trimmed)
+    }
+}
+
+fn normalize_tokens(
+    tokens: &[String],
+    synonyms: &[(String, String)],
+    transliterate: bool,
+) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for token in tokens {
+        let token = apply_synonyms(&fold_token(token.trim(), transliterate), synonyms);
+        if !token.is_empty() && !normalized.contains(&token) {
+            normalized.push(token);
+        }
+    }
+    normalized
+}
+
+/// Flattens the synonym dictionary into `(variant, canonical)` pairs, longest
+/// variant first so that e.g. `tuemmler` wins over `tue`.
+fn compile_synonyms(
+    dictionary: &BTreeMap<String, Vec<String>>,
+    transliterate: bool,
+) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    for (canonical, variants) in dictionary {
+        let canonical = fold_token(canonical.trim(), transliterate);
+        if canonical.is_empty() {
+            continue;
+        }
+        for variant in variants {
+            let variant = fold_token(variant.trim(), transliterate);
+            if !variant.is_empty() && variant != canonical {
+                pairs.push((variant, canonical.clone()));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+    pairs
+}
+
+fn apply_synonyms(value: &str, synonyms: &[(String, String)]) -> String {
+    let mut result = value.to_string();
+    for (variant, canonical) in synonyms {
+        if result.contains(variant.as_str()) {
+            result = result.replace(variant.as_str(), canonical);
+        }
+    }
+    result
+}
+
+/// Lowercases a graded candidate path and rewrites synonyms, ready for
+/// matching the positive and negative tokens.
+pub(crate) fn normalize_candidate(candidate: &str, rules: &CompiledRules) -> String {
+    apply_synonyms(&fold_candidate(candidate, rules), &rules.synonyms)
+}
+
+/// Lowercases a graded candidate path without rewriting synonyms, for the
+/// IND regex and the secondary and quality tokens.
+pub(crate) fn fold_candidate(candidate: &str, rules: &CompiledRules) -> String {
+    fold_token(candidate, rules.key_norm.transliterate)
+}
+
+fn fold_token(value: &str, transliterate: bool) -> String {
+    if transliterate {
+        fold_to_ascii(&value.to_lowercase())
+    } else {
+        value.to_lowercase()
+    }
+}
+
+/// German transliteration for umlauts and `ß`, letters without a
+/// decomposition spelled out, and every other accent dropped after NFD
+/// decomposition. Decomposed input, as macOS stores filenames, is composed
+/// first so `u` plus a diaeresis still becomes `ue`.
+pub(crate) fn fold_to_ascii(value: &str) -> String {
+    let mut folded = String::with_capacity(value.len());
+    for ch in value.nfc() {
+        let replacement = match ch {
+            'ä' => "ae",
+            'ö' => "oe",
+            'ü' => "ue",
+            'Ä' => "Ae",
+            'Ö' => "Oe",
+            'Ü' => "Ue",
+            'ß' => "ss",
+            'ẞ' => "SS",
+            'æ' => "ae",
+            'Æ' => "AE",
+            'œ' => "oe",
+            'Œ' => "OE",
+            'ø' => "o",
+            'Ø' => "O",
+            'ł' => "l",
+            'Ł' => "L",
+            'đ' => "d",
+            'Đ' => "D",
+            _ => {
+                folded.extend(
+                    std::iter::once(ch)
+                        .nfd()
+                        .filter(|ch| !is_combining_mark(*ch)),
+                );
+                continue;
+            }
+        };
+        folded.push_str(replacement);
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::classify::{any_token_match, classify_candidate};
+    use crate::scan::{discover_surveys, extract_base_key, extract_detected_id};
+    use crate::test_support::TestTree;
+
+    #[test]
+    fn synonyms_unify_token_spellings() {
+        let rules = Rules {
+            graded_positive_contains_any: vec!["Delfin".to_string()],
+            token_synonyms: BTreeMap::from([(
+                "dolphin".to_string(),
+                vec!["delfin".to_string(), "tuemmler".to_string()],
+            )]),
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        assert_eq!(compiled.positive_tokens, vec!["dolphin".to_string()]);
+
+        let candidates = vec!["20240101_AB/Tuemmler/img_0001.jpg".to_string()];
+        assert!(any_token_match(
+            &candidates,
+            &compiled.positive_tokens,
+            &compiled
+        ));
+        let others = vec!["20240101_AB/Wal/img_0001.jpg".to_string()];
+        assert!(!any_token_match(
+            &others,
+            &compiled.positive_tokens,
+            &compiled
+        ));
+
+        let rules = Rules {
+            graded_priority_ind_regex: "(?i)\\bind\\b".to_string(),
+            graded_priority_secondary_tokens: vec!["2nd".to_string()],
+            token_synonyms: BTreeMap::from([
+                ("dolphin".to_string(), vec!["ind".to_string()]),
+                ("second".to_string(), vec!["2nd".to_string()]),
+            ]),
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        assert_eq!(compiled.secondary_tokens, vec!["2nd".to_string()]);
+        assert_eq!(
+            classify_candidate("20240101_AB/IND/img_0001.jpg", &compiled),
+            "IND"
+        );
+        assert_eq!(
+            classify_candidate("20240101_AB/2nd/img_0001.jpg", &compiled),
+            "SECONDARY"
+        );
+    }
+
+    #[test]
+    fn rules_backup_only_when_content_changes() {
+        let tree = TestTree::new();
+        let rules_path = tree.join("rules.json");
+
+        assert!(backup_rules_file(&rules_path, "{}")
+            .expect("backup")
+            .is_none());
+
+        fs::write(&rules_path, "{\"a\": 1}").expect("write");
+        assert!(backup_rules_file(&rules_path, "{\"a\": 1}")
+            .expect("backup")
+            .is_none());
+
+        let backup = backup_rules_file(&rules_path, "{\"a\": 2}")
+            .expect("backup")
+            .expect("path");
+        assert!(backup.starts_with(tree.join(RULES_BACKUP_DIRNAME)));
+        assert_eq!(fs::read_to_string(backup).expect("read"), "{\"a\": 1}");
+    }
+
+    #[test]
+    fn rule_profiles_switch_the_active_rules() {
+        let tree = TestTree::new();
+        let host = tree.host();
+        let default_rules = get_or_init_rules(&host).expect("init");
+
+        let azores = Rules {
+            quality_tokens: vec!["Q1".to_string()],
+            ..default_rules.clone()
+        };
+        save_profile(&host, "Azores 2024", azores.clone()).expect("save");
+        assert_eq!(get_or_init_rules(&host).expect("rules"), default_rules);
+        let names = |profiles: Vec<RuleProfile>| {
+            profiles
+                .into_iter()
+                .map(|profile| (profile.name, profile.active))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(list_profiles(&host).expect("list")),
+            vec![
+                ("default".to_string(), true),
+                ("Azores 2024".to_string(), false)
+            ]
+        );
+
+        assert_eq!(
+            activate_profile(&host, "Azores 2024").expect("activate"),
+            azores
+        );
+        assert_eq!(get_or_init_rules(&host).expect("rules"), azores);
+        assert_eq!(get_or_init_rules(&tree.host()).expect("fresh host"), azores);
+        let edited = Rules {
+            transliterate: true,
+            ..azores
+        };
+        save_rules(&host, edited.clone()).expect("save active");
+        assert_eq!(load_profile(&host, "Azores 2024").expect("load"), edited);
+        assert_eq!(load_profile(&host, "default").expect("load"), default_rules);
+
+        assert!(delete_profile(&host, "Azores 2024").is_err());
+        assert!(delete_profile(&host, "default").is_err());
+        assert!(save_profile(&host, "../escape", default_rules.clone()).is_err());
+        assert!(activate_profile(&host, "Hawaii").is_err());
+        activate_profile(&host, "default").expect("activate default");
+        let remaining = delete_profile(&host, "Azores 2024").expect("delete");
+        assert_eq!(names(remaining), vec![("default".to_string(), true)]);
+    }
+
+    #[test]
+    fn rules_history_reports_token_diffs() {
+        let tree = TestTree::new();
+        let history_path = tree.join(RULES_HISTORY_FILENAME);
+
+        let mut rules = Rules::default();
+        record_rules_history(&history_path, &rules).expect("record");
+        record_rules_history(&history_path, &rules).expect("record unchanged");
+        rules
+            .graded_negative_contains_any
+            .push("noanimal".to_string());
+        record_rules_history(&history_path, &rules).expect("record change");
+
+        let history = read_rules_history(&history_path).expect("read");
+        let versions = diff_rules_history(&history);
+        assert_eq!(versions.len(), 2);
+        assert!(versions[0].initial);
+        assert!(versions[0].changes.is_empty());
+        assert_eq!(
+            versions[0].rules,
+            Some(serde_json::to_value(Rules::default()).expect("json"))
+        );
+        assert!(!versions[1].initial);
+        assert_eq!(versions[1].rules, None);
+        assert_eq!(versions[1].version, 2);
+        assert_eq!(versions[1].changes.len(), 1);
+        let change = &versions[1].changes[0];
+        assert_eq!(change.field, "graded_negative_contains_any");
+        assert_eq!(change.added, vec!["noanimal".to_string()]);
+        assert!(change.removed.is_empty());
+    }
+
+    #[test]
+    fn rule_validation_points_at_the_broken_field() {
+        assert!(validate_rules(&Rules::default()).valid);
+
+        let rules = Rules {
+            survey_id_regex_base: "^(\\d{8}_[A-Z]+".to_string(),
+            image_id_regex: "IMG_\\d+".to_string(),
+            graded_priority_ind_regex: "ind\\q".to_string(),
+            video_frame_regex: "^(?P<clip>.+)_\\d+$".to_string(),
+            ..Rules::default()
+        };
+        let validation = validate_rules(&rules);
+        assert!(!validation.valid);
+        let fields: Vec<&str> = validation
+            .errors
+            .iter()
+            .map(|error| error.field.as_str())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "survey_id_regex_base",
+                "image_id_regex",
+                "graded_priority_ind_regex",
+                "video_frame_regex"
+            ]
+        );
+        let unclosed = &validation.errors[0];
+        assert_eq!(unclosed.position, Some(1));
+        assert!(unclosed
+            .hint
+            .as_deref()
+            .is_some_and(|hint| hint.contains("never closed")));
+        assert_eq!(validation.errors[2].position, Some(3));
+        assert!(validation.errors[3].message.contains("frame"));
+
+        let rules = Rules {
+            exclude_dir_globs: vec![" ".to_string()],
+            ..Rules::default()
+        };
+        assert_eq!(
+            validate_rules(&rules).errors[0].field,
+            "exclude_dir_globs[0]"
+        );
+        let rules = Rules {
+            active_base_key_scheme: Some("missing".to_string()),
+            ..Rules::default()
+        };
+        assert_eq!(validate_rules(&rules).errors[0].field, "rules");
+    }
+
+    #[test]
+    fn transliteration_unifies_umlaut_variants() {
+        let base_regex = "(?i)\\b(\\d{8}_[a-z]+_[a-z]+)\\b".to_string();
+        let rules = |transliterate: bool| {
+            compile_rules(&Rules {
+                survey_id_regex_detected: base_regex.clone(),
+                survey_id_regex_base: base_regex.clone(),
+                graded_priority_secondary_tokens: vec!["schön".to_string()],
+                transliterate,
+                ..Rules::default()
+            })
+            .expect("compile")
+        };
+        let folded = rules(true);
+        let old = Path::new("/graded/20240101_Sylt_Süd");
+        let new = Path::new("/raw/20240101_Sylt_Sued");
+        let key = |path: &Path, rules: &CompiledRules| {
+            extract_detected_id(path, &rules.detected_re, &rules.key_norm)
+                .and_then(|detected| extract_base_key(&detected, &rules.base_re, &rules.key_norm))
+        };
+        assert_eq!(key(old, &folded).as_deref(), Some("20240101_SYLT_SUED"));
+        assert_eq!(key(old, &folded), key(new, &folded));
+        assert_eq!(
+            classify_candidate("Schoen/img_001.jpg", &folded),
+            "SECONDARY"
+        );
+        assert_eq!(
+            classify_candidate("SCHÖN/img_001.jpg", &folded),
+            "SECONDARY"
+        );
+
+        let plain = rules(false);
+        assert_ne!(key(old, &plain), key(new, &plain));
+        assert_eq!(classify_candidate("Schoen/img_001.jpg", &plain), "OTHER");
+        assert_eq!(fold_to_ascii("Île-Rousse ẞ Ærø"), "Ile-Rousse SS AEro");
+        assert_eq!(fold_to_ascii("Dvořák Łódź Şile"), "Dvorak Lodz Sile");
+        assert_eq!(fold_to_ascii("Su\u{308}d"), "Sued");
+    }
+
+    #[test]
+    fn active_base_key_scheme_replaces_default_regexes() {
+        let rules = Rules {
+            base_key_schemes: vec![BaseKeyScheme {
+                name: "trips".to_string(),
+                survey_id_regex_detected: "(?i)\\b(trip[ _-]\\d{4}[ _-][a-z])\\b".to_string(),
+                survey_id_regex_base: "(?i)\\b(trip[ _-]\\d{4}[ _-][a-z])\\b".to_string(),
+                case: None,
+                separator: Some("-".to_string()),
+            }],
+            active_base_key_scheme: Some("trips".to_string()),
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let root = tree.path();
+        fs::create_dir_all(root.join("Trip_0421_n")).expect("create");
+        fs::create_dir_all(root.join("20240101_AB")).expect("create");
+        let map =
+            discover_surveys(root, &compiled, false, false, &mut Vec::new()).expect("discover");
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["TRIP-0421-N"]);
+
+        let unknown = Rules {
+            active_base_key_scheme: Some("missing".to_string()),
+            ..Rules::default()
+        };
+        assert!(compile_rules(&unknown).is_err());
+    }
+}
@@ -0,0 +1,2676 @@
+//! Root and single-pair runs: previews and estimates before a run, and the
+//! run itself with its workspace, progress and checkpoint.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::control::{register_run, unregister_run, CancelToken, Generation};
+use crate::history::{active_workspaces, list_runs, record_run, workspaces_root, RunRecord};
+use crate::matching::{process_pair, process_pair_within, PairOptions};
+use crate::output::{
+    check_on_existing, existing_output_error, file_timestamp, label_columns, mark_output_dir,
+    output_target, render_html_report, render_summary_markdown, report_target, row_headers,
+    send_email_report, write_run_summary, AccessExport, CsvDelimiter, CsvQuoteStyle, CsvSettings,
+    LineEnding, OnExisting, OutputFormat, OutputLock, PostgresExport, QaWorkbook, RowWriter,
+    RunExports, SqliteExport, SurveyOutput, ThumbnailMode, ACCESS_COLUMNS,
+};
+use crate::paths::{join_roots, long_path, normalize_root, normalize_roots, root_of};
+use crate::problems::{
+    problem_severity, severity_count, write_pairings_csv, write_problem_files, write_problems_csv,
+    OrphanedGradedWriter, ProblemItem, ProblemSeverity,
+};
+use crate::review::load_manual_labels;
+use crate::rules::{compiled_rules, Rules};
+use crate::scan::{
+    extract_base_key, extract_detected_id, fill_preview_counts, load_scan_options, scan_roots,
+    spawn_preview_counts, BaseKeyFilter, PreviewItem, ScanCache, ScanResult, ScanSettings,
+    SurveyFolder,
+};
+use crate::settings::{load_settings, postgres_password, smtp_password};
+use crate::tracks::{load_survey_track, TrackSettings};
+use crate::{
+    default_true, new_run_id, unix_millis, AppError, Host, ProgressEvent, ProgressGranularity,
+    RunPhase, EXIT_OK, EXIT_PROBLEMS,
+};
+
+/// Rows per second assumed by `estimate_run` before any root run was recorded.
+const ESTIMATE_DEFAULT_ROWS_PER_SECOND: f64 = 250.0;
+/// Recent root runs whose throughput `estimate_run` averages.
+const ESTIMATE_HISTORY_RUNS: usize = 10;
+/// Typical size of one CSV field with its delimiter; the default columns
+/// make rows of about 220 bytes.
+const ESTIMATE_FIELD_BYTES: u64 = 24;
+/// How much smaller than CSV workbooks and Parquet files come out.
+const ESTIMATE_XLSX_DIVISOR: u64 = 2;
+const ESTIMATE_PARQUET_DIVISOR: u64 = 4;
+/// Typical size of a thumbnail embedded in the QA workbook.
+const ESTIMATE_THUMBNAIL_BYTES: u64 = 6_000;
+pub(crate) const WORKSPACE_MANIFEST: &str = "workspace.json";
+pub(crate) const RUN_SUMMARY_FILENAME: &str = "run_summary.json";
+const CHECKPOINT_FILENAME: &str = "run_checkpoint.json";
+pub(crate) const SCAN_CACHE_FILENAME: &str = "scan_cache.json";
+
+/// A preview scan kept for the run that usually follows it.
+pub(crate) struct CachedScan {
+    token: String,
+    raw_roots: Vec<PathBuf>,
+    graded_roots: Vec<PathBuf>,
+    settings: ScanSettings,
+    rules_hash: String,
+    scan: ScanResult,
+}
+
+impl CachedScan {
+    fn matches(
+        &self,
+        token: &str,
+        raw_roots: &[PathBuf],
+        graded_roots: &[PathBuf],
+        settings: &ScanSettings,
+        rules_hash: &str,
+    ) -> bool {
+        self.token == token
+            && self.raw_roots == raw_roots
+            && self.graded_roots == graded_roots
+            && &self.settings == settings
+            && self.rules_hash == rules_hash
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RootRunOptions {
+    pub write_per_survey: bool,
+    pub write_merged: bool,
+    pub merged_filename: String,
+    pub problems_filename: String,
+    /// Collapse problems sharing a type and parent folder into one row of
+    /// `problems_filename`; the full list then goes to `problems_detail_filename`.
+    #[serde(default)]
+    pub group_problems: bool,
+    #[serde(default = "default_problems_detail_filename")]
+    pub problems_detail_filename: String,
+    pub per_survey_dirname: String,
+    /// Audit trail of the folder pairing: chosen folders, alternatives and
+    /// the reason for each decision, one row per base key.
+    #[serde(default = "default_true")]
+    pub write_pairings: bool,
+    #[serde(default = "default_pairings_filename")]
+    pub pairings_filename: String,
+    /// Human-readable run report with per-survey stats, problems and provenance.
+    #[serde(default)]
+    pub write_summary_markdown: bool,
+    #[serde(default = "default_summary_markdown_filename")]
+    pub summary_markdown_filename: String,
+    /// Self-contained HTML page with the summary, a per-survey table with
+    /// yes/no bars and the problem list, for reading without the app.
+    #[serde(default)]
+    pub write_html_report: bool,
+    #[serde(default = "default_html_report_filename")]
+    pub html_report_filename: String,
+    /// Mail the summary and problems CSV using the SMTP settings after the run.
+    #[serde(default)]
+    pub send_email_report: bool,
+    /// Insert rows, problems and run metadata into the PostgreSQL database
+    /// from the settings; re-exporting a run replaces its earlier rows.
+    #[serde(default)]
+    pub export_postgres: bool,
+    /// QA workbook with all result rows, optionally with raw image thumbnails.
+    #[serde(default)]
+    pub write_qa_workbook: bool,
+    #[serde(default = "default_qa_workbook_filename")]
+    pub qa_workbook_filename: String,
+    #[serde(default)]
+    pub thumbnails: ThumbnailMode,
+    /// Maximum number of thumbnails embedded in the QA workbook.
+    #[serde(default = "default_thumbnail_limit")]
+    pub thumbnail_limit: usize,
+    /// Typed workbook with the fixed `ACCESS_COLUMNS` schema for appending
+    /// results to the MS Access sightings database.
+    #[serde(default)]
+    pub write_access_export: bool,
+    #[serde(default = "default_access_export_filename")]
+    pub access_export_filename: String,
+    /// Add rows, problems, surveys and the run summary to a SQLite database
+    /// in the output folder; earlier runs stay in it, keyed by `run_id`.
+    #[serde(default)]
+    pub write_sqlite: bool,
+    #[serde(default = "default_sqlite_filename")]
+    pub sqlite_filename: String,
+    /// List graded images whose file id matches no raw image of their survey.
+    #[serde(default)]
+    pub write_orphaned_graded: bool,
+    #[serde(default = "default_orphaned_graded_filename")]
+    pub orphaned_graded_filename: String,
+    #[serde(flatten)]
+    pub csv: CsvSettings,
+    #[serde(flatten)]
+    pub tracks: TrackSettings,
+    /// Severities that stop the run before any output is written.
+    #[serde(default)]
+    pub blocking_severities: Vec<ProblemSeverity>,
+    /// Token from `preview_root_scan`; the previewed scan is reused instead of
+    /// walking both roots again when roots, scan settings and rules still match.
+    #[serde(default)]
+    pub scan_token: Option<String>,
+    /// Abandon a survey that takes longer than this and continue the run.
+    #[serde(default)]
+    pub survey_timeout_secs: Option<u64>,
+    /// Report `LOW_COVERAGE` for surveys where fewer than this percentage of
+    /// raw images have a graded candidate.
+    #[serde(default)]
+    pub min_graded_coverage_pct: Option<f64>,
+    /// Only process these base keys (all when empty); `*` and `?` work as
+    /// wildcards and case is ignored.
+    #[serde(default)]
+    pub include_base_keys: Vec<String>,
+    /// Skip these base keys, with the same wildcards; wins over the includes.
+    #[serde(default)]
+    pub exclude_base_keys: Vec<String>,
+    /// Continue the interrupted run recorded in the output folder's checkpoint:
+    /// finished surveys are skipped and the merged CSV is appended to. The
+    /// QA workbook and the database exports are refused, since the rows of
+    /// the finished surveys are not read back.
+    #[serde(default)]
+    pub resume: bool,
+    /// Keep directory listings and file ids in the output folder's
+    /// `scan_cache.json`, so a rerun only reads folders whose mtime changed
+    /// and only re-identifies files whose size or mtime changed.
+    #[serde(default)]
+    pub incremental_cache: bool,
+    /// What to do with result files that already exist: the merged and
+    /// per-survey files and the run's reports, which `append` writes with a
+    /// timestamp suffix.
+    #[serde(default)]
+    pub on_existing: OnExisting,
+    #[serde(default)]
+    pub output_format: OutputFormat,
+    #[serde(default)]
+    pub progress: ProgressGranularity,
+    #[serde(flatten)]
+    pub scan: ScanSettings,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreviewOptions {
+    #[serde(default = "default_true")]
+    pub include_counts: bool,
+    #[serde(flatten)]
+    pub scan: ScanSettings,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            include_counts: true,
+            scan: ScanSettings::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SingleRunOptions {
+    pub output_filename: String,
+    /// Lists the folders and files the run could not read; only written
+    /// when there are any.
+    #[serde(default = "default_problems_filename")]
+    pub problems_filename: String,
+    #[serde(default)]
+    pub output_format: OutputFormat,
+    #[serde(default)]
+    pub on_existing: OnExisting,
+    #[serde(default)]
+    pub progress: ProgressGranularity,
+    #[serde(flatten)]
+    pub csv: CsvSettings,
+    #[serde(flatten)]
+    pub tracks: TrackSettings,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreviewScan {
+    pub scan_token: String,
+    pub items: Vec<PreviewItem>,
+}
+
+/// What a root run over the scanned roots would take, from a counted preview
+/// scan and the throughput of earlier runs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunEstimate {
+    /// Token of the cached scan, for `RootRunOptions::scan_token`.
+    pub scan_token: String,
+    pub surveys: u64,
+    /// Surveys with both a raw and a graded folder; only these are labeled.
+    pub paired_surveys: u64,
+    /// Raw images of the paired surveys, i.e. the expected CSV rows.
+    pub raw_images: u64,
+    pub graded_images: u64,
+    pub rows_per_second: f64,
+    /// Whether `rows_per_second` comes from recorded runs rather than the
+    /// built-in default.
+    pub from_history: bool,
+    pub estimated_seconds: u64,
+    /// Result files the run's options turn on, in their format and with
+    /// their columns: merged and per-survey files, QA workbook with its
+    /// thumbnails, Access export and SQLite database.
+    pub estimated_output_bytes: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunSummary {
+    #[serde(default)]
+    pub run_id: String,
+    pub processed_surveys: u64,
+    pub total_rows: u64,
+    pub dolphin_yes: u64,
+    pub dolphin_no: u64,
+    pub ambiguity_warnings: u64,
+    /// Graded candidates dropped as content duplicates (`graded_dedupe`).
+    #[serde(default)]
+    pub collapsed_candidates: u64,
+    /// Filename+size ids shared by different raw images (`file_id_collisions`).
+    #[serde(default)]
+    pub id_collisions: u64,
+    /// Raw images matched to graded images by `perceptual_fallback`.
+    #[serde(default)]
+    pub perceptual_matches: u64,
+    /// Whether the run reused the preview scan named by `scan_token`.
+    #[serde(default)]
+    pub scan_reused: bool,
+    pub problems_count: u64,
+    /// `problems_count` split by severity (see `problem_severity`).
+    #[serde(default)]
+    pub error_count: u64,
+    #[serde(default)]
+    pub warning_count: u64,
+    #[serde(default)]
+    pub info_count: u64,
+    pub output_dir: String,
+    pub merged_csv_path: Option<String>,
+    pub problems_csv_path: Option<String>,
+    #[serde(default)]
+    pub problems_detail_csv_path: Option<String>,
+    #[serde(default)]
+    pub pairings_csv_path: Option<String>,
+    #[serde(default)]
+    pub summary_markdown_path: Option<String>,
+    #[serde(default)]
+    pub html_report_path: Option<String>,
+    #[serde(default)]
+    pub qa_workbook_path: Option<String>,
+    #[serde(default)]
+    pub access_export_path: Option<String>,
+    #[serde(default)]
+    pub sqlite_path: Option<String>,
+    /// Graded images without a raw counterpart (`write_orphaned_graded`).
+    #[serde(default)]
+    pub orphaned_graded: u64,
+    #[serde(default)]
+    pub orphaned_graded_path: Option<String>,
+    #[serde(default)]
+    pub surveys: Vec<SurveySummary>,
+    /// Set when the email report was requested but could not be sent.
+    #[serde(default)]
+    pub email_error: Option<String>,
+    /// Set when the PostgreSQL export was requested but failed.
+    #[serde(default)]
+    pub postgres_error: Option<String>,
+    /// Set when the run could not be added to the run history.
+    #[serde(default)]
+    pub history_error: Option<String>,
+    /// The run was cancelled; outputs only cover the surveys listed in
+    /// `surveys` and a `RUN_CANCELLED` problem names what was skipped.
+    #[serde(default)]
+    pub cancelled: bool,
+    /// Surveys taken over from the checkpoint of an interrupted run.
+    #[serde(default)]
+    pub resumed_surveys: u64,
+    /// Rows of those surveys, counted in `total_rows` but not written again.
+    #[serde(default)]
+    pub resumed_rows: u64,
+    /// File ids taken from the `incremental_cache` instead of being computed.
+    #[serde(default)]
+    pub cached_file_ids: u64,
+}
+
+impl RunSummary {
+    /// `EXIT_PROBLEMS` when the run reported any problem, else `EXIT_OK`.
+    pub fn exit_code(&self) -> i32 {
+        if self.problems_count > 0 {
+            EXIT_PROBLEMS
+        } else {
+            EXIT_OK
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SurveySummary {
+    pub survey_id_base: String,
+    pub raw_path: String,
+    pub graded_path: String,
+    /// The raw and graded roots the survey folders were found under.
+    #[serde(default)]
+    pub raw_root: String,
+    #[serde(default)]
+    pub graded_root: String,
+    pub rows: u64,
+    pub dolphin_yes: u64,
+    pub dolphin_no: u64,
+    pub ambiguity_warnings: u64,
+    #[serde(default)]
+    pub collapsed_candidates: u64,
+    #[serde(default)]
+    pub id_collisions: u64,
+    #[serde(default)]
+    pub perceptual_matches: u64,
+    /// The graded map outgrew `graded_map_memory_mb` and was kept on disk.
+    #[serde(default)]
+    pub graded_map_on_disk: bool,
+    /// GPX track used for `include_track_position`.
+    #[serde(default)]
+    pub track_path: Option<String>,
+    /// Share of raw images with at least one graded candidate, 0 to 1.
+    #[serde(default)]
+    pub graded_coverage: f64,
+}
+
+pub(crate) struct RunProvenance<'a> {
+    pub(crate) started_at_ms: u64,
+    pub(crate) finished_at_ms: u64,
+    pub(crate) raw_root: String,
+    pub(crate) graded_root: String,
+    pub(crate) rules: &'a Rules,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct WorkspaceManifest {
+    run_id: String,
+    pub(crate) kind: String,
+    pub(crate) created_at_ms: u64,
+}
+
+/// Scratch folder for one run's intermediate files: the disk tables of
+/// spilled graded maps. It is removed by `finish`; a failed run leaves it
+/// behind for `cleanup_workspaces` unless nothing but the manifest was
+/// written to it. Partial result files and the checkpoint
+/// stay in the output folder, since they are renamed into place there and
+/// `resume` looks for the checkpoint next to the results.
+#[derive(Debug)]
+struct RunWorkspace {
+    run_id: String,
+    path: PathBuf,
+    cancel: CancelToken,
+}
+
+impl RunWorkspace {
+    fn create(host: &Host, run_id: String, kind: &str) -> Result<Self, AppError> {
+        let path = workspaces_root(host)?.join(&run_id);
+        fs::create_dir_all(&path)?;
+        let manifest = WorkspaceManifest {
+            run_id: run_id.clone(),
+            kind: kind.to_string(),
+            created_at_ms: unix_millis(),
+        };
+        fs::write(
+            path.join(WORKSPACE_MANIFEST),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+        if let Ok(mut active) = active_workspaces().lock() {
+            active.insert(run_id.clone());
+        }
+        let cancel = register_run(&run_id);
+        Ok(Self {
+            run_id,
+            path,
+            cancel,
+        })
+    }
+
+    fn finish(self) -> Result<(), AppError> {
+        if self.path.exists() {
+            fs::remove_dir_all(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RunWorkspace {
+    fn drop(&mut self) {
+        if let Ok(mut active) = active_workspaces().lock() {
+            active.remove(&self.run_id);
+        }
+        unregister_run(&self.run_id);
+        let untouched = fs::read_dir(&self.path).is_ok_and(|entries| {
+            entries
+                .flatten()
+                .all(|entry| entry.file_name() == WORKSPACE_MANIFEST)
+        });
+        if untouched {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Where a run stands across its surveys, for the overall counts and the ETA
+/// of its progress events.
+#[derive(Clone, Debug)]
+pub(crate) struct RunProgress {
+    run_id: String,
+    /// When processing of the first survey started.
+    started: Instant,
+    survey_index: u64,
+    survey_count: u64,
+    /// Surveys taken over from a checkpoint, left out of the ETA.
+    resumed: u64,
+    /// The run's token, whose time spent paused since `started` is left out
+    /// of the ETA.
+    cancel: CancelToken,
+    /// What the token had been paused for when `started` was taken.
+    paused_before: Duration,
+    /// Advanced when a survey is abandoned after `survey_timeout_secs`; the
+    /// events of a survey whose `ticket` is no longer current are dropped.
+    pub(crate) attempts: Generation,
+    pub(crate) ticket: u64,
+}
+
+impl Default for RunProgress {
+    fn default() -> Self {
+        Self {
+            run_id: String::new(),
+            started: Instant::now(),
+            survey_index: 0,
+            survey_count: 0,
+            resumed: 0,
+            cancel: CancelToken::default(),
+            paused_before: Duration::ZERO,
+            attempts: Generation::default(),
+            ticket: 0,
+        }
+    }
+}
+
+impl RunProgress {
+    fn new(run_id: &str, cancel: &CancelToken) -> Self {
+        Self {
+            run_id: run_id.to_string(),
+            cancel: cancel.clone(),
+            paused_before: cancel.paused_for(),
+            ..Self::default()
+        }
+    }
+
+    /// Starts the ETA clock when the first survey is processed.
+    fn start(&mut self) {
+        self.started = Instant::now();
+        self.paused_before = self.cancel.paused_for();
+    }
+
+    fn event(
+        &self,
+        phase: RunPhase,
+        survey_id_base: &str,
+        processed: u64,
+        total: u64,
+    ) -> ProgressEvent {
+        let eta_seconds = match phase {
+            RunPhase::IndexingGraded | RunPhase::Matching => self.eta_seconds(processed, total),
+            RunPhase::Discovering | RunPhase::Writing => None,
+        };
+        ProgressEvent {
+            run_id: self.run_id.clone(),
+            phase,
+            survey_id_base: survey_id_base.to_string(),
+            processed,
+            total,
+            survey_index: self.survey_index,
+            survey_count: self.survey_count,
+            eta_seconds,
+        }
+    }
+
+    /// Sends the event to the host unless its survey was abandoned.
+    pub(crate) fn emit(
+        &self,
+        host: &Host,
+        phase: RunPhase,
+        survey_id_base: &str,
+        processed: u64,
+        total: u64,
+    ) {
+        if self.attempts.is_current(self.ticket) {
+            host.progress(self.event(phase, survey_id_base, processed, total));
+        }
+    }
+
+    /// Remaining time if the surveys still to do take as long as the ones
+    /// done so far, with the current survey counted by its processed files.
+    fn eta_seconds(&self, processed: u64, total: u64) -> Option<u64> {
+        let surveys = self.survey_count.checked_sub(self.resumed)? as f64;
+        let current = if total > 0 {
+            processed.min(total) as f64 / total as f64
+        } else {
+            0.0
+        };
+        let done = self
+            .survey_index
+            .saturating_sub(1)
+            .saturating_sub(self.resumed) as f64
+            + current;
+        if self.survey_index == 0 || done <= 0.0 {
+            return None;
+        }
+        let paused = self.cancel.paused_for().saturating_sub(self.paused_before);
+        let elapsed = self.started.elapsed().saturating_sub(paused).as_secs_f64();
+        Some((elapsed * (surveys - done).max(0.0) / done).round() as u64)
+    }
+}
+
+/// Returns the preview without image counts. When `options.include_counts` is
+/// set, interactive hosts count in the background and hand the counts to
+/// their `ProgressSink` as `counts_updated` events; headless hosts wait for
+/// them and get them in the returned items instead. The scan is kept in the
+/// host's state under the returned token so the following run can skip
+/// discovery.
+pub fn preview_root_scan(
+    host: &Host,
+    graded_roots: Vec<PathBuf>,
+    raw_roots: Vec<PathBuf>,
+    options: PreviewOptions,
+    rules: Rules,
+) -> Result<PreviewScan, AppError> {
+    let resolve_mapped_drives = load_settings(host)?.resolve_mapped_drives;
+    let graded_roots = normalize_roots("graded", &graded_roots, resolve_mapped_drives)?;
+    let raw_roots = normalize_roots("raw", &raw_roots, resolve_mapped_drives)?;
+    let compiled = compiled_rules(host, &rules)?;
+    let scan_options = load_scan_options(&options.scan, &compiled)?;
+    let scan = scan_roots(&raw_roots, &graded_roots, &compiled, &scan_options)?;
+    let mut items = scan.preview.clone();
+    let generation = &host.state().preview_counts;
+    let ticket = generation.advance();
+    if options.include_counts {
+        match host.sink() {
+            Some(sink) if host.0.background_counts => {
+                // The thread ends by itself, early once a newer preview
+                // advanced the generation.
+                let _ = spawn_preview_counts(
+                    sink.clone(),
+                    generation.clone(),
+                    ticket,
+                    compiled,
+                    scan.preview.clone(),
+                );
+            }
+            _ => fill_preview_counts(&compiled, &mut items),
+        }
+    }
+    let scan_token = cache_preview_scan(host, raw_roots, graded_roots, options.scan, &rules, scan)?;
+    Ok(PreviewScan { scan_token, items })
+}
+
+/// Keeps `scan` in the host's state so a following run with the same roots,
+/// settings and rules can skip discovery; returns its token.
+fn cache_preview_scan(
+    host: &Host,
+    raw_roots: Vec<PathBuf>,
+    graded_roots: Vec<PathBuf>,
+    settings: ScanSettings,
+    rules: &Rules,
+    scan: ScanResult,
+) -> Result<String, AppError> {
+    let scan_token = new_run_id();
+    if let Ok(mut cached) = host.state().last_scan.lock() {
+        *cached = Some(CachedScan {
+            token: scan_token.clone(),
+            raw_roots,
+            graded_roots,
+            settings,
+            rules_hash: rules_hash(rules)?,
+            scan,
+        });
+    }
+    Ok(scan_token)
+}
+
+/// Scans and counts like a preview with `include_counts`, then estimates how
+/// long a root run with `options` would take and how much its result files
+/// would take on disk. The speed is averaged over the last recorded root
+/// runs, so estimates follow the machine and network the app runs on.
+pub fn estimate_run(
+    host: &Host,
+    graded_roots: Vec<PathBuf>,
+    raw_roots: Vec<PathBuf>,
+    options: RootRunOptions,
+    rules: Rules,
+) -> Result<RunEstimate, AppError> {
+    let mut options = options;
+    options.csv.label_columns = label_columns(&rules);
+    let headers = row_headers(&options.csv)?;
+    let resolve_mapped_drives = load_settings(host)?.resolve_mapped_drives;
+    let graded_roots = normalize_roots("graded", &graded_roots, resolve_mapped_drives)?;
+    let raw_roots = normalize_roots("raw", &raw_roots, resolve_mapped_drives)?;
+    let compiled = compiled_rules(host, &rules)?;
+    let scan_options = load_scan_options(&options.scan, &compiled)?;
+    let scan = scan_roots(&raw_roots, &graded_roots, &compiled, &scan_options)?;
+    let mut items = scan.preview.clone();
+    fill_preview_counts(&compiled, &mut items);
+    let paired: Vec<&PreviewItem> = items
+        .iter()
+        .filter(|item| item.raw_path.is_some() && item.graded_path.is_some())
+        .collect();
+    let raw_images: u64 = paired.iter().filter_map(|item| item.raw_image_count).sum();
+    let graded_images = paired
+        .iter()
+        .filter_map(|item| item.graded_image_count)
+        .sum();
+
+    let (rows, millis) = list_runs(host)?
+        .into_iter()
+        .filter(|run| {
+            run.kind == "root"
+                && !run.cancelled
+                && run.total_rows > 0
+                && run.finished_at_ms > run.started_at_ms
+        })
+        .take(ESTIMATE_HISTORY_RUNS)
+        .fold((0, 0), |(rows, millis), run| {
+            // Rows taken over from a checkpoint took no time in this run.
+            (
+                rows + run.total_rows.saturating_sub(run.resumed_rows),
+                millis + run.finished_at_ms - run.started_at_ms,
+            )
+        });
+    let from_history = millis > 0;
+    let rows_per_second = if from_history {
+        rows as f64 * 1000.0 / millis as f64
+    } else {
+        ESTIMATE_DEFAULT_ROWS_PER_SECOND
+    };
+    Ok(RunEstimate {
+        surveys: items.len() as u64,
+        paired_surveys: paired.len() as u64,
+        raw_images,
+        graded_images,
+        rows_per_second,
+        from_history,
+        estimated_seconds: (raw_images as f64 / rows_per_second).ceil() as u64,
+        estimated_output_bytes: estimated_output_bytes(
+            &options,
+            &headers,
+            raw_images,
+            graded_images.min(raw_images),
+        ),
+        scan_token: cache_preview_scan(host, raw_roots, graded_roots, options.scan, &rules, scan)?,
+    })
+}
+
+/// Disk space of the result files `options` turn on for `rows` rows, of which
+/// about `dolphin_rows` are labeled dolphin. The PostgreSQL export is left
+/// out, as it goes to the database server.
+fn estimated_output_bytes(
+    options: &RootRunOptions,
+    headers: &[String],
+    rows: u64,
+    dolphin_rows: u64,
+) -> u64 {
+    let csv_row = headers.len() as u64 * ESTIMATE_FIELD_BYTES;
+    let xlsx_row = csv_row / ESTIMATE_XLSX_DIVISOR;
+    let result_row = match options.output_format {
+        OutputFormat::Csv => csv_row,
+        // Every value is keyed by its header: `"header":` and a comma.
+        OutputFormat::Jsonl => {
+            csv_row
+                + headers
+                    .iter()
+                    .map(|header| header.len() as u64 + 4)
+                    .sum::<u64>()
+        }
+        OutputFormat::Xlsx => xlsx_row,
+        OutputFormat::Parquet => csv_row / ESTIMATE_PARQUET_DIVISOR,
+    };
+    let result_copies = u64::from(options.write_merged) + u64::from(options.write_per_survey);
+    let mut bytes = rows * result_row * result_copies;
+    if options.write_qa_workbook {
+        let thumbnails = match options.thumbnails {
+            ThumbnailMode::None => 0,
+            ThumbnailMode::All => rows,
+            ThumbnailMode::Dolphin => dolphin_rows,
+        }
+        .min(options.thumbnail_limit as u64);
+        bytes += rows * xlsx_row + thumbnails * ESTIMATE_THUMBNAIL_BYTES;
+    }
+    if options.write_access_export {
+        bytes += rows * ACCESS_COLUMNS.len() as u64 * ESTIMATE_FIELD_BYTES / ESTIMATE_XLSX_DIVISOR;
+    }
+    if options.write_sqlite {
+        // Row data plus its indexes.
+        bytes += rows * csv_row * 2;
+    }
+    bytes
+}
+
+/// The cached preview scan for `token`, if it was made for the same roots,
+/// scan settings and rules.
+fn reuse_preview_scan(
+    host: &Host,
+    token: &str,
+    raw_roots: &[PathBuf],
+    graded_roots: &[PathBuf],
+    settings: &ScanSettings,
+    rules: &Rules,
+) -> Result<Option<ScanResult>, AppError> {
+    let hash = rules_hash(rules)?;
+    let Ok(cached) = host.state().last_scan.lock() else {
+        return Ok(None);
+    };
+    Ok(cached
+        .as_ref()
+        .filter(|cached| cached.matches(token, raw_roots, graded_roots, settings, &hash))
+        .map(|cached| cached.scan.clone()))
+}
+
+fn rules_hash(rules: &Rules) -> Result<String, AppError> {
+    let data = serde_json::to_vec(rules)?;
+    Ok(format!("{:x}", Sha256::digest(&data)))
+}
+
+pub fn run_root_scan(
+    host: &Host,
+    graded_roots: Vec<PathBuf>,
+    raw_roots: Vec<PathBuf>,
+    output_dir: PathBuf,
+    options: RootRunOptions,
+    rules: Rules,
+) -> Result<RunSummary, AppError> {
+    let mut options = options;
+    options.csv.label_columns = label_columns(&rules);
+    let headers = row_headers(&options.csv)?;
+    check_on_existing(options.on_existing, options.output_format)?;
+    if options.resume && options.write_merged && !options.output_format.appendable() {
+        return Err(AppError::Validation(format!(
+            "Resuming needs CSV or JSON Lines output; a .{} merged file cannot be appended to.",
+            options.output_format.extension()
+        )));
+    }
+    let app_settings = load_settings(host)?;
+    let manual_labels = load_manual_labels(&output_dir)?.map(Arc::new);
+    let resolve_mapped_drives = app_settings.resolve_mapped_drives;
+    let graded_roots = normalize_roots("graded", &graded_roots, resolve_mapped_drives)?;
+    let raw_roots = normalize_roots("raw", &raw_roots, resolve_mapped_drives)?;
+    let key_filter = BaseKeyFilter::new(&options.include_base_keys, &options.exclude_base_keys)?;
+    let compiled = compiled_rules(host, &rules)?;
+    let scan_options = load_scan_options(&options.scan, &compiled)?;
+    let rules_hash = rules_hash(&rules)?;
+    let fresh = RunCheckpoint {
+        raw_root: join_roots(&raw_roots),
+        graded_root: join_roots(&graded_roots),
+        rules_hash: rules_hash.clone(),
+        output_format: options.output_format,
+        row_headers: headers,
+        delimiter: options.csv.delimiter,
+        quote_style: options.csv.quote_style,
+        line_ending: options.csv.line_ending,
+        utf8_bom: options.csv.utf8_bom,
+        merged_csv_len: None,
+        merged_path: None,
+        surveys: Vec::new(),
+        problems: Vec::new(),
+    };
+    let export_rows =
+        options.write_access_export || options.write_sqlite || options.export_postgres;
+
+    let run_id = new_run_id();
+    if !output_dir.exists() {
+        fs::create_dir_all(long_path(&output_dir))?;
+    }
+    mark_output_dir(&output_dir)?;
+    let _output_lock = OutputLock::acquire(&output_dir, &run_id)?;
+    // The checkpoint is read under the lock, but before the workspace and
+    // the scan, so a resume it refuses costs neither.
+    let checkpoint_path = output_dir.join(CHECKPOINT_FILENAME);
+    let mut checkpoint = match options.resume {
+        true => load_checkpoint(&checkpoint_path, &fresh)?,
+        false => None,
+    }
+    .unwrap_or(fresh);
+    if (export_rows || options.write_qa_workbook) && !checkpoint.surveys.is_empty() {
+        return Err(AppError::Validation(
+            "The QA workbook and the Access, SQLite and PostgreSQL exports need the rows of every survey, but the surveys finished before the interruption are only in the merged file; run without resume or turn these exports off.".to_string(),
+        ));
+    }
+    let workspace = RunWorkspace::create(host, run_id, "root")?;
+    let mut run_progress = RunProgress::new(&workspace.run_id, &workspace.cancel);
+    let reused = match options.scan_token.as_deref() {
+        Some(token) => reuse_preview_scan(
+            host,
+            token,
+            &raw_roots,
+            &graded_roots,
+            &options.scan,
+            &rules,
+        )?,
+        None => None,
+    };
+    let scan_reused = reused.is_some();
+    let scan = match reused {
+        Some(scan) => scan,
+        None => {
+            host.progress(run_progress.event(RunPhase::Discovering, "", 0, 0));
+            scan_roots(&raw_roots, &graded_roots, &compiled, &scan_options)?
+        }
+    };
+    let scan = key_filter.apply(scan);
+
+    let per_survey_dir = output_dir.join(&options.per_survey_dirname);
+    if options.write_per_survey {
+        fs::create_dir_all(&per_survey_dir)?;
+    }
+
+    let scan_cache = options.incremental_cache.then(|| {
+        Arc::new(ScanCache::load(
+            output_dir.join(SCAN_CACHE_FILENAME),
+            &rules_hash,
+        ))
+    });
+
+    // Reports describe the whole run, so a resumed run rewrites the ones
+    // its interrupted part wrote.
+    let stamp = file_timestamp(unix_millis());
+    let report_policy = match checkpoint.surveys.is_empty() {
+        true => options.on_existing,
+        false => OnExisting::Overwrite,
+    };
+    let report_path = |enabled: bool, filename: &str| match enabled {
+        true => report_target(output_dir.join(filename), report_policy, &stamp),
+        false => Ok(None),
+    };
+    let problems_csv_path = report_path(true, &options.problems_filename)?;
+    let problems_detail_csv_path =
+        report_path(options.group_problems, &options.problems_detail_filename)?;
+    let pairings_path = report_path(options.write_pairings, &options.pairings_filename)?;
+    let orphaned_graded_path = report_path(
+        options.write_orphaned_graded,
+        &options.orphaned_graded_filename,
+    )?;
+    let qa_workbook_path = report_path(options.write_qa_workbook, &options.qa_workbook_filename)?;
+    let access_export_path =
+        report_path(options.write_access_export, &options.access_export_filename)?;
+    let summary_markdown_path = report_path(
+        options.write_summary_markdown,
+        &options.summary_markdown_filename,
+    )?;
+    let html_report_path = report_path(options.write_html_report, &options.html_report_filename)?;
+    let merged_path = options
+        .output_format
+        .output_path(output_dir.join(&options.merged_filename));
+    let per_survey_path = |base_key: &str| {
+        per_survey_dir.join(format!(
+            "{}.{}",
+            base_key,
+            options.output_format.extension()
+        ))
+    };
+    let resumed_merged = checkpoint
+        .merged_csv_len
+        .map(|len| {
+            let path = checkpoint
+                .merged_path
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| merged_path.clone());
+            (len, path)
+        })
+        .filter(|(_, path)| path.exists());
+    if options.on_existing == OnExisting::Error {
+        let mut targets = Vec::new();
+        if options.write_merged && resumed_merged.is_none() {
+            targets.push(merged_path.clone());
+        }
+        if options.write_per_survey {
+            targets.extend(
+                scan.entries
+                    .iter()
+                    .filter(|entry| entry.status == "OK")
+                    .filter(|entry| {
+                        !checkpoint
+                            .surveys
+                            .iter()
+                            .any(|survey| survey.survey_id_base == entry.base_key)
+                    })
+                    .map(|entry| per_survey_path(&entry.base_key)),
+            );
+        }
+        if let Some(existing) = targets.iter().find(|path| path.exists()) {
+            return Err(existing_output_error(existing));
+        }
+    }
+    let mut problems = scan.problems;
+
+    let blocking = problems
+        .iter()
+        .filter(|problem| options.blocking_severities.contains(&problem.severity))
+        .count();
+    if blocking > 0 {
+        write_problem_files(
+            &problems,
+            problems_csv_path.as_deref(),
+            problems_detail_csv_path.as_deref(),
+            &options,
+        )?;
+        let see = problems_csv_path
+            .as_ref()
+            .map(|path| format!("; see {}", path.to_string_lossy()))
+            .unwrap_or_default();
+        return Err(AppError::Blocked(format!(
+            "Run blocked by {} problem(s) with a blocking severity{}.",
+            blocking, see
+        )));
+    }
+
+    let pairings_csv_path = match pairings_path {
+        Some(path) => {
+            write_pairings_csv(&path, &scan.entries, &options.csv)?;
+            Some(path.to_string_lossy().to_string())
+        }
+        None => None,
+    };
+
+    let (merged_path, mut merged_writer) = match (options.write_merged, resumed_merged) {
+        (false, _) => (merged_path, None),
+        (true, Some((len, path))) => {
+            let writer = RowWriter::resume(&path, len, options.output_format, &options.csv)?;
+            (path, Some(writer))
+        }
+        (true, None) => match output_target(merged_path.clone(), options.on_existing, &stamp)? {
+            Some((path, append)) => {
+                let writer = RowWriter::open(&path, append, options.output_format, &options.csv)?;
+                (path, Some(writer))
+            }
+            None => (merged_path, None),
+        },
+    };
+    checkpoint.merged_path = merged_writer
+        .as_ref()
+        .map(|_| merged_path.to_string_lossy().to_string());
+    let merged_written = merged_writer.is_some();
+
+    let mut processed_surveys = 0u64;
+    let mut total_rows = 0u64;
+    let mut dolphin_yes = 0u64;
+    let mut dolphin_no = 0u64;
+    let mut ambiguity_warnings = 0u64;
+    let mut collapsed_candidates = 0u64;
+    let mut id_collisions = 0u64;
+    let mut perceptual_matches = 0u64;
+    for survey in &checkpoint.surveys {
+        processed_surveys += 1;
+        total_rows += survey.rows;
+        dolphin_yes += survey.dolphin_yes;
+        dolphin_no += survey.dolphin_no;
+        ambiguity_warnings += survey.ambiguity_warnings;
+        collapsed_candidates += survey.collapsed_candidates;
+        id_collisions += survey.id_collisions;
+        perceptual_matches += survey.perceptual_matches;
+    }
+    let resumed_surveys = processed_surveys;
+    let resumed_rows = total_rows;
+    let resumed_keys = checkpoint
+        .surveys
+        .iter()
+        .map(|survey| survey.survey_id_base.clone())
+        .collect::<HashSet<_>>();
+    let mut surveys = checkpoint.surveys.clone();
+    problems.extend(checkpoint.problems.iter().cloned());
+    let mut qa_workbook = match &qa_workbook_path {
+        Some(_) => Some(QaWorkbook::new(&options)?),
+        None => None,
+    };
+    let mut orphaned_graded = match &orphaned_graded_path {
+        Some(path) => Some(OrphanedGradedWriter::create(path, &options.csv)?),
+        None => None,
+    };
+    let started_at_ms = unix_millis();
+    // The exports take the rows of each survey as it finishes, so a run never
+    // holds more than one survey's rows for them.
+    let mut exports = RunExports {
+        access: match access_export_path {
+            Some(_) => Some(AccessExport::new(&workspace.run_id)?),
+            None => None,
+        },
+        sqlite: match options.write_sqlite {
+            true => Some(SqliteExport::open(
+                &output_dir.join(&options.sqlite_filename),
+                &workspace.run_id,
+            )?),
+            false => None,
+        },
+        ..RunExports::default()
+    };
+    if options.export_postgres {
+        let provenance = RunProvenance {
+            started_at_ms,
+            finished_at_ms: started_at_ms,
+            raw_root: join_roots(&raw_roots),
+            graded_root: join_roots(&graded_roots),
+            rules: &rules,
+        };
+        let result = app_settings
+            .postgres
+            .as_ref()
+            .ok_or_else(|| AppError::Message("No PostgreSQL settings configured.".to_string()))
+            .and_then(|postgres| {
+                PostgresExport::open(
+                    postgres,
+                    postgres_password(host, postgres)?,
+                    &workspace.run_id,
+                    &provenance,
+                )
+            });
+        match result {
+            Ok(export) => exports.postgres = Some(export),
+            Err(err) => exports.postgres_error = Some(err.to_string()),
+        }
+    }
+    let pending_surveys = scan
+        .entries
+        .iter()
+        .filter(|entry| entry.status == "OK")
+        .count();
+    let mut finished_surveys = 0usize;
+    let mut cancelled = false;
+    run_progress.survey_count = pending_surveys as u64;
+    run_progress.start();
+
+    for entry in scan.entries {
+        if entry.status != "OK" {
+            continue;
+        }
+        if resumed_keys.contains(&entry.base_key) {
+            finished_surveys += 1;
+            run_progress.resumed += 1;
+            continue;
+        }
+        if workspace.cancel.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        run_progress.survey_index = finished_surveys as u64 + 1;
+        let raw = entry.raw.expect("raw required");
+        let graded = entry.graded.expect("graded required");
+        let survey_problems_start = problems.len();
+
+        let track = if options.csv.include_track_position {
+            let loaded =
+                load_survey_track(&options.tracks, &entry.base_key, &[&raw.path, &graded.path]);
+            let missing = match loaded {
+                Ok(Some(track)) => Ok(track),
+                Ok(None) => Err((
+                    "TRACK_MISSING",
+                    "No GPX track found for the survey; track columns stay empty.".to_string(),
+                )),
+                Err(err) => Err(("TRACK_ERROR", format!("GPX track not used: {}", err))),
+            };
+            match missing {
+                Ok(track) => Some(Arc::new(track)),
+                Err((problem_type, details)) => {
+                    problems.push(ProblemItem {
+                        survey_id_base: entry.base_key.clone(),
+                        survey_id_detected: raw.detected_id.clone(),
+                        raw_path: Some(raw.path.to_string_lossy().to_string()),
+                        graded_path: Some(graded.path.to_string_lossy().to_string()),
+                        problem_type: problem_type.to_string(),
+                        severity: problem_severity(problem_type),
+                        details: Some(details),
+                        suggested_path: None,
+                    });
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let track_path = track
+            .as_ref()
+            .map(|track| track.path.to_string_lossy().to_string());
+
+        let mut output = SurveyOutput::new(
+            &options.csv,
+            merged_writer.as_mut(),
+            qa_workbook.as_mut(),
+            export_rows.then_some(&mut exports),
+        )?;
+        let per_survey_target = match options.write_per_survey {
+            true => output_target(
+                per_survey_path(&entry.base_key),
+                options.on_existing,
+                &stamp,
+            ),
+            false => Ok(None),
+        };
+        let survey_result = per_survey_target.and_then(|target| {
+            if let Some((path, append)) = target {
+                output.open_file(&path, append, options.output_format)?;
+            }
+            let result = process_pair_within(
+                host,
+                &compiled,
+                &entry.base_key,
+                &raw,
+                &graded,
+                PairOptions {
+                    track,
+                    cache: scan_cache.clone(),
+                    find_orphans: options.write_orphaned_graded,
+                    manual_labels: manual_labels.clone(),
+                    run: run_progress.clone(),
+                    spill_dir: Some(workspace.path.clone()),
+                    ..PairOptions::new(
+                        options.progress,
+                        &options.csv,
+                        &app_settings,
+                        &workspace.cancel,
+                    )
+                },
+                options.survey_timeout_secs.map(Duration::from_secs),
+                &mut |row| output.push(row),
+            )?;
+            if result.is_some() {
+                output.commit()?;
+            }
+            Ok(result)
+        });
+        if !matches!(survey_result, Ok(Some(_))) {
+            output.rollback()?;
+        }
+        let pair_result = match survey_result {
+            Ok(Some(result)) => result,
+            Err(AppError::Cancelled) => {
+                cancelled = true;
+                break;
+            }
+            failed => {
+                finished_surveys += 1;
+                let (problem_type, details) = match failed {
+                    Err(err) => ("SURVEY_ERROR", format!("Survey skipped: {}", err)),
+                    _ => (
+                        "SURVEY_TIMEOUT",
+                        format!(
+                            "Survey abandoned after {} s.",
+                            options.survey_timeout_secs.unwrap_or_default()
+                        ),
+                    ),
+                };
+                problems.push(ProblemItem {
+                    survey_id_base: entry.base_key.clone(),
+                    survey_id_detected: raw.detected_id.clone(),
+                    raw_path: Some(raw.path.to_string_lossy().to_string()),
+                    graded_path: Some(graded.path.to_string_lossy().to_string()),
+                    problem_type: problem_type.to_string(),
+                    severity: problem_severity(problem_type),
+                    details: Some(details),
+                    suggested_path: None,
+                });
+                continue;
+            }
+        };
+        let counts = pair_result.counts;
+        ambiguity_warnings += pair_result.ambiguity_warnings;
+        collapsed_candidates += pair_result.collapsed_candidates;
+        id_collisions += pair_result.id_collisions.len() as u64;
+        perceptual_matches += pair_result.perceptual_matches;
+        for collision in &pair_result.id_collisions {
+            problems.push(ProblemItem {
+                survey_id_base: entry.base_key.clone(),
+                survey_id_detected: raw.detected_id.clone(),
+                raw_path: Some(raw.path.to_string_lossy().to_string()),
+                graded_path: Some(graded.path.to_string_lossy().to_string()),
+                problem_type: "FILE_ID_COLLISION".to_string(),
+                severity: problem_severity("FILE_ID_COLLISION"),
+                details: Some(format!(
+                    "{} raw images share the id '{}' ({} policy): {}",
+                    collision.raw_relpaths.len(),
+                    collision.file_id,
+                    compiled.collisions.as_str(),
+                    collision.raw_relpaths.join("; ")
+                )),
+                suggested_path: None,
+            });
+        }
+        problems.extend(
+            pair_result
+                .walk_errors
+                .iter()
+                .map(|error| error.problem(&entry.base_key, Some(&raw.path), Some(&graded.path))),
+        );
+        if let Some(writer) = orphaned_graded.as_mut() {
+            writer.write_survey(
+                &entry.base_key,
+                &graded.path,
+                &pair_result.orphaned_graded,
+                &options.csv,
+            )?;
+        }
+
+        processed_surveys += 1;
+        finished_surveys += 1;
+        let mut survey = SurveySummary {
+            survey_id_base: entry.base_key.clone(),
+            raw_path: raw.path.to_string_lossy().to_string(),
+            graded_path: graded.path.to_string_lossy().to_string(),
+            raw_root: root_of(&raw_roots, &raw.path),
+            graded_root: root_of(&graded_roots, &graded.path),
+            rows: counts.rows,
+            dolphin_yes: counts.dolphin_yes,
+            dolphin_no: counts.dolphin_no(),
+            ambiguity_warnings: pair_result.ambiguity_warnings,
+            collapsed_candidates: pair_result.collapsed_candidates,
+            id_collisions: pair_result.id_collisions.len() as u64,
+            perceptual_matches: pair_result.perceptual_matches,
+            graded_map_on_disk: pair_result.graded_map_on_disk,
+            track_path: track_path.clone(),
+            graded_coverage: 0.0,
+        };
+        let covered = counts.graded_covered;
+        if survey.rows > 0 {
+            survey.graded_coverage = covered as f64 / survey.rows as f64;
+        }
+        if let Some(min_pct) = options.min_graded_coverage_pct {
+            let coverage_pct = survey.graded_coverage * 100.0;
+            if survey.rows > 0 && coverage_pct < min_pct {
+                problems.push(ProblemItem {
+                    survey_id_base: entry.base_key.clone(),
+                    survey_id_detected: raw.detected_id.clone(),
+                    raw_path: Some(raw.path.to_string_lossy().to_string()),
+                    graded_path: Some(graded.path.to_string_lossy().to_string()),
+                    problem_type: "LOW_COVERAGE".to_string(),
+                    severity: problem_severity("LOW_COVERAGE"),
+                    details: Some(format!(
+                        "Only {} of {} raw images ({:.1}%) have a graded candidate \
+                         (minimum {}%); check the graded naming and image_id_regex.",
+                        covered, survey.rows, coverage_pct, min_pct
+                    )),
+                    suggested_path: None,
+                });
+            }
+        }
+        total_rows += survey.rows;
+        dolphin_yes += survey.dolphin_yes;
+        dolphin_no += survey.dolphin_no;
+
+        checkpoint.surveys.push(survey.clone());
+        checkpoint
+            .problems
+            .extend(problems[survey_problems_start..].iter().cloned());
+        checkpoint.merged_csv_len = match merged_writer.as_mut() {
+            Some(writer) => writer.csv_len()?,
+            None => None,
+        };
+        write_checkpoint(&checkpoint_path, &checkpoint)?;
+        surveys.push(survey);
+    }
+    run_progress.survey_index = run_progress.survey_count;
+    host.progress(run_progress.event(RunPhase::Writing, "", 0, 0));
+    if let Some(cache) = &scan_cache {
+        cache.save()?;
+    }
+
+    if cancelled {
+        problems.push(ProblemItem {
+            survey_id_base: String::new(),
+            survey_id_detected: None,
+            raw_path: Some(join_roots(&raw_roots)),
+            graded_path: Some(join_roots(&graded_roots)),
+            problem_type: "RUN_CANCELLED".to_string(),
+            severity: problem_severity("RUN_CANCELLED"),
+            details: Some(format!(
+                "Run cancelled; {} of {} surveys were not processed. Run again with `resume` to finish them.",
+                pending_surveys - finished_surveys,
+                pending_surveys
+            )),
+            suggested_path: None,
+        });
+    }
+    // Listed by survey key with the problems of the whole run last, so the
+    // order does not depend on when a problem was found. The sort is stable
+    // and keeps a survey's problems in the order they were found in.
+    problems.sort_by(|a, b| {
+        (a.survey_id_base.is_empty(), &a.survey_id_base)
+            .cmp(&(b.survey_id_base.is_empty(), &b.survey_id_base))
+    });
+
+    if let Some(writer) = merged_writer.take() {
+        writer.finish()?;
+    }
+    let merged_csv_path = if merged_written {
+        Some(merged_path.to_string_lossy().to_string())
+    } else {
+        None
+    };
+
+    write_problem_files(
+        &problems,
+        problems_csv_path.as_deref(),
+        problems_detail_csv_path.as_deref(),
+        &options,
+    )?;
+    let written = |path: Option<PathBuf>| path.map(|path| path.to_string_lossy().to_string());
+    let problems_csv_path = written(problems_csv_path.filter(|_| !problems.is_empty()));
+    let problems_detail_csv_path =
+        written(problems_detail_csv_path.filter(|_| !problems.is_empty()));
+    let orphaned_graded = match orphaned_graded {
+        Some(writer) => {
+            let count = writer.count;
+            writer.finish()?;
+            count
+        }
+        None => 0,
+    };
+    let orphaned_graded_path = written(orphaned_graded_path);
+
+    let mut summary = RunSummary {
+        run_id: workspace.run_id.clone(),
+        processed_surveys,
+        total_rows,
+        dolphin_yes,
+        dolphin_no,
+        ambiguity_warnings,
+        collapsed_candidates,
+        id_collisions,
+        perceptual_matches,
+        scan_reused,
+        problems_count: problems.len() as u64,
+        error_count: severity_count(&problems, ProblemSeverity::Error),
+        warning_count: severity_count(&problems, ProblemSeverity::Warning),
+        info_count: severity_count(&problems, ProblemSeverity::Info),
+        output_dir: output_dir.to_string_lossy().to_string(),
+        merged_csv_path,
+        problems_csv_path,
+        problems_detail_csv_path,
+        pairings_csv_path,
+        summary_markdown_path: None,
+        html_report_path: None,
+        qa_workbook_path: None,
+        access_export_path: None,
+        sqlite_path: None,
+        orphaned_graded,
+        orphaned_graded_path,
+        surveys,
+        email_error: None,
+        postgres_error: None,
+        history_error: None,
+        cancelled,
+        resumed_surveys,
+        resumed_rows,
+        cached_file_ids: scan_cache
+            .as_ref()
+            .map(|cache| cache.hits.load(Ordering::Relaxed))
+            .unwrap_or_default(),
+    };
+
+    if let (Some(path), Some(workbook)) = (qa_workbook_path, qa_workbook) {
+        workbook.save(&path)?;
+        summary.qa_workbook_path = written(Some(path));
+    }
+
+    if let (Some(path), Some(access)) = (access_export_path, exports.access.take()) {
+        access.save(&path)?;
+        summary.access_export_path = written(Some(path));
+    }
+
+    let provenance = RunProvenance {
+        started_at_ms,
+        finished_at_ms: unix_millis(),
+        raw_root: join_roots(&raw_roots),
+        graded_root: join_roots(&graded_roots),
+        rules: &rules,
+    };
+    if let Some(sqlite) = exports.sqlite.take() {
+        let path = output_dir.join(&options.sqlite_filename);
+        summary.sqlite_path = Some(path.to_string_lossy().to_string());
+        sqlite.finish(&summary, &problems, &provenance)?;
+    }
+    if let Some(path) = summary_markdown_path {
+        fs::write(
+            long_path(&path),
+            render_summary_markdown(&summary, &problems, &provenance),
+        )?;
+        summary.summary_markdown_path = written(Some(path));
+    }
+    if let Some(path) = html_report_path {
+        fs::write(
+            long_path(&path),
+            render_html_report(&summary, &problems, &provenance),
+        )?;
+        summary.html_report_path = written(Some(path));
+    }
+
+    if options.send_email_report {
+        let result = app_settings
+            .smtp
+            .as_ref()
+            .ok_or_else(|| AppError::Message("No SMTP settings configured.".to_string()))
+            .and_then(|smtp| send_email_report(smtp, smtp_password(host, smtp)?, &summary));
+        summary.email_error = result.err().map(|err| err.to_string());
+    }
+
+    // Dropping the export of a cancelled run rolls its transaction back.
+    let postgres = exports.postgres.take();
+    if options.export_postgres && cancelled {
+        summary.postgres_error = Some("Skipped because the run was cancelled.".to_string());
+    } else if let Some(postgres) = postgres {
+        summary.postgres_error = postgres
+            .finish(&summary, &problems, &provenance)
+            .err()
+            .map(|err| err.to_string());
+    } else {
+        summary.postgres_error = exports.postgres_error.take();
+    }
+
+    // The outputs are complete at this point, so a history that cannot be
+    // written only shows up in the summary.
+    summary.history_error = serde_json::to_value(&options)
+        .map_err(AppError::from)
+        .and_then(|options| {
+            record_run(
+                host,
+                &RunRecord {
+                    run_id: summary.run_id.clone(),
+                    kind: "root".to_string(),
+                    started_at_ms,
+                    finished_at_ms: unix_millis(),
+                    raw_root: provenance.raw_root.clone(),
+                    graded_root: provenance.graded_root.clone(),
+                    options,
+                    rules: rules.clone(),
+                    summary: summary.clone(),
+                },
+            )
+        })
+        .err()
+        .map(|err| err.to_string());
+    write_run_summary(&output_dir, &summary)?;
+    // Kept until every result file is written, so a run that fails or dies
+    // while writing them can still be resumed.
+    if !cancelled && checkpoint_path.exists() {
+        fs::remove_file(&checkpoint_path)?;
+    }
+    workspace.finish()?;
+    Ok(summary)
+}
+
+pub fn run_single_pair(
+    host: &Host,
+    graded_dir: PathBuf,
+    raw_dir: PathBuf,
+    output_dir: PathBuf,
+    survey_id_override: Option<String>,
+    options: SingleRunOptions,
+    rules: Rules,
+) -> Result<RunSummary, AppError> {
+    single_pair_run(
+        host,
+        new_run_id(),
+        graded_dir,
+        raw_dir,
+        output_dir,
+        survey_id_override,
+        options,
+        rules,
+    )
+}
+
+/// `run_single_pair` under the given run id, so the run queue can name the
+/// run before it starts.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn single_pair_run(
+    host: &Host,
+    run_id: String,
+    graded_dir: PathBuf,
+    raw_dir: PathBuf,
+    output_dir: PathBuf,
+    survey_id_override: Option<String>,
+    options: SingleRunOptions,
+    rules: Rules,
+) -> Result<RunSummary, AppError> {
+    let started_at_ms = unix_millis();
+    let mut options = options;
+    options.csv.label_columns = label_columns(&rules);
+    row_headers(&options.csv)?;
+    check_on_existing(options.on_existing, options.output_format)?;
+    let app_settings = load_settings(host)?;
+    let resolve_mapped_drives = app_settings.resolve_mapped_drives;
+    let graded_dir = normalize_root(&graded_dir, resolve_mapped_drives);
+    let raw_dir = normalize_root(&raw_dir, resolve_mapped_drives);
+    let compiled = compiled_rules(host, &rules)?;
+
+    let detected = survey_id_override
+        .and_then(|value| {
+            extract_base_key(&value, &compiled.base_re, &compiled.key_norm)
+                .map(|base| (value, base))
+        })
+        .or_else(|| {
+            extract_detected_id(&graded_dir, &compiled.detected_re, &compiled.key_norm).and_then(
+                |detected| {
+                    extract_base_key(&detected, &compiled.base_re, &compiled.key_norm)
+                        .map(|base| (detected, base))
+                },
+            )
+        })
+        .ok_or_else(|| {
+            AppError::Validation(
+                "Unable to derive survey id base; please provide an override.".to_string(),
+            )
+        })?;
+
+    if !output_dir.exists() {
+        fs::create_dir_all(long_path(&output_dir))?;
+    }
+    mark_output_dir(&output_dir)?;
+    let _output_lock = OutputLock::acquire(&output_dir, &run_id)?;
+    let workspace = RunWorkspace::create(host, run_id, "single")?;
+
+    let (detected_full, base_key) = detected;
+
+    let raw_detected = extract_detected_id(&raw_dir, &compiled.detected_re, &compiled.key_norm);
+    let raw_folder = SurveyFolder {
+        path: raw_dir,
+        detected_id: raw_detected,
+        part: None,
+    };
+    let graded_folder = SurveyFolder {
+        path: graded_dir,
+        detected_id: Some(detected_full.clone()),
+        part: None,
+    };
+
+    let output_path = options
+        .output_format
+        .output_path(output_dir.join(&options.output_filename));
+    let stamp = file_timestamp(unix_millis());
+    let output_target = output_target(output_path, options.on_existing, &stamp)?;
+    let problems_csv_path = report_target(
+        output_dir.join(&options.problems_filename),
+        options.on_existing,
+        &stamp,
+    )?;
+
+    let track = if options.csv.include_track_position {
+        load_survey_track(
+            &options.tracks,
+            &base_key,
+            &[&raw_folder.path, &graded_folder.path],
+        )?
+        .map(Arc::new)
+    } else {
+        None
+    };
+    let mut output = SurveyOutput::new(&options.csv, None, None, None)?;
+    if let Some((path, append)) = &output_target {
+        output.open_file(path, *append, options.output_format)?;
+    }
+    let pair_result = process_pair(
+        host,
+        &compiled,
+        &base_key,
+        &raw_folder,
+        &graded_folder,
+        PairOptions {
+            track,
+            manual_labels: load_manual_labels(&output_dir)?.map(Arc::new),
+            run: RunProgress {
+                survey_index: 1,
+                survey_count: 1,
+                ..RunProgress::new(&workspace.run_id, &workspace.cancel)
+            },
+            spill_dir: Some(workspace.path.clone()),
+            ..PairOptions::new(
+                options.progress,
+                &options.csv,
+                &app_settings,
+                &workspace.cancel,
+            )
+        },
+        &mut |row| output.push(row),
+    );
+    let pair_result = match pair_result {
+        Ok(result) => result,
+        Err(err) => {
+            output.rollback()?;
+            return Err(err);
+        }
+    };
+    let counts = pair_result.counts;
+    let workspace_run_id = workspace.run_id.clone();
+    host.progress(
+        RunProgress {
+            run_id: workspace_run_id.clone(),
+            survey_index: 1,
+            survey_count: 1,
+            ..RunProgress::default()
+        }
+        .event(RunPhase::Writing, &base_key, 0, 0),
+    );
+    output.commit()?;
+
+    let problems = pair_result
+        .walk_errors
+        .iter()
+        .map(|error| error.problem(&base_key, Some(&raw_folder.path), Some(&graded_folder.path)))
+        .collect::<Vec<_>>();
+    let problems_csv_path = problems_csv_path.filter(|_| !problems.is_empty());
+    if let Some(path) = problems_csv_path.as_ref() {
+        write_problems_csv(path, &problems, &options.csv)?;
+    }
+
+    let mut summary = RunSummary {
+        run_id: workspace_run_id,
+        processed_surveys: 1,
+        total_rows: counts.rows,
+        dolphin_yes: counts.dolphin_yes,
+        dolphin_no: counts.dolphin_no(),
+        ambiguity_warnings: pair_result.ambiguity_warnings,
+        collapsed_candidates: pair_result.collapsed_candidates,
+        id_collisions: pair_result.id_collisions.len() as u64,
+        perceptual_matches: pair_result.perceptual_matches,
+        scan_reused: false,
+        problems_count: problems.len() as u64,
+        error_count: severity_count(&problems, ProblemSeverity::Error),
+        warning_count: severity_count(&problems, ProblemSeverity::Warning),
+        info_count: severity_count(&problems, ProblemSeverity::Info),
+        output_dir: output_dir.to_string_lossy().to_string(),
+        merged_csv_path: output_target.map(|(path, _)| path.to_string_lossy().to_string()),
+        problems_csv_path: problems_csv_path.map(|path| path.to_string_lossy().to_string()),
+        problems_detail_csv_path: None,
+        pairings_csv_path: None,
+        summary_markdown_path: None,
+        html_report_path: None,
+        qa_workbook_path: None,
+        access_export_path: None,
+        sqlite_path: None,
+        orphaned_graded: 0,
+        orphaned_graded_path: None,
+        surveys: Vec::new(),
+        email_error: None,
+        postgres_error: None,
+        history_error: None,
+        cancelled: false,
+        resumed_surveys: 0,
+        resumed_rows: 0,
+        cached_file_ids: 0,
+    };
+    summary.history_error = serde_json::to_value(&options)
+        .map_err(AppError::from)
+        .and_then(|options| {
+            record_run(
+                host,
+                &RunRecord {
+                    run_id: summary.run_id.clone(),
+                    kind: "single".to_string(),
+                    started_at_ms,
+                    finished_at_ms: unix_millis(),
+                    raw_root: raw_folder.path.to_string_lossy().to_string(),
+                    graded_root: graded_folder.path.to_string_lossy().to_string(),
+                    options,
+                    rules,
+                    summary: summary.clone(),
+                },
+            )
+        })
+        .err()
+        .map(|err| err.to_string());
+    write_run_summary(&output_dir, &summary)?;
+    workspace.finish()?;
+    Ok(summary)
+}
+
+fn default_problems_filename() -> String {
+    "problems.csv".to_string()
+}
+
+fn default_problems_detail_filename() -> String {
+    "problems_detailed.csv".to_string()
+}
+
+fn default_summary_markdown_filename() -> String {
+    "summary.md".to_string()
+}
+
+fn default_html_report_filename() -> String {
+    "report.html".to_string()
+}
+
+fn default_pairings_filename() -> String {
+    "pairings.csv".to_string()
+}
+
+fn default_qa_workbook_filename() -> String {
+    "qa.xlsx".to_string()
+}
+
+fn default_access_export_filename() -> String {
+    "access_import.xlsx".to_string()
+}
+
+fn default_sqlite_filename() -> String {
+    "results.sqlite".to_string()
+}
+
+fn default_orphaned_graded_filename() -> String {
+    "orphaned_graded.csv".to_string()
+}
+
+fn default_thumbnail_limit() -> usize {
+    500
+}
+
+/// Progress of a root run, rewritten after every finished survey so that
+/// `resume` can pick up an interrupted run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RunCheckpoint {
+    raw_root: String,
+    graded_root: String,
+    rules_hash: String,
+    /// Format, header and CSV dialect of the merged file. Rows appended in
+    /// another format, with other columns or another dialect would not fit
+    /// the rows already there.
+    #[serde(default)]
+    output_format: OutputFormat,
+    #[serde(default)]
+    row_headers: Vec<String>,
+    #[serde(default)]
+    delimiter: CsvDelimiter,
+    #[serde(default)]
+    quote_style: CsvQuoteStyle,
+    #[serde(default)]
+    line_ending: LineEnding,
+    #[serde(default)]
+    utf8_bom: bool,
+    /// Size of the merged CSV after the last finished survey; rows of a
+    /// survey that was interrupted while being written are cut off on resume.
+    merged_csv_len: Option<u64>,
+    /// The merged CSV being written, which differs from `merged_filename`
+    /// under `on_existing: timestamp_suffix`.
+    #[serde(default)]
+    merged_path: Option<String>,
+    surveys: Vec<SurveySummary>,
+    /// Problems found while processing the finished surveys.
+    problems: Vec<ProblemItem>,
+}
+
+/// The checkpoint in `path`, if any. A checkpoint from other roots, rules or
+/// result columns than the `fresh` one of this run is refused rather than
+/// mixed into it.
+fn load_checkpoint(path: &Path, fresh: &RunCheckpoint) -> Result<Option<RunCheckpoint>, AppError> {
+    if !long_path(path).exists() {
+        return Ok(None);
+    }
+    let checkpoint: RunCheckpoint = serde_json::from_str(&fs::read_to_string(long_path(path))?)?;
+    if checkpoint.raw_root != fresh.raw_root || checkpoint.graded_root != fresh.graded_root {
+        return Err(AppError::Validation(format!(
+            "The checkpoint in {} belongs to a run over other roots; choose another output folder or run without resume.",
+            path.to_string_lossy()
+        )));
+    }
+    if checkpoint.rules_hash != fresh.rules_hash {
+        return Err(AppError::Validation(format!(
+            "The rules changed since the run in {} was interrupted; run without resume.",
+            path.to_string_lossy()
+        )));
+    }
+    if checkpoint.output_format != fresh.output_format {
+        return Err(AppError::Validation(format!(
+            "The run in {} was interrupted while writing {} output; resume it with that output format or run without resume.",
+            path.to_string_lossy(),
+            checkpoint.output_format.extension()
+        )));
+    }
+    if checkpoint.row_headers != fresh.row_headers
+        || checkpoint.delimiter != fresh.delimiter
+        || checkpoint.quote_style != fresh.quote_style
+        || checkpoint.line_ending != fresh.line_ending
+        || checkpoint.utf8_bom != fresh.utf8_bom
+    {
+        return Err(AppError::Validation(format!(
+            "The result columns, the delimiter, quoting, line endings or byte order mark changed since the run in {} was interrupted; run without resume.",
+            path.to_string_lossy()
+        )));
+    }
+    Ok(Some(checkpoint))
+}
+
+fn write_checkpoint(path: &Path, checkpoint: &RunCheckpoint) -> Result<(), AppError> {
+    // Write and rename so a crash never leaves a truncated checkpoint behind.
+    let partial = path.with_extension("json.partial");
+    fs::write(long_path(&partial), serde_json::to_string(checkpoint)?)?;
+    fs::rename(long_path(&partial), long_path(path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::output::{append_csv_writer, init_csv_writer, write_rows_to_writer, CsvRow};
+    use crate::synthetic::{generate_synthetic_tree, SyntheticTreeOptions};
+    use crate::test_support::{csv_row, root_options, TestTree};
+
+    #[test]
+    fn run_estimates_use_the_throughput_of_recorded_runs() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let synthetic = generate_synthetic_tree(SyntheticTreeOptions {
+            output_dir: Some(root.join("tree").to_string_lossy().to_string()),
+            surveys: 3,
+            images_per_survey: 10,
+            seed: 3,
+            ..SyntheticTreeOptions::default()
+        })
+        .expect("generate");
+        let host = tree.host();
+        let estimate_with = |host: &Host, options: serde_json::Value| {
+            estimate_run(
+                host,
+                vec![PathBuf::from(&synthetic.graded_root)],
+                vec![PathBuf::from(&synthetic.raw_root)],
+                root_options(options),
+                Rules::default(),
+            )
+            .expect("estimate")
+        };
+        let estimate = |host: &Host| estimate_with(host, serde_json::json!({}));
+
+        let first = estimate(&host);
+        assert_eq!((first.surveys, first.paired_surveys), (3, 3));
+        assert_eq!(first.raw_images, synthetic.raw_images);
+        assert_eq!(first.graded_images, synthetic.graded_images);
+        assert!(!first.from_history);
+        assert_eq!(
+            first.estimated_seconds,
+            (synthetic.raw_images as f64 / ESTIMATE_DEFAULT_ROWS_PER_SECOND).ceil() as u64
+        );
+        // Nine default columns, merged file only.
+        let csv_row = 9 * ESTIMATE_FIELD_BYTES;
+        assert_eq!(first.estimated_output_bytes, synthetic.raw_images * csv_row);
+        let per_survey = estimate_with(&host, serde_json::json!({ "write_per_survey": true }));
+        assert_eq!(
+            per_survey.estimated_output_bytes,
+            synthetic.raw_images * csv_row * 2
+        );
+        let narrow = estimate_with(
+            &host,
+            serde_json::json!({
+                "columns": ["survey_id_base", "raw_relpath", "filename", "dolphin"],
+                "output_format": "parquet"
+            }),
+        );
+        assert_eq!(
+            narrow.estimated_output_bytes,
+            synthetic.raw_images * 4 * ESTIMATE_FIELD_BYTES / ESTIMATE_PARQUET_DIVISOR
+        );
+        let qa = estimate_with(
+            &host,
+            serde_json::json!({
+                "write_merged": false,
+                "write_qa_workbook": true,
+                "thumbnails": "all",
+                "thumbnail_limit": 5,
+                "write_sqlite": true
+            }),
+        );
+        assert_eq!(
+            qa.estimated_output_bytes,
+            synthetic.raw_images * csv_row / ESTIMATE_XLSX_DIVISOR
+                + 5 * ESTIMATE_THUMBNAIL_BYTES
+                + synthetic.raw_images * csv_row * 2
+        );
+
+        let summary: RunSummary = serde_json::from_value(serde_json::json!({
+            "processed_surveys": 3,
+            "total_rows": 4000,
+            "dolphin_yes": 0,
+            "dolphin_no": 4000,
+            "ambiguity_warnings": 0,
+            "problems_count": 0,
+            "output_dir": "out",
+            "merged_csv_path": null,
+            "problems_csv_path": null,
+            "resumed_rows": 1000
+        }))
+        .expect("summary");
+        record_run(
+            &host,
+            &RunRecord {
+                run_id: "20240101T000000Z-estimate".to_string(),
+                kind: "root".to_string(),
+                started_at_ms: 1_000,
+                finished_at_ms: 11_000,
+                raw_root: synthetic.raw_root.clone(),
+                graded_root: synthetic.graded_root.clone(),
+                options: serde_json::Value::Null,
+                rules: Rules::default(),
+                summary,
+            },
+        )
+        .expect("record");
+        let second = estimate(&host);
+        assert!(second.from_history);
+        assert_eq!(second.rows_per_second, 300.0);
+        assert_eq!(
+            second.estimated_seconds,
+            (synthetic.raw_images as f64 / 300.0).ceil() as u64
+        );
+    }
+
+    #[test]
+    fn surveys_are_merged_across_several_roots() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let old_raw = root.join("2010-2015").join("raw");
+        let new_raw = root.join("2016-present").join("raw");
+        let graded = root.join("graded");
+        for (raw_root, key) in [(&old_raw, "20140101_AB"), (&new_raw, "20240101_AB")] {
+            let raw_dir = raw_root.join(key);
+            let graded_dir = graded.join(key).join("Dolphins");
+            fs::create_dir_all(&raw_dir).expect("create");
+            fs::create_dir_all(&graded_dir).expect("create");
+            fs::write(raw_dir.join("IMG_0001.JPG"), b"x").expect("write");
+            fs::write(graded_dir.join("IMG_0001.JPG"), b"x").expect("write");
+        }
+        fs::create_dir_all(new_raw.join("20140101_AB")).expect("create");
+        let host = tree.host();
+        let options = root_options(serde_json::json!({}));
+        assert!(matches!(
+            run_root_scan(
+                &host,
+                vec![graded.clone()],
+                Vec::new(),
+                root.join("out"),
+                options.clone(),
+                Rules::default(),
+            ),
+            Err(AppError::Validation(_))
+        ));
+
+        let summary = run_root_scan(
+            &host,
+            vec![graded.clone()],
+            vec![old_raw.clone(), new_raw.clone()],
+            root.join("out"),
+            options,
+            Rules::default(),
+        )
+        .expect("run");
+        let problems = fs::read_to_string(root.join("out").join("problems.csv")).expect("read");
+        assert!(problems.contains("DUPLICATE_RAW"));
+        assert_eq!(summary.surveys.len(), 1);
+        let survey = &summary.surveys[0];
+        assert_eq!(survey.survey_id_base, "20240101_AB");
+        let canonical = |path: &Path| fs::canonicalize(path).expect("canonical");
+        assert_eq!(
+            PathBuf::from(&survey.raw_root),
+            canonical(&new_raw),
+            "survey should report the root it came from"
+        );
+        assert_eq!(PathBuf::from(&survey.graded_root), canonical(&graded));
+    }
+
+    #[test]
+    fn problem_severities_are_reported_and_block_runs() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/IND/IMG_0001.JPG", "x");
+        tree.write("raw/20240102_CD/IMG_0001.JPG", "y");
+        tree.write("raw/2023/20240103_EF/IMG_0001.JPG", "z");
+        tree.write("raw/2024/20240103_EF/IMG_0001.JPG", "z");
+        tree.write("graded/20240103_EF/IND/IMG_0001.JPG", "z");
+        let host = tree.host();
+        let problems_csv = || fs::read_to_string(tree.join("out/problems.csv")).expect("problems");
+
+        let summary = tree
+            .run_root(&host, root_options(serde_json::json!({})), Rules::default())
+            .expect("run");
+        assert_eq!((summary.error_count, summary.warning_count), (1, 1));
+        assert_eq!(summary.surveys.len(), 1);
+        let problems = problems_csv();
+        let mut lines = problems.lines();
+        assert!(lines
+            .next()
+            .expect("header")
+            .contains(",problem_type,severity,"));
+        let severities: Vec<_> = lines
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                (
+                    fields[0].to_string(),
+                    fields[4].to_string(),
+                    fields[5].to_string(),
+                )
+            })
+            .collect();
+        assert!(severities.contains(&(
+            "20240102_CD".to_string(),
+            "GRADED_MISSING".to_string(),
+            "warning".to_string()
+        )));
+        assert!(severities.contains(&(
+            "20240103_EF".to_string(),
+            "DUPLICATE_RAW".to_string(),
+            "error".to_string()
+        )));
+
+        // Blocking on warnings stops the run before any rows, but the
+        // problems list still tells why.
+        fs::remove_dir_all(tree.join("out")).expect("remove");
+        let blocked = tree.run_root(
+            &host,
+            root_options(serde_json::json!({ "blocking_severities": ["warning"] })),
+            Rules::default(),
+        );
+        assert!(matches!(blocked, Err(AppError::Blocked(_))), "{blocked:?}");
+        assert!(!tree.join("out/merged.csv").exists());
+        assert!(problems_csv().contains("GRADED_MISSING"));
+
+        // The message names the problems file actually written, and the
+        // blocked run leaves no workspace behind.
+        let blocked = tree.run_root(
+            &host,
+            root_options(serde_json::json!({
+                "blocking_severities": ["warning"],
+                "on_existing": "timestamp_suffix"
+            })),
+            Rules::default(),
+        );
+        let Err(AppError::Blocked(message)) = blocked else {
+            panic!("{blocked:?}");
+        };
+        let listed = message
+            .split_once("; see ")
+            .and_then(|(_, path)| path.strip_suffix('.'))
+            .expect("path");
+        assert_ne!(Path::new(listed), tree.join("out/problems.csv"));
+        assert!(fs::read_to_string(listed)
+            .expect("listed")
+            .contains("GRADED_MISSING"));
+        assert!(crate::history::list_workspaces(&host)
+            .expect("workspaces")
+            .is_empty());
+
+        // Without problems of a blocking severity the run goes ahead.
+        let summary = tree
+            .run_root(
+                &host,
+                root_options(serde_json::json!({
+                    "blocking_severities": ["info"],
+                    "exclude_base_keys": ["20240102_CD", "20240103_EF"]
+                })),
+                Rules::default(),
+            )
+            .expect("not blocked");
+        assert_eq!(summary.total_rows, 1);
+    }
+
+    #[test]
+    fn a_failing_survey_is_skipped_and_the_run_goes_on() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        // A graded delivery that is no zip archive fails its survey only.
+        tree.write("graded/20240101_AB.zip", "not a zip");
+        tree.write("raw/20240102_CD/IMG_0001.JPG", "y");
+        tree.write("graded/20240102_CD/IND/IMG_0001.JPG", "y");
+        let host = tree.host();
+
+        let summary = tree
+            .run_root(
+                &host,
+                root_options(serde_json::json!({ "write_sqlite": true })),
+                Rules {
+                    scan_archives: true,
+                    ..Rules::default()
+                },
+            )
+            .expect("run");
+        assert_eq!(summary.processed_surveys, 1);
+        assert_eq!(summary.surveys.len(), 1);
+        assert_eq!(summary.surveys[0].survey_id_base, "20240102_CD");
+        assert_eq!(summary.total_rows, 1);
+        assert_eq!(summary.error_count, 1);
+        let problems = fs::read_to_string(tree.join("out/problems.csv")).expect("problems");
+        let failed = problems
+            .lines()
+            .find(|line| line.contains("SURVEY_ERROR"))
+            .expect("survey error");
+        assert!(failed.starts_with("20240101_AB,"), "{failed}");
+        assert!(failed.contains(",error,Survey skipped: "), "{failed}");
+        let merged = fs::read_to_string(tree.join("out/merged.csv")).expect("merged");
+        assert!(!merged.contains("20240101_AB"));
+        assert!(merged.contains("20240102_CD"));
+        let conn = rusqlite::Connection::open(tree.join("out/results.sqlite")).expect("sqlite");
+        let exported: Vec<(String, i64)> = conn
+            .prepare(
+                "SELECT r.survey_id_base, runs.total_rows FROM result_rows r
+                 JOIN runs ON runs.run_id = r.run_id",
+            )
+            .expect("prepare")
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .expect("query")
+            .collect::<Result<_, _>>()
+            .expect("rows");
+        assert_eq!(exported, vec![("20240102_CD".to_string(), 1)]);
+    }
+
+    #[test]
+    fn cached_scan_requires_matching_inputs() {
+        let rules = Rules::default();
+        let hash = rules_hash(&rules).expect("hash");
+        let cached = CachedScan {
+            token: "1-0".to_string(),
+            raw_roots: vec![PathBuf::from("/raw")],
+            graded_roots: vec![PathBuf::from("/graded")],
+            settings: ScanSettings::default(),
+            rules_hash: hash.clone(),
+            scan: ScanResult {
+                entries: Vec::new(),
+                problems: Vec::new(),
+                preview: Vec::new(),
+            },
+        };
+        let raw = &[PathBuf::from("/raw")];
+        let graded = &[PathBuf::from("/graded")];
+        let settings = ScanSettings::default();
+        assert!(cached.matches("1-0", raw, graded, &settings, &hash));
+        assert!(!cached.matches("2-0", raw, graded, &settings, &hash));
+        assert!(!cached.matches("1-0", graded, raw, &settings, &hash));
+
+        let changed = Rules {
+            graded_negative_contains_any: vec!["reject".to_string()],
+            ..Rules::default()
+        };
+        let changed_hash = rules_hash(&changed).expect("hash");
+        assert_ne!(hash, changed_hash);
+        assert!(!cached.matches("1-0", raw, graded, &settings, &changed_hash));
+    }
+
+    #[test]
+    fn progress_events_carry_run_position_and_eta() {
+        let mut run = RunProgress {
+            run_id: "run-1".to_string(),
+            started: Instant::now() - Duration::from_secs(60),
+            survey_index: 3,
+            survey_count: 5,
+            ..RunProgress::default()
+        };
+        let event = run.event(RunPhase::Matching, "20240101_AB", 0, 10);
+        assert_eq!(event.run_id, "run-1");
+        assert_eq!((event.survey_index, event.survey_count), (3, 5));
+        // Two surveys took a minute, so the remaining three take 90 s.
+        assert_eq!(event.eta_seconds, Some(90));
+        let halfway = run.event(RunPhase::Matching, "20240101_AB", 5, 10);
+        assert_eq!(halfway.eta_seconds, Some(60));
+        assert_eq!(run.event(RunPhase::Writing, "", 0, 0).eta_seconds, None);
+
+        run.resumed = 1;
+        assert_eq!(
+            run.event(RunPhase::IndexingGraded, "20240101_AB", 0, 0)
+                .eta_seconds,
+            Some(180)
+        );
+        run.survey_index = 1;
+        run.resumed = 0;
+        assert_eq!(run.event(RunPhase::Matching, "x", 0, 10).eta_seconds, None);
+
+        // One of 101 surveys took a second of work and 0.4 s paused: the
+        // remaining 100 take about 100 s, not 140 s.
+        let cancel = CancelToken::default();
+        let mut paused = RunProgress::new("run-2", &cancel);
+        paused.survey_count = 101;
+        paused.survey_index = 2;
+        paused.started = Instant::now() - Duration::from_secs(1);
+        cancel.pause();
+        std::thread::sleep(Duration::from_millis(400));
+        cancel.resume();
+        let eta = paused.event(RunPhase::Matching, "x", 0, 10).eta_seconds;
+        assert!(eta.is_some_and(|eta| (100..120).contains(&eta)), "{eta:?}");
+        assert_eq!(
+            serde_json::to_value(RunPhase::IndexingGraded).expect("json"),
+            "indexing_graded"
+        );
+    }
+
+    #[test]
+    fn a_run_that_fails_writing_its_reports_can_be_resumed() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/IND/IMG_0001.JPG", "x");
+        tree.mkdir("raw/20240102_CD");
+        // A folder in the way of the problems CSV fails the end of the run.
+        tree.mkdir("out/problems.csv");
+        let host = tree.host();
+        assert!(tree
+            .run_root(&host, root_options(serde_json::json!({})), Rules::default())
+            .is_err());
+        assert!(tree.join("out").join(CHECKPOINT_FILENAME).exists());
+
+        fs::remove_dir(tree.join("out/problems.csv")).expect("remove");
+        let summary = tree
+            .run_root(
+                &host,
+                root_options(serde_json::json!({ "resume": true })),
+                Rules::default(),
+            )
+            .expect("resume");
+        assert_eq!((summary.resumed_surveys, summary.total_rows), (1, 1));
+        assert!(!tree.join("out").join(CHECKPOINT_FILENAME).exists());
+    }
+
+    #[test]
+    fn resume_refuses_exports_that_need_every_row() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/IND/IMG_0001.JPG", "x");
+        let host = tree.host();
+        let summary = tree
+            .run_root(&host, root_options(serde_json::json!({})), Rules::default())
+            .expect("run");
+        // The same run, interrupted after its only survey.
+        let merged = tree.join("out").join("merged.csv");
+        let checkpoint = RunCheckpoint {
+            raw_root: join_roots(&normalize_roots("raw", &[tree.join("raw")], false).expect("raw")),
+            graded_root: join_roots(
+                &normalize_roots("graded", &[tree.join("graded")], false).expect("graded"),
+            ),
+            rules_hash: rules_hash(&Rules::default()).expect("hash"),
+            output_format: OutputFormat::Csv,
+            row_headers: row_headers(&CsvSettings::default()).expect("headers"),
+            delimiter: CsvDelimiter::Comma,
+            quote_style: CsvQuoteStyle::Necessary,
+            line_ending: LineEnding::default(),
+            utf8_bom: false,
+            merged_csv_len: Some(fs::metadata(&merged).expect("merged").len()),
+            merged_path: Some(merged.to_string_lossy().to_string()),
+            surveys: summary.surveys,
+            problems: Vec::new(),
+        };
+        let checkpoint_path = tree.join("out").join(CHECKPOINT_FILENAME);
+        write_checkpoint(&checkpoint_path, &checkpoint).expect("checkpoint");
+
+        let resumed = |exports: serde_json::Value| {
+            let mut options = root_options(exports);
+            options.resume = true;
+            tree.run_root(&host, options, Rules::default())
+        };
+        assert!(matches!(
+            resumed(serde_json::json!({ "write_sqlite": true })),
+            Err(AppError::Validation(_))
+        ));
+        // Rows with other columns or another delimiter would not fit the file.
+        assert!(matches!(
+            resumed(serde_json::json!({ "delimiter": "semicolon" })),
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            resumed(serde_json::json!({ "include_confidence": true })),
+            Err(AppError::Validation(_))
+        ));
+        // Nor would JSON lines appended to the merged CSV.
+        let before = fs::read(&merged).expect("merged");
+        assert!(matches!(
+            resumed(serde_json::json!({ "output_format": "jsonl" })),
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            resumed(serde_json::json!({ "line_ending": "crlf" })),
+            Err(AppError::Validation(_))
+        ));
+        assert_eq!(fs::read(&merged).expect("merged"), before);
+        // Refused resumes leave no workspace behind.
+        assert!(crate::history::list_workspaces(&host)
+            .expect("workspaces")
+            .is_empty());
+        // Nor does an output format that cannot be resumed touch the folder.
+        let mut options = root_options(serde_json::json!({ "output_format": "xlsx" }));
+        options.resume = true;
+        let result = run_root_scan(
+            &host,
+            vec![tree.join("graded")],
+            vec![tree.join("raw")],
+            tree.join("other"),
+            options,
+            Rules::default(),
+        );
+        assert!(matches!(result, Err(AppError::Validation(_))));
+        assert!(!tree.join("other").exists());
+        let summary = resumed(serde_json::json!({})).expect("resume");
+        assert_eq!(summary.resumed_surveys, 1);
+        assert_eq!(summary.total_rows, 1);
+    }
+
+    #[test]
+    fn cancelled_runs_keep_the_finished_surveys() {
+        /// Cancels its run when survey `at` of the run starts.
+        struct CancelAt(u64);
+
+        impl crate::ProgressSink for CancelAt {
+            fn progress(&self, event: crate::ProgressEvent) {
+                if event.phase == RunPhase::IndexingGraded && event.survey_index == self.0 {
+                    crate::control::cancel_runs(Some(&event.run_id));
+                }
+            }
+        }
+
+        let tree = TestTree::new();
+        for key in ["20240101_AB", "20240102_AB", "20240103_AB"] {
+            tree.write(format!("raw/{key}/IMG_0001.JPG"), "x");
+            tree.write(format!("raw/{key}/IMG_0002.JPG"), "y");
+            tree.write(format!("graded/{key}/IND/IMG_0001.JPG"), "x");
+        }
+        let host = Host::headless_with_sink(tree.join("data"), Arc::new(CancelAt(2)));
+        let summary = tree
+            .run_root(&host, root_options(serde_json::json!({})), Rules::default())
+            .expect("cancelled run");
+        assert!(summary.cancelled);
+        assert_eq!((summary.processed_surveys, summary.total_rows), (1, 2));
+        let merged = fs::read_to_string(tree.join("out").join("merged.csv")).expect("merged");
+        let surveys: BTreeSet<&str> = merged
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split(',').next())
+            .collect();
+        assert_eq!(surveys, BTreeSet::from(["20240101_AB"]));
+        let problems = fs::read_to_string(tree.join("out").join("problems.csv")).expect("problems");
+        assert!(problems.contains("RUN_CANCELLED"));
+        assert!(problems.contains("2 of 3 surveys were not processed"));
+        assert!(tree.join("out").join(CHECKPOINT_FILENAME).exists());
+
+        let resumed = tree
+            .run_root(
+                &tree.host(),
+                root_options(serde_json::json!({ "resume": true })),
+                Rules::default(),
+            )
+            .expect("resumed run");
+        assert!(!resumed.cancelled);
+        assert_eq!((resumed.resumed_surveys, resumed.total_rows), (1, 6));
+        assert!(!tree.join("out").join(CHECKPOINT_FILENAME).exists());
+
+        // A single pair has nothing to keep, so its run fails as cancelled.
+        let host = Host::headless_with_sink(tree.join("data"), Arc::new(CancelAt(1)));
+        let options: SingleRunOptions =
+            serde_json::from_value(serde_json::json!({ "output_filename": "single.csv" }))
+                .expect("options");
+        let result = run_single_pair(
+            &host,
+            tree.join("graded").join("20240101_AB"),
+            tree.join("raw").join("20240101_AB"),
+            tree.join("single"),
+            None,
+            options,
+            Rules::default(),
+        );
+        assert!(matches!(result, Err(AppError::Cancelled)));
+        assert!(!tree.join("single").join("single.csv").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn single_runs_report_unreadable_folders() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/IND/IMG_0001.JPG", "x");
+        std::os::unix::fs::symlink(
+            tree.join("missing"),
+            tree.join("raw/20240101_AB/unreadable"),
+        )
+        .expect("symlink");
+        let options: SingleRunOptions =
+            serde_json::from_value(serde_json::json!({ "output_filename": "single.csv" }))
+                .expect("options");
+        let summary = run_single_pair(
+            &tree.host(),
+            tree.join("graded/20240101_AB"),
+            tree.join("raw/20240101_AB"),
+            tree.join("out"),
+            None,
+            options,
+            Rules {
+                follow_symlinks: true,
+                ..Rules::default()
+            },
+        )
+        .expect("run");
+        assert_eq!(summary.total_rows, 1);
+        assert_eq!((summary.problems_count, summary.error_count), (1, 1));
+        assert_eq!(summary.exit_code(), EXIT_PROBLEMS);
+        let path = summary.problems_csv_path.expect("problems");
+        assert_eq!(Path::new(&path), tree.join("out/problems.csv"));
+        let problems = fs::read_to_string(path).expect("read");
+        assert!(problems.contains("SCAN_ERROR"));
+        assert!(problems.contains("unreadable"));
+    }
+
+    #[test]
+    fn the_markdown_summary_is_written_on_request() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("raw/20240101_AB/IMG_0002.JPG", "y");
+        tree.write("graded/20240101_AB/IND/IMG_0001.JPG", "x");
+        tree.write("graded/20240102_CD/IND/IMG_0001.JPG", "x");
+        let host = tree.host();
+        let summary = tree
+            .run_root(&host, root_options(serde_json::json!({})), Rules::default())
+            .expect("run");
+        assert_eq!(summary.summary_markdown_path, None);
+        assert!(!tree.join("out").join("summary.md").exists());
+
+        let summary = tree
+            .run_root(
+                &host,
+                root_options(serde_json::json!({ "write_summary_markdown": true })),
+                Rules::default(),
+            )
+            .expect("run");
+        let path = summary.summary_markdown_path.expect("markdown");
+        let markdown = fs::read_to_string(path).expect("read");
+        assert!(markdown.starts_with("# Survey labeling run\n\n| Metric | Value |\n|---|---:|\n"));
+        assert!(markdown.contains("| Processed surveys | 1 |\n| Total rows | 2 |\n| Dolphin yes | 1 |\n| Dolphin no | 1 |\n"));
+        assert!(markdown.contains("| 20240101_AB | 2 | 1 | 1 | 50.0% | 0 |\n"));
+        assert!(markdown.contains("| 20240102_CD | RAW_MISSING | warning |  |\n"));
+        assert!(markdown.contains(&format!(
+            "- Merged CSV: `{}`\n",
+            tree.join("out").join("merged.csv").to_string_lossy()
+        )));
+        assert!(markdown.contains(&format!(
+            "- Raw root: `{}`\n- Graded root: `{}`\n",
+            tree.join("raw").to_string_lossy(),
+            tree.join("graded").to_string_lossy()
+        )));
+        assert!(markdown.contains("### Rules\n\n```json\n{"));
+    }
+
+    #[test]
+    fn settings_are_read_once_per_run() {
+        struct BreakSettings(PathBuf);
+
+        impl crate::ProgressSink for BreakSettings {
+            fn progress(&self, event: crate::ProgressEvent) {
+                if event.phase == RunPhase::Writing {
+                    fs::write(&self.0, "{").expect("write");
+                }
+            }
+        }
+
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/IND/IMG_0001.JPG", "x");
+        let settings = tree.write("data/settings.json", "{}");
+        let host = Host::headless_with_sink(tree.join("data"), Arc::new(BreakSettings(settings)));
+        let summary = tree
+            .run_root(
+                &host,
+                root_options(serde_json::json!({
+                    "send_email_report": true,
+                    "export_postgres": true
+                })),
+                Rules::default(),
+            )
+            .expect("run");
+        assert_eq!(
+            summary.email_error.as_deref(),
+            Some("No SMTP settings configured.")
+        );
+        assert_eq!(
+            summary.postgres_error.as_deref(),
+            Some("No PostgreSQL settings configured.")
+        );
+    }
+
+    #[test]
+    fn on_existing_applies_to_every_report() {
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/IND/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/IND/renamed.JPG", "y");
+        tree.write("graded/20240102_CD/IND/IMG_0001.JPG", "x");
+        let host = tree.host();
+        let run = |on_existing: &str| {
+            tree.run_root(
+                &host,
+                root_options(serde_json::json!({
+                    "on_existing": on_existing,
+                    "write_pairings": true,
+                    "write_orphaned_graded": true,
+                    "write_qa_workbook": true,
+                    "write_summary_markdown": true,
+                    "write_html_report": true,
+                })),
+                Rules::default(),
+            )
+        };
+        let reports = |summary: &RunSummary| {
+            [
+                summary.merged_csv_path.clone(),
+                summary.problems_csv_path.clone(),
+                summary.pairings_csv_path.clone(),
+                summary.orphaned_graded_path.clone(),
+                summary.qa_workbook_path.clone(),
+                summary.summary_markdown_path.clone(),
+                summary.html_report_path.clone(),
+            ]
+        };
+        let files = || fs::read_dir(tree.join("out")).expect("out").count();
+
+        let first = run("overwrite").expect("first");
+        assert!(reports(&first).iter().all(Option::is_some));
+        let written = files();
+
+        let skipped = run("skip").expect("skip");
+        assert!(reports(&skipped).iter().all(Option::is_none));
+        assert_eq!(files(), written);
+
+        assert!(matches!(run("error"), Err(AppError::Validation(_))));
+        assert_eq!(files(), written);
+
+        let suffixed = run("timestamp_suffix").expect("suffixed");
+        for (first, suffixed) in reports(&first).iter().zip(reports(&suffixed)) {
+            let (first, suffixed) = (first.clone().expect("first"), suffixed.expect("suffixed"));
+            assert_ne!(first, suffixed);
+            assert!(Path::new(&suffixed).is_file(), "{suffixed}");
+        }
+        assert_eq!(files(), written + 7);
+
+        // Appending adds rows to the merged file and suffixes the reports.
+        let appended = run("append").expect("append");
+        assert_eq!(appended.merged_csv_path, first.merged_csv_path);
+        assert_ne!(appended.problems_csv_path, first.problems_csv_path);
+        assert_eq!(files(), written + 13);
+    }
+
+    #[test]
+    fn resume_truncates_merged_csv_to_checkpoint() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let row = |key: &str| CsvRow {
+            raw_path: root.join("img_001.jpg"),
+            ..csv_row(key, "img_001.jpg", 1)
+        };
+        let settings = CsvSettings::default();
+        let merged = root.join("merged.csv");
+        let mut writer = init_csv_writer(&merged, &settings).expect("writer");
+        write_rows_to_writer(&mut writer, &[row("20240101_AB")], &settings).expect("rows");
+        let len = writer.get_ref().metadata().expect("metadata").len();
+        // Rows of a survey that crashed before its checkpoint was written.
+        write_rows_to_writer(&mut writer, &[row("20240102_CD")], &settings).expect("rows");
+        drop(writer);
+
+        let mut writer = append_csv_writer(&merged, len, &settings).expect("append");
+        write_rows_to_writer(&mut writer, &[row("20240103_EF")], &settings).expect("rows");
+        drop(writer);
+        let mut reader = csv::Reader::from_path(&merged).expect("read");
+        let keys = reader
+            .records()
+            .map(|record| record.expect("record")[0].to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["20240101_AB", "20240103_EF"]);
+
+        let path = root.join(CHECKPOINT_FILENAME);
+        let fresh = RunCheckpoint {
+            raw_root: root.join("raw").to_string_lossy().to_string(),
+            graded_root: root.join("graded").to_string_lossy().to_string(),
+            rules_hash: "hash".to_string(),
+            output_format: OutputFormat::Csv,
+            row_headers: row_headers(&settings).expect("headers"),
+            delimiter: CsvDelimiter::Comma,
+            quote_style: CsvQuoteStyle::Necessary,
+            line_ending: LineEnding::default(),
+            utf8_bom: false,
+            merged_csv_len: None,
+            merged_path: None,
+            surveys: Vec::new(),
+            problems: Vec::new(),
+        };
+        assert!(load_checkpoint(&path, &fresh).expect("load").is_none());
+        let checkpoint = RunCheckpoint {
+            merged_csv_len: Some(len),
+            ..fresh.clone()
+        };
+        write_checkpoint(&path, &checkpoint).expect("checkpoint");
+        let loaded = load_checkpoint(&path, &fresh)
+            .expect("load")
+            .expect("checkpoint");
+        assert_eq!(loaded.merged_csv_len, Some(len));
+        let refused = |other: RunCheckpoint| {
+            matches!(load_checkpoint(&path, &other), Err(AppError::Validation(_)))
+        };
+        assert!(refused(RunCheckpoint {
+            rules_hash: "other".to_string(),
+            ..fresh.clone()
+        }));
+        assert!(refused(RunCheckpoint {
+            raw_root: root.to_string_lossy().to_string(),
+            ..fresh.clone()
+        }));
+        assert!(refused(RunCheckpoint {
+            delimiter: CsvDelimiter::Tab,
+            ..fresh.clone()
+        }));
+        assert!(refused(RunCheckpoint {
+            quote_style: CsvQuoteStyle::Always,
+            ..fresh.clone()
+        }));
+        assert!(refused(RunCheckpoint {
+            utf8_bom: true,
+            ..fresh.clone()
+        }));
+        assert!(refused(RunCheckpoint {
+            row_headers: vec!["survey_id_base".to_string()],
+            ..fresh
+        }));
+    }
+}
@@ -0,0 +1,2249 @@
+//! Discovery of survey folders below the raw and graded roots, their
+//! pairing by base key, and listing of the images inside them.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use walkdir::{DirEntry, FilterEntry, WalkDir};
+
+use crate::control::{CancelToken, Generation};
+use crate::matching::{
+    archive_images, compute_file_id, file_id_with_size, is_video, survey_fields,
+};
+use crate::output::{is_output_dir, normalize_relpath, OUTPUT_MARKER_FILENAME};
+use crate::paths::{long_path, short_path};
+use crate::problems::{problem_severity, ProblemItem, ProblemSeverity};
+use crate::rules::{CompiledRules, KeyCase, KeyNormalization};
+use crate::{archives, tracks, AppError, CountUpdateEvent, ProgressSink, PREVIEW_COUNT_WORKERS};
+
+/// Appended to the `; `-separated folder list of a duplicate problem that
+/// `resolve_duplicates` settled.
+pub(crate) const AUTO_RESOLVED_NOTE: &str = " (resolved automatically to the suggested path)";
+
+/// Scan-level settings shared by preview and root runs.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScanSettings {
+    #[serde(default)]
+    pub expected_surveys_path: Option<String>,
+    /// CSV of `survey id, expected raw image count` rows.
+    #[serde(default)]
+    pub expected_counts_path: Option<String>,
+    /// Allowed deviation from the expected count, in percent.
+    #[serde(default)]
+    pub count_tolerance_pct: f64,
+    /// Whether a survey with several raw or graded folders is labeled from
+    /// the best-scored folder or left for manual resolution.
+    #[serde(default)]
+    pub resolve_duplicates: DuplicateResolution,
+    /// Pair a survey found only under the raw root with one found only under
+    /// the graded root when their base keys are at most this many edits
+    /// apart. 0 turns it off.
+    #[serde(default)]
+    pub fuzzy_max_edit_distance: usize,
+    /// Also pair them when their `survey_date`s are at most this many days
+    /// apart and their `area`s match. 0 turns it off.
+    #[serde(default)]
+    pub fuzzy_date_tolerance_days: u32,
+    /// Only take surveys whose `survey_date` is on or after this day
+    /// (`YYYY-MM-DD`); surveys without a date are left out once a bound is set.
+    #[serde(default)]
+    pub date_from: Option<String>,
+    /// Only take surveys whose `survey_date` is on or before this day.
+    #[serde(default)]
+    pub date_to: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateResolution {
+    /// Report the duplicates and skip the survey.
+    #[default]
+    Manual,
+    /// Report the duplicates and continue with the suggested folder.
+    Auto,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreviewItem {
+    pub base_key: String,
+    pub raw_path: Option<String>,
+    pub graded_path: Option<String>,
+    pub status: String,
+    pub problem_type: Option<String>,
+    pub severity: Option<ProblemSeverity>,
+    pub details: Option<String>,
+    pub raw_image_count: Option<u64>,
+    pub graded_image_count: Option<u64>,
+    pub survey_id_raw_detected: Option<String>,
+    pub survey_id_graded_detected: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct SurveyFolder {
+    pub(crate) path: PathBuf,
+    pub(crate) detected_id: Option<String>,
+    pub(crate) part: Option<SurveyPart>,
+}
+
+/// The share of a combo folder that belongs to one survey: files whose name
+/// carries `base_key`, plus files without an id when `base_key` is the
+/// folder's own key.
+#[derive(Clone, Debug)]
+pub(crate) struct SurveyPart {
+    base_key: String,
+    pub(crate) folder_key: String,
+}
+
+impl SurveyPart {
+    pub(crate) fn contains(&self, path: &Path, rules: &CompiledRules) -> bool {
+        let file_key = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| filename_base_key(name, rules));
+        file_key.as_deref().unwrap_or(&self.folder_key) == self.base_key
+    }
+}
+
+/// A folder or file a walk could not read, reported as `SCAN_ERROR`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct WalkError {
+    pub(crate) path: PathBuf,
+    message: String,
+}
+
+impl WalkError {
+    fn new(path: &Path, err: impl std::fmt::Display) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        }
+    }
+
+    fn from_walkdir(root: &Path, err: &walkdir::Error) -> Self {
+        let path = short_path(err.path().unwrap_or(root));
+        match err.io_error() {
+            Some(io) => Self::new(&path, io),
+            None => Self::new(&path, err),
+        }
+    }
+
+    pub(crate) fn problem(
+        &self,
+        base_key: &str,
+        raw: Option<&Path>,
+        graded: Option<&Path>,
+    ) -> ProblemItem {
+        ProblemItem {
+            survey_id_base: base_key.to_string(),
+            survey_id_detected: None,
+            raw_path: raw.map(|path| path.to_string_lossy().to_string()),
+            graded_path: graded.map(|path| path.to_string_lossy().to_string()),
+            problem_type: "SCAN_ERROR".to_string(),
+            severity: problem_severity("SCAN_ERROR"),
+            details: Some(format!(
+                "Could not read {}: {}",
+                self.path.to_string_lossy(),
+                self.message
+            )),
+            suggested_path: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ScanOptions {
+    pub(crate) expected_surveys: Option<HashSet<String>>,
+    pub(crate) expected_counts: Option<HashMap<String, u64>>,
+    pub(crate) count_tolerance_pct: f64,
+    pub(crate) resolve_duplicates: DuplicateResolution,
+    pub(crate) fuzzy_max_edit_distance: usize,
+    pub(crate) fuzzy_date_tolerance_days: u32,
+    /// `date_from` and `date_to` as `days_from_civil` day numbers.
+    pub(crate) date_from: Option<i64>,
+    pub(crate) date_to: Option<i64>,
+}
+
+pub(crate) fn extract_detected_id(
+    path: &Path,
+    regex: &Regex,
+    norm: &KeyNormalization,
+) -> Option<String> {
+    let path_str = path.to_string_lossy();
+    let path_str = norm.prepare(&path_str);
+    regex
+        .captures_iter(&path_str)
+        .last()
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str().to_string())
+}
+
+/// Base key of the survey id in a filename, using the detected regex first
+/// and the base regex directly as a fallback.
+fn filename_base_key(name: &str, rules: &CompiledRules) -> Option<String> {
+    rules
+        .detected_re
+        .captures_iter(&rules.key_norm.prepare(name))
+        .last()
+        .and_then(|captures| captures.get(1))
+        .and_then(|detected| extract_base_key(detected.as_str(), &rules.base_re, &rules.key_norm))
+        .or_else(|| extract_base_key(name, &rules.base_re, &rules.key_norm))
+}
+
+pub(crate) fn extract_base_key(
+    value: &str,
+    regex: &Regex,
+    norm: &KeyNormalization,
+) -> Option<String> {
+    let value = norm.prepare(value);
+    regex
+        .captures_iter(&value)
+        .last()
+        .and_then(|captures| captures.get(1))
+        .map(|m| norm.apply(m.as_str()))
+}
+
+/// Pairs the survey folders found below all raw roots with those below all
+/// graded roots. A survey found under two roots of the same side is reported
+/// like any other duplicate folder.
+pub(crate) fn scan_roots(
+    raw_roots: &[PathBuf],
+    graded_roots: &[PathBuf],
+    rules: &CompiledRules,
+    options: &ScanOptions,
+) -> Result<ScanResult, AppError> {
+    let mut problems = Vec::new();
+    let mut raw_map: HashMap<String, Vec<SurveyFolder>> = HashMap::new();
+    for raw_root in raw_roots {
+        let mut errors = Vec::new();
+        for (base_key, folders) in
+            discover_surveys(raw_root, rules, rules.split_combo_raw, false, &mut errors)?
+        {
+            raw_map.entry(base_key).or_default().extend(folders);
+        }
+        problems.extend(
+            errors
+                .iter()
+                .map(|error| error.problem("", Some(raw_root), None)),
+        );
+    }
+    let mut graded_map: HashMap<String, Vec<SurveyFolder>> = HashMap::new();
+    for graded_root in graded_roots {
+        let mut errors = Vec::new();
+        for (base_key, folders) in discover_surveys(
+            graded_root,
+            rules,
+            rules.split_combo_graded,
+            rules.scan_archives,
+            &mut errors,
+        )? {
+            graded_map.entry(base_key).or_default().extend(folders);
+        }
+        problems.extend(
+            errors
+                .iter()
+                .map(|error| error.problem("", None, Some(graded_root))),
+        );
+    }
+    raw_map.retain(|base_key, _| in_date_range(base_key, rules, options));
+    graded_map.retain(|base_key, _| in_date_range(base_key, rules, options));
+    let mut expected_surveys = options.expected_surveys.clone();
+    let mut expected_counts = options.expected_counts.clone();
+    if rules.key_norm.case == KeyCase::Preserve {
+        let spellings = key_spellings(&raw_map, &graded_map);
+        raw_map = respell_keys(raw_map, &spellings);
+        graded_map = respell_keys(graded_map, &spellings);
+        expected_surveys = expected_surveys.map(|expected| {
+            expected
+                .into_iter()
+                .map(|key| respell_key(key, &spellings))
+                .collect()
+        });
+        expected_counts = expected_counts.map(|counts| {
+            counts
+                .into_iter()
+                .map(|(key, count)| (respell_key(key, &spellings), count))
+                .collect()
+        });
+    }
+    let fuzzy_pairs = fuzzy_key_pairs(&raw_map, &graded_map, rules, options);
+    for (raw_key, (graded_key, _)) in &fuzzy_pairs {
+        if let Some(folders) = graded_map.remove(graded_key) {
+            graded_map.insert(raw_key.clone(), folders);
+        }
+    }
+
+    // Sorted so that problems are listed in the same order on every run.
+    let mut base_keys: BTreeSet<String> = raw_map.keys().cloned().collect();
+    base_keys.extend(graded_map.keys().cloned());
+
+    let mut entries = Vec::new();
+    let mut preview = Vec::new();
+
+    if let Some(expected) = expected_surveys.as_ref() {
+        let mut missing = expected
+            .iter()
+            .filter(|base_key| !base_keys.contains(*base_key))
+            .filter(|base_key| in_date_range(base_key, rules, options))
+            .collect::<Vec<_>>();
+        missing.sort();
+        for base_key in missing {
+            problems.push(ProblemItem {
+                survey_id_base: base_key.clone(),
+                survey_id_detected: None,
+                raw_path: None,
+                graded_path: None,
+                problem_type: "EXPECTED_MISSING".to_string(),
+                severity: problem_severity("EXPECTED_MISSING"),
+                details: Some("Listed as expected but no raw or graded folder found.".to_string()),
+                suggested_path: None,
+            });
+            preview.push(PreviewItem {
+                base_key: base_key.clone(),
+                raw_path: None,
+                graded_path: None,
+                status: "PROBLEM".to_string(),
+                problem_type: Some("EXPECTED_MISSING".to_string()),
+                severity: Some(problem_severity("EXPECTED_MISSING")),
+                details: Some("Listed as expected but no raw or graded folder found.".to_string()),
+                raw_image_count: None,
+                graded_image_count: None,
+                survey_id_raw_detected: None,
+                survey_id_graded_detected: None,
+            });
+        }
+    }
+
+    for base_key in base_keys {
+        let raw_list = raw_map.get(&base_key).cloned().unwrap_or_default();
+        let graded_list = graded_map.get(&base_key).cloned().unwrap_or_default();
+
+        let raw_missing = raw_list.is_empty();
+        let graded_missing = graded_list.is_empty();
+
+        let (raw, raw_problem, raw_decided_by) = select_unique(
+            &base_key,
+            &raw_list,
+            "DUPLICATE_RAW",
+            rules,
+            options.resolve_duplicates,
+        );
+        let (graded, graded_problem, graded_decided_by) = select_unique(
+            &base_key,
+            &graded_list,
+            "DUPLICATE_GRADED",
+            rules,
+            options.resolve_duplicates,
+        );
+
+        if let Some(problem) = raw_problem.as_ref() {
+            problems.push(problem.clone());
+        }
+        if let Some(problem) = graded_problem.as_ref() {
+            problems.push(problem.clone());
+        }
+        if let Some((graded_key, reason)) = fuzzy_pairs.get(&base_key) {
+            problems.push(ProblemItem {
+                survey_id_base: base_key.clone(),
+                survey_id_detected: raw.as_ref().and_then(|folder| folder.detected_id.clone()),
+                raw_path: raw
+                    .as_ref()
+                    .map(|folder| folder.path.to_string_lossy().to_string()),
+                graded_path: graded
+                    .as_ref()
+                    .map(|folder| folder.path.to_string_lossy().to_string()),
+                problem_type: "FUZZY_MATCH".to_string(),
+                severity: problem_severity("FUZZY_MATCH"),
+                details: Some(format!(
+                    "Paired with graded survey {}: {}.",
+                    graded_key, reason
+                )),
+                suggested_path: None,
+            });
+        }
+
+        let mut status = "OK".to_string();
+        let mut problem_type = None;
+        let mut details = None;
+
+        if raw_missing {
+            status = "PROBLEM".to_string();
+            problem_type = Some("RAW_MISSING".to_string());
+            details = Some("No raw survey folder found.".to_string());
+            problems.push(ProblemItem {
+                survey_id_base: base_key.clone(),
+                survey_id_detected: graded
+                    .as_ref()
+                    .and_then(|folder| folder.detected_id.clone()),
+                raw_path: None,
+                graded_path: graded
+                    .as_ref()
+                    .map(|folder| folder.path.to_string_lossy().to_string()),
+                problem_type: "RAW_MISSING".to_string(),
+                severity: problem_severity("RAW_MISSING"),
+                details: None,
+                suggested_path: None,
+            });
+        }
+
+        if graded_missing {
+            status = "PROBLEM".to_string();
+            problem_type = Some("GRADED_MISSING".to_string());
+            details = Some("No graded survey folder found.".to_string());
+            problems.push(ProblemItem {
+                survey_id_base: base_key.clone(),
+                survey_id_detected: raw.as_ref().and_then(|folder| folder.detected_id.clone()),
+                raw_path: raw
+                    .as_ref()
+                    .map(|folder| folder.path.to_string_lossy().to_string()),
+                graded_path: None,
+                problem_type: "GRADED_MISSING".to_string(),
+                severity: problem_severity("GRADED_MISSING"),
+                details: None,
+                suggested_path: None,
+            });
+        }
+
+        let unresolved = [(&raw, &raw_problem), (&graded, &graded_problem)]
+            .into_iter()
+            .find_map(|(folder, problem)| problem.as_ref().filter(|_| folder.is_none()));
+        if let Some(problem) = unresolved {
+            status = "PROBLEM".to_string();
+            if problem_type.is_none() {
+                problem_type = Some(problem.problem_type.clone());
+                details = problem.details.clone();
+            }
+        }
+
+        let unexpected = expected_surveys
+            .as_ref()
+            .is_some_and(|expected| !expected.contains(&base_key));
+        if unexpected {
+            problems.push(ProblemItem {
+                survey_id_base: base_key.clone(),
+                survey_id_detected: raw
+                    .as_ref()
+                    .or(graded.as_ref())
+                    .and_then(|folder| folder.detected_id.clone()),
+                raw_path: raw
+                    .as_ref()
+                    .map(|folder| folder.path.to_string_lossy().to_string()),
+                graded_path: graded
+                    .as_ref()
+                    .map(|folder| folder.path.to_string_lossy().to_string()),
+                problem_type: "UNEXPECTED_SURVEY".to_string(),
+                severity: problem_severity("UNEXPECTED_SURVEY"),
+                details: Some("Survey is not on the expected list.".to_string()),
+                suggested_path: None,
+            });
+            if problem_type.is_none() {
+                problem_type = Some("UNEXPECTED_SURVEY".to_string());
+                details = Some("Survey is not on the expected list.".to_string());
+            }
+        }
+
+        let mut raw_count = None;
+        if let (Some(expected_count), Some(folder)) = (
+            expected_counts
+                .as_ref()
+                .and_then(|counts| counts.get(&base_key)),
+            raw.as_ref(),
+        ) {
+            let actual = count_images(&folder.path, rules)?;
+            raw_count = Some(actual);
+            if count_deviates(*expected_count, actual, options.count_tolerance_pct) {
+                let detail = format!("Expected {} raw images, found {}.", expected_count, actual);
+                problems.push(ProblemItem {
+                    survey_id_base: base_key.clone(),
+                    survey_id_detected: folder.detected_id.clone(),
+                    raw_path: Some(folder.path.to_string_lossy().to_string()),
+                    graded_path: graded
+                        .as_ref()
+                        .map(|folder| folder.path.to_string_lossy().to_string()),
+                    problem_type: "COUNT_DEVIATION".to_string(),
+                    severity: problem_severity("COUNT_DEVIATION"),
+                    details: Some(detail.clone()),
+                    suggested_path: None,
+                });
+                if problem_type.is_none() {
+                    problem_type = Some("COUNT_DEVIATION".to_string());
+                    details = Some(detail);
+                }
+            }
+        }
+
+        let preview_item = PreviewItem {
+            base_key: base_key.clone(),
+            raw_path: raw
+                .as_ref()
+                .map(|folder| folder.path.to_string_lossy().to_string()),
+            graded_path: graded
+                .as_ref()
+                .map(|folder| folder.path.to_string_lossy().to_string()),
+            status: status.clone(),
+            problem_type: problem_type.clone(),
+            severity: problem_type.as_deref().map(problem_severity),
+            details: details.clone(),
+            raw_image_count: raw_count,
+            graded_image_count: None,
+            survey_id_raw_detected: raw.as_ref().and_then(|folder| folder.detected_id.clone()),
+            survey_id_graded_detected: graded
+                .as_ref()
+                .and_then(|folder| folder.detected_id.clone()),
+        };
+
+        preview.push(preview_item);
+        entries.push(ScanEntry {
+            base_key,
+            raw,
+            graded,
+            status,
+            raw_candidates: raw_list,
+            graded_candidates: graded_list,
+            raw_decided_by,
+            graded_decided_by,
+        });
+    }
+
+    preview.sort_by(|a, b| a.base_key.cmp(&b.base_key));
+    entries.sort_by(|a, b| a.base_key.cmp(&b.base_key));
+
+    Ok(ScanResult {
+        entries,
+        problems,
+        preview,
+    })
+}
+
+pub(crate) fn load_scan_options(
+    settings: &ScanSettings,
+    rules: &CompiledRules,
+) -> Result<ScanOptions, AppError> {
+    let date_from = parse_date_bound("date_from", settings.date_from.as_deref())?;
+    let date_to = parse_date_bound("date_to", settings.date_to.as_deref())?;
+    if let (Some(from), Some(to)) = (date_from, date_to) {
+        if from > to {
+            return Err(AppError::Validation(
+                "date_from must not be after date_to.".to_string(),
+            ));
+        }
+    }
+    Ok(ScanOptions {
+        expected_surveys: load_expected_surveys(settings.expected_surveys_path.as_deref(), rules)?,
+        expected_counts: load_expected_counts(settings.expected_counts_path.as_deref(), rules)?,
+        count_tolerance_pct: settings.count_tolerance_pct.max(0.0),
+        resolve_duplicates: settings.resolve_duplicates,
+        fuzzy_max_edit_distance: settings.fuzzy_max_edit_distance,
+        fuzzy_date_tolerance_days: settings.fuzzy_date_tolerance_days,
+        date_from,
+        date_to,
+    })
+}
+
+/// A `date_from`/`date_to` bound as a day number; `YYYYMMDD` works as well.
+fn parse_date_bound(name: &str, value: Option<&str>) -> Result<Option<i64>, AppError> {
+    let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    let normalized = if value.len() == 8 && value.chars().all(|ch| ch.is_ascii_digit()) {
+        format!("{}-{}-{}", &value[..4], &value[4..6], &value[6..])
+    } else {
+        value.to_string()
+    };
+    survey_day(&normalized).map(Some).ok_or_else(|| {
+        AppError::Validation(format!(
+            "{} must be a date like 2024-06-01, got '{}'.",
+            name, value
+        ))
+    })
+}
+
+/// Whether the `survey_date` of `base_key` lies within the scan's date range.
+/// Keys kept in their found casing still name one survey when they differ
+/// only in case. Each lowercase key maps to the spelling used for it: the
+/// first raw key in sort order, else the first graded one.
+fn key_spellings(
+    raw_map: &HashMap<String, Vec<SurveyFolder>>,
+    graded_map: &HashMap<String, Vec<SurveyFolder>>,
+) -> HashMap<String, String> {
+    let mut raw_keys = raw_map.keys().collect::<Vec<_>>();
+    raw_keys.sort();
+    let mut graded_keys = graded_map.keys().collect::<Vec<_>>();
+    graded_keys.sort();
+    let mut spellings = HashMap::new();
+    for key in raw_keys.into_iter().chain(graded_keys) {
+        spellings
+            .entry(key.to_lowercase())
+            .or_insert_with(|| key.clone());
+    }
+    spellings
+}
+
+fn respell_key(key: String, spellings: &HashMap<String, String>) -> String {
+    spellings.get(&key.to_lowercase()).cloned().unwrap_or(key)
+}
+
+/// Merges the folders of keys that share a spelling, in key order.
+fn respell_keys(
+    map: HashMap<String, Vec<SurveyFolder>>,
+    spellings: &HashMap<String, String>,
+) -> HashMap<String, Vec<SurveyFolder>> {
+    let mut entries = map.into_iter().collect::<Vec<_>>();
+    entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+    let mut respelled: HashMap<String, Vec<SurveyFolder>> = HashMap::new();
+    for (key, folders) in entries {
+        respelled
+            .entry(respell_key(key, spellings))
+            .or_default()
+            .extend(folders);
+    }
+    respelled
+}
+
+fn in_date_range(base_key: &str, rules: &CompiledRules, options: &ScanOptions) -> bool {
+    if options.date_from.is_none() && options.date_to.is_none() {
+        return true;
+    }
+    let Some(day) = survey_fields(base_key, rules)
+        .0
+        .as_deref()
+        .and_then(survey_day)
+    else {
+        return false;
+    };
+    options.date_from.is_none_or(|from| day >= from) && options.date_to.is_none_or(|to| day <= to)
+}
+
+/// Reads a list or CSV of expected survey ids. Every cell is matched against the
+/// base-key regex, so header rows and extra columns are ignored.
+fn load_expected_surveys(
+    path: Option<&str>,
+    rules: &CompiledRules,
+) -> Result<Option<HashSet<String>>, AppError> {
+    let Some(path) = path.filter(|path| !path.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let data = fs::read_to_string(path)?;
+    Ok(Some(parse_expected_surveys(&data, rules)))
+}
+
+pub(crate) fn parse_expected_surveys(data: &str, rules: &CompiledRules) -> HashSet<String> {
+    data.lines()
+        .filter_map(|line| {
+            line.split([',', ';', '\t']).find_map(|cell| {
+                extract_base_key(
+                    cell.trim().trim_matches('"'),
+                    &rules.base_re,
+                    &rules.key_norm,
+                )
+            })
+        })
+        .collect()
+}
+
+fn load_expected_counts(
+    path: Option<&str>,
+    rules: &CompiledRules,
+) -> Result<Option<HashMap<String, u64>>, AppError> {
+    let Some(path) = path.filter(|path| !path.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let data = fs::read_to_string(path)?;
+    Ok(Some(parse_expected_counts(&data, rules)))
+}
+
+/// Parses `survey id, count` rows; the count is the first integer cell after
+/// the cell holding the survey id.
+pub(crate) fn parse_expected_counts(data: &str, rules: &CompiledRules) -> HashMap<String, u64> {
+    let mut counts = HashMap::new();
+    for line in data.lines() {
+        let cells: Vec<&str> = line
+            .split([',', ';', '\t'])
+            .map(|cell| cell.trim().trim_matches('"'))
+            .collect();
+        let Some((index, base_key)) = cells.iter().enumerate().find_map(|(index, cell)| {
+            extract_base_key(cell, &rules.base_re, &rules.key_norm).map(|key| (index, key))
+        }) else {
+            continue;
+        };
+        if let Some(count) = cells[index + 1..]
+            .iter()
+            .find_map(|cell| cell.parse::<u64>().ok())
+        {
+            counts.insert(base_key, count);
+        }
+    }
+    counts
+}
+
+pub(crate) fn count_deviates(expected: u64, actual: u64, tolerance_pct: f64) -> bool {
+    let allowed = expected as f64 * tolerance_pct / 100.0;
+    (actual as f64 - expected as f64).abs() > allowed
+}
+
+/// Default severity of a problem type: errors are structural conflicts that
+/// need fixing, warnings mean data is missing or suspicious, info is FYI.
+/// `include_base_keys` and `exclude_base_keys` of a root run as
+/// case-insensitive wildcard patterns.
+pub(crate) struct BaseKeyFilter {
+    include: Vec<(String, Regex)>,
+    exclude: Vec<Regex>,
+}
+
+impl BaseKeyFilter {
+    pub(crate) fn new(include: &[String], exclude: &[String]) -> Result<Self, AppError> {
+        let pattern = |glob: &String| -> Result<Regex, AppError> {
+            let glob = glob.trim();
+            if glob.is_empty() {
+                return Err(AppError::Validation(
+                    "Base key filters must not be empty.".to_string(),
+                ));
+            }
+            let escaped = regex::escape(glob).replace("\\*", ".*").replace("\\?", ".");
+            Ok(Regex::new(&format!("(?i)^{}$", escaped))?)
+        };
+        Ok(Self {
+            include: include
+                .iter()
+                .map(|glob| Ok((glob.trim().to_string(), pattern(glob)?)))
+                .collect::<Result<_, AppError>>()?,
+            exclude: exclude.iter().map(pattern).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub(crate) fn matches(&self, base_key: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|(_, re)| re.is_match(base_key)))
+            && !self.exclude.iter().any(|re| re.is_match(base_key))
+    }
+
+    /// Drops the entries and survey problems of filtered-out base keys and
+    /// reports include patterns that matched no survey as `FILTER_NO_MATCH`.
+    pub(crate) fn apply(&self, mut scan: ScanResult) -> ScanResult {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return scan;
+        }
+        for (glob, re) in &self.include {
+            if !scan
+                .entries
+                .iter()
+                .any(|entry| re.is_match(&entry.base_key))
+            {
+                scan.problems.push(ProblemItem {
+                    survey_id_base: glob.clone(),
+                    survey_id_detected: None,
+                    raw_path: None,
+                    graded_path: None,
+                    problem_type: "FILTER_NO_MATCH".to_string(),
+                    severity: problem_severity("FILTER_NO_MATCH"),
+                    details: Some(format!(
+                        "No survey below the roots matches the include filter '{}'.",
+                        glob
+                    )),
+                    suggested_path: None,
+                });
+            }
+        }
+        scan.entries.retain(|entry| self.matches(&entry.base_key));
+        scan.preview.retain(|item| self.matches(&item.base_key));
+        scan.problems.retain(|problem| {
+            problem.survey_id_base.is_empty()
+                || problem.problem_type == "FILTER_NO_MATCH"
+                || self.matches(&problem.survey_id_base)
+        });
+        scan
+    }
+}
+
+/// Pairs base keys found only under the raw root with keys found only under
+/// the graded root that are near misses, keyed by the raw key with the graded
+/// key and the reason. Closest pairs are taken first; keys with several
+/// equally close partners are left unpaired.
+fn fuzzy_key_pairs(
+    raw_map: &HashMap<String, Vec<SurveyFolder>>,
+    graded_map: &HashMap<String, Vec<SurveyFolder>>,
+    rules: &CompiledRules,
+    options: &ScanOptions,
+) -> HashMap<String, (String, String)> {
+    let mut pairs = HashMap::new();
+    if options.fuzzy_max_edit_distance == 0 && options.fuzzy_date_tolerance_days == 0 {
+        return pairs;
+    }
+    let mut raw_only = raw_map
+        .keys()
+        .filter(|key| !graded_map.contains_key(*key))
+        .collect::<Vec<_>>();
+    let mut graded_only = graded_map
+        .keys()
+        .filter(|key| !raw_map.contains_key(*key))
+        .collect::<Vec<_>>();
+    raw_only.sort();
+    graded_only.sort();
+
+    let mut candidates = Vec::new();
+    for raw_key in &raw_only {
+        for graded_key in &graded_only {
+            let edits = levenshtein(raw_key, graded_key);
+            let by_edits = (options.fuzzy_max_edit_distance > 0
+                && edits <= options.fuzzy_max_edit_distance)
+                .then(|| (edits, format!("base keys {} edit(s) apart", edits)));
+            let by_date = key_days_apart(raw_key, graded_key, rules)
+                .filter(|days| {
+                    options.fuzzy_date_tolerance_days > 0
+                        && *days <= u64::from(options.fuzzy_date_tolerance_days)
+                })
+                .map(|days| (days as usize, format!("survey dates {} day(s) apart", days)));
+            let best = [by_edits, by_date]
+                .into_iter()
+                .flatten()
+                .min_by_key(|(cost, _)| *cost);
+            if let Some((cost, reason)) = best {
+                candidates.push((cost, (*raw_key).clone(), (*graded_key).clone(), reason));
+            }
+        }
+    }
+    candidates.sort();
+
+    let mut used = HashSet::new();
+    for (cost, raw_key, graded_key, reason) in &candidates {
+        if used.contains(raw_key) || used.contains(graded_key) {
+            continue;
+        }
+        let tied = candidates
+            .iter()
+            .any(|(other_cost, other_raw, other_graded, _)| {
+                other_cost == cost
+                    && (other_raw == raw_key) != (other_graded == graded_key)
+                    && !used.contains(other_raw)
+                    && !used.contains(other_graded)
+            });
+        used.insert(raw_key.clone());
+        used.insert(graded_key.clone());
+        if !tied {
+            pairs.insert(raw_key.clone(), (graded_key.clone(), reason.clone()));
+        }
+    }
+    pairs
+}
+
+/// Days between the `survey_date`s of two base keys with the same `area`.
+fn key_days_apart(a: &str, b: &str, rules: &CompiledRules) -> Option<u64> {
+    let (date_a, area_a) = survey_fields(a, rules);
+    let (date_b, area_b) = survey_fields(b, rules);
+    if area_a?.to_lowercase() != area_b?.to_lowercase() {
+        return None;
+    }
+    Some(survey_day(&date_a?)?.abs_diff(survey_day(&date_b?)?))
+}
+
+/// Day number of a `YYYY-MM-DD` survey date.
+fn survey_day(date: &str) -> Option<i64> {
+    let mut parts = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    (parts.next().is_none() && (1..=12).contains(&month) && (1..=31).contains(&day))
+        .then(|| tracks::days_from_civil(year, month, day))
+}
+
+/// The one folder of a survey, or a duplicate problem naming the folder the
+/// resolver suggests; under `DuplicateResolution::Auto` that folder is used
+/// and the problem is only a warning.
+fn select_unique(
+    base_key: &str,
+    list: &[SurveyFolder],
+    problem_type: &str,
+    rules: &CompiledRules,
+    resolution: DuplicateResolution,
+) -> (
+    Option<SurveyFolder>,
+    Option<ProblemItem>,
+    Option<&'static str>,
+) {
+    if list.len() <= 1 {
+        return (list.first().cloned(), None, None);
+    }
+    let mut detail = list
+        .iter()
+        .map(|item| item.path.to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("; ");
+    let (best, decided_by) = best_duplicate(list, rules);
+    let (selected, severity) = match resolution {
+        DuplicateResolution::Manual => (None, problem_severity(problem_type)),
+        DuplicateResolution::Auto => {
+            detail.push_str(AUTO_RESOLVED_NOTE);
+            (Some(best.clone()), ProblemSeverity::Warning)
+        }
+    };
+    let decided_by = selected.as_ref().map(|_| decided_by);
+    (
+        selected,
+        Some(ProblemItem {
+            survey_id_base: base_key.to_string(),
+            survey_id_detected: list.first().and_then(|folder| folder.detected_id.clone()),
+            raw_path: None,
+            graded_path: None,
+            problem_type: problem_type.to_string(),
+            severity,
+            details: Some(detail),
+            suggested_path: Some(best.path.to_string_lossy().to_string()),
+        }),
+        decided_by,
+    )
+}
+
+/// Scores duplicate folders of one survey: most images first, then the most
+/// complete detected id (e.g. `20240101_AB_CD` over `20240101_AB`), then the
+/// shallowest path; ties go to the first path in sort order. Also returns
+/// the first of these criteria that set the winner apart from the runner-up.
+fn best_duplicate<'a>(
+    list: &'a [SurveyFolder],
+    rules: &CompiledRules,
+) -> (&'a SurveyFolder, &'static str) {
+    let score = |folder: &SurveyFolder| {
+        let images = collect_images(
+            &folder.path,
+            folder.part.as_ref(),
+            rules,
+            None,
+            &mut Vec::new(),
+        )
+        .map(|images| images.len())
+        .unwrap_or_default();
+        let id_len = folder.detected_id.as_ref().map(String::len).unwrap_or(0);
+        let depth = folder.path.components().count();
+        (
+            images,
+            id_len,
+            std::cmp::Reverse(depth),
+            std::cmp::Reverse(folder.path.clone()),
+        )
+    };
+    let mut scored = list
+        .iter()
+        .map(|folder| (score(folder), folder))
+        .collect::<Vec<_>>();
+    scored.sort_by(|a, b| b.0.cmp(&a.0));
+    let (best, best_folder) = &scored[0];
+    let decided_by = match scored.get(1) {
+        Some((runner_up, _)) if best.0 != runner_up.0 => "most images",
+        Some((runner_up, _)) if best.1 != runner_up.1 => "most complete detected id",
+        Some((runner_up, _)) if best.2 != runner_up.2 => "shallowest path",
+        _ => "first path in sort order",
+    };
+    (best_folder, decided_by)
+}
+
+/// Survey folders below `root` by base key. With `archives`, zip and 7z
+/// files carrying a survey id count as survey folders too.
+pub(crate) fn discover_surveys(
+    root: &Path,
+    rules: &CompiledRules,
+    split_combo: bool,
+    archives: bool,
+    errors: &mut Vec<WalkError>,
+) -> Result<HashMap<String, Vec<SurveyFolder>>, AppError> {
+    let mut map: HashMap<String, Vec<SurveyFolder>> = HashMap::new();
+    let mut walker = walk_tree(root, rules);
+    while let Some(entry) = walker.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                errors.push(WalkError::from_walkdir(root, &err));
+                continue;
+            }
+        };
+        let path: &Path = &short_path(entry.path());
+        let is_archive = archives && entry.file_type().is_file() && archives::is_archive(path);
+        if !entry.file_type().is_dir() && !is_archive {
+            continue;
+        }
+        if entry.depth() > 0 && is_excluded_dir(root, path, rules) {
+            walker.skip_current_dir();
+            continue;
+        }
+        let detected_id = extract_detected_id(path, &rules.detected_re, &rules.key_norm);
+        let base_key = detected_id
+            .as_ref()
+            .and_then(|detected| extract_base_key(detected, &rules.base_re, &rules.key_norm))
+            .or_else(|| {
+                let path_str = path.to_string_lossy();
+                extract_base_key(&path_str, &rules.base_re, &rules.key_norm)
+            });
+        if let Some(base_key) = base_key {
+            let part_keys = if split_combo && !is_archive {
+                combo_survey_keys(path, &base_key, rules)
+            } else {
+                Vec::new()
+            };
+            if part_keys.len() > 1 {
+                for part_key in part_keys {
+                    map.entry(part_key.clone()).or_default().push(SurveyFolder {
+                        path: path.to_path_buf(),
+                        detected_id: Some(part_key.clone()),
+                        part: Some(SurveyPart {
+                            base_key: part_key,
+                            folder_key: base_key.clone(),
+                        }),
+                    });
+                }
+            } else {
+                map.entry(base_key).or_default().push(SurveyFolder {
+                    path: path.to_path_buf(),
+                    detected_id,
+                    part: None,
+                });
+            }
+            if !is_archive {
+                walker.skip_current_dir();
+            }
+        }
+    }
+    Ok(map)
+}
+
+/// Survey keys found in the image filenames below `folder`, sorted. Images
+/// without an id count towards `folder_key`.
+fn combo_survey_keys(folder: &Path, folder_key: &str, rules: &CompiledRules) -> Vec<String> {
+    let mut keys = HashSet::new();
+    for entry in walk_tree(folder, rules).filter_map(Result::ok) {
+        if !entry.file_type().is_file() || !is_supported_image(entry.path(), rules) {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy();
+        keys.insert(filename_base_key(&name, rules).unwrap_or_else(|| folder_key.to_string()));
+    }
+    let mut keys: Vec<String> = keys.into_iter().collect();
+    keys.sort();
+    keys
+}
+
+/// Walks `root` while pruning directories that hold a previous run's output,
+/// down to the rules' `max_scan_depth` and into symlinks if they say so.
+pub(crate) fn walk_tree(
+    root: &Path,
+    rules: &CompiledRules,
+) -> FilterEntry<walkdir::IntoIter, impl FnMut(&DirEntry) -> bool> {
+    let mut walker = WalkDir::new(long_path(root)).follow_links(rules.follow_symlinks);
+    if let Some(depth) = rules.max_scan_depth {
+        walker = walker.max_depth(depth);
+    }
+    walker
+        .into_iter()
+        .filter_entry(|entry| !is_output_dir(entry))
+}
+
+pub(crate) fn count_images(root: &Path, rules: &CompiledRules) -> Result<u64, AppError> {
+    if archives::is_archive(root) && root.is_file() {
+        return Ok(archive_images(root, false, None, rules)?.len() as u64);
+    }
+    let mut images = Vec::new();
+    let mut walker = walk_tree(root, rules);
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if entry.file_type().is_dir()
+            && entry.depth() > 0
+            && is_excluded_dir(root, &short_path(entry.path()), rules)
+        {
+            walker.skip_current_dir();
+        } else if entry.file_type().is_file()
+            && (is_supported_image(entry.path(), rules)
+                || rules.video_mode && is_video(entry.path(), rules))
+        {
+            images.push(entry.into_path());
+        }
+    }
+    drop_shadowed_camera_raws(&mut images, rules);
+    Ok(images.len() as u64)
+}
+
+/// Counts the images of every item in place, in parallel.
+pub(crate) fn fill_preview_counts(rules: &CompiledRules, items: &mut [PreviewItem]) {
+    let counts = Mutex::new(Vec::new());
+    count_preview_items(rules, items, |index, event| {
+        if let Ok(mut counts) = counts.lock() {
+            counts.push((index, event));
+        }
+        true
+    });
+    for (index, event) in counts.into_inner().unwrap_or_default() {
+        items[index].raw_image_count = event.raw_image_count;
+        items[index].graded_image_count = event.graded_image_count;
+    }
+}
+
+/// Counts in the background and sends each count to `sink` while `ticket` is
+/// current; once a newer preview advanced `generation`, the remaining items
+/// are left uncounted and a count already made is dropped.
+pub(crate) fn spawn_preview_counts(
+    sink: Arc<dyn ProgressSink>,
+    generation: Generation,
+    ticket: u64,
+    rules: Arc<CompiledRules>,
+    items: Vec<PreviewItem>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        count_preview_items(&rules, &items, |_, event| {
+            let current = generation.is_current(ticket);
+            if current {
+                sink.counts_updated(event);
+            }
+            current
+        });
+    })
+}
+
+/// Counts the images of every preview item on a small worker pool and hands
+/// each result with the item's index to `on_count`, until it returns false.
+fn count_preview_items(
+    rules: &CompiledRules,
+    items: &[PreviewItem],
+    on_count: impl Fn(usize, CountUpdateEvent) -> bool + Sync,
+) {
+    let next = AtomicUsize::new(0);
+    let workers = std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+        .clamp(1, PREVIEW_COUNT_WORKERS);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let count_folder = |path: &Option<String>| {
+                    path.as_ref()
+                        .and_then(|path| count_images(Path::new(path), rules).ok())
+                };
+                let wanted = on_count(
+                    index,
+                    CountUpdateEvent {
+                        base_key: item.base_key.clone(),
+                        raw_image_count: count_folder(&item.raw_path),
+                        graded_image_count: count_folder(&item.graded_path),
+                    },
+                );
+                if !wanted {
+                    break;
+                }
+            });
+        }
+    });
+}
+
+/// Whether `dir`, relative to the walked `root`, matches one of the rules'
+/// `exclude_dir_globs`.
+pub(crate) fn is_excluded_dir(root: &Path, dir: &Path, rules: &CompiledRules) -> bool {
+    if rules.excluded_dirs.is_empty() {
+        return false;
+    }
+    let path = format!("{}/", normalize_relpath(dir, root));
+    rules.excluded_dirs.iter().any(|re| re.is_match(&path))
+}
+
+pub(crate) fn is_supported_image(path: &Path, rules: &CompiledRules) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            let ext = format!(".{}", ext.to_lowercase());
+            rules.extensions.contains(&ext) || rules.camera_raw_extensions.contains(&ext)
+        })
+        .unwrap_or(false)
+}
+
+fn is_camera_raw(path: &Path, rules: &CompiledRules) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            rules
+                .camera_raw_extensions
+                .contains(&format!(".{}", ext.to_lowercase()))
+        })
+}
+
+/// Drops camera RAW files that have a sibling of the same stem in another
+/// supported format; the sibling stands for the picture.
+pub(crate) fn drop_shadowed_camera_raws<T: AsRef<Path>>(files: &mut Vec<T>, rules: &CompiledRules) {
+    if rules.camera_raw_extensions.is_empty() {
+        return;
+    }
+    let key = |path: &Path| {
+        (
+            path.parent().map(Path::to_path_buf),
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_lowercase()),
+        )
+    };
+    let developed: HashSet<_> = files
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|path| !is_camera_raw(path, rules))
+        .map(key)
+        .collect();
+    if developed.is_empty() {
+        return;
+    }
+    files.retain(|file| {
+        let path = file.as_ref();
+        !is_camera_raw(path, rules) || !developed.contains(&key(path))
+    });
+}
+
+pub(crate) fn collect_images(
+    root: &Path,
+    part: Option<&SurveyPart>,
+    rules: &CompiledRules,
+    cache: Option<&ScanCache>,
+    errors: &mut Vec<WalkError>,
+) -> Result<Vec<PathBuf>, AppError> {
+    let files = collect_listed_images(root, part, rules, cache, errors)?;
+    Ok(files.into_iter().map(|file| file.path).collect())
+}
+
+/// `collect_images` keeping what the listing knew about each file.
+pub(crate) fn collect_listed_images(
+    root: &Path,
+    part: Option<&SurveyPart>,
+    rules: &CompiledRules,
+    cache: Option<&ScanCache>,
+    errors: &mut Vec<WalkError>,
+) -> Result<Vec<ListedFile>, AppError> {
+    let excluded = |dir: &Path| is_excluded_dir(root, dir, rules);
+    let cancel = CancelToken::default();
+    let mut files = list_tree(root, rules, cache, excluded, &cancel, errors)?;
+    files.retain(|file| {
+        is_supported_image(&file.path, rules)
+            && part.is_none_or(|part| part.contains(&file.path, rules))
+    });
+    drop_shadowed_camera_raws(&mut files, rules);
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Size and modification time of a file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct FileStat {
+    pub(crate) size: u64,
+    mtime_ms: u64,
+}
+
+impl FileStat {
+    pub(crate) fn of(metadata: &fs::Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime_ms: mtime_millis(metadata),
+        }
+    }
+}
+
+/// A file found by `list_tree`, with its stat when the listing had it for
+/// free: WalkDir entries carry their metadata on Windows, where the
+/// directory enumeration returns it.
+#[derive(Clone, Debug)]
+pub(crate) struct ListedFile {
+    pub(crate) path: PathBuf,
+    pub(crate) stat: Option<FileStat>,
+}
+
+impl AsRef<Path> for ListedFile {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Paths of the files below `root`; see `list_tree`.
+pub(crate) fn tree_files(
+    root: &Path,
+    rules: &CompiledRules,
+    cache: Option<&ScanCache>,
+    prune: impl Fn(&Path) -> bool,
+    cancel: &CancelToken,
+    errors: &mut Vec<WalkError>,
+) -> Result<Vec<PathBuf>, AppError> {
+    let files = list_tree(root, rules, cache, prune, cancel, errors)?;
+    Ok(files.into_iter().map(|file| file.path).collect())
+}
+
+/// Files below `root`, skipping output folders and the subfolders `prune`
+/// matches. Directory listings come from `cache` when one is given; folders
+/// that cannot be read end up in `errors`.
+pub(crate) fn list_tree(
+    root: &Path,
+    rules: &CompiledRules,
+    cache: Option<&ScanCache>,
+    prune: impl Fn(&Path) -> bool,
+    cancel: &CancelToken,
+    errors: &mut Vec<WalkError>,
+) -> Result<Vec<ListedFile>, AppError> {
+    let mut files = Vec::new();
+    let Some(cache) = cache else {
+        let mut walker = walk_tree(root, rules);
+        while let Some(entry) = walker.next() {
+            cancel.check()?;
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    errors.push(WalkError::from_walkdir(root, &err));
+                    continue;
+                }
+            };
+            let path = short_path(entry.path());
+            if entry.file_type().is_dir() && entry.depth() > 0 && prune(&path) {
+                walker.skip_current_dir();
+            } else if entry.file_type().is_file() {
+                // Elsewhere `metadata` is one more stat; `listed_file_ids`
+                // batches those.
+                let stat = match cfg!(windows) && !entry.path_is_symlink() {
+                    true => entry
+                        .metadata()
+                        .ok()
+                        .map(|metadata| FileStat::of(&metadata)),
+                    false => None,
+                };
+                files.push(ListedFile {
+                    path: path.into_owned(),
+                    stat,
+                });
+            }
+        }
+        return Ok(files);
+    };
+    // Canonical folders already listed, so symlink loops end the descent.
+    let mut visited = HashSet::new();
+    let mut pending = vec![(root.to_path_buf(), 0usize)];
+    while let Some((dir, depth)) = pending.pop() {
+        cancel.check()?;
+        if rules.max_scan_depth.is_some_and(|max| depth >= max)
+            || dir.join(OUTPUT_MARKER_FILENAME).is_file()
+        {
+            continue;
+        }
+        if rules.follow_symlinks {
+            let canonical = fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone());
+            if !visited.insert(canonical) {
+                errors.push(WalkError::new(&dir, "symlink loop"));
+                continue;
+            }
+        }
+        let (subdirs, dir_files) = match cache.list_dir(&dir, rules.follow_symlinks) {
+            Ok(listing) => listing,
+            Err(err) => {
+                errors.push(WalkError::new(&dir, err));
+                continue;
+            }
+        };
+        files.extend(
+            dir_files
+                .into_iter()
+                .map(|path| ListedFile { path, stat: None }),
+        );
+        pending.extend(
+            subdirs
+                .into_iter()
+                .rev()
+                .filter(|subdir| !prune(subdir))
+                .map(|subdir| (subdir, depth + 1)),
+        );
+    }
+    Ok(files)
+}
+
+pub(crate) fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b_chars.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b_chars.len() + 1];
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let cost = usize::from(a_char != *b_char);
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b_chars.len()]
+}
+
+/// Directory listings and file ids from earlier runs into the same output
+/// folder (`incremental_cache`). A folder whose mtime is unchanged is not
+/// read again and a file whose size and mtime are unchanged is not hashed
+/// again; the whole cache is dropped when the rules change.
+#[derive(Debug)]
+pub(crate) struct ScanCache {
+    path: PathBuf,
+    pub(crate) state: Mutex<ScanCacheState>,
+    /// File ids answered from the cache during this run.
+    pub(crate) hits: AtomicU64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct ScanCacheState {
+    rules_hash: String,
+    pub(crate) dirs: HashMap<String, CachedDir>,
+    files: HashMap<String, CachedFileId>,
+}
+
+/// Entry names of one directory as of `mtime_ms`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct CachedDir {
+    mtime_ms: u64,
+    subdirs: Vec<String>,
+    pub(crate) files: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedFileId {
+    size: u64,
+    mtime_ms: u64,
+    file_id: String,
+    ambiguous: bool,
+}
+
+impl ScanCache {
+    /// The cache stored in `path`; a missing or unreadable cache, or one
+    /// built under other rules, starts out empty.
+    pub(crate) fn load(path: PathBuf, rules_hash: &str) -> Self {
+        let state = fs::read_to_string(&path)
+            .ok()
+            .and_then(|data| serde_json::from_str::<ScanCacheState>(&data).ok())
+            .filter(|state| state.rules_hash == rules_hash)
+            .unwrap_or_else(|| ScanCacheState {
+                rules_hash: rules_hash.to_string(),
+                ..ScanCacheState::default()
+            });
+        Self {
+            path,
+            state: Mutex::new(state),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Subfolders and files of `dir`, sorted by name, counting symlinks as
+    /// what they point to when `follow_symlinks` is set. Read from disk only
+    /// when the folder's mtime differs from the cached listing; files that
+    /// disappeared since then also leave the file id cache.
+    pub(crate) fn list_dir(
+        &self,
+        dir: &Path,
+        follow_symlinks: bool,
+    ) -> Result<(Vec<PathBuf>, Vec<PathBuf>), AppError> {
+        let key = dir.to_string_lossy().to_string();
+        let mtime_ms = mtime_millis(&fs::metadata(long_path(dir))?);
+        let cached = match self.state.lock() {
+            Ok(state) => state.dirs.get(&key).cloned(),
+            Err(_) => None,
+        };
+        let listing = match cached {
+            Some(listing) if listing.mtime_ms == mtime_ms => listing,
+            previous => {
+                let mut listing = CachedDir {
+                    mtime_ms,
+                    subdirs: Vec::new(),
+                    files: Vec::new(),
+                };
+                for entry in fs::read_dir(long_path(dir))? {
+                    let entry = entry?;
+                    let mut file_type = entry.file_type()?;
+                    if follow_symlinks && file_type.is_symlink() {
+                        match fs::metadata(entry.path()) {
+                            Ok(target) => file_type = target.file_type(),
+                            Err(_) => continue,
+                        }
+                    }
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if file_type.is_dir() {
+                        listing.subdirs.push(name);
+                    } else if file_type.is_file() {
+                        listing.files.push(name);
+                    }
+                }
+                listing.subdirs.sort();
+                listing.files.sort();
+                if let Ok(mut state) = self.state.lock() {
+                    for name in previous.map(|previous| previous.files).unwrap_or_default() {
+                        if listing.files.binary_search(&name).is_err() {
+                            state
+                                .files
+                                .remove(&dir.join(&name).to_string_lossy().to_string());
+                        }
+                    }
+                    state.dirs.insert(key, listing.clone());
+                }
+                listing
+            }
+        };
+        let join = |names: &[String]| names.iter().map(|name| dir.join(name)).collect();
+        Ok((join(&listing.subdirs), join(&listing.files)))
+    }
+
+    /// `compute_file_id` for `path`, reused while the file keeps its size and
+    /// mtime. `stat` saves looking them up when the caller has them.
+    pub(crate) fn file_id(
+        &self,
+        path: &Path,
+        stat: Option<FileStat>,
+        rules: &CompiledRules,
+    ) -> (String, bool) {
+        let stat = stat.or_else(|| {
+            fs::metadata(path)
+                .ok()
+                .map(|metadata| FileStat::of(&metadata))
+        });
+        let Some(FileStat { size, mtime_ms }) = stat else {
+            return compute_file_id(path, rules);
+        };
+        let key = path.to_string_lossy().to_string();
+        if let Ok(state) = self.state.lock() {
+            if let Some(cached) = state
+                .files
+                .get(&key)
+                .filter(|cached| cached.size == size && cached.mtime_ms == mtime_ms)
+            {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return (cached.file_id.clone(), cached.ambiguous);
+            }
+        }
+        let (file_id, ambiguous) = file_id_with_size(path, rules, || Some(size));
+        if let Ok(mut state) = self.state.lock() {
+            state.files.insert(
+                key,
+                CachedFileId {
+                    size,
+                    mtime_ms,
+                    file_id: file_id.clone(),
+                    ambiguous,
+                },
+            );
+        }
+        (file_id, ambiguous)
+    }
+
+    pub(crate) fn save(&self) -> Result<(), AppError> {
+        let data = match self.state.lock() {
+            Ok(state) => serde_json::to_string(&*state)?,
+            Err(_) => return Ok(()),
+        };
+        let partial = self.path.with_extension("json.partial");
+        fs::write(&partial, data)?;
+        fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+}
+
+fn mtime_millis(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ScanEntry {
+    pub(crate) base_key: String,
+    pub(crate) raw: Option<SurveyFolder>,
+    pub(crate) graded: Option<SurveyFolder>,
+    pub(crate) status: String,
+    /// Every folder found for the base key, chosen or not.
+    pub(crate) raw_candidates: Vec<SurveyFolder>,
+    pub(crate) graded_candidates: Vec<SurveyFolder>,
+    /// What set the chosen folder apart when duplicates were resolved
+    /// automatically, e.g. `most images`.
+    pub(crate) raw_decided_by: Option<&'static str>,
+    pub(crate) graded_decided_by: Option<&'static str>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ScanResult {
+    pub(crate) entries: Vec<ScanEntry>,
+    pub(crate) problems: Vec<ProblemItem>,
+    pub(crate) preview: Vec<PreviewItem>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    use crate::matching::{
+        build_graded_map, dhash, exif_capture_datetime, read_image_exif, CR3_CANON_UUID,
+    };
+    use crate::output::{mark_output_dir, thumbnail_png};
+    use crate::rules::{
+        compile_rules, default_image_id_regex, dir_glob_regex, KeyCase, MatchingStrategy, Rules,
+    };
+    use crate::runs::SCAN_CACHE_FILENAME;
+    use crate::test_support::{default_compiled, TestTree};
+
+    #[test]
+    fn counts_of_a_replaced_preview_are_dropped() {
+        #[derive(Default)]
+        struct Counts(Mutex<Vec<CountUpdateEvent>>);
+
+        impl ProgressSink for Counts {
+            fn progress(&self, _event: crate::ProgressEvent) {}
+
+            fn counts_updated(&self, event: CountUpdateEvent) {
+                self.0.lock().expect("counts").push(event);
+            }
+        }
+
+        let tree = TestTree::new();
+        for key in ["20240101_AB", "20240102_AB", "20240103_AB"] {
+            tree.write(format!("raw/{key}/IMG_0001.JPG"), "x");
+            tree.write(format!("graded/{key}/IND/IMG_0001.JPG"), "x");
+        }
+        let compiled = Arc::new(compile_rules(&Rules::default()).expect("compile"));
+        let options = load_scan_options(&ScanSettings::default(), &compiled).expect("options");
+        let scan = scan_roots(
+            &[tree.join("raw")],
+            &[tree.join("graded")],
+            &compiled,
+            &options,
+        )
+        .expect("scan");
+        let generation = Generation::default();
+        let sink = Arc::new(Counts::default());
+        let count = |ticket: u64| {
+            spawn_preview_counts(
+                sink.clone(),
+                generation.clone(),
+                ticket,
+                Arc::clone(&compiled),
+                scan.preview.clone(),
+            )
+            .join()
+            .expect("join");
+            std::mem::take(&mut *sink.0.lock().expect("counts"))
+        };
+
+        let current = generation.advance();
+        let counts = count(current);
+        assert_eq!(counts.len(), 3);
+        assert!(counts
+            .iter()
+            .all(|event| event.raw_image_count == Some(1) && event.graded_image_count == Some(1)));
+
+        generation.advance();
+        assert!(count(current).is_empty());
+    }
+
+    #[test]
+    fn extract_detected_and_base() {
+        let rules = Rules {
+            extensions: vec![".jpg".to_string()],
+            survey_id_regex_detected: "(?i)\\b(\\d{8}_[A-Z]{2}(?:_[A-Z]{2})?)\\b".to_string(),
+            survey_id_regex_base: "(?i)\\b(\\d{8}_[A-Z]{2})(?:_[A-Z]{2})?\\b".to_string(),
+            image_id_regex: default_image_id_regex(),
+            graded_priority_ind_regex: "(?i)\\bind".to_string(),
+            graded_priority_secondary_tokens: vec!["best".to_string()],
+            graded_negative_contains_any: vec![],
+            graded_positive_contains_any: vec!["*".to_string()],
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+
+        let path = PathBuf::from("/data/20250101_AB_CD/some");
+        let detected = extract_detected_id(&path, &compiled.detected_re, &compiled.key_norm)
+            .expect("detected");
+        let base =
+            extract_base_key(&detected, &compiled.base_re, &compiled.key_norm).expect("base");
+        assert_eq!(detected, "20250101_AB_CD");
+        assert_eq!(base, "20250101_AB");
+    }
+
+    #[test]
+    fn date_range_limits_the_scan() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        for key in ["20230531_AB", "20230601_AB", "20230930_CD", "20231001_CD"] {
+            fs::create_dir_all(root.join("raw").join(key)).expect("create");
+            fs::create_dir_all(root.join("graded").join(key)).expect("create");
+        }
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+        let options = load_scan_options(
+            &ScanSettings {
+                date_from: Some("2023-06-01".to_string()),
+                date_to: Some("20230930".to_string()),
+                ..ScanSettings::default()
+            },
+            &compiled,
+        )
+        .expect("options");
+        let scan = scan_roots(
+            &[root.join("raw")],
+            &[root.join("graded")],
+            &compiled,
+            &options,
+        )
+        .expect("scan");
+        let mut keys: Vec<&str> = scan
+            .entries
+            .iter()
+            .map(|entry| entry.base_key.as_str())
+            .collect();
+        keys.sort();
+        assert_eq!(keys, vec!["20230601_AB", "20230930_CD"]);
+
+        let invalid = |from: &str, to: &str| {
+            load_scan_options(
+                &ScanSettings {
+                    date_from: Some(from.to_string()),
+                    date_to: Some(to.to_string()),
+                    ..ScanSettings::default()
+                },
+                &compiled,
+            )
+            .is_err()
+        };
+        assert!(invalid("2023-13-01", ""));
+        assert!(invalid("2023-10-01", "2023-06-01"));
+        assert!(!invalid("", "2023-06-01"));
+    }
+
+    #[test]
+    fn fuzzy_matching_pairs_near_miss_keys() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_root = root.join("raw");
+        let graded_root = root.join("graded");
+        for key in ["20230607_AB", "20230531_CD", "20230610_EF"] {
+            fs::create_dir_all(raw_root.join(key)).expect("create");
+        }
+        for key in ["20230606_AB", "20230601_CD", "20230609_EF", "20230611_EF"] {
+            fs::create_dir_all(graded_root.join(key)).expect("create");
+        }
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+        let scan_with = |edits: usize, days: u32| {
+            let scan = scan_roots(
+                std::slice::from_ref(&raw_root),
+                std::slice::from_ref(&graded_root),
+                &compiled,
+                &ScanOptions {
+                    fuzzy_max_edit_distance: edits,
+                    fuzzy_date_tolerance_days: days,
+                    ..ScanOptions::default()
+                },
+            )
+            .expect("scan");
+            let mut paired = scan
+                .entries
+                .iter()
+                .filter(|entry| entry.status == "OK")
+                .map(|entry| entry.base_key.clone())
+                .collect::<Vec<_>>();
+            paired.sort();
+            let fuzzy = scan
+                .problems
+                .iter()
+                .filter(|problem| problem.problem_type == "FUZZY_MATCH")
+                .count();
+            (paired, fuzzy)
+        };
+
+        assert_eq!(scan_with(0, 0), (Vec::<String>::new(), 0));
+        assert_eq!(
+            scan_with(1, 0),
+            (
+                vec!["20230607_AB".to_string(), "20230610_EF".to_string()],
+                2
+            )
+        );
+        assert_eq!(
+            scan_with(0, 1),
+            (
+                vec!["20230531_CD".to_string(), "20230607_AB".to_string()],
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn duplicate_folders_get_a_suggested_path() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let raw_root = root.join("raw");
+        let graded_root = root.join("graded");
+        let full = raw_root.join("cards").join("20240101_AB_CD");
+        let partial = raw_root.join("20240101_AB");
+        for (folder, count) in [(&full, 3), (&partial, 1)] {
+            fs::create_dir_all(folder).expect("create");
+            for index in 0..count {
+                fs::write(folder.join(format!("img_00{}.jpg", index)), "x").expect("write");
+            }
+        }
+        fs::create_dir_all(graded_root.join("20240101_AB")).expect("create");
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+
+        let manual = scan_roots(
+            std::slice::from_ref(&raw_root),
+            std::slice::from_ref(&graded_root),
+            &compiled,
+            &ScanOptions::default(),
+        )
+        .expect("scan");
+        assert_eq!(manual.entries[0].status, "PROBLEM");
+        assert_eq!(manual.problems.len(), 1);
+        assert_eq!(manual.problems[0].severity, ProblemSeverity::Error);
+        let suggested = full.to_string_lossy().to_string();
+        assert_eq!(manual.problems[0].suggested_path, Some(suggested.clone()));
+
+        let auto = scan_roots(
+            std::slice::from_ref(&raw_root),
+            std::slice::from_ref(&graded_root),
+            &compiled,
+            &ScanOptions {
+                resolve_duplicates: DuplicateResolution::Auto,
+                ..ScanOptions::default()
+            },
+        )
+        .expect("scan");
+        assert_eq!(auto.entries[0].status, "OK");
+        assert_eq!(
+            auto.entries[0]
+                .raw
+                .as_ref()
+                .map(|folder| folder.path.clone()),
+            Some(full.clone())
+        );
+        assert_eq!(auto.problems[0].severity, ProblemSeverity::Warning);
+        assert_eq!(auto.problems[0].suggested_path, Some(suggested));
+    }
+
+    #[test]
+    fn expected_surveys_parse_lists_and_csv() {
+        let rules = Rules::default();
+        let compiled = compile_rules(&rules).expect("compile");
+        let data = "survey_id;notes\n20240101_ab_cd;first\n\"20240102_CD\";\nno id here\n";
+        let expected = parse_expected_surveys(data, &compiled);
+        assert_eq!(expected.len(), 2);
+        assert!(expected.contains("20240101_AB"));
+        assert!(expected.contains("20240102_CD"));
+    }
+
+    #[test]
+    fn expected_counts_respect_tolerance() {
+        let rules = Rules::default();
+        let compiled = compile_rules(&rules).expect("compile");
+        let counts = parse_expected_counts("survey,photos\n20240101_AB,200\n", &compiled);
+        assert_eq!(counts.get("20240101_AB"), Some(&200));
+        assert!(!count_deviates(200, 195, 5.0));
+        assert!(count_deviates(200, 189, 5.0));
+        assert!(count_deviates(200, 201, 0.0));
+    }
+
+    #[test]
+    fn unreadable_folders_become_scan_errors() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let missing = root.join("20240101_AB");
+        let rules = compile_rules(&Rules::default()).expect("compile");
+
+        let mut errors = Vec::new();
+        let files = collect_images(&missing, None, &rules, None, &mut errors).expect("collect");
+        assert!(files.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, missing);
+
+        let cache = ScanCache::load(root.join(SCAN_CACHE_FILENAME), "hash");
+        let mut cached_errors = Vec::new();
+        collect_images(&missing, None, &rules, Some(&cache), &mut cached_errors).expect("collect");
+        assert_eq!(cached_errors.len(), 1);
+
+        let problem = errors[0].problem("20240101_AB", Some(&missing), None);
+        assert_eq!(problem.problem_type, "SCAN_ERROR");
+        assert_eq!(problem.severity, ProblemSeverity::Error);
+        assert!(problem
+            .details
+            .as_deref()
+            .is_some_and(|details| details.contains(&*missing.to_string_lossy())));
+    }
+
+    #[test]
+    fn scan_cache_reuses_unchanged_files_and_folders() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let survey = root.join("20240101_AB");
+        fs::create_dir_all(survey.join("sub")).expect("create");
+        fs::write(survey.join("IMG_0001.JPG"), b"one").expect("write");
+        fs::write(survey.join("sub").join("IMG_0002.JPG"), b"two").expect("write");
+        let rules = compile_rules(&Rules {
+            matching_strategy: MatchingStrategy::Checksum,
+            ..Rules::default()
+        })
+        .expect("compile");
+        let cache_path = root.join(SCAN_CACHE_FILENAME);
+
+        let cache = ScanCache::load(cache_path.clone(), "hash");
+        let files =
+            collect_images(&survey, None, &rules, Some(&cache), &mut Vec::new()).expect("collect");
+        assert_eq!(files.len(), 2);
+        let ids: Vec<_> = files
+            .iter()
+            .map(|path| cache.file_id(path, None, &rules))
+            .collect();
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 0);
+        cache.save().expect("save");
+
+        let cache = ScanCache::load(cache_path.clone(), "hash");
+        let again: Vec<_> = files
+            .iter()
+            .map(|path| cache.file_id(path, None, &rules))
+            .collect();
+        assert_eq!(again, ids);
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 2);
+        // A folder with an unchanged mtime is listed from the cache.
+        let key = survey.to_string_lossy().to_string();
+        if let Ok(mut state) = cache.state.lock() {
+            let listing = state.dirs.get_mut(&key).expect("listing");
+            listing.files.push("IMG_0003.JPG".to_string());
+        }
+        let (_, listed) = cache.list_dir(&survey, false).expect("list");
+        assert!(listed.contains(&survey.join("IMG_0003.JPG")));
+
+        fs::write(survey.join("IMG_0001.JPG"), b"changed").expect("write");
+        assert_ne!(cache.file_id(&files[0], None, &rules), ids[0]);
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 2);
+
+        // Other rules start from an empty cache.
+        let other = ScanCache::load(cache_path, "other");
+        other.file_id(&files[1], None, &rules);
+        assert_eq!(other.hits.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn base_key_filters_select_surveys() {
+        let filter = BaseKeyFilter::new(
+            &["20240101_AB".to_string(), "202402*_c?".to_string()],
+            &["20240215_*".to_string()],
+        )
+        .expect("filter");
+        assert!(filter.matches("20240101_AB"));
+        assert!(filter.matches("20240203_CD"));
+        assert!(!filter.matches("20240215_CD"));
+        assert!(!filter.matches("20240102_AB"));
+        assert!(BaseKeyFilter::new(&[" ".to_string()], &[]).is_err());
+
+        let entry = |key: &str| ScanEntry {
+            base_key: key.to_string(),
+            raw: None,
+            graded: None,
+            status: "OK".to_string(),
+            raw_candidates: Vec::new(),
+            graded_candidates: Vec::new(),
+            raw_decided_by: None,
+            graded_decided_by: None,
+        };
+        let scan = ScanResult {
+            entries: vec![entry("20240101_AB"), entry("20240102_AB")],
+            problems: Vec::new(),
+            preview: Vec::new(),
+        };
+        let filter =
+            BaseKeyFilter::new(&["20240101_ab".to_string(), "20991231_*".to_string()], &[])
+                .expect("filter");
+        let scan = filter.apply(scan);
+        assert_eq!(scan.entries.len(), 1);
+        assert_eq!(scan.problems.len(), 1);
+        assert_eq!(scan.problems[0].problem_type, "FILTER_NO_MATCH");
+        assert_eq!(scan.problems[0].survey_id_base, "20991231_*");
+    }
+
+    #[test]
+    fn excluded_dir_globs_skip_backup_and_thumbnail_folders() {
+        let rules = Rules {
+            exclude_dir_globs: vec!["**/thumbnails/**".to_string(), "**/backup*".to_string()],
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let root = tree.path();
+        let survey = root.join("20240101_AB");
+        fs::create_dir_all(survey.join("thumbnails")).expect("create");
+        fs::create_dir_all(root.join("Backup 2023").join("20240101_AB")).expect("create");
+        fs::write(survey.join("img_001.jpg"), "a").expect("write");
+        fs::write(survey.join("thumbnails").join("img_002.jpg"), "b").expect("write");
+
+        let mut errors = Vec::new();
+        let surveys =
+            discover_surveys(root, &compiled, false, false, &mut errors).expect("discover");
+        assert_eq!(surveys["20240101_AB"].len(), 1);
+        let images = collect_images(&survey, None, &compiled, None, &mut errors).expect("images");
+        assert_eq!(images, vec![survey.join("img_001.jpg")]);
+        let result = build_graded_map(
+            &survey,
+            None,
+            &compiled,
+            None,
+            None,
+            &CancelToken::default(),
+        )
+        .expect("map");
+        assert!(result.map.candidates("img_002").expect("lookup").is_empty());
+        assert_eq!(count_images(&survey, &compiled).expect("count"), 1);
+
+        assert!(dir_glob_regex("thumbnails")
+            .expect("glob")
+            .is_match("thumbnails/"));
+        assert!(!dir_glob_regex("thumbnails")
+            .expect("glob")
+            .is_match("20240101_AB/thumbnails/"));
+        assert!(dir_glob_regex(" ").is_err());
+    }
+
+    #[test]
+    fn scan_depth_and_symlinks_shape_the_walk() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let survey = root.join("survey");
+        fs::create_dir_all(survey.join("a").join("b")).expect("create");
+        fs::write(survey.join("img_001.jpg"), "1").expect("write");
+        fs::write(survey.join("a").join("img_002.jpg"), "2").expect("write");
+        fs::write(survey.join("a").join("b").join("img_003.jpg"), "3").expect("write");
+        let compiled = |rules: Rules| compile_rules(&rules).expect("compile");
+        let shallow = compiled(Rules {
+            max_scan_depth: Some(2),
+            ..Rules::default()
+        });
+        let cache = ScanCache::load(root.join("cache.json"), "hash");
+        let mut errors = Vec::new();
+        let walked = collect_images(&survey, None, &shallow, None, &mut errors).expect("walk");
+        let cached =
+            collect_images(&survey, None, &shallow, Some(&cache), &mut errors).expect("cached");
+        assert_eq!(walked.len(), 2);
+        assert_eq!(walked, cached);
+
+        #[cfg(unix)]
+        {
+            let archive = root.join("archive");
+            fs::create_dir_all(&archive).expect("create");
+            fs::write(archive.join("img_004.jpg"), "4").expect("write");
+            std::os::unix::fs::symlink(&archive, survey.join("linked")).expect("symlink");
+            std::os::unix::fs::symlink(&survey, survey.join("a").join("loop")).expect("symlink");
+            let plain = compiled(Rules::default());
+            let following = compiled(Rules {
+                follow_symlinks: true,
+                ..Rules::default()
+            });
+            let images = collect_images(&survey, None, &plain, None, &mut errors).expect("walk");
+            assert_eq!(images.len(), 3);
+            let mut loops = Vec::new();
+            let images = collect_images(&survey, None, &following, None, &mut loops).expect("walk");
+            assert!(images.contains(&survey.join("linked").join("img_004.jpg")));
+            assert_eq!(images.len(), 4);
+            assert!(!loops.is_empty());
+        }
+    }
+
+    #[test]
+    fn camera_raws_are_images_unless_a_developed_sibling_exists() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        for name in [
+            "IMG_0001.JPG",
+            "IMG_0001.CR2",
+            "IMG_0002.nef",
+            "IMG_0003.arw",
+        ] {
+            fs::write(root.join(name), b"raw").expect("write");
+        }
+        let rules = compile_rules(&Rules::default()).expect("compile");
+        let files = collect_images(root, None, &rules, None, &mut Vec::new()).expect("images");
+        let names: Vec<_> = files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["IMG_0001.JPG", "IMG_0002.nef", "IMG_0003.arw"]);
+        assert_eq!(count_images(root, &rules).expect("count"), 3);
+
+        // CR3: ftyp, then moov > Canon uuid > CMT2 with DateTimeOriginal.
+        let isobmff = |kind: &[u8], body: &[u8]| {
+            let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            data.extend(kind);
+            data.extend(body);
+            data
+        };
+        let mut cmt2 = b"II*\0".to_vec();
+        cmt2.extend(8u32.to_le_bytes());
+        cmt2.extend(1u16.to_le_bytes());
+        cmt2.extend([0x03, 0x90, 2, 0]);
+        cmt2.extend(20u32.to_le_bytes());
+        cmt2.extend(26u32.to_le_bytes());
+        cmt2.extend(0u32.to_le_bytes());
+        cmt2.extend(b"2024:05:01 10:20:30\0");
+        let mut canon = CR3_CANON_UUID.to_vec();
+        canon.extend(isobmff(b"CMT2", &cmt2));
+        let mut cr3 = isobmff(b"ftyp", b"crx \0\0\0\x01");
+        cr3.extend(isobmff(b"moov", &isobmff(b"uuid", &canon)));
+        let cr3_path = root.join("IMG_0004.CR3");
+        fs::write(&cr3_path, cr3).expect("write");
+        let exif = read_image_exif(&cr3_path);
+        assert_eq!(
+            exif.iter().find_map(exif_capture_datetime).as_deref(),
+            Some("2024-05-01T10:20:30")
+        );
+        assert!(read_image_exif(&root.join("IMG_0002.nef")).is_empty());
+
+        // Thumbnails and hashes come from the embedded JPEG preview.
+        let mut preview = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(32, 24)
+            .write_to(&mut preview, image::ImageFormat::Jpeg)
+            .expect("jpeg");
+        let mut nef = b"MM\0*\0\0\0\x08".to_vec();
+        nef.extend([0xFF, 0xD8, 0xFF, 0x00]);
+        nef.extend(preview.into_inner());
+        let nef_path = root.join("IMG_0005.NEF");
+        fs::write(&nef_path, nef).expect("write");
+        assert!(thumbnail_png(&nef_path).is_some());
+        assert!(dhash(&nef_path).is_some());
+    }
+
+    #[test]
+    fn base_key_case_is_configurable() {
+        let regex = Regex::new("(?i)\\b(\\d{8}_[A-Z]{2})\\b").expect("regex");
+        assert_eq!(
+            extract_base_key("20240101_Ab", &regex, &KeyNormalization::default()).as_deref(),
+            Some("20240101_AB")
+        );
+        assert_eq!(
+            extract_base_key(
+                "20240101_Ab",
+                &regex,
+                &KeyNormalization {
+                    case: KeyCase::Lower,
+                    separator: None,
+                    transliterate: false,
+                },
+            )
+            .as_deref(),
+            Some("20240101_ab")
+        );
+        assert_eq!(
+            extract_base_key(
+                "20240101_Ab",
+                &regex,
+                &KeyNormalization {
+                    case: KeyCase::Preserve,
+                    separator: None,
+                    transliterate: false,
+                },
+            )
+            .as_deref(),
+            Some("20240101_Ab")
+        );
+
+        let rules = Rules {
+            base_key_case: KeyCase::Lower,
+            ..Rules::default()
+        };
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let root = tree.path();
+        fs::create_dir_all(root.join("20240101_AB")).expect("create");
+        let map =
+            discover_surveys(root, &compiled, false, false, &mut Vec::new()).expect("discover");
+        assert!(map.contains_key("20240101_ab"));
+    }
+
+    #[test]
+    fn preserved_keys_pair_regardless_of_case() {
+        let tree = TestTree::new();
+        tree.mkdir("raw/20240101_Ab");
+        tree.mkdir("graded/20240101_AB");
+        let compiled = compile_rules(&Rules {
+            base_key_case: KeyCase::Preserve,
+            ..Rules::default()
+        })
+        .expect("compile");
+        let mut options = load_scan_options(&ScanSettings::default(), &compiled).expect("options");
+        options.expected_surveys = Some(HashSet::from(["20240101_ab".to_string()]));
+        let scan = scan_roots(
+            &[tree.join("raw")],
+            &[tree.join("graded")],
+            &compiled,
+            &options,
+        )
+        .expect("scan");
+        assert!(scan.problems.is_empty(), "{:?}", scan.problems);
+        assert_eq!(scan.entries.len(), 1);
+        assert_eq!(scan.entries[0].base_key, "20240101_Ab");
+    }
+
+    #[test]
+    fn discovery_skips_marked_output_dirs() {
+        let rules = Rules::default();
+        let compiled = compile_rules(&rules).expect("compile");
+        let tree = TestTree::new();
+        let root = tree.path();
+        fs::create_dir_all(root.join("20240101_AB")).expect("create");
+        let output_dir = root.join("out");
+        fs::create_dir_all(output_dir.join("per_survey").join("20240102_CD")).expect("create");
+        mark_output_dir(&output_dir).expect("mark");
+
+        let map =
+            discover_surveys(root, &compiled, false, false, &mut Vec::new()).expect("discover");
+        assert!(map.contains_key("20240101_AB"));
+        assert!(!map.contains_key("20240102_CD"));
+    }
+
+    /// Text without digits, so it cannot hold a survey id of its own.
+    pub(crate) fn id_free_noise() -> impl Strategy<Value = String> {
+        "\\PC{0,12}".prop_map(|text| text.chars().filter(|ch| !ch.is_numeric()).collect())
+    }
+
+    proptest! {
+        #[test]
+        fn base_keys_are_total_and_stable(
+            value in "\\PC{0,40}",
+            transliterate in any::<bool>(),
+        ) {
+            let compiled = default_compiled(transliterate);
+            let key = extract_base_key(&value, &compiled.base_re, &compiled.key_norm);
+            prop_assert_eq!(
+                &key,
+                &extract_base_key(&value, &compiled.base_re, &compiled.key_norm)
+            );
+            if let Some(key) = key {
+                prop_assert_eq!(
+                    extract_base_key(&key, &compiled.base_re, &compiled.key_norm),
+                    Some(key)
+                );
+            }
+        }
+
+        #[test]
+        fn base_keys_ignore_surrounding_text(
+            date in "[0-9]{8}",
+            area in "[A-Za-z]{2}",
+            part in prop::option::of("_[A-Za-z]{2}"),
+            prefix in "\\PC{0,12}",
+            suffix in id_free_noise(),
+            before in "[ \\t\u{a0}\u{3000}]{1,3}",
+            after in "[ \\t\u{a0}\u{3000}]{1,3}",
+        ) {
+            let compiled = default_compiled(false);
+            let name = format!(
+                "{}{}{}_{}{}{}{}",
+                prefix,
+                before,
+                date,
+                area,
+                part.unwrap_or_default(),
+                after,
+                suffix
+            );
+            prop_assert_eq!(
+                extract_base_key(&name, &compiled.base_re, &compiled.key_norm),
+                Some(format!("{}_{}", date, area.to_uppercase()))
+            );
+        }
+    }
+}
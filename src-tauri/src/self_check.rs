@@ -0,0 +1,254 @@
+//! Self-check of the pipeline on a bundled sample tree with known labels.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::run_csv_reader;
+use crate::rules::Rules;
+use crate::runs::{run_root_scan, RootRunOptions};
+use crate::{unix_millis, AppError, Host};
+
+const SELF_CHECK_JSON: &str = include_str!("../assets/self_check.json");
+
+/// Outcome of `run_self_check`. The sample tree is kept at `root` when the
+/// check fails so the outputs can be inspected.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SelfCheckReport {
+    pub passed: bool,
+    pub expected_rows: u64,
+    pub actual_rows: u64,
+    pub mismatches: Vec<SelfCheckMismatch>,
+    pub root: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SelfCheckMismatch {
+    pub survey_id_base: String,
+    pub raw_relpath: String,
+    /// Compared column, or `row` when the row is missing or unexpected.
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Bundled sample tree (`assets/self_check.json`): files to create, run
+/// options and the rows a run with the default rules must produce.
+#[derive(Clone, Debug, Deserialize)]
+struct SelfCheckFixture {
+    files: Vec<String>,
+    options: RootRunOptions,
+    expected: Vec<SelfCheckRow>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct SelfCheckRow {
+    survey_id_base: String,
+    raw_relpath: String,
+    dolphin: u8,
+    graded_relpath: String,
+    graded_winner_type: String,
+}
+
+/// Processes the bundled sample tree end-to-end with the default rules and
+/// compares the merged CSV against the expected rows, so an installation can
+/// be verified before it is trusted with real data.
+pub fn run_self_check(host: &Host) -> Result<SelfCheckReport, AppError> {
+    let fixture: SelfCheckFixture = serde_json::from_str(SELF_CHECK_JSON)?;
+    let root = std::env::temp_dir().join(format!("survey-labeler-self-check-{}", unix_millis()));
+    write_self_check_tree(&root, &fixture)?;
+    let summary = run_root_scan(
+        host,
+        vec![root.join("Graded")],
+        vec![root.join("Raw")],
+        root.join("Output"),
+        fixture.options.clone(),
+        Rules::default(),
+    )?;
+    let merged = summary.merged_csv_path.ok_or_else(|| {
+        AppError::Message("The self-check run did not write a merged CSV.".to_string())
+    })?;
+    let actual = read_self_check_rows(Path::new(&merged))?;
+    let mismatches = compare_self_check(&fixture.expected, &actual);
+    let passed = mismatches.is_empty();
+    if passed {
+        let _ = fs::remove_dir_all(&root);
+    }
+    Ok(SelfCheckReport {
+        passed,
+        expected_rows: fixture.expected.len() as u64,
+        actual_rows: actual.len() as u64,
+        mismatches,
+        root: root.to_string_lossy().to_string(),
+    })
+}
+
+fn write_self_check_tree(root: &Path, fixture: &SelfCheckFixture) -> Result<(), AppError> {
+    for relpath in &fixture.files {
+        let path = root.join(relpath);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, format!("self-check:{}", relpath))?;
+    }
+    Ok(())
+}
+
+fn read_self_check_rows(path: &Path) -> Result<Vec<SelfCheckRow>, AppError> {
+    let mut reader = run_csv_reader(path)?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| {
+                AppError::Message(format!(
+                    "Column {} missing in {}.",
+                    name,
+                    path.to_string_lossy()
+                ))
+            })
+    };
+    let columns = [
+        column("survey_id_base")?,
+        column("raw_relpath")?,
+        column("dolphin")?,
+        column("graded_relpath")?,
+        column("graded_winner_type")?,
+    ];
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let field = |index: usize| record.get(columns[index]).unwrap_or_default().to_string();
+        rows.push(SelfCheckRow {
+            survey_id_base: field(0),
+            raw_relpath: field(1),
+            dolphin: field(2).parse().unwrap_or(u8::MAX),
+            graded_relpath: field(3),
+            graded_winner_type: field(4),
+        });
+    }
+    Ok(rows)
+}
+
+fn compare_self_check(
+    expected: &[SelfCheckRow],
+    actual: &[SelfCheckRow],
+) -> Vec<SelfCheckMismatch> {
+    let key = |row: &SelfCheckRow| (row.survey_id_base.clone(), row.raw_relpath.clone());
+    let actual_by_key = actual
+        .iter()
+        .map(|row| (key(row), row))
+        .collect::<BTreeMap<_, _>>();
+    let expected_keys = expected.iter().map(key).collect::<HashSet<_>>();
+    let mismatch =
+        |row: &SelfCheckRow, field: &str, expected: String, actual: String| SelfCheckMismatch {
+            survey_id_base: row.survey_id_base.clone(),
+            raw_relpath: row.raw_relpath.clone(),
+            field: field.to_string(),
+            expected,
+            actual,
+        };
+
+    let mut mismatches = Vec::new();
+    for row in expected {
+        let Some(found) = actual_by_key.get(&key(row)) else {
+            mismatches.push(mismatch(
+                row,
+                "row",
+                "present".to_string(),
+                "missing".to_string(),
+            ));
+            continue;
+        };
+        let fields = [
+            (
+                "dolphin",
+                row.dolphin.to_string(),
+                found.dolphin.to_string(),
+            ),
+            (
+                "graded_relpath",
+                row.graded_relpath.clone(),
+                found.graded_relpath.clone(),
+            ),
+            (
+                "graded_winner_type",
+                row.graded_winner_type.clone(),
+                found.graded_winner_type.clone(),
+            ),
+        ];
+        for (field, expected, actual) in fields {
+            if expected != actual {
+                mismatches.push(mismatch(row, field, expected, actual));
+            }
+        }
+    }
+    for row in actual {
+        if !expected_keys.contains(&key(row)) {
+            mismatches.push(mismatch(
+                row,
+                "row",
+                "absent".to_string(),
+                "unexpected".to_string(),
+            ));
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rules::compile_rules;
+    use crate::scan::{load_scan_options, scan_roots};
+    use crate::test_support::TestTree;
+
+    #[test]
+    fn self_check_fixture_matches_sample_tree() {
+        let fixture: SelfCheckFixture = serde_json::from_str(SELF_CHECK_JSON).expect("fixture");
+        let tree = TestTree::new();
+        let root = tree.path();
+        write_self_check_tree(root, &fixture).expect("tree");
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+        let options = load_scan_options(&fixture.options.scan, &compiled).expect("options");
+        let scan = scan_roots(
+            &[root.join("Raw")],
+            &[root.join("Graded")],
+            &compiled,
+            &options,
+        )
+        .expect("scan");
+        let paired = scan
+            .entries
+            .iter()
+            .filter(|entry| entry.raw.is_some() && entry.graded.is_some())
+            .map(|entry| entry.base_key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paired, vec!["20240105_AB", "20240212_CD"]);
+
+        let mut actual = fixture.expected.clone();
+        actual[1].dolphin = 0;
+        actual.remove(3);
+        let mismatches = compare_self_check(&fixture.expected, &actual);
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].field, "dolphin");
+        assert_eq!(mismatches[1].field, "row");
+        assert_eq!(mismatches[1].actual, "missing");
+    }
+
+    #[test]
+    fn self_check_passes_and_cleans_up() {
+        let fixture: SelfCheckFixture = serde_json::from_str(SELF_CHECK_JSON).expect("fixture");
+        let tree = TestTree::new();
+        let report = run_self_check(&tree.host()).expect("self check");
+        assert_eq!(report.mismatches, Vec::new());
+        assert!(report.passed);
+        assert_eq!(report.expected_rows, fixture.expected.len() as u64);
+        assert_eq!(report.actual_rows, report.expected_rows);
+        assert!(!Path::new(&report.root).exists());
+    }
+}
@@ -0,0 +1,343 @@
+//! App-wide settings that are not matching rules, stored as `settings.json`:
+//! mail and database connections and how roots are resolved. Passwords are
+//! kept in the OS keyring instead of the file.
+
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{app_data_dir, AppError, Host};
+
+/// App-wide settings that are not matching rules, stored as `settings.json`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AppSettings {
+    #[serde(default)]
+    pub smtp: Option<SmtpSettings>,
+    #[serde(default)]
+    pub postgres: Option<PostgresSettings>,
+    /// Rewrite roots on mapped network drives (`Z:\surveys`) to their UNC
+    /// form (`\\nas\surveys`) so every machine sees the same paths. Windows only.
+    #[serde(default)]
+    pub resolve_mapped_drives: bool,
+    /// Memory budget for one survey's graded file map; larger graded folders
+    /// are spilled to a SQLite file in the run's workspace. Unlimited when
+    /// unset.
+    #[serde(default)]
+    pub graded_map_memory_mb: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Kept in the OS keyring, never in `settings.json`. Reads return
+    /// [`REDACTED_PASSWORD`] when one is stored.
+    #[serde(default)]
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Database the `export_postgres` run option writes to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostgresSettings {
+    pub host: String,
+    #[serde(default = "default_postgres_port")]
+    pub port: u16,
+    pub user: String,
+    /// Kept in the OS keyring like the SMTP password.
+    #[serde(default)]
+    pub password: Option<String>,
+    pub dbname: String,
+    /// Schema holding the `runs`, `result_rows` and `problems` tables; they
+    /// are created when missing.
+    #[serde(default = "default_postgres_schema")]
+    pub schema: String,
+    #[serde(default)]
+    pub tls: PostgresTls,
+    /// PEM file with the CA certificates the server's certificate is checked
+    /// against, for a database with its own CA. The public web roots when
+    /// unset.
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostgresTls {
+    /// Encrypt the connection and verify the server's certificate.
+    #[default]
+    Require,
+    /// Send everything unencrypted, for a database that is only reachable on
+    /// the lab network or through a tunnel.
+    Disable,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    #[default]
+    Starttls,
+    Tls,
+    None,
+}
+
+/// Stands in for a stored password in settings handed to the frontend.
+/// Saving it back keeps the stored password.
+pub const REDACTED_PASSWORD: &str = "********";
+
+const KEYRING_SERVICE: &str = "survey-label-exporter";
+const SMTP_ACCOUNT: &str = "smtp";
+const POSTGRES_ACCOUNT: &str = "postgres";
+
+impl AppSettings {
+    /// The settings with every stored password replaced by
+    /// [`REDACTED_PASSWORD`], for showing them.
+    fn redacted(mut self) -> Self {
+        let redact = |password: &mut Option<String>| {
+            if password.is_some() {
+                *password = Some(REDACTED_PASSWORD.to_string());
+            }
+        };
+        if let Some(smtp) = self.smtp.as_mut() {
+            redact(&mut smtp.password);
+        }
+        if let Some(postgres) = self.postgres.as_mut() {
+            redact(&mut postgres.password);
+        }
+        self
+    }
+}
+
+/// Reads `settings.json`. Passwords are not filled in from the OS keyring,
+/// so runs that send no mail and export nothing never touch it; see
+/// [`smtp_password`] and [`postgres_password`].
+pub fn load_settings(host: &Host) -> Result<AppSettings, AppError> {
+    let path = settings_file_path(host)?;
+    if !path.exists() {
+        return Ok(AppSettings::default());
+    }
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+/// The settings for the settings view: stored passwords show as
+/// [`REDACTED_PASSWORD`].
+pub fn get_settings(host: &Host) -> Result<AppSettings, AppError> {
+    let mut settings = load_settings(host)?;
+    if let Some(smtp) = settings.smtp.as_mut() {
+        smtp.password = smtp_password(host, smtp)?;
+    }
+    if let Some(postgres) = settings.postgres.as_mut() {
+        postgres.password = postgres_password(host, postgres)?;
+    }
+    Ok(settings.redacted())
+}
+
+/// Writes `settings.json` without passwords and stores them in the OS
+/// keyring. [`REDACTED_PASSWORD`] keeps the stored password, an empty or
+/// missing one deletes it. A password an older version wrote into the file
+/// moves to the keyring. Returns the saved settings redacted.
+pub fn save_settings(host: &Host, mut settings: AppSettings) -> Result<AppSettings, AppError> {
+    let previous = load_settings(host)?;
+    let smtp_password = settings.smtp.as_mut().and_then(|smtp| smtp.password.take());
+    save_password(
+        host,
+        SMTP_ACCOUNT,
+        smtp_password,
+        previous.smtp.and_then(|smtp| smtp.password),
+    )?;
+    let postgres_password = settings
+        .postgres
+        .as_mut()
+        .and_then(|postgres| postgres.password.take());
+    save_password(
+        host,
+        POSTGRES_ACCOUNT,
+        postgres_password,
+        previous.postgres.and_then(|postgres| postgres.password),
+    )?;
+
+    let path = settings_file_path(host)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(&settings)?)?;
+    get_settings(host)
+}
+
+/// Password for `smtp`: one left in `settings.json` by an older version,
+/// otherwise the one in the OS keyring.
+pub(crate) fn smtp_password(host: &Host, smtp: &SmtpSettings) -> Result<Option<String>, AppError> {
+    match &smtp.password {
+        Some(password) => Ok(Some(password.clone())),
+        None => stored_password(host, SMTP_ACCOUNT),
+    }
+}
+
+/// Password for `postgres`, looked up like [`smtp_password`].
+pub(crate) fn postgres_password(
+    host: &Host,
+    postgres: &PostgresSettings,
+) -> Result<Option<String>, AppError> {
+    match &postgres.password {
+        Some(password) => Ok(Some(password.clone())),
+        None => stored_password(host, POSTGRES_ACCOUNT),
+    }
+}
+
+/// Stores `password` as sent by the settings view; `legacy` is the one
+/// still written in `settings.json`.
+fn save_password(
+    host: &Host,
+    account: &str,
+    password: Option<String>,
+    legacy: Option<String>,
+) -> Result<(), AppError> {
+    match password {
+        Some(password) if password == REDACTED_PASSWORD => match legacy {
+            Some(legacy) => store_password(host, account, Some(&legacy)),
+            None => Ok(()),
+        },
+        Some(password) if !password.is_empty() => store_password(host, account, Some(&password)),
+        _ => store_password(host, account, None),
+    }
+}
+
+/// Keyring entry of `account`. The data folder is part of the name, so CLI
+/// runs with their own `--data-dir` keep their own passwords.
+fn keyring_entry(host: &Host, account: &str) -> Result<keyring::Entry, AppError> {
+    let user = format!("{account}@{}", app_data_dir(host)?.display());
+    keyring::Entry::new(KEYRING_SERVICE, &user).map_err(keyring_error)
+}
+
+fn stored_password(host: &Host, account: &str) -> Result<Option<String>, AppError> {
+    match keyring_entry(host, account)?.get_password() {
+        Ok(password) => Ok(Some(password)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(keyring_error(err)),
+    }
+}
+
+fn store_password(host: &Host, account: &str, password: Option<&str>) -> Result<(), AppError> {
+    let entry = keyring_entry(host, account)?;
+    match password {
+        Some(password) => entry.set_password(password).map_err(keyring_error),
+        None => match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(keyring_error(err)),
+        },
+    }
+}
+
+fn keyring_error(err: keyring::Error) -> AppError {
+    AppError::Message(format!("OS keyring: {err}"))
+}
+
+fn settings_file_path(host: &Host) -> Result<PathBuf, AppError> {
+    Ok(app_data_dir(host)?.join("settings.json"))
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_postgres_port() -> u16 {
+    5432
+}
+
+fn default_postgres_schema() -> String {
+    "public".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{use_memory_keyring, TestTree};
+
+    fn smtp(password: Option<&str>) -> SmtpSettings {
+        SmtpSettings {
+            host: "mail.example.org".to_string(),
+            port: 587,
+            security: SmtpSecurity::Starttls,
+            username: Some("lab".to_string()),
+            password: password.map(str::to_string),
+            from: "lab@example.org".to_string(),
+            to: vec!["team@example.org".to_string()],
+        }
+    }
+
+    fn with_smtp(password: Option<&str>) -> AppSettings {
+        AppSettings {
+            smtp: Some(smtp(password)),
+            ..AppSettings::default()
+        }
+    }
+
+    #[test]
+    fn passwords_live_in_the_keyring_and_are_redacted() {
+        use_memory_keyring();
+        let tree = TestTree::new();
+        let host = tree.host();
+
+        let saved = save_settings(&host, with_smtp(Some("s3cret"))).expect("save");
+        let shown = saved
+            .smtp
+            .as_ref()
+            .and_then(|smtp| smtp.password.as_deref());
+        assert_eq!(shown, Some(REDACTED_PASSWORD));
+        let file = fs::read_to_string(tree.join("data").join("settings.json")).expect("file");
+        assert!(!file.contains("s3cret"));
+        let loaded = load_settings(&host).expect("load").smtp.expect("smtp");
+        assert_eq!(loaded.password, None);
+        assert_eq!(
+            smtp_password(&host, &loaded).expect("password").as_deref(),
+            Some("s3cret")
+        );
+
+        // Saving the redacted form back keeps the password.
+        save_settings(&host, get_settings(&host).expect("get")).expect("save");
+        assert_eq!(
+            smtp_password(&host, &loaded).expect("password").as_deref(),
+            Some("s3cret")
+        );
+
+        // Clearing it deletes it.
+        let cleared = save_settings(&host, with_smtp(Some(""))).expect("save");
+        assert_eq!(cleared.smtp.and_then(|smtp| smtp.password), None);
+        assert_eq!(smtp_password(&host, &loaded).expect("password"), None);
+    }
+
+    #[test]
+    fn a_password_left_in_the_file_moves_to_the_keyring() {
+        use_memory_keyring();
+        let tree = TestTree::new();
+        let host = tree.host();
+        let legacy = serde_json::to_string(&with_smtp(Some("old"))).expect("json");
+        tree.write("data/settings.json", legacy);
+
+        let shown = get_settings(&host).expect("get");
+        assert_eq!(
+            shown
+                .smtp
+                .as_ref()
+                .and_then(|smtp| smtp.password.as_deref()),
+            Some(REDACTED_PASSWORD)
+        );
+        save_settings(&host, shown).expect("save");
+        let file = fs::read_to_string(tree.join("data").join("settings.json")).expect("file");
+        assert!(!file.contains("\"old\""));
+        assert_eq!(
+            stored_password(&host, SMTP_ACCOUNT)
+                .expect("password")
+                .as_deref(),
+            Some("old")
+        );
+    }
+}
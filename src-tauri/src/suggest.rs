@@ -0,0 +1,452 @@
+//! Rule suggestions: uncovered graded folder tokens, and patterns derived
+//! from sample paths.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::classify::folder_tokens;
+use crate::output::normalize_relpath;
+use crate::paths::short_path;
+use crate::rules::{compile_rules, KeyNormalization, Rules};
+use crate::scan::{
+    extract_base_key, extract_detected_id, is_supported_image, levenshtein, walk_tree,
+};
+use crate::AppError;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenSuggestion {
+    pub token: String,
+    pub variants: Vec<String>,
+    pub suggested_role: String,
+    pub image_count: u64,
+    pub folder_count: u64,
+    pub example_folder: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RulesSuggestion {
+    pub survey_id_regex_detected: String,
+    pub survey_id_regex_base: String,
+    pub image_id_regex: Option<String>,
+    pub samples: Vec<SampleMatch>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SampleMatch {
+    pub path: String,
+    pub survey_id_detected: Option<String>,
+    pub survey_id_base: Option<String>,
+    pub image_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct TokenStats {
+    image_count: u64,
+    folders: HashSet<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ShapeKind {
+    Digits,
+    Letters,
+    Separator(char),
+}
+
+/// One run of digits, letters or a single separator, with the run lengths
+/// seen across samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ShapeToken {
+    kind: ShapeKind,
+    min_len: usize,
+    max_len: usize,
+}
+
+/// Tokenizes graded folder names and suggests tokens the rules do not cover
+/// yet, most frequent first. Near-identical spellings are clustered together.
+pub fn suggest_tokens(
+    graded_root: PathBuf,
+    rules: Rules,
+    limit: usize,
+) -> Result<Vec<TokenSuggestion>, AppError> {
+    let compiled = compile_rules(&rules)?;
+    let mut stats: HashMap<String, TokenStats> = HashMap::new();
+    for entry in walk_tree(&graded_root, &compiled).filter_map(Result::ok) {
+        if !entry.file_type().is_file() || !is_supported_image(entry.path(), &compiled) {
+            continue;
+        }
+        let relpath = normalize_relpath(&short_path(entry.path()), &graded_root);
+        let Some((folder, _)) = relpath.rsplit_once('/') else {
+            continue;
+        };
+        let mut seen = HashSet::new();
+        for component in folder.split('/') {
+            if compiled.detected_re.is_match(component) {
+                continue;
+            }
+            for token in folder_tokens(component, &compiled) {
+                if !seen.insert(token.clone()) {
+                    continue;
+                }
+                let stat = stats.entry(token).or_default();
+                stat.image_count += 1;
+                stat.folders.insert(folder.to_string());
+            }
+        }
+    }
+
+    let mut ranked: Vec<(String, TokenStats)> = stats.into_iter().collect();
+    ranked.sort_by(|a, b| {
+        b.1.image_count
+            .cmp(&a.1.image_count)
+            .then_with(|| a.0.cmp(&b.0))
+    });
+
+    let mut suggestions: Vec<TokenSuggestion> = Vec::new();
+    for (token, stat) in ranked {
+        let example = stat.folders.iter().min().cloned();
+        let cluster = suggestions.iter_mut().find(|suggestion| {
+            token.chars().count() >= 5 && levenshtein(&suggestion.token, &token) <= 1
+        });
+        match cluster {
+            Some(suggestion) => {
+                suggestion.variants.push(token);
+                suggestion.image_count += stat.image_count;
+                suggestion.folder_count += stat.folders.len() as u64;
+            }
+            None => suggestions.push(TokenSuggestion {
+                suggested_role: suggest_token_role(&token).to_string(),
+                variants: vec![token.clone()],
+                token,
+                image_count: stat.image_count,
+                folder_count: stat.folders.len() as u64,
+                example_folder: example,
+            }),
+        }
+    }
+    suggestions.sort_by(|a, b| {
+        b.image_count
+            .cmp(&a.image_count)
+            .then_with(|| a.token.cmp(&b.token))
+    });
+    suggestions.truncate(limit);
+    Ok(suggestions)
+}
+
+/// Proposes survey-id and image-id regexes from a few representative folder or
+/// file paths and reports what each sample yields under the proposal.
+pub fn suggest_rules_from_samples(sample_paths: &[String]) -> Result<RulesSuggestion, AppError> {
+    let samples: Vec<Vec<String>> = sample_paths
+        .iter()
+        .map(|path| {
+            path.split(['/', '\\'])
+                .filter(|component| !component.is_empty())
+                .map(|component| component.to_string())
+                .collect()
+        })
+        .filter(|components: &Vec<String>| !components.is_empty())
+        .collect();
+    if samples.is_empty() {
+        return Err(AppError::Validation(
+            "Provide at least one sample path.".to_string(),
+        ));
+    }
+
+    let folder_shapes: Vec<Vec<Vec<ShapeToken>>> = samples
+        .iter()
+        .map(|components| {
+            let folders = if is_file_component(components.last().expect("non-empty")) {
+                &components[..components.len() - 1]
+            } else {
+                &components[..]
+            };
+            folders
+                .iter()
+                .map(|component| shape_tokens(component))
+                .filter(|shape| shape.iter().any(|token| token.kind == ShapeKind::Digits))
+                .collect()
+        })
+        .collect();
+
+    let (base_shape, tail_shape) = best_survey_shape(&folder_shapes).ok_or_else(|| {
+        AppError::Message(
+            "No folder component with digits is shared by the samples; unable to suggest a survey id pattern."
+                .to_string(),
+        )
+    })?;
+    let base_pattern = shapes_to_regex(&base_shape, "[A-Z]");
+    let tail_pattern = shapes_to_regex(&tail_shape, "[A-Z]");
+    let (survey_id_regex_detected, survey_id_regex_base) = if tail_pattern.is_empty() {
+        (
+            format!("(?i)\\b({})\\b", base_pattern),
+            format!("(?i)\\b({})\\b", base_pattern),
+        )
+    } else {
+        (
+            format!("(?i)\\b({}(?:{})?)\\b", base_pattern, tail_pattern),
+            format!("(?i)\\b({})(?:{})?\\b", base_pattern, tail_pattern),
+        )
+    };
+
+    let stems: Vec<Vec<ShapeToken>> = samples
+        .iter()
+        .filter_map(|components| components.last())
+        .filter(|name| is_file_component(name))
+        .map(|name| shape_tokens(name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name)))
+        .collect();
+    let image_id_regex = image_id_shape(&stems).map(|shape| {
+        format!(
+            "^({})(?:[ _-][A-Za-z0-9]+)*$",
+            shapes_to_regex(&shape, "[A-Za-z]")
+        )
+    });
+
+    let detected_re = Regex::new(&survey_id_regex_detected)?;
+    let base_re = Regex::new(&survey_id_regex_base)?;
+    let image_re = image_id_regex.as_deref().map(Regex::new).transpose()?;
+    let matches = sample_paths
+        .iter()
+        .map(|path| {
+            let detected =
+                extract_detected_id(Path::new(path), &detected_re, &KeyNormalization::default());
+            let base = detected.as_ref().and_then(|detected| {
+                extract_base_key(detected, &base_re, &KeyNormalization::default())
+            });
+            let image_id = image_re.as_ref().and_then(|regex| {
+                Path::new(path)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| regex.captures(stem))
+                    .and_then(|captures| captures.get(1))
+                    .map(|m| m.as_str().to_string())
+            });
+            SampleMatch {
+                path: path.clone(),
+                survey_id_detected: detected,
+                survey_id_base: base,
+                image_id,
+            }
+        })
+        .collect();
+
+    Ok(RulesSuggestion {
+        survey_id_regex_detected,
+        survey_id_regex_base,
+        image_id_regex,
+        samples: matches,
+    })
+}
+
+/// Rough first guess of how a token is used, based on common grading words.
+fn suggest_token_role(token: &str) -> &'static str {
+    const NEGATIVE_HINTS: [&str; 10] = [
+        "no", "none", "kein", "nicht", "reject", "empty", "leer", "bad", "blur", "unsure",
+    ];
+    const SECONDARY_HINTS: [&str; 7] = ["best", "top", "fav", "good", "gut", "nice", "select"];
+    if NEGATIVE_HINTS.iter().any(|hint| token.starts_with(hint)) {
+        "negative"
+    } else if SECONDARY_HINTS.iter().any(|hint| token.starts_with(hint)) {
+        "secondary"
+    } else {
+        "positive"
+    }
+}
+
+fn is_file_component(component: &str) -> bool {
+    component
+        .rsplit_once('.')
+        .is_some_and(|(stem, ext)| !stem.is_empty() && (1..=5).contains(&ext.len()))
+}
+
+fn shape_tokens(value: &str) -> Vec<ShapeToken> {
+    let mut tokens: Vec<ShapeToken> = Vec::new();
+    for ch in value.chars() {
+        let kind = if ch.is_ascii_digit() {
+            ShapeKind::Digits
+        } else if ch.is_alphabetic() {
+            ShapeKind::Letters
+        } else {
+            ShapeKind::Separator(ch)
+        };
+        match tokens.last_mut() {
+            Some(last) if last.kind == kind && !matches!(kind, ShapeKind::Separator(_)) => {
+                last.min_len += 1;
+                last.max_len += 1;
+            }
+            _ => tokens.push(ShapeToken {
+                kind,
+                min_len: 1,
+                max_len: 1,
+            }),
+        }
+    }
+    tokens
+}
+
+/// Longest common run of token kinds, widening length ranges as needed.
+fn common_shape_prefix(a: &[ShapeToken], b: &[ShapeToken]) -> Vec<ShapeToken> {
+    a.iter()
+        .zip(b.iter())
+        .take_while(|(left, right)| left.kind == right.kind)
+        .map(|(left, right)| ShapeToken {
+            kind: left.kind,
+            min_len: left.min_len.min(right.min_len),
+            max_len: left.max_len.max(right.max_len),
+        })
+        .collect()
+}
+
+fn trim_trailing_separators(shape: &mut Vec<ShapeToken>) {
+    while matches!(shape.last(), Some(token) if matches!(token.kind, ShapeKind::Separator(_))) {
+        shape.pop();
+    }
+}
+
+/// Picks the folder shape shared by most samples. Returns the common prefix
+/// (the base key) and the longest optional tail seen after it.
+fn best_survey_shape(
+    folder_shapes: &[Vec<Vec<ShapeToken>>],
+) -> Option<(Vec<ShapeToken>, Vec<ShapeToken>)> {
+    let first = folder_shapes.iter().find(|shapes| !shapes.is_empty())?;
+    let mut best: Option<(usize, Vec<ShapeToken>, Vec<ShapeToken>)> = None;
+    for candidate in first {
+        let mut prefix = candidate.clone();
+        let mut matched = Vec::new();
+        for shapes in folder_shapes {
+            let closest = shapes
+                .iter()
+                .map(|shape| (common_shape_prefix(&prefix, shape), shape))
+                .filter(|(common, _)| common.iter().any(|token| token.kind == ShapeKind::Digits))
+                .max_by_key(|(common, _)| common.len());
+            if let Some((common, shape)) = closest {
+                prefix = common;
+                matched.push(shape.clone());
+            }
+        }
+        trim_trailing_separators(&mut prefix);
+        let tail = matched
+            .iter()
+            .filter(|shape| shape.len() > prefix.len())
+            .map(|shape| shape[prefix.len()..].to_vec())
+            .max_by_key(|tail| tail.len())
+            .unwrap_or_default();
+        let score = matched.len();
+        let better = match best.as_ref() {
+            Some((best_score, best_prefix, _)) => {
+                score > *best_score || (score == *best_score && prefix.len() > best_prefix.len())
+            }
+            None => true,
+        };
+        if better && !prefix.is_empty() {
+            best = Some((score, prefix, tail));
+        }
+    }
+    best.map(|(_, prefix, tail)| (prefix, tail))
+}
+
+/// Common stem shape up to and including its last digit run (the frame number).
+fn image_id_shape(stems: &[Vec<ShapeToken>]) -> Option<Vec<ShapeToken>> {
+    let mut iter = stems.iter();
+    let mut prefix = iter.next()?.clone();
+    for stem in iter {
+        prefix = common_shape_prefix(&prefix, stem);
+    }
+    let last_digits = prefix
+        .iter()
+        .rposition(|token| token.kind == ShapeKind::Digits)?;
+    prefix.truncate(last_digits + 1);
+    Some(prefix)
+}
+
+fn shapes_to_regex(shape: &[ShapeToken], letter_class: &str) -> String {
+    shape
+        .iter()
+        .map(|token| {
+            let quantifier = if token.min_len == token.max_len {
+                format!("{{{}}}", token.min_len)
+            } else {
+                format!("{{{},{}}}", token.min_len, token.max_len)
+            };
+            match token.kind {
+                ShapeKind::Digits => format!("\\d{}", quantifier),
+                ShapeKind::Letters => format!("{}{}", letter_class, quantifier),
+                ShapeKind::Separator(ch) => regex::escape(&ch.to_string()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::TestTree;
+    use std::fs;
+
+    #[test]
+    fn token_suggestions_cluster_uncovered_tokens() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        for (folder, file) in [
+            ("20240101_AB/Delfine", "a_0001.jpg"),
+            ("20240101_AB/Delfine", "a_0002.jpg"),
+            ("20240101_AB/Delfin", "a_0003.jpg"),
+            ("20240101_AB/best", "a_0004.jpg"),
+            ("20240101_AB/kein Tier", "a_0005.jpg"),
+        ] {
+            fs::create_dir_all(root.join(folder)).expect("create");
+            fs::write(root.join(folder).join(file), b"x").expect("write");
+        }
+
+        let suggestions =
+            suggest_tokens(root.to_path_buf(), Rules::default(), 10).expect("suggest");
+        assert_eq!(suggestions[0].token, "delfine");
+        assert_eq!(suggestions[0].variants, vec!["delfine", "delfin"]);
+        assert_eq!(suggestions[0].image_count, 3);
+        assert!(suggestions
+            .iter()
+            .all(|suggestion| suggestion.token != "best"));
+        let kein = suggestions
+            .iter()
+            .find(|suggestion| suggestion.token == "kein")
+            .expect("kein");
+        assert_eq!(kein.suggested_role, "negative");
+    }
+
+    #[test]
+    fn suggested_rules_match_samples() {
+        let samples = vec![
+            "/data/raw/20250101_AB_CD/20250101_ALA_0449.jpg".to_string(),
+            "/data/graded/20250102_EF/IND/20250102_ALA_12345 best.JPG".to_string(),
+            "D:\\graded\\20250103_GH_IJ".to_string(),
+        ];
+        let suggestion = suggest_rules_from_samples(&samples).expect("suggest");
+        assert_eq!(
+            suggestion.survey_id_regex_base,
+            "(?i)\\b(\\d{8}_[A-Z]{2})(?:_[A-Z]{2})?\\b"
+        );
+        assert_eq!(
+            suggestion.image_id_regex.as_deref(),
+            Some("^(\\d{8}_[A-Za-z]{3}_\\d{4,5})(?:[ _-][A-Za-z0-9]+)*$")
+        );
+        let bases: Vec<Option<&str>> = suggestion
+            .samples
+            .iter()
+            .map(|sample| sample.survey_id_base.as_deref())
+            .collect();
+        assert_eq!(
+            bases,
+            vec![
+                Some("20250101_AB"),
+                Some("20250102_EF"),
+                Some("20250103_GH")
+            ]
+        );
+        assert_eq!(
+            suggestion.samples[1].image_id.as_deref(),
+            Some("20250102_ALA_12345")
+        );
+    }
+}
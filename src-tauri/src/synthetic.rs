@@ -0,0 +1,279 @@
+//! Generated survey trees for tests, benchmarks and trying the app out.
+
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{unix_millis, AppError};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyntheticTreeOptions {
+    /// Target folder; a fresh folder under the system temp dir when omitted.
+    #[serde(default)]
+    pub output_dir: Option<String>,
+    #[serde(default = "default_synthetic_surveys")]
+    pub surveys: u32,
+    #[serde(default = "default_synthetic_images")]
+    pub images_per_survey: u32,
+    /// Share of raw images that get a graded copy, between 0 and 1.
+    #[serde(default = "default_synthetic_graded_ratio")]
+    pub graded_ratio: f64,
+    #[serde(default)]
+    pub duplicate_raw: bool,
+    #[serde(default)]
+    pub duplicate_graded: bool,
+    #[serde(default)]
+    pub missing_pairs: bool,
+    /// Adds spaces, suffix tokens, uppercase extensions and non-image files.
+    #[serde(default)]
+    pub edge_cases: bool,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Default for SyntheticTreeOptions {
+    fn default() -> Self {
+        Self {
+            output_dir: None,
+            surveys: default_synthetic_surveys(),
+            images_per_survey: default_synthetic_images(),
+            graded_ratio: default_synthetic_graded_ratio(),
+            duplicate_raw: false,
+            duplicate_graded: false,
+            missing_pairs: false,
+            edge_cases: false,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyntheticTree {
+    pub root: String,
+    pub raw_root: String,
+    pub graded_root: String,
+    pub base_keys: Vec<String>,
+    pub raw_images: u64,
+    pub graded_images: u64,
+    /// Rows and dolphin=1 rows a root run with the default rules should yield.
+    pub expected_rows: u64,
+    pub expected_dolphin_yes: u64,
+}
+
+/// Writes a synthetic raw/graded tree that follows the default rules, so rules
+/// and options can be tried out without touching real survey data.
+pub fn generate_synthetic_tree(options: SyntheticTreeOptions) -> Result<SyntheticTree, AppError> {
+    const AREAS: [&str; 6] = ["AB", "CD", "EF", "GH", "IJ", "KL"];
+    const GRADED_FOLDERS: [&str; 3] = ["IND", "best", "other"];
+
+    let root = match options
+        .output_dir
+        .as_deref()
+        .filter(|dir| !dir.trim().is_empty())
+    {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir().join(format!("survey-labeler-synthetic-{}", unix_millis())),
+    };
+    let raw_root = root.join("Raw");
+    let graded_root = root.join("Graded");
+    fs::create_dir_all(&raw_root)?;
+    fs::create_dir_all(&graded_root)?;
+
+    let mut rng = SimpleRng::new(options.seed);
+    let graded_ratio = options.graded_ratio.clamp(0.0, 1.0);
+    let mut tree = SyntheticTree {
+        root: root.to_string_lossy().to_string(),
+        raw_root: raw_root.to_string_lossy().to_string(),
+        graded_root: graded_root.to_string_lossy().to_string(),
+        base_keys: Vec::new(),
+        raw_images: 0,
+        graded_images: 0,
+        expected_rows: 0,
+        expected_dolphin_yes: 0,
+    };
+
+    for index in 0..options.surveys {
+        let date = synthetic_date(index);
+        let area = AREAS[index as usize % AREAS.len()];
+        let base_key = format!("{}_{}", date, area);
+        let detected = format!("{}_{}", base_key, AREAS[(index as usize + 1) % AREAS.len()]);
+        let raw_dir = raw_root.join(&date[..4]).join(&date[4..6]).join(&base_key);
+        let graded_dir = graded_root.join(&detected);
+
+        let skip_raw = options.missing_pairs && index % 5 == 3;
+        let skip_graded = options.missing_pairs && index % 5 == 4;
+        let duplicated = (options.duplicate_raw && index == 0)
+            || (options.duplicate_graded && index == 1.min(options.surveys - 1));
+        let processed = !skip_raw && !skip_graded && !duplicated;
+
+        if !skip_raw {
+            fs::create_dir_all(&raw_dir)?;
+        }
+        if !skip_graded {
+            fs::create_dir_all(&graded_dir)?;
+        }
+        for image in 1..=options.images_per_survey {
+            let stem = format!("{}_ALA_{:04}", date, image);
+            let extension = if options.edge_cases && image % 7 == 0 {
+                "JPG"
+            } else {
+                "jpg"
+            };
+            let raw_name = if options.edge_cases && image % 11 == 0 {
+                format!("{}_QP_D.{}", stem, extension)
+            } else {
+                format!("{}.{}", stem, extension)
+            };
+            if !skip_raw {
+                let folder = if options.edge_cases && image % 13 == 0 {
+                    raw_dir.join("card 2")
+                } else {
+                    raw_dir.clone()
+                };
+                fs::create_dir_all(&folder)?;
+                fs::write(folder.join(&raw_name), format!("synthetic:{}", raw_name))?;
+                tree.raw_images += 1;
+                if processed {
+                    tree.expected_rows += 1;
+                }
+            }
+            if !skip_graded && rng.next_f64() < graded_ratio {
+                let folder = graded_dir.join(GRADED_FOLDERS[rng.next_below(GRADED_FOLDERS.len())]);
+                fs::create_dir_all(&folder)?;
+                let graded_name = if options.edge_cases && image % 5 == 0 {
+                    format!("{} best.jpg", stem)
+                } else {
+                    format!("{}.jpg", stem)
+                };
+                fs::write(folder.join(&graded_name), format!("synthetic:{}", raw_name))?;
+                tree.graded_images += 1;
+                if processed && !skip_raw {
+                    tree.expected_dolphin_yes += 1;
+                }
+            }
+        }
+        if options.edge_cases && !skip_raw {
+            fs::write(raw_dir.join("notes.txt"), "synthetic survey notes\n")?;
+        }
+        if options.duplicate_raw && index == 0 && !skip_raw {
+            let copy = raw_root.join("backup").join(&base_key);
+            fs::create_dir_all(&copy)?;
+            fs::write(
+                copy.join(format!("{}_ALA_0001.jpg", date)),
+                "synthetic:copy",
+            )?;
+            tree.raw_images += 1;
+        }
+        if options.duplicate_graded && index == 1.min(options.surveys - 1) && !skip_graded {
+            let copy = graded_root.join(format!("{} copy", detected));
+            fs::create_dir_all(&copy)?;
+            fs::write(
+                copy.join(format!("{}_ALA_0001.jpg", date)),
+                "synthetic:copy",
+            )?;
+            tree.graded_images += 1;
+        }
+        tree.base_keys.push(base_key);
+    }
+    Ok(tree)
+}
+
+fn default_synthetic_surveys() -> u32 {
+    5
+}
+
+fn default_synthetic_images() -> u32 {
+    20
+}
+
+fn default_synthetic_graded_ratio() -> f64 {
+    0.3
+}
+
+/// `YYYYMMDD` for the n-th synthetic survey, one survey per day from 2025-01-01
+/// using 28-day months so every date stays valid.
+fn synthetic_date(index: u32) -> String {
+    let day = 1 + index % 28;
+    let month = 1 + (index / 28) % 12;
+    let year = 2025 + index / (28 * 12);
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+/// Small deterministic xorshift generator for synthetic data.
+struct SimpleRng(u64);
+
+impl SimpleRng {
+    fn new(seed: u64) -> Self {
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut value = self.0;
+        value ^= value << 13;
+        value ^= value >> 7;
+        value ^= value << 17;
+        self.0 = value;
+        value
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rules::{compile_rules, Rules};
+    use crate::scan::{scan_roots, ScanOptions};
+    use crate::test_support::TestTree;
+
+    #[test]
+    fn synthetic_tree_scans_as_described() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let tree = generate_synthetic_tree(SyntheticTreeOptions {
+            output_dir: Some(root.to_string_lossy().to_string()),
+            surveys: 6,
+            images_per_survey: 15,
+            duplicate_raw: true,
+            missing_pairs: true,
+            edge_cases: true,
+            seed: 7,
+            ..SyntheticTreeOptions::default()
+        })
+        .expect("generate");
+
+        let compiled = compile_rules(&Rules::default()).expect("compile");
+        let scan = scan_roots(
+            &[PathBuf::from(&tree.raw_root)],
+            &[PathBuf::from(&tree.graded_root)],
+            &compiled,
+            &ScanOptions::default(),
+        )
+        .expect("scan");
+        let mut problem_types: Vec<&str> = scan
+            .problems
+            .iter()
+            .map(|problem| problem.problem_type.as_str())
+            .collect();
+        problem_types.sort();
+        assert_eq!(
+            problem_types,
+            vec!["DUPLICATE_RAW", "GRADED_MISSING", "RAW_MISSING"]
+        );
+        assert_eq!(
+            scan.entries
+                .iter()
+                .filter(|entry| entry.status == "OK")
+                .count(),
+            3
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Helpers shared by the unit tests of several modules.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+
+use tempfile::TempDir;
+
+use crate::output::CsvRow;
+use crate::rules::{compile_rules, CompiledRules, Rules};
+use crate::runs::{run_root_scan, RootRunOptions, RunSummary};
+use crate::{AppError, Host};
+
+/// Default rules compiled once for the property tests, with and without
+/// transliteration.
+pub(crate) fn default_compiled(transliterate: bool) -> &'static CompiledRules {
+    static COMPILED: OnceLock<[CompiledRules; 2]> = OnceLock::new();
+    let compiled = COMPILED.get_or_init(|| {
+        [false, true].map(|transliterate| {
+            compile_rules(&Rules {
+                transliterate,
+                ..Rules::default()
+            })
+            .expect("compile")
+        })
+    });
+    &compiled[usize::from(transliterate)]
+}
+
+/// A temporary folder for one test. It is removed when the test ends, also
+/// when it panics, and two tests never share one.
+pub(crate) struct TestTree {
+    dir: TempDir,
+}
+
+impl TestTree {
+    pub(crate) fn new() -> Self {
+        Self {
+            dir: TempDir::new().expect("temp dir"),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.dir.path().join(path)
+    }
+
+    /// Creates the folder `path` below the tree with its parents.
+    pub(crate) fn mkdir(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = self.join(path);
+        fs::create_dir_all(&path).expect("create");
+        path
+    }
+
+    /// Writes `contents` to `path` below the tree, creating its folders.
+    pub(crate) fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.join(path);
+        fs::create_dir_all(path.parent().expect("parent")).expect("create");
+        fs::write(&path, contents).expect("write");
+        path
+    }
+
+    /// A headless host without progress output whose data folder is `data`
+    /// below the tree.
+    pub(crate) fn host(&self) -> Host {
+        Host::headless(self.join("data"), false)
+    }
+
+    /// Runs the `graded` and `raw` roots of the tree into `out`.
+    pub(crate) fn run_root(
+        &self,
+        host: &Host,
+        options: RootRunOptions,
+        rules: Rules,
+    ) -> Result<RunSummary, AppError> {
+        run_root_scan(
+            host,
+            vec![self.join("graded")],
+            vec![self.join("raw")],
+            self.join("out"),
+            options,
+            rules,
+        )
+    }
+}
+
+/// Root run options that write `merged.csv` and `problems.csv` only, with
+/// the fields of `overrides` on top.
+pub(crate) fn root_options(overrides: serde_json::Value) -> RootRunOptions {
+    let mut options = serde_json::json!({
+        "write_per_survey": false,
+        "write_merged": true,
+        "merged_filename": "merged.csv",
+        "problems_filename": "problems.csv",
+        "per_survey_dirname": "per_survey"
+    });
+    if let (Some(options), Some(overrides)) = (options.as_object_mut(), overrides.as_object()) {
+        options.extend(overrides.clone());
+    }
+    serde_json::from_value(options).expect("options")
+}
+
+/// A row of survey `survey_id_base` whose raw image `raw_relpath` has one
+/// graded copy below `IND` when `dolphin` is 1 and none otherwise. Tests
+/// set the fields they care about with struct update syntax.
+pub(crate) fn csv_row(survey_id_base: &str, raw_relpath: &str, dolphin: u8) -> CsvRow {
+    let filename = Path::new(raw_relpath)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (graded_relpath, graded_hits, graded_winner_type) = if dolphin == 1 {
+        (format!("IND/{filename}"), 1, "IND".to_string())
+    } else {
+        ("RAW".to_string(), 0, "RAW".to_string())
+    };
+    CsvRow {
+        survey_id_base: survey_id_base.to_string(),
+        raw_relpath: raw_relpath.to_string(),
+        filename,
+        dolphin,
+        labels: Vec::new(),
+        graded_relpath,
+        graded_hits,
+        graded_winner_type,
+        survey_id_raw_detected: None,
+        survey_id_graded_detected: None,
+        winner: None,
+        raw_path: PathBuf::from(raw_relpath),
+        survey_date: None,
+        area: None,
+        notes: None,
+        quality: None,
+        label_confidence: 1.0,
+        decision_reason: String::new(),
+        capture_datetime: None,
+        gps: None,
+        track_position: None,
+        video_frame: None,
+        manual_label: false,
+    }
+}
+
+/// Replaces the OS keyring with one in memory for the rest of the test
+/// process. Entries are shared by name like in a real keyring, and tests
+/// keep apart by their data folders, which are part of the names.
+pub(crate) fn use_memory_keyring() {
+    static INSTALLED: OnceLock<()> = OnceLock::new();
+    INSTALLED.get_or_init(|| {
+        keyring::set_default_credential_builder(Box::new(MemoryKeyring::default()));
+    });
+}
+
+type Secrets = Arc<Mutex<HashMap<(String, String), Vec<u8>>>>;
+
+#[derive(Debug, Default)]
+struct MemoryKeyring {
+    secrets: Secrets,
+}
+
+#[derive(Debug)]
+struct MemoryCredential {
+    secrets: Secrets,
+    key: (String, String),
+}
+
+impl CredentialBuilderApi for MemoryKeyring {
+    fn build(
+        &self,
+        _target: Option<&str>,
+        service: &str,
+        user: &str,
+    ) -> keyring::Result<Box<Credential>> {
+        Ok(Box::new(MemoryCredential {
+            secrets: Arc::clone(&self.secrets),
+            key: (service.to_string(), user.to_string()),
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl CredentialApi for MemoryCredential {
+    fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+        let mut secrets = self.secrets.lock().expect("secrets");
+        secrets.insert(self.key.clone(), secret.to_vec());
+        Ok(())
+    }
+
+    fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+        let secrets = self.secrets.lock().expect("secrets");
+        secrets
+            .get(&self.key)
+            .cloned()
+            .ok_or(keyring::Error::NoEntry)
+    }
+
+    fn delete_credential(&self) -> keyring::Result<()> {
+        let mut secrets = self.secrets.lock().expect("secrets");
+        secrets
+            .remove(&self.key)
+            .map(|_| ())
+            .ok_or(keyring::Error::NoEntry)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,333 @@
+//! GPX tracks: finding the track recorded during a survey and placing images
+//! on it by their EXIF capture time.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Where survey tracks come from and how camera times line up with them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackSettings {
+    /// Folder searched for `.gpx` files whose name contains the survey id,
+    /// after the raw and graded survey folders.
+    #[serde(default)]
+    pub tracks_dir: Option<String>,
+    /// GPX file per survey id; takes precedence over discovery.
+    #[serde(default)]
+    pub gpx_files: BTreeMap<String, String>,
+    /// Camera clock offset from UTC in minutes (e.g. `120` for CEST). EXIF
+    /// capture times carry no zone, GPX times are UTC.
+    #[serde(default)]
+    pub camera_utc_offset_minutes: i32,
+    /// Images between two track points further apart than this get no position.
+    #[serde(default = "default_track_max_gap_secs")]
+    pub track_max_gap_secs: u64,
+}
+
+impl Default for TrackSettings {
+    fn default() -> Self {
+        Self {
+            tracks_dir: None,
+            gpx_files: BTreeMap::new(),
+            camera_utc_offset_minutes: 0,
+            track_max_gap_secs: default_track_max_gap_secs(),
+        }
+    }
+}
+
+fn default_track_max_gap_secs() -> u64 {
+    300
+}
+
+/// Interpolated image position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct TrackFix {
+    pub(crate) lat: f64,
+    pub(crate) lon: f64,
+    /// Distance to the nearest recorded track point in metres; 0 when the
+    /// image time matches a point.
+    pub(crate) error_m: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TrackPoint {
+    /// Seconds since the Unix epoch, UTC.
+    time: f64,
+    lat: f64,
+    lon: f64,
+}
+
+/// Timestamped points of one GPX file, sorted by time.
+#[derive(Clone, Debug)]
+pub(crate) struct SurveyTrack {
+    pub(crate) path: PathBuf,
+    points: Vec<TrackPoint>,
+    utc_offset_secs: f64,
+    max_gap_secs: f64,
+}
+
+impl SurveyTrack {
+    pub(crate) fn load(path: &Path, settings: &TrackSettings) -> Result<Self, AppError> {
+        let data = fs::read_to_string(path)?;
+        let mut points = parse_gpx(&data).map_err(|err| {
+            AppError::Validation(format!(
+                "Invalid GPX file {}: {}",
+                path.to_string_lossy(),
+                err
+            ))
+        })?;
+        if points.is_empty() {
+            return Err(AppError::Validation(format!(
+                "GPX file {} has no timestamped track points.",
+                path.to_string_lossy()
+            )));
+        }
+        points.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Self {
+            path: path.to_path_buf(),
+            points,
+            utc_offset_secs: f64::from(settings.camera_utc_offset_minutes) * 60.0,
+            max_gap_secs: settings.track_max_gap_secs as f64,
+        })
+    }
+
+    /// Position for a `capture_datetime` value in camera time.
+    pub(crate) fn position_for(&self, capture_datetime: &str) -> Option<TrackFix> {
+        self.position_at(parse_timestamp(capture_datetime)? - self.utc_offset_secs)
+    }
+
+    /// Linear interpolation between the points around `time`. Times outside
+    /// the track or inside a gap longer than `max_gap_secs` yield `None`.
+    fn position_at(&self, time: f64) -> Option<TrackFix> {
+        let index = self.points.partition_point(|point| point.time <= time);
+        let before = self.points.get(index.checked_sub(1)?)?;
+        if before.time == time {
+            return Some(TrackFix {
+                lat: before.lat,
+                lon: before.lon,
+                error_m: 0.0,
+            });
+        }
+        let after = self.points.get(index)?;
+        let span = after.time - before.time;
+        if span > self.max_gap_secs {
+            return None;
+        }
+        let fraction = (time - before.time) / span;
+        let lat = before.lat + (after.lat - before.lat) * fraction;
+        let lon = before.lon + (after.lon - before.lon) * fraction;
+        let error_m = distance_m(lat, lon, before.lat, before.lon)
+            .min(distance_m(lat, lon, after.lat, after.lon));
+        Some(TrackFix { lat, lon, error_m })
+    }
+}
+
+/// Track of a survey: the explicit `gpx_files` entry, else a `.gpx` file in
+/// one of `survey_dirs` (preferring names containing the survey id), else a
+/// file in `tracks_dir` named after the survey id.
+pub(crate) fn load_survey_track(
+    settings: &TrackSettings,
+    base_key: &str,
+    survey_dirs: &[&Path],
+) -> Result<Option<SurveyTrack>, AppError> {
+    let path = match settings.gpx_files.get(base_key) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => find_gpx(settings, base_key, survey_dirs)?,
+    };
+    path.map(|path| SurveyTrack::load(&path, settings))
+        .transpose()
+}
+
+fn find_gpx(
+    settings: &TrackSettings,
+    base_key: &str,
+    survey_dirs: &[&Path],
+) -> Result<Option<PathBuf>, AppError> {
+    let key = base_key.to_lowercase();
+    let names_key = |path: &PathBuf| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_lowercase().contains(&key))
+            .unwrap_or(false)
+    };
+    for dir in survey_dirs {
+        let files = gpx_files_in(dir)?;
+        if let Some(path) = files.iter().find(|path| names_key(path)).or(files.first()) {
+            return Ok(Some(path.clone()));
+        }
+    }
+    if let Some(dir) = settings.tracks_dir.as_deref() {
+        return Ok(gpx_files_in(Path::new(dir))?.into_iter().find(names_key));
+    }
+    Ok(None)
+}
+
+fn gpx_files_in(dir: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_gpx = path
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("gpx"))
+            .unwrap_or(false);
+        if is_gpx && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Track and route points that carry a `<time>`; untimed points are skipped.
+/// Waypoints are ignored, they usually mark sightings rather than the route.
+fn parse_gpx(data: &str) -> Result<Vec<TrackPoint>, String> {
+    let doc = roxmltree::Document::parse(data).map_err(|err| err.to_string())?;
+    let mut points = Vec::new();
+    for node in doc
+        .descendants()
+        .filter(|node| node.has_tag_name("trkpt") || node.has_tag_name("rtept"))
+    {
+        let coordinate = |name: &str| -> Result<f64, String> {
+            node.attribute(name)
+                .and_then(|value| value.trim().parse::<f64>().ok())
+                .ok_or_else(|| format!("point without a valid '{}' attribute", name))
+        };
+        let (lat, lon) = (coordinate("lat")?, coordinate("lon")?);
+        let Some(time) = node
+            .children()
+            .find(|child| child.has_tag_name("time"))
+            .and_then(|child| child.text())
+        else {
+            continue;
+        };
+        let time = parse_timestamp(time).ok_or_else(|| format!("invalid time '{}'", time))?;
+        points.push(TrackPoint { time, lat, lon });
+    }
+    Ok(points)
+}
+
+/// Seconds since the Unix epoch for `YYYY-MM-DD[T ]HH:MM:SS[.fff][Z|±HH:MM]`.
+/// Times without a zone are taken as UTC.
+pub(crate) fn parse_timestamp(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let (date, rest) = value.split_at_checked(10)?;
+    let mut date_parts = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+    let rest = rest.strip_prefix(['T', ' '])?;
+    let (time, zone) = rest.split_at(rest.find(['Z', 'z', '+', '-']).unwrap_or(rest.len()));
+    let mut time_parts = time.split(':');
+    let hour = time_parts.next()?.parse::<i64>().ok()?;
+    let minute = time_parts.next()?.parse::<i64>().ok()?;
+    let second = time_parts.next()?.parse::<f64>().ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0.0..61.0).contains(&second)
+    {
+        return None;
+    }
+    let offset_minutes = match zone {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            let digits = zone[1..].replace(':', "");
+            let (hours, minutes) = digits.split_at_checked(2)?;
+            let minutes = if minutes.is_empty() { "0" } else { minutes };
+            sign * (hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?)
+        }
+    };
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60
+        - offset_minutes * 60;
+    Some(seconds as f64 + second)
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Great-circle distance in metres.
+fn distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = (lat2 - lat1).to_radians();
+    let dlambda = (lon2 - lon1).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestTree;
+
+    const GPX: &str = r#"<?xml version="1.0"?>
+<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk><trkseg>
+    <trkpt lat="43.5" lon="16.0"><time>2024-05-01T08:00:00Z</time></trkpt>
+    <trkpt lat="43.6" lon="16.2"><time>2024-05-01T08:01:40Z</time></trkpt>
+    <trkpt lat="43.7" lon="16.4"><time>2024-05-01T09:00:00Z</time></trkpt>
+    <trkpt lat="44.0" lon="17.0"/>
+  </trkseg></trk>
+</gpx>"#;
+
+    #[test]
+    fn timestamps_parse_with_zones() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(
+            parse_timestamp("2024-05-01T10:00:00+02:00"),
+            Some(1_714_550_400.0)
+        );
+        assert_eq!(
+            parse_timestamp("2024-05-01 08:00:00.5"),
+            Some(1_714_550_400.5)
+        );
+        assert_eq!(parse_timestamp("2024-13-01T08:00:00Z"), None);
+        assert_eq!(parse_timestamp("2024-05-01"), None);
+    }
+
+    #[test]
+    fn survey_track_interpolates_within_gap_limit() {
+        let tree = TestTree::new();
+        let root = tree.path();
+        let survey = root.join("20240501_AB");
+        fs::create_dir_all(&survey).expect("create");
+        fs::write(survey.join("20240501_AB.GPX"), GPX).expect("write");
+        let settings = TrackSettings {
+            camera_utc_offset_minutes: 120,
+            ..TrackSettings::default()
+        };
+        let track = load_survey_track(&settings, "20240501_AB", &[&survey])
+            .expect("load")
+            .expect("track");
+        assert_eq!(track.points.len(), 3);
+
+        let exact = track.position_for("2024-05-01T10:00:00").expect("exact");
+        assert_eq!((exact.lat, exact.lon, exact.error_m), (43.5, 16.0, 0.0));
+        let halfway = track.position_for("2024-05-01T10:00:50").expect("halfway");
+        assert!((halfway.lat - 43.55).abs() < 1e-9);
+        assert!((halfway.lon - 16.1).abs() < 1e-9);
+        assert!(halfway.error_m > 9_000.0 && halfway.error_m < 10_000.0);
+        // 58 minutes between the last two points exceeds the 5 minute limit.
+        assert_eq!(track.position_for("2024-05-01T10:30:00"), None);
+        assert_eq!(track.position_for("2024-05-01T09:59:59"), None);
+
+        assert!(load_survey_track(&settings, "20240502_CD", &[root])
+            .expect("load")
+            .is_none());
+    }
+}
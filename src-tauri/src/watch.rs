@@ -0,0 +1,497 @@
+//! Watching a graded and a raw root and matching the surveys that change.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+
+use crate::control::CancelToken;
+use crate::matching::{process_pair, PairOptions};
+use crate::output::CsvSettings;
+use crate::paths::normalize_root;
+use crate::rules::{compiled_rules, CompiledRules, Rules};
+use crate::scan::{
+    load_scan_options, scan_roots, ScanEntry, ScanOptions, ScanSettings, SurveyFolder,
+};
+use crate::settings::load_settings;
+use crate::{new_run_id, AppError, Host, ProgressGranularity, SurveyUpdateEvent};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WatchOptions {
+    /// Quiet time after the last filesystem change before the affected
+    /// surveys are matched again.
+    #[serde(default = "default_watch_debounce_ms")]
+    pub debounce_ms: u64,
+    #[serde(flatten)]
+    pub scan: ScanSettings,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            debounce_ms: default_watch_debounce_ms(),
+            scan: ScanSettings::default(),
+        }
+    }
+}
+
+/// A running watch: dropping the watcher ends its event loop.
+struct ActiveWatch {
+    _watcher: RecommendedWatcher,
+    cancel: CancelToken,
+}
+
+fn active_watches() -> &'static Mutex<HashMap<String, ActiveWatch>> {
+    static WATCHES: OnceLock<Mutex<HashMap<String, ActiveWatch>>> = OnceLock::new();
+    WATCHES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Watches both roots and, whenever files below them change, scans the roots
+/// again and re-matches only the surveys whose folders saw a change, appeared
+/// or changed pairing. Each of those surveys is reported as a `survey-updated`
+/// event; no result files are written. Returns the id for `stop_watch`.
+pub fn start_watch(
+    host: &Host,
+    graded_root: PathBuf,
+    raw_root: PathBuf,
+    options: WatchOptions,
+    rules: Rules,
+) -> Result<String, AppError> {
+    let app_settings = load_settings(host)?;
+    let graded_root = normalize_root(&graded_root, app_settings.resolve_mapped_drives);
+    let raw_root = normalize_root(&raw_root, app_settings.resolve_mapped_drives);
+    let compiled = compiled_rules(host, &rules)?;
+    let scan_options = load_scan_options(&options.scan, &compiled)?;
+    let known = scan_roots(
+        std::slice::from_ref(&raw_root),
+        std::slice::from_ref(&graded_root),
+        &compiled,
+        &scan_options,
+    )?
+    .entries;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|err| AppError::Message(format!("Cannot start watching: {}", err)))?;
+    for root in [&raw_root, &graded_root] {
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .map_err(|err| {
+                AppError::Message(format!("Cannot watch {}: {}", root.to_string_lossy(), err))
+            })?;
+    }
+
+    let watch_id = new_run_id();
+    let cancel = CancelToken::default();
+    let watch = WatchLoop {
+        host: host.clone(),
+        watch_id: watch_id.clone(),
+        raw_root,
+        graded_root,
+        rules: compiled,
+        scan_options,
+        pair_options: PairOptions::new(
+            ProgressGranularity::Percent { step: 100 },
+            &CsvSettings::default(),
+            &app_settings,
+            &cancel,
+        ),
+        debounce: Duration::from_millis(options.debounce_ms),
+    };
+    std::thread::spawn(move || watch.run(receiver, known));
+    if let Ok(mut watches) = active_watches().lock() {
+        watches.insert(
+            watch_id.clone(),
+            ActiveWatch {
+                _watcher: watcher,
+                cancel,
+            },
+        );
+    }
+    Ok(watch_id)
+}
+
+/// Stops the watch with `watch_id`, or every watch when `None`. Returns how
+/// many watches were stopped.
+pub fn stop_watch(watch_id: Option<&str>) -> usize {
+    let Ok(mut watches) = active_watches().lock() else {
+        return 0;
+    };
+    let ids: Vec<String> = watches
+        .keys()
+        .filter(|id| watch_id.is_none_or(|wanted| wanted == id.as_str()))
+        .cloned()
+        .collect();
+    for id in &ids {
+        if let Some(watch) = watches.remove(id) {
+            watch.cancel.cancel();
+        }
+    }
+    ids.len()
+}
+
+/// Everything the event loop of one watch needs after `start_watch` returns.
+struct WatchLoop {
+    host: Host,
+    watch_id: String,
+    raw_root: PathBuf,
+    graded_root: PathBuf,
+    rules: Arc<CompiledRules>,
+    scan_options: ScanOptions,
+    pair_options: PairOptions,
+    debounce: Duration,
+}
+
+impl WatchLoop {
+    fn run(
+        self,
+        receiver: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+        mut known: Vec<ScanEntry>,
+    ) {
+        // Reads, including the watch's own, show up as access events.
+        let event_paths = |event: notify::Result<notify::Event>| match event {
+            Ok(event) if event.kind.is_access() => Vec::new(),
+            Ok(event) => event.paths,
+            Err(err) => err.paths,
+        };
+        // Ends when `stop_watch` drops the watcher and with it the sender.
+        while let Ok(event) = receiver.recv() {
+            let mut changed = event_paths(event);
+            loop {
+                match receiver.recv_timeout(self.debounce) {
+                    Ok(event) => changed.extend(event_paths(event)),
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            if self.pair_options.cancel.is_cancelled() {
+                return;
+            }
+            if changed.is_empty() {
+                continue;
+            }
+            let scan = match scan_roots(
+                std::slice::from_ref(&self.raw_root),
+                std::slice::from_ref(&self.graded_root),
+                &self.rules,
+                &self.scan_options,
+            ) {
+                Ok(scan) => scan,
+                Err(err) => {
+                    self.host.survey_updated(self.scan_error(
+                        String::new(),
+                        None,
+                        None,
+                        err.to_string(),
+                    ));
+                    continue;
+                }
+            };
+            // Folders that could not be read, up to a whole root, leave their
+            // surveys out of the scan; say why before they show as removed.
+            for problem in &scan.problems {
+                if problem.problem_type == "SCAN_ERROR" {
+                    self.host.survey_updated(self.scan_error(
+                        problem.survey_id_base.clone(),
+                        problem.raw_path.clone(),
+                        problem.graded_path.clone(),
+                        problem.details.clone().unwrap_or_default(),
+                    ));
+                }
+            }
+            let (updated, removed) = changed_surveys(&known, &scan.entries, &changed);
+            for entry in updated {
+                if self.pair_options.cancel.is_cancelled() {
+                    return;
+                }
+                self.host.survey_updated(self.survey_update(entry));
+            }
+            for base_key in removed {
+                self.host.survey_updated(SurveyUpdateEvent {
+                    watch_id: self.watch_id.clone(),
+                    survey_id_base: base_key,
+                    status: "REMOVED".to_string(),
+                    raw_path: None,
+                    graded_path: None,
+                    rows: 0,
+                    dolphin_yes: 0,
+                    dolphin_no: 0,
+                    ambiguity_warnings: 0,
+                    error: None,
+                });
+            }
+            known = scan.entries;
+        }
+    }
+
+    fn scan_error(
+        &self,
+        survey_id_base: String,
+        raw_path: Option<String>,
+        graded_path: Option<String>,
+        error: String,
+    ) -> SurveyUpdateEvent {
+        SurveyUpdateEvent {
+            watch_id: self.watch_id.clone(),
+            survey_id_base,
+            status: "SCAN_ERROR".to_string(),
+            raw_path,
+            graded_path,
+            rows: 0,
+            dolphin_yes: 0,
+            dolphin_no: 0,
+            ambiguity_warnings: 0,
+            error: Some(error),
+        }
+    }
+
+    fn survey_update(&self, entry: &ScanEntry) -> SurveyUpdateEvent {
+        let path = |folder: &Option<SurveyFolder>| {
+            folder
+                .as_ref()
+                .map(|folder| folder.path.to_string_lossy().to_string())
+        };
+        let mut event = SurveyUpdateEvent {
+            watch_id: self.watch_id.clone(),
+            survey_id_base: entry.base_key.clone(),
+            status: entry.status.clone(),
+            raw_path: path(&entry.raw),
+            graded_path: path(&entry.graded),
+            rows: 0,
+            dolphin_yes: 0,
+            dolphin_no: 0,
+            ambiguity_warnings: 0,
+            error: None,
+        };
+        let (Some(raw), Some(graded), "OK") = (&entry.raw, &entry.graded, entry.status.as_str())
+        else {
+            return event;
+        };
+        match process_pair(
+            &self.host,
+            &self.rules,
+            &entry.base_key,
+            raw,
+            graded,
+            self.pair_options.clone(),
+            &mut |_| Ok(()),
+        ) {
+            Ok(result) => {
+                event.rows = result.counts.rows;
+                event.dolphin_yes = result.counts.dolphin_yes;
+                event.dolphin_no = result.counts.dolphin_no();
+                event.ambiguity_warnings = result.ambiguity_warnings;
+            }
+            Err(err) => event.error = Some(err.to_string()),
+        }
+        event
+    }
+}
+
+/// Surveys of `current` to match again after the files in `changed` changed:
+/// those with a changed path below one of their folders and those that are
+/// new or paired differently than in `previous`. Also returns the base keys
+/// of `previous` that are gone.
+fn changed_surveys<'a>(
+    previous: &[ScanEntry],
+    current: &'a [ScanEntry],
+    changed: &[PathBuf],
+) -> (Vec<&'a ScanEntry>, Vec<String>) {
+    let folder_path =
+        |folder: &Option<SurveyFolder>| folder.as_ref().map(|folder| folder.path.clone());
+    let before: HashMap<&str, &ScanEntry> = previous
+        .iter()
+        .map(|entry| (entry.base_key.as_str(), entry))
+        .collect();
+    let updated = current
+        .iter()
+        .filter(|entry| {
+            let touched = entry
+                .raw_candidates
+                .iter()
+                .chain(&entry.graded_candidates)
+                .any(|folder| changed.iter().any(|path| path.starts_with(&folder.path)));
+            touched
+                || before.get(entry.base_key.as_str()).is_none_or(|old| {
+                    old.status != entry.status
+                        || folder_path(&old.raw) != folder_path(&entry.raw)
+                        || folder_path(&old.graded) != folder_path(&entry.graded)
+                })
+        })
+        .collect();
+    let current_keys: HashSet<&str> = current
+        .iter()
+        .map(|entry| entry.base_key.as_str())
+        .collect();
+    let removed = previous
+        .iter()
+        .filter(|entry| !current_keys.contains(entry.base_key.as_str()))
+        .map(|entry| entry.base_key.clone())
+        .collect();
+    (updated, removed)
+}
+
+fn default_watch_debounce_ms() -> u64 {
+    2000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scan::load_scan_options;
+    use crate::test_support::TestTree;
+
+    #[test]
+    fn a_root_that_cannot_be_scanned_is_reported() {
+        #[derive(Default)]
+        struct Updates(Mutex<Vec<SurveyUpdateEvent>>);
+
+        impl crate::ProgressSink for Updates {
+            fn progress(&self, _event: crate::ProgressEvent) {}
+
+            fn survey_updated(&self, event: SurveyUpdateEvent) {
+                self.0.lock().expect("updates").push(event);
+            }
+        }
+
+        let tree = TestTree::new();
+        tree.write("raw/20240101_AB/IMG_0001.JPG", "x");
+        tree.write("graded/20240101_AB/Dolphins/IMG_0001.JPG", "x");
+        let updates = Arc::new(Updates::default());
+        let host = Host::headless_with_sink(tree.join("data"), updates.clone());
+        let rules = compiled_rules(&host, &Rules::default()).expect("rules");
+        let scan_options = load_scan_options(&ScanSettings::default(), &rules).expect("options");
+        let (raw_root, graded_root) = (tree.join("raw"), tree.join("graded"));
+        let known = scan_roots(
+            std::slice::from_ref(&raw_root),
+            std::slice::from_ref(&graded_root),
+            &rules,
+            &scan_options,
+        )
+        .expect("scan")
+        .entries;
+        let cancel = CancelToken::default();
+        let watch = WatchLoop {
+            host,
+            watch_id: "watch".to_string(),
+            raw_root: raw_root.clone(),
+            graded_root,
+            rules,
+            scan_options,
+            pair_options: PairOptions::new(
+                ProgressGranularity::default(),
+                &CsvSettings::default(),
+                &Default::default(),
+                &cancel,
+            ),
+            debounce: Duration::from_millis(10),
+        };
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let watching = std::thread::spawn(move || watch.run(receiver, known));
+
+        std::fs::remove_dir_all(&raw_root).expect("remove");
+        let removed =
+            notify::Event::new(notify::EventKind::Remove(notify::event::RemoveKind::Folder))
+                .add_path(raw_root.clone());
+        sender.send(Ok(removed)).expect("send");
+        for _ in 0..500 {
+            if updates.0.lock().expect("updates").len() >= 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(sender);
+        watching.join().expect("watch");
+
+        let updates = updates.0.lock().expect("updates");
+        let statuses: Vec<&str> = updates
+            .iter()
+            .map(|update| update.status.as_str())
+            .collect();
+        assert_eq!(statuses, ["SCAN_ERROR", "PROBLEM"]);
+        assert_eq!(
+            updates[0].raw_path.as_deref(),
+            Some(raw_root.to_string_lossy().as_ref())
+        );
+        assert!(updates[0]
+            .error
+            .as_deref()
+            .is_some_and(|error| error.starts_with("Could not read")));
+    }
+
+    #[test]
+    fn watch_rematches_only_changed_surveys() {
+        let folder = |path: &str| SurveyFolder {
+            path: PathBuf::from(path),
+            detected_id: None,
+            part: None,
+        };
+        let entry = |key: &str, raw: Option<&str>, graded: Option<&str>| ScanEntry {
+            base_key: key.to_string(),
+            raw: raw.map(folder),
+            graded: graded.map(folder),
+            status: if raw.is_some() && graded.is_some() {
+                "OK"
+            } else {
+                "PROBLEM"
+            }
+            .to_string(),
+            raw_candidates: raw.map(folder).into_iter().collect(),
+            graded_candidates: graded.map(folder).into_iter().collect(),
+            raw_decided_by: None,
+            graded_decided_by: None,
+        };
+        let previous = vec![
+            entry(
+                "20240101_AB",
+                Some("/raw/20240101_AB"),
+                Some("/graded/20240101_AB"),
+            ),
+            entry(
+                "20240102_AB",
+                Some("/raw/20240102_AB"),
+                Some("/graded/20240102_AB"),
+            ),
+            entry("20240103_AB", Some("/raw/20240103_AB"), None),
+            entry(
+                "20240104_AB",
+                Some("/raw/20240104_AB"),
+                Some("/graded/20240104_AB"),
+            ),
+        ];
+        let current = vec![
+            entry(
+                "20240101_AB",
+                Some("/raw/20240101_AB"),
+                Some("/graded/20240101_AB"),
+            ),
+            entry(
+                "20240102_AB",
+                Some("/raw/20240102_AB"),
+                Some("/graded/20240102_AB"),
+            ),
+            entry(
+                "20240103_AB",
+                Some("/raw/20240103_AB"),
+                Some("/graded/20240103_AB"),
+            ),
+            entry("20240105_AB", Some("/raw/20240105_AB"), None),
+        ];
+        let changed = vec![
+            PathBuf::from("/graded/20240102_AB/Dolphins/IMG_0001.JPG"),
+            PathBuf::from("/graded/20240103_AB"),
+            PathBuf::from("/raw/unrelated.txt"),
+        ];
+        let (updated, removed) = changed_surveys(&previous, &current, &changed);
+        let keys: Vec<&str> = updated
+            .iter()
+            .map(|entry| entry.base_key.as_str())
+            .collect();
+        assert_eq!(keys, vec!["20240102_AB", "20240103_AB", "20240105_AB"]);
+        assert_eq!(removed, vec!["20240104_AB".to_string()]);
+    }
+}
@@ -0,0 +1,177 @@
+//! Runs each `survey-labeler-cli` subcommand on a one-survey tree and checks
+//! its JSON output, exit code and result files.
+
+use std::fs;
+use std::path::Path;
+
+use assert_cmd::Command;
+use serde_json::Value;
+use tempfile::TempDir;
+
+/// A raw survey of two images, one of them with a graded copy.
+fn survey_tree() -> TempDir {
+    let dir = TempDir::new().expect("temp dir");
+    for (path, content) in [
+        ("Raw/20240101_AB/IMG_0001.jpg", "ab-1"),
+        ("Raw/20240101_AB/IMG_0002.jpg", "ab-2"),
+        ("Graded/20240101_AB/IND/IMG_0001.jpg", "ab-1"),
+    ] {
+        let path = dir.path().join(path);
+        fs::create_dir_all(path.parent().expect("parent")).expect("create");
+        fs::write(path, content).expect("write");
+    }
+    dir
+}
+
+/// The CLI with its data folder inside `root` and progress turned off.
+fn cli(root: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("survey-labeler-cli").expect("binary");
+    cmd.current_dir(root)
+        .args(["--data-dir", "data", "--quiet"]);
+    cmd
+}
+
+fn stdout_json(cmd: &mut Command) -> Value {
+    let output = cmd.assert().success().get_output().stdout.clone();
+    serde_json::from_slice(&output).expect("json on stdout")
+}
+
+fn run_into(root: &Path, output_dir: &str) -> Value {
+    stdout_json(cli(root).args([
+        "run",
+        "--graded-root",
+        "Graded",
+        "--raw-root",
+        "Raw",
+        "--output-dir",
+        output_dir,
+    ]))
+}
+
+#[test]
+fn preview_lists_the_paired_survey() {
+    let tree = survey_tree();
+    let preview = stdout_json(cli(tree.path()).args([
+        "preview",
+        "--graded-root",
+        "Graded",
+        "--raw-root",
+        "Raw",
+    ]));
+    let items = preview["items"].as_array().expect("items");
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["base_key"], "20240101_AB");
+    assert_eq!(items[0]["status"], "OK");
+    assert_eq!(items[0]["raw_image_count"], 2);
+    assert_eq!(items[0]["graded_image_count"], 1);
+}
+
+#[test]
+fn estimate_counts_surveys_and_images() {
+    let tree = survey_tree();
+    let estimate = stdout_json(cli(tree.path()).args([
+        "estimate",
+        "--graded-root",
+        "Graded",
+        "--raw-root",
+        "Raw",
+    ]));
+    assert_eq!(estimate["paired_surveys"], 1);
+    assert_eq!(estimate["raw_images"], 2);
+    assert_eq!(estimate["graded_images"], 1);
+}
+
+#[test]
+fn run_writes_the_merged_csv() {
+    let tree = survey_tree();
+    let summary = run_into(tree.path(), "out");
+    assert_eq!(summary["total_rows"], 2);
+    assert_eq!(summary["dolphin_yes"], 1);
+    assert_eq!(summary["dolphin_no"], 1);
+    let merged = fs::read_to_string(tree.path().join("out").join("merged.csv")).expect("merged");
+    assert!(merged.contains("20240101_AB,IMG_0001.jpg,IMG_0001.jpg,1,IND/IMG_0001.jpg"));
+    assert!(merged.contains("20240101_AB,IMG_0002.jpg,IMG_0002.jpg,0,RAW"));
+}
+
+#[test]
+fn single_labels_one_folder_pair() {
+    let tree = survey_tree();
+    let summary = stdout_json(cli(tree.path()).args([
+        "single",
+        "--graded-dir",
+        "Graded/20240101_AB",
+        "--raw-dir",
+        "Raw/20240101_AB",
+        "--output-dir",
+        "out",
+    ]));
+    assert_eq!(summary["total_rows"], 2);
+    assert_eq!(summary["dolphin_yes"], 1);
+    assert!(tree.path().join("out").join("single.csv").is_file());
+}
+
+#[test]
+fn diff_reports_relabeled_rows() {
+    let tree = survey_tree();
+    run_into(tree.path(), "out");
+    let old_csv = tree.path().join("out").join("merged.csv");
+    let new_csv = tree.path().join("relabeled.csv");
+    let relabeled = fs::read_to_string(&old_csv)
+        .expect("merged")
+        .replace("IMG_0002.jpg,0,RAW", "IMG_0002.jpg,1,RAW");
+    fs::write(&new_csv, relabeled).expect("write");
+    let diff = stdout_json(cli(tree.path()).args([
+        "diff",
+        "--old-csv",
+        "out/merged.csv",
+        "--new-csv",
+        "relabeled.csv",
+    ]));
+    assert_eq!(diff["changed_rows"], 1);
+    assert_eq!(diff["unchanged_rows"], 1);
+    assert_eq!(diff["added_rows"], 0);
+}
+
+#[test]
+fn export_dataset_sorts_images_by_label() {
+    let tree = survey_tree();
+    run_into(tree.path(), "out");
+    let export = stdout_json(cli(tree.path()).args([
+        "export-dataset",
+        "--merged-csv",
+        "out/merged.csv",
+        "--output-dir",
+        "dataset",
+    ]));
+    assert_eq!(export["dolphin"], 1);
+    assert_eq!(export["no_dolphin"], 1);
+    assert_eq!(export["missing"].as_array().map(Vec::len), Some(0));
+    assert!(tree.path().join("dataset").join("dolphin").is_dir());
+}
+
+#[test]
+fn invalid_options_exit_with_a_json_validation_error() {
+    let tree = survey_tree();
+    fs::write(tree.path().join("options.json"), "{").expect("write");
+    let output = cli(tree.path())
+        .args([
+            "--error-format",
+            "json",
+            "run",
+            "--graded-root",
+            "Graded",
+            "--raw-root",
+            "Raw",
+            "--output-dir",
+            "out",
+            "--options",
+            "options.json",
+        ])
+        .assert()
+        .code(3)
+        .get_output()
+        .stderr
+        .clone();
+    let report: Value = serde_json::from_slice(&output).expect("json on stderr");
+    assert_eq!(report["kind"], "validation");
+}
@@ -0,0 +1,152 @@
+//! Runs the whole root scan pipeline headless on a small survey tree with
+//! the usual trouble cases and compares the result files with the CSVs in
+//! `tests/fixtures/golden`. Set `SURVEY_LABELER_UPDATE_GOLDEN=1` to rewrite
+//! the fixtures after an intended change of the output, then review the diff.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use survey_labeler::{run_root_scan, Host, RootRunOptions, Rules};
+
+/// Result files compared with their fixtures, relative to the output folder.
+const GOLDEN_FILES: [&str; 6] = [
+    "merged.csv",
+    "problems.csv",
+    "pairings.csv",
+    "per_survey/20240101_AB.csv",
+    "per_survey/20240103_EF.csv",
+    "per_survey/20240106_MN.csv",
+];
+
+/// Files of the tree as (path below the root, content). Equal content keeps
+/// the sizes of raw and graded copies equal where filename+size ids apply.
+const TREE: &[(&str, &str)] = &[
+    // Plain survey: winners by priority, an edit suffix, a rejected image
+    // and a raw image without graded copy.
+    ("Raw/20240101_AB/IMG_0001.jpg", "ab-1"),
+    ("Raw/20240101_AB/IMG_0002.jpg", "ab-2"),
+    ("Raw/20240101_AB/IMG_0003.jpg", "ab-3"),
+    ("Raw/20240101_AB/IMG_0004.jpg", "ab-4"),
+    ("Graded/20240101_AB/IND/IMG_0001.jpg", "ab-1"),
+    ("Graded/20240101_AB/other/IMG_0001.jpg", "ab-1"),
+    ("Graded/20240101_AB/best/IMG_0002_edit.jpg", "ab-2"),
+    ("Graded/20240101_AB/other/IMG_0002.jpg", "ab-2"),
+    ("Graded/20240101_AB/reject/IMG_0003.jpg", "ab-3"),
+    // Raw survey without graded folder.
+    ("Raw/20240102_CD/IMG_0001.jpg", "cd-1"),
+    // Names without image id: matched by filename and size, and two raw
+    // cards holding a file of the same name and size.
+    ("Raw/20240103_EF/card1/frame.jpg", "ef-1"),
+    ("Raw/20240103_EF/card2/frame.jpg", "ef-2"),
+    ("Raw/20240103_EF/card1/shot.jpg", "ef-shot"),
+    ("Graded/20240103_EF/best/frame.jpg", "ef-1"),
+    ("Graded/20240103_EF/other/SHOT.JPG", "ef-shot"),
+    // Survey found twice below the raw root, left out of the run.
+    ("Raw/20240104_IJ/IMG_0010.jpg", "ij-10"),
+    ("Raw/backup/20240104_IJ/IMG_0010.jpg", "ij-10"),
+    ("Graded/20240104_IJ/IND/IMG_0010.jpg", "ij-10"),
+    // Graded survey without raw folder.
+    ("Graded/20240105_KL/IND/IMG_0020.jpg", "kl-20"),
+    // Sub-survey suffix, lowercase area and spaces in the names.
+    ("Raw/20240106_mn_op Boat/IMG_0030 1.jpg", "mn-30"),
+    ("Raw/20240106_mn_op Boat/IMG_0031.jpg", "mn-31"),
+    ("Graded/20240106_MN day 2/ind pick/IMG_0030.jpg", "mn-30"),
+];
+
+#[test]
+fn root_scan_matches_golden_fixtures() {
+    let root = std::env::temp_dir().join("survey_labeler_golden_test");
+    let _ = fs::remove_dir_all(&root);
+    for (path, content) in TREE {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().expect("parent")).expect("create");
+        fs::write(path, content).expect("write");
+    }
+
+    let options: RootRunOptions = serde_json::from_value(serde_json::json!({
+        "write_per_survey": true,
+        "write_merged": true,
+        "merged_filename": "merged.csv",
+        "problems_filename": "problems.csv",
+        "per_survey_dirname": "per_survey",
+        "write_summary_markdown": false
+    }))
+    .expect("options");
+    let rules = Rules {
+        graded_negative_contains_any: vec!["reject".to_string()],
+        ..Rules::default()
+    };
+    let output_dir = root.join("out");
+    run_root_scan(
+        &Host::headless(root.join("data"), false),
+        vec![root.join("Graded")],
+        vec![root.join("Raw")],
+        output_dir.clone(),
+        options,
+        rules,
+    )
+    .expect("run");
+
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("golden");
+    let update = std::env::var_os("SURVEY_LABELER_UPDATE_GOLDEN").is_some();
+    let mut mismatches = Vec::new();
+    for name in GOLDEN_FILES {
+        let actual = normalize(
+            &fs::read_to_string(output_dir.join(name)).expect("read"),
+            &root,
+        );
+        let fixture = fixtures.join(name);
+        if update {
+            fs::create_dir_all(fixture.parent().expect("parent")).expect("create");
+            fs::write(&fixture, &actual).expect("write fixture");
+        } else if fs::read_to_string(&fixture)
+            .map(|expected| expected.replace("\r\n", "\n"))
+            .ok()
+            != Some(actual.clone())
+        {
+            mismatches.push(format!("{}:\n{}", name, actual));
+        }
+    }
+    let mut unexpected = tree_files(&output_dir.join("per_survey"));
+    unexpected.retain(|name| !GOLDEN_FILES.contains(&name.as_str()));
+    let _ = fs::remove_dir_all(&root);
+    assert!(
+        unexpected.is_empty(),
+        "result files without fixture: {:?}",
+        unexpected
+    );
+    assert!(
+        mismatches.is_empty(),
+        "output differs from tests/fixtures/golden (rerun with \
+         SURVEY_LABELER_UPDATE_GOLDEN=1 to accept it):\n\n{}",
+        mismatches.join("\n")
+    );
+}
+
+/// Result text with the temp folder and platform differences taken out.
+fn normalize(text: &str, root: &Path) -> String {
+    text.replace(&root.to_string_lossy().to_string(), "<root>")
+        .replace('\\', "/")
+        .replace("\r\n", "\n")
+}
+
+/// Files below `dir` as `per_survey/<name>` paths.
+fn tree_files(dir: &Path) -> Vec<String> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("read per_survey")
+        .map(|entry| entry.expect("entry").path())
+        .collect();
+    files.sort();
+    files
+        .iter()
+        .map(|path| {
+            format!(
+                "per_survey/{}",
+                path.file_name().expect("name").to_string_lossy()
+            )
+        })
+        .collect()
+}
@@ -7,6 +7,7 @@ import { open as openShell } from '@tauri-apps/plugin-shell'
 
 type Rules = {
   extensions: string[]
+  camera_raw_extensions?: string[]
   survey_id_regex_detected: string
   survey_id_regex_base: string
   image_id_regex: string
@@ -14,18 +15,185 @@ type Rules = {
   graded_priority_secondary_tokens: string[]
   graded_negative_contains_any: string[]
   graded_positive_contains_any: string[]
+  token_synonyms?: Record<string, string[]>
+  graded_excluded_subfolders?: string[]
+  exclude_dir_globs?: string[]
+  max_scan_depth?: number | null
+  follow_symlinks?: boolean
+  scan_archives?: boolean
+  video_mode?: boolean
+  video_extensions?: string[]
+  video_frame_regex?: string
+  graded_dedupe?: CandidateDedupe
+  file_id_collisions?: CollisionPolicy
+  matching_strategy?: MatchingStrategy
+  split_combo_graded_folders?: boolean
+  split_combo_raw_folders?: boolean
+  base_key_case?: 'upper' | 'lower' | 'preserve'
+  transliterate?: boolean
+  base_key_schemes?: BaseKeyScheme[]
+  active_base_key_scheme?: string | null
+  survey_fields_regex?: string
+  notes_filenames?: string[]
+  notes_line_regex?: string
+  quality_tokens?: string[]
+  perceptual_fallback?: boolean
+  perceptual_max_distance?: number
+  labels?: LabelRule[]
 }
 
+type LabelRule = {
+  name: string
+  positive_contains_any: string[]
+  negative_contains_any?: string[]
+}
+
+type BaseKeyScheme = {
+  name: string
+  survey_id_regex_detected: string
+  survey_id_regex_base: string
+  case?: 'upper' | 'lower' | 'preserve' | null
+  separator?: string | null
+}
+
+type CandidateDedupe = 'off' | 'size' | 'hash'
+
+type CollisionPolicy = 'warn' | 'hash' | 'refuse'
+
+type MatchingStrategy = 'filename' | 'checksum'
+
+type RuleProfile = {
+  name: string
+  active: boolean
+  saved_at_ms: number | null
+}
+
+type RulesUpdate = {
+  rules: Rules
+  backup_path: string | null
+}
+
+type ProblemSeverity = 'error' | 'warning' | 'info'
+
 type RootRunOptions = {
   write_per_survey: boolean
   write_merged: boolean
   merged_filename: string
   problems_filename: string
   per_survey_dirname: string
+  write_pairings?: boolean
+  pairings_filename?: string
+  write_summary_markdown?: boolean
+  summary_markdown_filename?: string
+  write_html_report?: boolean
+  html_report_filename?: string
+  send_email_report?: boolean
+  export_postgres?: boolean
+  write_qa_workbook?: boolean
+  qa_workbook_filename?: string
+  write_access_export?: boolean
+  access_export_filename?: string
+  write_sqlite?: boolean
+  sqlite_filename?: string
+  write_orphaned_graded?: boolean
+  orphaned_graded_filename?: string
+  thumbnails?: 'none' | 'all' | 'dolphin'
+  thumbnail_limit?: number
+  line_ending?: LineEnding
+  delimiter?: CsvDelimiter
+  quote_style?: CsvQuoteStyle
+  utf8_bom?: boolean
+  header_map?: Record<string, string>
+  columns?: string[]
+  include_winner_details?: boolean
+  include_survey_fields?: boolean
+  include_notes?: boolean
+  include_quality?: boolean
+  include_confidence?: boolean
+  include_decision_reason?: boolean
+  include_label_source?: boolean
+  include_capture_time?: boolean
+  include_exif_gps?: boolean
+  include_track_position?: boolean
+  include_video_frame?: boolean
+  tracks_dir?: string | null
+  gpx_files?: Record<string, string>
+  camera_utc_offset_minutes?: number
+  track_max_gap_secs?: number
+  hyperlinks?: HyperlinkStyle
+  group_problems?: boolean
+  problems_detail_filename?: string
+  blocking_severities?: ProblemSeverity[]
+  scan_token?: string | null
+  survey_timeout_secs?: number | null
+  min_graded_coverage_pct?: number | null
+  include_base_keys?: string[]
+  exclude_base_keys?: string[]
+  resume?: boolean
+  incremental_cache?: boolean
+  on_existing?: OnExisting
+  output_format?: OutputFormat
+  progress?: ProgressGranularity
+  expected_surveys_path?: string | null
+  expected_counts_path?: string | null
+  count_tolerance_pct?: number
+  resolve_duplicates?: 'manual' | 'auto'
+  fuzzy_max_edit_distance?: number
+  fuzzy_date_tolerance_days?: number
+  date_from?: string | null
+  date_to?: string | null
 }
 
+type LineEnding = 'lf' | 'crlf'
+
+type CsvDelimiter = 'comma' | 'semicolon' | 'tab'
+
+type CsvQuoteStyle = 'necessary' | 'always' | 'non_numeric' | 'never'
+
+type OutputFormat = 'csv' | 'xlsx' | 'parquet' | 'jsonl'
+
+type OnExisting = 'overwrite' | 'skip' | 'append' | 'error' | 'timestamp_suffix'
+
+type HyperlinkStyle = 'none' | 'formula' | 'uri'
+
+type ProgressGranularity =
+  | { mode: 'every_file' }
+  | { mode: 'every_n'; files: number }
+  | { mode: 'percent'; step: number }
+
 type SingleRunOptions = {
   output_filename: string
+  problems_filename?: string
+  output_format?: OutputFormat
+  on_existing?: OnExisting
+  progress?: ProgressGranularity
+  line_ending?: LineEnding
+  delimiter?: CsvDelimiter
+  quote_style?: CsvQuoteStyle
+  utf8_bom?: boolean
+  header_map?: Record<string, string>
+  columns?: string[]
+  include_winner_details?: boolean
+  include_survey_fields?: boolean
+  include_notes?: boolean
+  include_quality?: boolean
+  include_confidence?: boolean
+  include_decision_reason?: boolean
+  include_label_source?: boolean
+  include_capture_time?: boolean
+  include_exif_gps?: boolean
+  include_track_position?: boolean
+  include_video_frame?: boolean
+  tracks_dir?: string | null
+  gpx_files?: Record<string, string>
+  camera_utc_offset_minutes?: number
+  track_max_gap_secs?: number
+  hyperlinks?: HyperlinkStyle
+}
+
+type PreviewScan = {
+  scan_token: string
+  items: PreviewItem[]
 }
 
 type PreviewItem = {
@@ -34,6 +202,7 @@ type PreviewItem = {
   graded_path: string | null
   status: string
   problem_type: string | null
+  severity?: ProblemSeverity | null
   details: string | null
   raw_image_count: number | null
   graded_image_count: number | null
@@ -42,24 +211,215 @@ type PreviewItem = {
 }
 
 type RunSummary = {
+  run_id?: string
   processed_surveys: number
   total_rows: number
   dolphin_yes: number
   dolphin_no: number
   ambiguity_warnings: number
+  collapsed_candidates?: number
+  id_collisions?: number
+  perceptual_matches?: number
+  scan_reused?: boolean
   problems_count: number
+  error_count?: number
+  warning_count?: number
+  info_count?: number
   output_dir: string
   merged_csv_path: string | null
   problems_csv_path: string | null
+  problems_detail_csv_path?: string | null
+  pairings_csv_path?: string | null
+  summary_markdown_path?: string | null
+  html_report_path?: string | null
+  qa_workbook_path?: string | null
+  access_export_path?: string | null
+  sqlite_path?: string | null
+  orphaned_graded?: number
+  orphaned_graded_path?: string | null
+  surveys?: SurveySummary[]
+  email_error?: string | null
+  postgres_error?: string | null
+  history_error?: string | null
+  cancelled?: boolean
+  resumed_surveys?: number
+  cached_file_ids?: number
 }
 
+type QueueStatus = 'queued' | 'running' | 'done' | 'failed'
+
+type QueuedRun = {
+  job_id: string
+  run_id: string | null
+  status: QueueStatus
+  graded_dir: string
+  raw_dir: string
+  output_dir: string
+  survey_id: string | null
+  enqueued_at_ms: number
+  summary: RunSummary | null
+  error: string | null
+}
+
+type SurveySummary = {
+  survey_id_base: string
+  raw_path: string
+  graded_path: string
+  raw_root?: string
+  graded_root?: string
+  rows: number
+  dolphin_yes: number
+  dolphin_no: number
+  ambiguity_warnings: number
+  collapsed_candidates?: number
+  id_collisions?: number
+  perceptual_matches?: number
+  graded_map_on_disk?: boolean
+  track_path?: string | null
+  graded_coverage?: number
+}
+
+type SelfCheckReport = {
+  passed: boolean
+  expected_rows: number
+  actual_rows: number
+  mismatches: {
+    survey_id_base: string
+    raw_relpath: string
+    field: string
+    expected: string
+    actual: string
+  }[]
+  root: string
+}
+
+type RunPhase = 'discovering' | 'indexing_graded' | 'matching' | 'writing'
+
 type ProgressEvent = {
+  run_id: string
+  phase: RunPhase
   survey_id_base: string
   processed: number
   total: number
+  survey_index: number
+  survey_count: number
+  eta_seconds: number | null
 }
 
-type Mode = 'root' | 'single' | 'settings'
+type RunListItem = {
+  run_id: string
+  kind: string
+  started_at_ms: number
+  finished_at_ms: number
+  raw_root: string
+  graded_root: string
+  output_dir: string
+  processed_surveys: number
+  total_rows: number
+  problems_count: number
+  cancelled: boolean
+}
+
+type RunRecord = {
+  run_id: string
+  kind: string
+  started_at_ms: number
+  finished_at_ms: number
+  raw_root: string
+  graded_root: string
+  options: Record<string, unknown>
+  rules: Rules
+  summary: RunSummary
+}
+
+type SurveyUpdateEvent = {
+  watch_id: string
+  survey_id_base: string
+  status: string
+  raw_path: string | null
+  graded_path: string | null
+  rows: number
+  dolphin_yes: number
+  dolphin_no: number
+  ambiguity_warnings: number
+  error: string | null
+}
+
+type CountUpdateEvent = {
+  base_key: string
+  raw_image_count: number | null
+  graded_image_count: number | null
+}
+
+type RunPauseEvent = {
+  run_id: string
+  paused: boolean
+}
+
+type ManualLabel = {
+  row_id: string
+  label: number
+  note: string
+  updated_at_ms: number
+}
+
+type ReviewItem = {
+  row_id: string
+  survey_id_base: string
+  raw_relpath: string
+  dolphin: number
+  label_confidence: number | null
+  graded_hits: number
+  graded_relpath: string
+  decision_reason: string | null
+  reason: 'low_confidence' | 'several_candidates'
+  raw_path: string | null
+  graded_path: string | null
+  manual: ManualLabel | null
+}
+
+type ReviewQueue = {
+  merged_csv: string
+  total_rows: number
+  queued_rows: number
+  items: ReviewItem[]
+}
+
+type Thumbnail = {
+  path: string
+  width: number
+  height: number
+  data_url: string | null
+}
+
+type RunEstimate = {
+  scan_token: string
+  surveys: number
+  paired_surveys: number
+  raw_images: number
+  graded_images: number
+  rows_per_second: number
+  from_history: boolean
+  estimated_seconds: number
+  estimated_output_bytes: number
+}
+
+type RuleFieldError = {
+  field: string
+  message: string
+  position: number | null
+  hint: string | null
+}
+
+type RulesValidation = {
+  valid: boolean
+  errors: RuleFieldError[]
+}
+
+type Mode = 'root' | 'single' | 'review' | 'settings'
+
+const REVIEW_BATCH = 50
+const REVIEW_THUMBNAIL_PX = 320
 
 const listToText = (list: string[]) => list.join('\n')
 const textToList = (value: string) =>
@@ -104,30 +464,72 @@ const PathField = ({
   </label>
 )
 
+const formatDuration = (seconds: number) => {
+  const minutes = Math.ceil(seconds / 60)
+  return minutes < 60 ? `${minutes} min` : `${Math.floor(minutes / 60)} h ${minutes % 60} min`
+}
+
 const SectionTitle = ({ title }: { title: string }) => <h2>{title}</h2>
 
+const FieldErrors = ({
+  errors,
+  field,
+  labelled = false
+}: {
+  errors: RuleFieldError[]
+  field: string
+  labelled?: boolean
+}) => (
+  <>
+    {errors
+      .filter((error) => error.field === field || error.field.startsWith(`${field}[`))
+      .map((error, index) => (
+        <span className="field-error" key={index}>
+          {(labelled || error.field !== field) && <code>{error.field} </code>}
+          {error.position !== null && <code>@{error.position} </code>}
+          {error.message}
+          {error.hint && <em> {error.hint}</em>}
+        </span>
+      ))}
+  </>
+)
+
 function App() {
   const { t, i18n } = useTranslation()
   const [mode, setMode] = useState<Mode>('root')
   const [rules, setRules] = useState<Rules | null>(null)
   const [draftRules, setDraftRules] = useState<Rules | null>(null)
+  const [profiles, setProfiles] = useState<RuleProfile[]>([])
+  const [profileName, setProfileName] = useState('')
   const [statusMessage, setStatusMessage] = useState<string | null>(null)
   const [errorMessage, setErrorMessage] = useState<string | null>(null)
   const [busy, setBusy] = useState(false)
+  const [pausedRun, setPausedRun] = useState(false)
+  const paused = busy && pausedRun
 
   const [gradedRoot, setGradedRoot] = useState('')
   const [rawRoot, setRawRoot] = useState('')
   const [outputDir, setOutputDir] = useState('')
   const [rootOptions, setRootOptions] = useState<RootRunOptions>(defaultRootOptions)
   const [preview, setPreview] = useState<PreviewItem[]>([])
+  const [scanToken, setScanToken] = useState<string | null>(null)
   const [summary, setSummary] = useState<RunSummary | null>(null)
+  const [ruleErrors, setRuleErrors] = useState<RuleFieldError[]>([])
   const [progress, setProgress] = useState<ProgressEvent | null>(null)
+  const [watchId, setWatchId] = useState<string | null>(null)
+  const [runs, setRuns] = useState<RunListItem[] | null>(null)
 
   const [singleGradedDir, setSingleGradedDir] = useState('')
   const [singleRawDir, setSingleRawDir] = useState('')
   const [singleOutputDir, setSingleOutputDir] = useState('')
   const [singleOverride, setSingleOverride] = useState('')
   const [singleOptions, setSingleOptions] = useState<SingleRunOptions>(defaultSingleOptions)
+  const [runQueue, setRunQueue] = useState<QueuedRun[]>([])
+
+  const [reviewCsv, setReviewCsv] = useState('')
+  const [reviewQueue, setReviewQueue] = useState<ReviewQueue | null>(null)
+  const [reviewNotes, setReviewNotes] = useState<Record<string, string>>({})
+  const [thumbnails, setThumbnails] = useState<Record<string, string>>({})
 
   useEffect(() => {
     const load = async () => {
@@ -135,6 +537,7 @@ function App() {
         const loaded = await invoke<Rules>('get_config')
         setRules(loaded)
         setDraftRules(loaded)
+        setProfiles(await invoke<RuleProfile[]>('list_profiles_cmd'))
       } catch (err) {
         setErrorMessage(String(err))
       }
@@ -151,6 +554,87 @@ function App() {
     }
   }, [])
 
+  useEffect(() => {
+    const unlistenPromise = listen<CountUpdateEvent>('count_update', (event) => {
+      const update = event.payload
+      setPreview((items) =>
+        items.map((item) =>
+          item.base_key === update.base_key
+            ? {
+                ...item,
+                raw_image_count: update.raw_image_count,
+                graded_image_count: update.graded_image_count
+              }
+            : item
+        )
+      )
+    })
+    return () => {
+      unlistenPromise.then((unlisten) => unlisten())
+    }
+  }, [])
+
+  useEffect(() => {
+    const unlistenPromise = listen<SurveyUpdateEvent>('survey-updated', (event) => {
+      const update = event.payload
+      if (update.status === 'SCAN_ERROR') {
+        setErrorMessage(update.error ?? update.status)
+        return
+      }
+      setPreview((items) => {
+        if (update.status === 'REMOVED') {
+          return items.filter((item) => item.base_key !== update.survey_id_base)
+        }
+        const known = items.find((item) => item.base_key === update.survey_id_base)
+        const updated: PreviewItem = {
+          base_key: update.survey_id_base,
+          raw_path: update.raw_path,
+          graded_path: update.graded_path,
+          status: update.status,
+          problem_type: known?.problem_type ?? null,
+          severity: known?.severity ?? null,
+          details: update.error ?? known?.details ?? null,
+          raw_image_count: known?.raw_image_count ?? null,
+          graded_image_count: known?.graded_image_count ?? null,
+          survey_id_raw_detected: known?.survey_id_raw_detected ?? null,
+          survey_id_graded_detected: known?.survey_id_graded_detected ?? null
+        }
+        return known
+          ? items.map((item) => (item.base_key === update.survey_id_base ? updated : item))
+          : [...items, updated]
+      })
+    })
+    return () => {
+      unlistenPromise.then((unlisten) => unlisten())
+    }
+  }, [])
+
+  useEffect(() => {
+    const refresh = async () => {
+      try {
+        setRunQueue(await invoke<QueuedRun[]>('list_run_queue_cmd'))
+      } catch (err) {
+        setErrorMessage(String(err))
+      }
+    }
+    refresh()
+    const unlistenPromise = listen<QueuedRun>('run-queue', () => {
+      refresh()
+    })
+    return () => {
+      unlistenPromise.then((unlisten) => unlisten())
+    }
+  }, [])
+
+  useEffect(() => {
+    const unlistenPromise = listen<RunPauseEvent>('run-paused', (event) => {
+      setPausedRun(event.payload.paused)
+    })
+    return () => {
+      unlistenPromise.then((unlisten) => unlisten())
+    }
+  }, [])
+
   const languageOptions = useMemo(
     () => [
       { value: 'en', label: 'English' },
@@ -174,12 +658,46 @@ function App() {
     setSummary(null)
     setProgress(null)
     try {
-      const result = await invoke<PreviewItem[]>('preview_root_scan_cmd', {
-        gradedRoot,
-        rawRoot,
+      const result = await invoke<PreviewScan>('preview_root_scan_cmd', {
+        gradedRoots: [gradedRoot],
+        rawRoots: [rawRoot],
+        options: {
+          include_counts: true,
+          date_from: rootOptions.date_from || null,
+          date_to: rootOptions.date_to || null
+        },
+        config: rules
+      })
+      setPreview(result.items)
+      setScanToken(result.scan_token)
+    } catch (err) {
+      setErrorMessage(String(err))
+    } finally {
+      setBusy(false)
+    }
+  }
+
+  const handleEstimate = async () => {
+    if (!rules) return
+    setBusy(true)
+    setErrorMessage(null)
+    setStatusMessage(null)
+    try {
+      const estimate = await invoke<RunEstimate>('estimate_run_cmd', {
+        gradedRoots: [gradedRoot],
+        rawRoots: [rawRoot],
+        options: rootOptions,
         config: rules
       })
-      setPreview(result)
+      setScanToken(estimate.scan_token)
+      setStatusMessage(
+        t(estimate.from_history ? 'root.estimateFromHistory' : 'root.estimate', {
+          surveys: estimate.paired_surveys,
+          images: estimate.raw_images,
+          duration: formatDuration(estimate.estimated_seconds),
+          size: (estimate.estimated_output_bytes / 1_000_000).toFixed(1)
+        })
+      )
     } catch (err) {
       setErrorMessage(String(err))
     } finally {
@@ -196,10 +714,10 @@ function App() {
     setProgress(null)
     try {
       const result = await invoke<RunSummary>('run_root_scan_cmd', {
-        gradedRoot,
-        rawRoot,
+        gradedRoots: [gradedRoot],
+        rawRoots: [rawRoot],
         outputDir,
-        options: rootOptions,
+        options: { ...rootOptions, scan_token: scanToken },
         config: rules
       })
       setSummary(result)
@@ -210,6 +728,122 @@ function App() {
     }
   }
 
+  const handleToggleWatch = async () => {
+    setErrorMessage(null)
+    try {
+      if (watchId) {
+        await invoke<number>('stop_watch_cmd', { watchId })
+        setWatchId(null)
+      } else {
+        setWatchId(
+          await invoke<string>('start_watch_cmd', {
+            gradedRoot,
+            rawRoot,
+            options: null,
+            config: rules
+          })
+        )
+      }
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const handleLoadRuns = async () => {
+    setErrorMessage(null)
+    try {
+      setRuns(await invoke<RunListItem[]>('list_runs_cmd'))
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const handleShowRun = async (runId: string) => {
+    setErrorMessage(null)
+    try {
+      const record = await invoke<RunRecord>('get_run_cmd', { runId })
+      setSummary(record.summary)
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const loadThumbnail = async (path: string | null) => {
+    if (!path) return
+    try {
+      const thumbnail = await invoke<Thumbnail>('get_thumbnail_cmd', {
+        path,
+        maxPx: REVIEW_THUMBNAIL_PX,
+        inline: true
+      })
+      const dataUrl = thumbnail.data_url
+      if (dataUrl) {
+        setThumbnails((current) => ({ ...current, [path]: dataUrl }))
+      }
+    } catch {
+      // Undecodable or missing images are shown without a picture.
+    }
+  }
+
+  const handleLoadReview = async () => {
+    setErrorMessage(null)
+    try {
+      const queue = await invoke<ReviewQueue>('get_review_queue_cmd', {
+        mergedCsv: reviewCsv,
+        options: { limit: REVIEW_BATCH }
+      })
+      setReviewQueue(queue)
+      for (const item of queue.items) {
+        loadThumbnail(item.raw_path)
+        loadThumbnail(item.graded_path)
+      }
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const handleManualLabel = async (item: ReviewItem, label: number) => {
+    setErrorMessage(null)
+    try {
+      await invoke<ManualLabel | null>('set_manual_label_cmd', {
+        mergedCsv: reviewQueue?.merged_csv ?? reviewCsv,
+        rowId: item.row_id,
+        label,
+        note: reviewNotes[item.row_id] ?? null
+      })
+      setReviewQueue((current) =>
+        current && {
+          ...current,
+          items: current.items.filter((other) => other.row_id !== item.row_id)
+        }
+      )
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const handleCancelRun = async () => {
+    setPausedRun(false)
+    try {
+      await invoke<number>('cancel_run_cmd', { runId: null })
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const handleTogglePause = async () => {
+    try {
+      const changed = await invoke<number>(paused ? 'resume_run_cmd' : 'pause_run_cmd', {
+        runId: null
+      })
+      if (changed > 0 || paused) {
+        setPausedRun(!paused)
+      }
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
   const handleRunSingle = async () => {
     if (!rules) return
     setBusy(true)
@@ -234,6 +868,42 @@ function App() {
     }
   }
 
+  const handleEnqueueSingle = async () => {
+    if (!rules) return
+    setErrorMessage(null)
+    try {
+      await invoke<QueuedRun>('enqueue_single_run_cmd', {
+        gradedDir: singleGradedDir,
+        rawDir: singleRawDir,
+        outputDir: singleOutputDir,
+        surveyIdOverride: singleOverride.length > 0 ? singleOverride : null,
+        options: singleOptions,
+        config: rules
+      })
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const handleRemoveQueued = async (jobId: string) => {
+    setErrorMessage(null)
+    try {
+      await invoke<boolean>('remove_queued_run_cmd', { jobId })
+      setRunQueue(await invoke<QueuedRun[]>('list_run_queue_cmd'))
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const handleCancelQueued = async (runId: string) => {
+    setErrorMessage(null)
+    try {
+      await invoke<number>('cancel_run_cmd', { runId })
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
   const handleOpenOutput = async (path: string) => {
     setErrorMessage(null)
     try {
@@ -243,14 +913,40 @@ function App() {
     }
   }
 
+  const handleRevealPath = async (path: string) => {
+    setErrorMessage(null)
+    try {
+      await invoke('reveal_path_cmd', { path })
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
+  const handleOpenImage = async (path: string) => {
+    setErrorMessage(null)
+    try {
+      await invoke('open_image_cmd', { path })
+    } catch (err) {
+      setErrorMessage(String(err))
+    }
+  }
+
   const handleSaveRules = async () => {
     if (!draftRules) return
     setBusy(true)
     setErrorMessage(null)
     try {
-      const saved = await invoke<Rules>('save_config', { rules: draftRules })
-      setRules(saved)
-      setDraftRules(saved)
+      const validation = await invoke<RulesValidation>('validate_rules_cmd', {
+        rules: draftRules
+      })
+      setRuleErrors(validation.errors)
+      if (!validation.valid) {
+        setErrorMessage(t('settings.invalidRules', { count: validation.errors.length }))
+        return
+      }
+      const saved = await invoke<RulesUpdate>('save_config', { rules: draftRules })
+      setRules(saved.rules)
+      setDraftRules(saved.rules)
       setStatusMessage(t('settings.saveSuccess'))
     } catch (err) {
       setErrorMessage(String(err))
@@ -259,14 +955,64 @@ function App() {
     }
   }
 
+  const handleActivateProfile = async (name: string) => {
+    setBusy(true)
+    setErrorMessage(null)
+    try {
+      const loaded = await invoke<Rules>('activate_profile_cmd', { name })
+      setRules(loaded)
+      setDraftRules(loaded)
+      setProfiles(await invoke<RuleProfile[]>('list_profiles_cmd'))
+      setStatusMessage(t('settings.profileActivated', { name }))
+    } catch (err) {
+      setErrorMessage(String(err))
+    } finally {
+      setBusy(false)
+    }
+  }
+
+  const handleSaveProfile = async () => {
+    if (!draftRules || !profileName.trim()) return
+    setBusy(true)
+    setErrorMessage(null)
+    try {
+      await invoke<RulesUpdate>('save_profile_cmd', { name: profileName, rules: draftRules })
+      setProfiles(await invoke<RuleProfile[]>('list_profiles_cmd'))
+      setStatusMessage(t('settings.profileSaved', { name: profileName.trim() }))
+    } catch (err) {
+      setErrorMessage(String(err))
+    } finally {
+      setBusy(false)
+    }
+  }
+
+  const handleDeleteProfile = async () => {
+    if (!profileName.trim()) return
+    setBusy(true)
+    setErrorMessage(null)
+    try {
+      setProfiles(await invoke<RuleProfile[]>('delete_profile_cmd', { name: profileName }))
+      setStatusMessage(t('settings.profileDeleted', { name: profileName.trim() }))
+      setProfileName('')
+    } catch (err) {
+      setErrorMessage(String(err))
+    } finally {
+      setBusy(false)
+    }
+  }
+
   const handleResetRules = async () => {
     setBusy(true)
     setErrorMessage(null)
     try {
-      const saved = await invoke<Rules>('reset_config')
-      setRules(saved)
-      setDraftRules(saved)
-      setStatusMessage(t('settings.resetSuccess'))
+      const saved = await invoke<RulesUpdate>('reset_config')
+      setRules(saved.rules)
+      setDraftRules(saved.rules)
+      setStatusMessage(
+        saved.backup_path
+          ? `${t('settings.resetSuccess')} ${t('settings.backupSaved', { path: saved.backup_path })}`
+          : t('settings.resetSuccess')
+      )
     } catch (err) {
       setErrorMessage(String(err))
     } finally {
@@ -274,107 +1020,455 @@ function App() {
     }
   }
 
+  const handleSelfCheck = async () => {
+    setBusy(true)
+    setErrorMessage(null)
+    try {
+      const report = await invoke<SelfCheckReport>('run_self_check_cmd')
+      if (report.passed) {
+        setStatusMessage(t('settings.selfCheckPassed', { rows: report.actual_rows }))
+      } else {
+        setErrorMessage(
+          t('settings.selfCheckFailed', { count: report.mismatches.length, path: report.root })
+        )
+      }
+    } catch (err) {
+      setErrorMessage(String(err))
+    } finally {
+      setBusy(false)
+    }
+  }
+
+  const progressView = progress && (
+    <div className="progress">
+      <div>
+        <strong>{paused ? t('progress.paused') : t(`progress.phases.${progress.phase}`)}</strong>
+        {progress.survey_count > 0 &&
+          ` · ${t('progress.survey', {
+            index: progress.survey_index,
+            count: progress.survey_count
+          })}`}
+        {progress.survey_id_base && ` · ${progress.survey_id_base}`}
+      </div>
+      {progress.phase === 'matching' && (
+        <div>
+          {t('progress.filesProcessed')}: {progress.processed}/{progress.total}
+        </div>
+      )}
+      {progress.eta_seconds !== null && (
+        <div>{t('progress.remaining', { duration: formatDuration(progress.eta_seconds) })}</div>
+      )}
+    </div>
+  )
+
   const settingsView = draftRules ? (
     <section className="panel">
       <SectionTitle title={t('settings.title')} />
       <div className="grid">
         <label className="field">
-          <span>{t('settings.extensions')}</span>
-          <textarea
-            value={listToText(draftRules.extensions)}
+          <span>{t('settings.profile')}</span>
+          <select
+            value={profiles.find((profile) => profile.active)?.name ?? 'default'}
+            onChange={(event) => handleActivateProfile(event.target.value)}
+            disabled={busy}
+          >
+            {profiles.map((profile) => (
+              <option key={profile.name} value={profile.name}>
+                {profile.name === 'default' ? t('settings.defaultProfile') : profile.name}
+              </option>
+            ))}
+          </select>
+        </label>
+        <label className="field">
+          <span>{t('settings.profileName')}</span>
+          <input value={profileName} onChange={(event) => setProfileName(event.target.value)} />
+        </label>
+      </div>
+      <div className="actions">
+        <button
+          className="secondary"
+          onClick={handleSaveProfile}
+          disabled={busy || !profileName.trim()}
+        >
+          {t('settings.saveProfile')}
+        </button>
+        <button
+          className="secondary"
+          onClick={handleDeleteProfile}
+          disabled={busy || !profileName.trim()}
+        >
+          {t('settings.deleteProfile')}
+        </button>
+      </div>
+      <div className="grid">
+        <label className="field">
+          <span>{t('settings.extensions')}</span>
+          <textarea
+            value={listToText(draftRules.extensions)}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                extensions: textToList(event.target.value)
+              })
+            }
+          />
+        </label>
+        <label className="field">
+          <span>{t('settings.cameraRawExtensions')}</span>
+          <textarea
+            value={listToText(draftRules.camera_raw_extensions ?? [])}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                camera_raw_extensions: textToList(event.target.value)
+              })
+            }
+          />
+        </label>
+        <label className="field">
+          <span>{t('settings.detectedRegex')}</span>
+          <input
+            value={draftRules.survey_id_regex_detected}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                survey_id_regex_detected: event.target.value
+              })
+            }
+          />
+          <FieldErrors errors={ruleErrors} field="survey_id_regex_detected" />
+        </label>
+        <label className="field">
+          <span>{t('settings.baseRegex')}</span>
+          <input
+            value={draftRules.survey_id_regex_base}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                survey_id_regex_base: event.target.value
+              })
+            }
+          />
+          <FieldErrors errors={ruleErrors} field="survey_id_regex_base" />
+        </label>
+        <label className="field">
+          <span>{t('settings.imageIdRegex')}</span>
+          <input
+            value={draftRules.image_id_regex}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                image_id_regex: event.target.value
+              })
+            }
+          />
+          <FieldErrors errors={ruleErrors} field="image_id_regex" />
+        </label>
+        <label className="field">
+          <span>{t('settings.indRegex')}</span>
+          <input
+            value={draftRules.graded_priority_ind_regex}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                graded_priority_ind_regex: event.target.value
+              })
+            }
+          />
+          <FieldErrors errors={ruleErrors} field="graded_priority_ind_regex" />
+        </label>
+        <label className="field">
+          <span>{t('settings.secondaryTokens')}</span>
+          <textarea
+            value={listToText(draftRules.graded_priority_secondary_tokens)}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                graded_priority_secondary_tokens: textToList(event.target.value)
+              })
+            }
+          />
+        </label>
+        <label className="field">
+          <span>{t('settings.negativeTokens')}</span>
+          <textarea
+            value={listToText(draftRules.graded_negative_contains_any)}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                graded_negative_contains_any: textToList(event.target.value)
+              })
+            }
+          />
+        </label>
+        <label className="field">
+          <span>{t('settings.positiveTokens')}</span>
+          <textarea
+            value={listToText(draftRules.graded_positive_contains_any)}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                graded_positive_contains_any: textToList(event.target.value)
+              })
+            }
+          />
+        </label>
+        <label className="field">
+          <span>{t('settings.excludedSubfolders')}</span>
+          <textarea
+            value={listToText(draftRules.graded_excluded_subfolders ?? [])}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                graded_excluded_subfolders: textToList(event.target.value)
+              })
+            }
+          />
+        </label>
+        <label className="field">
+          <span>{t('settings.excludeDirGlobs')}</span>
+          <textarea
+            value={listToText(draftRules.exclude_dir_globs ?? [])}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                exclude_dir_globs: textToList(event.target.value)
+              })
+            }
+          />
+          <FieldErrors errors={ruleErrors} field="exclude_dir_globs" />
+        </label>
+        <label className="field">
+          <span>{t('settings.qualityTokens')}</span>
+          <textarea
+            value={listToText(draftRules.quality_tokens ?? [])}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                quality_tokens: textToList(event.target.value)
+              })
+            }
+          />
+        </label>
+        <label className="field">
+          <span>{t('settings.dedupe')}</span>
+          <select
+            value={draftRules.graded_dedupe ?? 'off'}
             onChange={(event) =>
               setDraftRules({
                 ...draftRules,
-                extensions: textToList(event.target.value)
+                graded_dedupe: event.target.value as CandidateDedupe
               })
             }
-          />
+          >
+            <option value="off">{t('settings.dedupeOff')}</option>
+            <option value="size">{t('settings.dedupeSize')}</option>
+            <option value="hash">{t('settings.dedupeHash')}</option>
+          </select>
         </label>
         <label className="field">
-          <span>{t('settings.detectedRegex')}</span>
+          <span>{t('settings.fileIdCollisions')}</span>
+          <select
+            value={draftRules.file_id_collisions ?? 'warn'}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                file_id_collisions: event.target.value as CollisionPolicy
+              })
+            }
+          >
+            <option value="warn">{t('settings.collisionWarn')}</option>
+            <option value="hash">{t('settings.collisionHash')}</option>
+            <option value="refuse">{t('settings.collisionRefuse')}</option>
+          </select>
+        </label>
+        <label className="field">
+          <span>{t('settings.matchingStrategy')}</span>
+          <select
+            value={draftRules.matching_strategy ?? 'filename'}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                matching_strategy: event.target.value as MatchingStrategy
+              })
+            }
+          >
+            <option value="filename">{t('settings.matchingFilename')}</option>
+            <option value="checksum">{t('settings.matchingChecksum')}</option>
+          </select>
+        </label>
+        <label className="toggle">
           <input
-            value={draftRules.survey_id_regex_detected}
+            type="checkbox"
+            checked={draftRules.split_combo_graded_folders ?? false}
             onChange={(event) =>
               setDraftRules({
                 ...draftRules,
-                survey_id_regex_detected: event.target.value
+                split_combo_graded_folders: event.target.checked
               })
             }
           />
+          <span>{t('settings.splitComboGraded')}</span>
         </label>
-        <label className="field">
-          <span>{t('settings.baseRegex')}</span>
+        <label className="toggle">
           <input
-            value={draftRules.survey_id_regex_base}
+            type="checkbox"
+            checked={draftRules.split_combo_raw_folders ?? false}
             onChange={(event) =>
               setDraftRules({
                 ...draftRules,
-                survey_id_regex_base: event.target.value
+                split_combo_raw_folders: event.target.checked
               })
             }
           />
+          <span>{t('settings.splitComboRaw')}</span>
         </label>
-        <label className="field">
-          <span>{t('settings.imageIdRegex')}</span>
+        <label className="toggle">
           <input
-            value={draftRules.image_id_regex}
+            type="checkbox"
+            checked={draftRules.transliterate ?? false}
             onChange={(event) =>
               setDraftRules({
                 ...draftRules,
-                image_id_regex: event.target.value
+                transliterate: event.target.checked
               })
             }
           />
+          <span>{t('settings.transliterate')}</span>
         </label>
-        <label className="field">
-          <span>{t('settings.indRegex')}</span>
+        <label className="toggle">
           <input
-            value={draftRules.graded_priority_ind_regex}
+            type="checkbox"
+            checked={draftRules.perceptual_fallback ?? false}
             onChange={(event) =>
               setDraftRules({
                 ...draftRules,
-                graded_priority_ind_regex: event.target.value
+                perceptual_fallback: event.target.checked
+              })
+            }
+          />
+          <span>{t('settings.perceptualFallback')}</span>
+        </label>
+        <label className="toggle">
+          <input
+            type="checkbox"
+            checked={draftRules.follow_symlinks ?? false}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                follow_symlinks: event.target.checked
+              })
+            }
+          />
+          <span>{t('settings.followSymlinks')}</span>
+        </label>
+        <label className="toggle">
+          <input
+            type="checkbox"
+            checked={draftRules.scan_archives ?? false}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                scan_archives: event.target.checked
+              })
+            }
+          />
+          <span>{t('settings.scanArchives')}</span>
+        </label>
+        <label className="toggle">
+          <input
+            type="checkbox"
+            checked={draftRules.video_mode ?? false}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                video_mode: event.target.checked
               })
             }
           />
+          <span>{t('settings.videoMode')}</span>
         </label>
         <label className="field">
-          <span>{t('settings.secondaryTokens')}</span>
+          <span>{t('settings.videoExtensions')}</span>
           <textarea
-            value={listToText(draftRules.graded_priority_secondary_tokens)}
+            value={listToText(draftRules.video_extensions ?? [])}
             onChange={(event) =>
               setDraftRules({
                 ...draftRules,
-                graded_priority_secondary_tokens: textToList(event.target.value)
+                video_extensions: textToList(event.target.value)
               })
             }
           />
         </label>
         <label className="field">
-          <span>{t('settings.negativeTokens')}</span>
-          <textarea
-            value={listToText(draftRules.graded_negative_contains_any)}
+          <span>{t('settings.videoFrameRegex')}</span>
+          <input
+            value={draftRules.video_frame_regex ?? ''}
             onChange={(event) =>
               setDraftRules({
                 ...draftRules,
-                graded_negative_contains_any: textToList(event.target.value)
+                video_frame_regex: event.target.value
               })
             }
           />
+          <FieldErrors errors={ruleErrors} field="video_frame_regex" />
         </label>
         <label className="field">
-          <span>{t('settings.positiveTokens')}</span>
-          <textarea
-            value={listToText(draftRules.graded_positive_contains_any)}
+          <span>{t('settings.maxScanDepth')}</span>
+          <input
+            type="number"
+            min={1}
+            value={draftRules.max_scan_depth ?? ''}
             onChange={(event) =>
               setDraftRules({
                 ...draftRules,
-                graded_positive_contains_any: textToList(event.target.value)
+                max_scan_depth: event.target.value ? Number(event.target.value) : null
               })
             }
           />
         </label>
+        <label className="field">
+          <span>{t('settings.baseKeyCase')}</span>
+          <select
+            value={draftRules.base_key_case ?? 'upper'}
+            onChange={(event) =>
+              setDraftRules({
+                ...draftRules,
+                base_key_case: event.target.value as 'upper' | 'lower' | 'preserve'
+              })
+            }
+          >
+            <option value="upper">{t('settings.caseUpper')}</option>
+            <option value="lower">{t('settings.caseLower')}</option>
+            <option value="preserve">{t('settings.casePreserve')}</option>
+          </select>
+        </label>
+        {(draftRules.base_key_schemes ?? []).length > 0 && (
+          <label className="field">
+            <span>{t('settings.baseKeyScheme')}</span>
+            <select
+              value={draftRules.active_base_key_scheme ?? ''}
+              onChange={(event) =>
+                setDraftRules({
+                  ...draftRules,
+                  active_base_key_scheme: event.target.value || null
+                })
+              }
+            >
+              <option value="">{t('settings.defaultScheme')}</option>
+              {(draftRules.base_key_schemes ?? []).map((scheme) => (
+                <option key={scheme.name} value={scheme.name}>
+                  {scheme.name}
+                </option>
+              ))}
+            </select>
+            <FieldErrors errors={ruleErrors} field="base_key_schemes" />
+          </label>
+        )}
       </div>
+      {['survey_fields_regex', 'notes_line_regex', 'rules'].map((field) => (
+        <FieldErrors key={field} errors={ruleErrors} field={field} labelled />
+      ))}
       <p className="help">{t('settings.helperImageIdRegex')}</p>
       <p className="help">{t('settings.helperTokens')}</p>
       <div className="actions">
@@ -384,6 +1478,9 @@ function App() {
         <button className="secondary" onClick={handleResetRules} disabled={busy}>
           {t('common.reset')}
         </button>
+        <button className="secondary" onClick={handleSelfCheck} disabled={busy}>
+          {t('settings.selfCheck')}
+        </button>
       </div>
     </section>
   ) : null
@@ -414,6 +1511,9 @@ function App() {
         <button className={mode === 'single' ? 'active' : ''} onClick={() => setMode('single')}>
           {t('nav.single')}
         </button>
+        <button className={mode === 'review' ? 'active' : ''} onClick={() => setMode('review')}>
+          {t('nav.review')}
+        </button>
         <button className={mode === 'settings' ? 'active' : ''} onClick={() => setMode('settings')}>
           {t('nav.settings')}
         </button>
@@ -481,7 +1581,81 @@ function App() {
               />
               <span>{t('root.writeMerged')}</span>
             </label>
+            <label className="toggle">
+              <input
+                type="checkbox"
+                checked={rootOptions.resume ?? false}
+                onChange={(event) =>
+                  setRootOptions({
+                    ...rootOptions,
+                    resume: event.target.checked
+                  })
+                }
+              />
+              <span>{t('root.resume')}</span>
+            </label>
+            <label className="toggle">
+              <input
+                type="checkbox"
+                checked={rootOptions.incremental_cache ?? false}
+                onChange={(event) =>
+                  setRootOptions({
+                    ...rootOptions,
+                    incremental_cache: event.target.checked
+                  })
+                }
+              />
+              <span>{t('root.incrementalCache')}</span>
+            </label>
+            <label className="toggle">
+              <input
+                type="checkbox"
+                checked={rootOptions.resolve_duplicates === 'auto'}
+                onChange={(event) =>
+                  setRootOptions({
+                    ...rootOptions,
+                    resolve_duplicates: event.target.checked ? 'auto' : 'manual'
+                  })
+                }
+              />
+              <span>{t('root.resolveDuplicates')}</span>
+            </label>
             <div className="grid">
+              <label className="field">
+                <span>{t('root.outputFormat')}</span>
+                <select
+                  value={rootOptions.output_format ?? 'csv'}
+                  onChange={(event) =>
+                    setRootOptions({
+                      ...rootOptions,
+                      output_format: event.target.value as OutputFormat
+                    })
+                  }
+                >
+                  <option value="csv">CSV</option>
+                  <option value="xlsx">Excel (.xlsx)</option>
+                  <option value="parquet">Parquet (.parquet)</option>
+                  <option value="jsonl">JSON Lines (.jsonl)</option>
+                </select>
+              </label>
+              <label className="field">
+                <span>{t('root.onExisting')}</span>
+                <select
+                  value={rootOptions.on_existing ?? 'overwrite'}
+                  onChange={(event) =>
+                    setRootOptions({
+                      ...rootOptions,
+                      on_existing: event.target.value as OnExisting
+                    })
+                  }
+                >
+                  <option value="overwrite">{t('root.existingOverwrite')}</option>
+                  <option value="skip">{t('root.existingSkip')}</option>
+                  <option value="append">{t('root.existingAppend')}</option>
+                  <option value="error">{t('root.existingError')}</option>
+                  <option value="timestamp_suffix">{t('root.existingTimestamp')}</option>
+                </select>
+              </label>
               <label className="field">
                 <span>{t('root.mergedFilename')}</span>
                 <input
@@ -518,6 +1692,56 @@ function App() {
                   }
                 />
               </label>
+              <label className="field">
+                <span>{t('root.dateFrom')}</span>
+                <input
+                  type="date"
+                  value={rootOptions.date_from ?? ''}
+                  onChange={(event) =>
+                    setRootOptions({
+                      ...rootOptions,
+                      date_from: event.target.value || null
+                    })
+                  }
+                />
+              </label>
+              <label className="field">
+                <span>{t('root.dateTo')}</span>
+                <input
+                  type="date"
+                  value={rootOptions.date_to ?? ''}
+                  onChange={(event) =>
+                    setRootOptions({
+                      ...rootOptions,
+                      date_to: event.target.value || null
+                    })
+                  }
+                />
+              </label>
+              <label className="field">
+                <span>{t('root.includeBaseKeys')}</span>
+                <textarea
+                  value={listToText(rootOptions.include_base_keys ?? [])}
+                  onChange={(event) =>
+                    setRootOptions({
+                      ...rootOptions,
+                      include_base_keys: textToList(event.target.value)
+                    })
+                  }
+                />
+              </label>
+              <label className="field">
+                <span>{t('root.excludeBaseKeys')}</span>
+                <textarea
+                  value={listToText(rootOptions.exclude_base_keys ?? [])}
+                  onChange={(event) =>
+                    setRootOptions({
+                      ...rootOptions,
+                      exclude_base_keys: textToList(event.target.value)
+                    })
+                  }
+                />
+              </label>
             </div>
           </div>
 
@@ -525,6 +1749,20 @@ function App() {
             <button onClick={handlePreview} disabled={busy || !rules || !gradedRoot || !rawRoot}>
               {t('common.preview')}
             </button>
+            <button
+              className="secondary"
+              onClick={handleEstimate}
+              disabled={busy || !rules || !gradedRoot || !rawRoot}
+            >
+              {t('root.estimateButton')}
+            </button>
+            <button
+              className="secondary"
+              onClick={handleToggleWatch}
+              disabled={!rules || !gradedRoot || !rawRoot}
+            >
+              {watchId ? t('root.stopWatch') : t('root.startWatch')}
+            </button>
             <button
               className="primary"
               onClick={handleRunRoot}
@@ -532,18 +1770,19 @@ function App() {
             >
               {t('common.run')}
             </button>
+            {busy && (
+              <>
+                <button className="secondary" onClick={handleTogglePause}>
+                  {paused ? t('common.resume') : t('common.pause')}
+                </button>
+                <button className="secondary" onClick={handleCancelRun}>
+                  {t('common.cancel')}
+                </button>
+              </>
+            )}
           </div>
 
-          {progress && (
-            <div className="progress">
-              <div>
-                <strong>{t('progress.label')}:</strong> {progress.survey_id_base}
-              </div>
-              <div>
-                {t('progress.filesProcessed')}: {progress.processed}/{progress.total}
-              </div>
-            </div>
-          )}
+          {progressView}
 
           <div className="preview">
             <h3>{t('root.previewTitle')}</h3>
@@ -562,8 +1801,21 @@ function App() {
                 {preview.map((item) => (
                   <div className={`row ${item.status === 'OK' ? 'ok' : 'problem'}`} key={item.base_key}>
                     <span>{item.base_key}</span>
-                    <span title={item.raw_path ?? ''}>{item.raw_path ?? '-'}</span>
-                    <span title={item.graded_path ?? ''}>{item.graded_path ?? '-'}</span>
+                    {[item.raw_path, item.graded_path].map((path, index) =>
+                      path ? (
+                        <button
+                          key={index}
+                          type="button"
+                          className="link"
+                          title={t('common.revealPath', { path })}
+                          onClick={() => handleRevealPath(path)}
+                        >
+                          {path}
+                        </button>
+                      ) : (
+                        <span key={index}>-</span>
+                      )
+                    )}
                     <span>{item.status}</span>
                     <span>{item.raw_image_count ?? '-'}</span>
                     <span>{item.graded_image_count ?? '-'}</span>
@@ -616,6 +1868,41 @@ function App() {
                 }
               />
             </label>
+            <label className="field">
+              <span>{t('root.outputFormat')}</span>
+              <select
+                value={singleOptions.output_format ?? 'csv'}
+                onChange={(event) =>
+                  setSingleOptions({
+                    ...singleOptions,
+                    output_format: event.target.value as OutputFormat
+                  })
+                }
+              >
+                <option value="csv">CSV</option>
+                <option value="xlsx">Excel (.xlsx)</option>
+                <option value="parquet">Parquet (.parquet)</option>
+                <option value="jsonl">JSON Lines (.jsonl)</option>
+              </select>
+            </label>
+            <label className="field">
+              <span>{t('root.onExisting')}</span>
+              <select
+                value={singleOptions.on_existing ?? 'overwrite'}
+                onChange={(event) =>
+                  setSingleOptions({
+                    ...singleOptions,
+                    on_existing: event.target.value as OnExisting
+                  })
+                }
+              >
+                <option value="overwrite">{t('root.existingOverwrite')}</option>
+                <option value="skip">{t('root.existingSkip')}</option>
+                <option value="append">{t('root.existingAppend')}</option>
+                <option value="error">{t('root.existingError')}</option>
+                <option value="timestamp_suffix">{t('root.existingTimestamp')}</option>
+              </select>
+            </label>
           </div>
 
           <div className="actions">
@@ -626,26 +1913,225 @@ function App() {
             >
               {t('common.run')}
             </button>
+            <button
+              className="secondary"
+              onClick={handleEnqueueSingle}
+              disabled={!rules || !singleGradedDir || !singleRawDir || !singleOutputDir}
+            >
+              {t('queue.add')}
+            </button>
+            {busy && (
+              <>
+                <button className="secondary" onClick={handleTogglePause}>
+                  {paused ? t('common.resume') : t('common.pause')}
+                </button>
+                <button className="secondary" onClick={handleCancelRun}>
+                  {t('common.cancel')}
+                </button>
+              </>
+            )}
           </div>
 
-          {progress && (
-            <div className="progress">
-              <div>
-                <strong>{t('progress.label')}:</strong> {progress.survey_id_base}
-              </div>
-              <div>
-                {t('progress.filesProcessed')}: {progress.processed}/{progress.total}
+          {progressView}
+
+          {runQueue.length > 0 && (
+            <>
+              <SectionTitle title={t('queue.title')} />
+              <p className="help">{t('queue.help')}</p>
+              <div className="table">
+                <div className="row head">
+                  <span>{t('queue.status')}</span>
+                  <span>{t('root.table.rawPath')}</span>
+                  <span>{t('root.table.gradedPath')}</span>
+                  <span>{t('summary.totalRows')}</span>
+                  <span>{t('common.error')}</span>
+                  <span />
+                </div>
+                {runQueue.map((run) => (
+                  <div
+                    className={`row ${run.status === 'failed' ? 'problem' : run.status === 'done' ? 'ok' : ''}`}
+                    key={run.job_id}
+                  >
+                    <span>{t(`queue.statuses.${run.status}`)}</span>
+                    <span title={run.raw_dir}>{run.raw_dir}</span>
+                    <span title={run.graded_dir}>{run.graded_dir}</span>
+                    <span>{run.summary?.total_rows ?? ''}</span>
+                    <span title={run.error ?? ''}>{run.error}</span>
+                    <span>
+                      {run.status === 'queued' && (
+                        <button className="secondary" onClick={() => handleRemoveQueued(run.job_id)}>
+                          {t('queue.remove')}
+                        </button>
+                      )}
+                      {run.status === 'running' && run.run_id && (
+                        <button className="secondary" onClick={() => handleCancelQueued(run.run_id!)}>
+                          {t('common.cancel')}
+                        </button>
+                      )}
+                      {run.summary && (
+                        <button className="secondary" onClick={() => setSummary(run.summary)}>
+                          {t('history.show')}
+                        </button>
+                      )}
+                    </span>
+                  </div>
+                ))}
               </div>
+            </>
+          )}
+        </section>
+      )}
+
+      {mode === 'review' && (
+        <section className="panel">
+          <SectionTitle title={t('review.title')} />
+          <p className="help">{t('review.help')}</p>
+          <label className="field">
+            <span>{t('review.mergedCsv')}</span>
+            <div className="field-row">
+              <input
+                value={reviewCsv}
+                onChange={(event) => setReviewCsv(event.target.value)}
+                placeholder={summary?.merged_csv_path ?? '/'}
+              />
+              {summary?.merged_csv_path && (
+                <button
+                  type="button"
+                  className="secondary"
+                  onClick={() => setReviewCsv(summary.merged_csv_path ?? '')}
+                >
+                  {t('review.useLastRun')}
+                </button>
+              )}
             </div>
+          </label>
+          <div className="actions">
+            <button className="primary" onClick={handleLoadReview} disabled={!reviewCsv}>
+              {t('review.load')}
+            </button>
+          </div>
+          {reviewQueue && (
+            <p className="help">
+              {t('review.counts', {
+                queued: reviewQueue.queued_rows,
+                total: reviewQueue.total_rows
+              })}
+            </p>
           )}
+          {reviewQueue && reviewQueue.items.length === 0 && (
+            <p className="help">{t('review.empty')}</p>
+          )}
+          {reviewQueue?.items.map((item) => (
+            <div className="review-item" key={item.row_id}>
+              <div className="review-images">
+                {[item.raw_path, item.graded_path].map((path, index) => (
+                  <figure key={index}>
+                    {path && thumbnails[path] ? (
+                      <img
+                        src={thumbnails[path]}
+                        alt={path}
+                        title={t('review.openImage')}
+                        onClick={() => handleOpenImage(path)}
+                      />
+                    ) : (
+                      <div className="review-placeholder">-</div>
+                    )}
+                    <figcaption>{index === 0 ? t('review.raw') : t('review.graded')}</figcaption>
+                  </figure>
+                ))}
+              </div>
+              <div className="review-details">
+                {item.raw_path ? (
+                  <button
+                    type="button"
+                    className="link"
+                    title={t('common.revealPath', { path: item.raw_path })}
+                    onClick={() => item.raw_path && handleRevealPath(item.raw_path)}
+                  >
+                    <strong>{item.row_id}</strong>
+                  </button>
+                ) : (
+                  <strong title={item.row_id}>{item.row_id}</strong>
+                )}
+                <span className="muted">
+                  {t('review.current', { dolphin: item.dolphin })}
+                  {item.label_confidence !== null && ` · ${item.label_confidence.toFixed(2)}`}
+                  {` · ${t(`review.reasons.${item.reason}`)}`}
+                </span>
+                {item.decision_reason && <span className="muted">{item.decision_reason}</span>}
+                <span className="muted" title={item.graded_relpath}>
+                  {item.graded_relpath}
+                </span>
+                <input
+                  value={reviewNotes[item.row_id] ?? item.manual?.note ?? ''}
+                  placeholder={t('review.note')}
+                  onChange={(event) =>
+                    setReviewNotes({ ...reviewNotes, [item.row_id]: event.target.value })
+                  }
+                />
+                <div className="actions">
+                  <button className="primary" onClick={() => handleManualLabel(item, 1)}>
+                    {t('review.dolphin')}
+                  </button>
+                  <button className="secondary" onClick={() => handleManualLabel(item, 0)}>
+                    {t('review.noDolphin')}
+                  </button>
+                </div>
+              </div>
+            </div>
+          ))}
         </section>
       )}
 
       {mode === 'settings' && settingsView}
 
+      {mode === 'settings' && (
+        <section className="panel">
+          <SectionTitle title={t('history.title')} />
+          <div className="actions">
+            <button className="secondary" onClick={handleLoadRuns}>
+              {t('history.load')}
+            </button>
+          </div>
+          {runs && runs.length === 0 && <p className="help">{t('history.empty')}</p>}
+          {runs && runs.length > 0 && (
+            <div className="table">
+              <div className="row head">
+                <span>{t('history.finishedAt')}</span>
+                <span>{t('root.table.rawPath')}</span>
+                <span>{t('root.table.gradedPath')}</span>
+                <span>{t('summary.totalRows')}</span>
+                <span>{t('summary.problemsCount')}</span>
+                <span />
+              </div>
+              {runs.map((run) => (
+                <div className={`row ${run.cancelled ? 'problem' : 'ok'}`} key={run.run_id}>
+                  <span>
+                    {new Date(run.finished_at_ms).toLocaleString()} ({run.kind})
+                  </span>
+                  <span title={run.raw_root}>{run.raw_root}</span>
+                  <span title={run.graded_root}>{run.graded_root}</span>
+                  <span>{run.total_rows}</span>
+                  <span>{run.problems_count}</span>
+                  <span>
+                    <button className="secondary" onClick={() => handleShowRun(run.run_id)}>
+                      {t('history.show')}
+                    </button>
+                  </span>
+                </div>
+              ))}
+            </div>
+          )}
+        </section>
+      )}
+
       {summary && (
         <section className="panel summary">
           <SectionTitle title={t('summary.title')} />
+          {summary.cancelled && <p className="help">{t('summary.cancelled')}</p>}
+          {summary.history_error && (
+            <p className="help">{t('summary.historyError', { error: summary.history_error })}</p>
+          )}
           <div className="summary-grid">
             <div>
               <span>{t('summary.processedSurveys')}</span>
@@ -667,22 +2153,68 @@ function App() {
               <span>{t('summary.problemsCount')}</span>
               <strong>{summary.problems_count}</strong>
             </div>
+            {!!summary.error_count && (
+              <div>
+                <span>{t('summary.errorCount')}</span>
+                <strong>{summary.error_count}</strong>
+              </div>
+            )}
+            {!!summary.warning_count && (
+              <div>
+                <span>{t('summary.warningCount')}</span>
+                <strong>{summary.warning_count}</strong>
+              </div>
+            )}
             <div>
               <span>{t('summary.ambiguityWarnings')}</span>
               <strong>{summary.ambiguity_warnings}</strong>
             </div>
+            {!!summary.collapsed_candidates && (
+              <div>
+                <span>{t('summary.collapsedCandidates')}</span>
+                <strong>{summary.collapsed_candidates}</strong>
+              </div>
+            )}
+            {!!summary.id_collisions && (
+              <div>
+                <span>{t('summary.idCollisions')}</span>
+                <strong>{summary.id_collisions}</strong>
+              </div>
+            )}
+            {!!summary.perceptual_matches && (
+              <div>
+                <span>{t('summary.perceptualMatches')}</span>
+                <strong>{summary.perceptual_matches}</strong>
+              </div>
+            )}
           </div>
           <div className="summary-links">
             {summary.merged_csv_path && (
               <div>
                 <span>{t('summary.mergedCsv')}:</span>
-                <code>{summary.merged_csv_path}</code>
+                <button
+                  type="button"
+                  className="link"
+                  title={t('common.revealPath', { path: summary.merged_csv_path })}
+                  onClick={() => summary.merged_csv_path && handleRevealPath(summary.merged_csv_path)}
+                >
+                  <code>{summary.merged_csv_path}</code>
+                </button>
               </div>
             )}
             {summary.problems_csv_path && (
               <div>
                 <span>{t('summary.problemsCsv')}:</span>
-                <code>{summary.problems_csv_path}</code>
+                <button
+                  type="button"
+                  className="link"
+                  title={t('common.revealPath', { path: summary.problems_csv_path })}
+                  onClick={() =>
+                    summary.problems_csv_path && handleRevealPath(summary.problems_csv_path)
+                  }
+                >
+                  <code>{summary.problems_csv_path}</code>
+                </button>
               </div>
             )}
           </div>